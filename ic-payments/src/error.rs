@@ -1,6 +1,9 @@
-use candid::{CandidType, Deserialize, Nat};
+use candid::{CandidType, Deserialize, Nat, Principal};
 use ic_exports::ic_cdk::api::call::RejectionCode;
 use ic_exports::icrc_types::icrc1::transfer::TransferError;
+use ic_exports::icrc_types::icrc2::approve::ApproveError;
+use ic_exports::icrc_types::icrc2::transfer_from::TransferFromError;
+use ic_exports::ledger::TransferError as IcpTransferError;
 use thiserror::Error;
 
 use crate::BalanceError;
@@ -22,6 +25,12 @@ pub enum TransferFailReason {
     #[error("transaction is too old to be executed")]
     TooOld,
 
+    #[error("allowance approved to the canister is not enough: {0}")]
+    InsufficientAllowance(Nat),
+
+    #[error("approve request was rejected: {0:?}")]
+    ApproveRejected(ApproveError),
+
     #[error("unknown")]
     Unknown,
 }
@@ -108,6 +117,15 @@ pub enum ParametersError {
 
     #[error("target account cannot be equal to the source account")]
     TargetAccountInvalid,
+
+    #[error("recovery entry is not found")]
+    UnknownRecoveryEntry,
+
+    #[error("first step of a double-step transfer cannot be resolved as succeeded")]
+    IncompleteDoubleStep,
+
+    #[error("token {0} is not registered")]
+    UnknownToken(Principal),
 }
 
 impl From<(RejectionCode, String)> for InternalPaymentError {
@@ -149,6 +167,74 @@ impl From<TransferError> for InternalPaymentError {
     }
 }
 
+impl From<TransferFromError> for InternalPaymentError {
+    fn from(err: TransferFromError) -> Self {
+        // Apart from the allowance, `icrc2_transfer_from` fails in the same way as
+        // `icrc1_transfer`, so the terminal can handle both kinds of transfers the same way.
+        let err = match err {
+            TransferFromError::InsufficientAllowance { allowance } => {
+                return Self::TransferFailed(TransferFailReason::InsufficientAllowance(allowance))
+            }
+            TransferFromError::BadFee { expected_fee } => TransferError::BadFee { expected_fee },
+            TransferFromError::BadBurn { min_burn_amount } => {
+                TransferError::BadBurn { min_burn_amount }
+            }
+            TransferFromError::InsufficientFunds { balance } => {
+                TransferError::InsufficientFunds { balance }
+            }
+            TransferFromError::TooOld => TransferError::TooOld,
+            TransferFromError::CreatedInFuture { ledger_time } => {
+                TransferError::CreatedInFuture { ledger_time }
+            }
+            TransferFromError::Duplicate { duplicate_of } => {
+                TransferError::Duplicate { duplicate_of }
+            }
+            TransferFromError::TemporarilyUnavailable => TransferError::TemporarilyUnavailable,
+            TransferFromError::GenericError {
+                error_code,
+                message,
+            } => TransferError::GenericError {
+                error_code,
+                message,
+            },
+        };
+
+        err.into()
+    }
+}
+
+impl From<IcpTransferError> for InternalPaymentError {
+    fn from(err: IcpTransferError) -> Self {
+        let err = match err {
+            IcpTransferError::BadFee { expected_fee } => {
+                return Self::WrongFee(expected_fee.e8s().into())
+            }
+            IcpTransferError::InsufficientFunds { balance } => TransferError::InsufficientFunds {
+                balance: balance.e8s().into(),
+            },
+            IcpTransferError::TxTooOld { .. } => TransferError::TooOld,
+            // Legacy ledger doesn't report its time.
+            IcpTransferError::TxCreatedInFuture => {
+                TransferError::CreatedInFuture { ledger_time: 0 }
+            }
+            IcpTransferError::TxDuplicate { duplicate_of } => TransferError::Duplicate {
+                duplicate_of: duplicate_of.into(),
+            },
+        };
+
+        err.into()
+    }
+}
+
+impl From<ApproveError> for InternalPaymentError {
+    fn from(err: ApproveError) -> Self {
+        match err {
+            ApproveError::BadFee { expected_fee } => Self::WrongFee(expected_fee),
+            _ => Self::TransferFailed(TransferFailReason::ApproveRejected(err)),
+        }
+    }
+}
+
 impl From<InternalPaymentError> for PaymentError {
     fn from(internal: InternalPaymentError) -> Self {
         match internal {
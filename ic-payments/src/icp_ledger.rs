@@ -0,0 +1,208 @@
+//! Convenience methods to call the legacy interface of the ICP ledger, which identifies accounts
+//! by [`AccountIdentifier`] instead of ICRC-1 [`Account`].
+
+use ic_canister::virtual_canister_call;
+use ic_exports::candid::{Nat, Principal};
+use ic_exports::icrc_types::icrc1::account::{Account, Subaccount};
+use ic_exports::icrc_types::icrc1::transfer::Memo;
+use ic_exports::ledger::{
+    AccountBalanceArgs, AccountIdentifier, Block, BlockIndex, GetBlocksArgs, GetBlocksResult,
+    Memo as IcpMemo, Operation, QueryBlocksResponse, Subaccount as IcpSubaccount, Timestamp,
+    Tokens, TransferArgs, TransferError, DEFAULT_SUBACCOUNT,
+};
+
+use crate::error::{InternalPaymentError, Result};
+use crate::icrc1::TokenTransferInfo;
+use crate::token_terminal::TX_WINDOW;
+
+/// Maximum number of blocks requested from the ledger in one call.
+const BLOCKS_PAGE_SIZE: u64 = 1000;
+
+/// Returns the legacy account identifier of the ICRC-1 `account`.
+pub fn account_identifier(account: &Account) -> AccountIdentifier {
+    let subaccount = account
+        .subaccount
+        .map(IcpSubaccount)
+        .unwrap_or(DEFAULT_SUBACCOUNT);
+    AccountIdentifier::new(&account.owner, &subaccount)
+}
+
+/// Converts the ICRC-1 transaction memo into the legacy `u64` memo.
+///
+/// Memos created from `u64` values are converted back into the same values. Longer memos are
+/// truncated to their first 8 bytes.
+pub fn icp_memo(memo: Option<&Memo>) -> IcpMemo {
+    let mut bytes = [0; 8];
+    if let Some(memo) = memo {
+        let len = memo.0.len().min(8);
+        bytes[8 - len..].copy_from_slice(&memo.0[..len]);
+    }
+
+    IcpMemo(u64::from_be_bytes(bytes))
+}
+
+/// Returns current balance of the `account` in the ICP `ledger` canister.
+pub async fn get_icp_balance(ledger: Principal, account: &Account) -> Result<Nat> {
+    let args = AccountBalanceArgs {
+        account: account_identifier(account),
+    };
+    let balance = virtual_canister_call!(ledger, "account_balance", (args,), Tokens).await?;
+    Ok(balance.e8s().into())
+}
+
+/// Requests a transfer with the legacy `transfer` method of the ICP `ledger` canister.
+///
+/// The ledger deduplicates transactions by `created_at_time` and `memo`, the same way ICRC-1
+/// tokens do.
+pub async fn transfer_icp(
+    ledger: Principal,
+    to: Account,
+    amount: Nat,
+    fee: Nat,
+    from_subaccount: Option<Subaccount>,
+    created_at_time: Option<u64>,
+    memo: Option<Memo>,
+) -> Result<TokenTransferInfo> {
+    let args = TransferArgs {
+        memo: icp_memo(memo.as_ref()),
+        amount: to_tokens(&amount)?,
+        fee: to_tokens(&fee)?,
+        from_subaccount: from_subaccount.map(IcpSubaccount),
+        to: account_identifier(&to),
+        created_at_time: created_at_time.map(|timestamp_nanos| Timestamp { timestamp_nanos }),
+    };
+
+    let block_index = virtual_canister_call!(
+        ledger,
+        "transfer",
+        (args,),
+        std::result::Result<BlockIndex, TransferError>
+    )
+    .await??;
+
+    Ok(TokenTransferInfo {
+        token_tx_id: block_index.into(),
+        amount_transferred: amount,
+        token_principal: ledger,
+    })
+}
+
+/// Returns `length` blocks of the ICP `ledger` starting from `start`, including the blocks moved
+/// to the archive canisters.
+pub async fn get_icp_blocks(
+    ledger: Principal,
+    start: BlockIndex,
+    length: u64,
+) -> Result<Vec<Block>> {
+    let args = GetBlocksArgs { start, length };
+    let response =
+        virtual_canister_call!(ledger, "query_blocks", (args,), QueryBlocksResponse).await?;
+
+    // Archived blocks always precede the blocks stored in the ledger itself.
+    let mut blocks = vec![];
+    for range in response.archived_blocks {
+        let callback = ic_exports::candid::Func::from(range.callback);
+        let args = GetBlocksArgs {
+            start: range.start,
+            length: range.length,
+        };
+        let archived = virtual_canister_call!(
+            callback.principal,
+            &callback.method,
+            (args,),
+            GetBlocksResult
+        )
+        .await?
+        .map_err(|_| InternalPaymentError::MaybeFailed)?;
+        blocks.extend(archived.blocks);
+    }
+
+    blocks.extend(response.blocks);
+    Ok(blocks)
+}
+
+/// Returns the number of blocks in the ICP `ledger`.
+pub async fn get_icp_chain_length(ledger: Principal) -> Result<u64> {
+    let args = GetBlocksArgs {
+        start: 0,
+        length: 0,
+    };
+    let response =
+        virtual_canister_call!(ledger, "query_blocks", (args,), QueryBlocksResponse).await?;
+    Ok(response.chain_length)
+}
+
+/// Searches the ICP `ledger` for the transfer with the given parameters, created by the
+/// [`transfer_icp`] call. Returns the index of the block with the transfer, or `None` if the
+/// ledger doesn't have such a transfer.
+///
+/// Only the blocks created within `search_period` nanoseconds since `created_at_time` are checked.
+/// The ledger clock may be behind the clock of the canister, so the search starts 5 minutes
+/// before `created_at_time`.
+#[allow(clippy::too_many_arguments)]
+pub async fn find_icp_transfer(
+    ledger: Principal,
+    from: &Account,
+    to: &Account,
+    amount: &Nat,
+    created_at_time: u64,
+    memo: Option<&Memo>,
+    search_period: u64,
+) -> Result<Option<BlockIndex>> {
+    let from = account_identifier(from);
+    let to = account_identifier(to);
+    let amount = to_tokens(amount)?;
+    let memo = icp_memo(memo);
+    let search_since = created_at_time.saturating_sub(TX_WINDOW);
+    let search_until = created_at_time.saturating_add(search_period);
+
+    let chain_length = get_icp_chain_length(ledger).await?;
+
+    // Blocks are ordered by time, so the first block the transfer can be in is found by binary
+    // search.
+    let (mut lo, mut hi) = (0, chain_length);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let block = get_icp_blocks(ledger, mid, 1).await?.pop();
+        match block {
+            Some(block) if block.timestamp.timestamp_nanos < search_since => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+
+    let mut index = lo;
+    while index < chain_length {
+        let blocks = get_icp_blocks(ledger, index, BLOCKS_PAGE_SIZE).await?;
+        if blocks.is_empty() {
+            break;
+        }
+
+        for block in blocks {
+            if block.timestamp.timestamp_nanos > search_until {
+                return Ok(None);
+            }
+
+            let tx = block.transaction;
+            let is_match = tx.memo == memo
+                && tx.created_at_time.timestamp_nanos == created_at_time
+                && matches!(
+                    tx.operation,
+                    Some(Operation::Transfer { from: tx_from, to: tx_to, amount: tx_amount, .. })
+                        if tx_from == from && tx_to == to && tx_amount == amount
+                );
+            if is_match {
+                return Ok(Some(index));
+            }
+
+            index += 1;
+        }
+    }
+
+    Ok(None)
+}
+
+fn to_tokens(amount: &Nat) -> Result<Tokens> {
+    u64::try_from(amount.0.clone())
+        .map(Tokens::from_e8s)
+        .map_err(|_| InternalPaymentError::Overflow)
+}
@@ -2,6 +2,9 @@ use ic_canister::virtual_canister_call;
 use ic_exports::candid::{CandidType, Nat, Principal};
 use ic_exports::icrc_types::icrc1::account::{Account, Subaccount};
 use ic_exports::icrc_types::icrc1::transfer::{Memo, TransferArg, TransferError};
+use ic_exports::icrc_types::icrc2::allowance::{Allowance, AllowanceArgs};
+use ic_exports::icrc_types::icrc2::approve::{ApproveArgs, ApproveError};
+use ic_exports::icrc_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
 use serde::Deserialize;
 
 use crate::error::Result;
@@ -52,6 +55,64 @@ pub async fn transfer_icrc1(
     })
 }
 
+/// Requests a transfer from the `from` account in an ICRC-2 `token` canister, using the allowance
+/// `from` has approved to the `this` canister account with `spender_subaccount`.
+#[allow(clippy::too_many_arguments)]
+pub async fn transfer_from_icrc2(
+    token: Principal,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Nat,
+    spender_subaccount: Option<Subaccount>,
+    created_at_time: Option<Timestamp>,
+    memo: Option<Memo>,
+) -> Result<TokenTransferInfo> {
+    let args = TransferFromArgs {
+        spender_subaccount,
+        from,
+        to,
+        amount: amount.clone(),
+        fee: Some(fee),
+        memo,
+        created_at_time,
+    };
+
+    let tx_id = virtual_canister_call!(
+        token,
+        "icrc2_transfer_from",
+        (args,),
+        std::result::Result<TxId, TransferFromError>
+    )
+    .await??;
+
+    Ok(TokenTransferInfo {
+        token_tx_id: tx_id,
+        amount_transferred: amount,
+        token_principal: token,
+    })
+}
+
+/// Approves the `args.spender` to transfer up to `args.amount` tokens from the `this` canister
+/// account in an ICRC-2 `token` canister. Returns the id of the approve transaction.
+pub async fn approve_icrc2(token: Principal, args: ApproveArgs) -> Result<TxId> {
+    Ok(
+        virtual_canister_call!(token, "icrc2_approve", (args,), std::result::Result<TxId, ApproveError>)
+            .await??,
+    )
+}
+
+/// Returns the allowance the `account` has approved to the `spender` in an ICRC-2 `token`
+/// canister.
+pub async fn get_icrc2_allowance(
+    token: Principal,
+    account: Account,
+    spender: Account,
+) -> Result<Allowance> {
+    let args = AllowanceArgs { account, spender };
+    Ok(virtual_canister_call!(token, "icrc2_allowance", (args,), Allowance).await?)
+}
+
 /// Requests fee and minting account configuration from an ICRC-1 canister.
 pub async fn get_icrc1_configuration(token: Principal) -> Result<TokenConfiguration> {
     // ICRC-1 standard metadata doesn't include a minting account, so we have to do two requests
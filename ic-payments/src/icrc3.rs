@@ -0,0 +1,350 @@
+//! Methods to read the transaction log of ICRC-1 tokens, either with ICRC-3 `icrc3_get_blocks`
+//! method or with `get_transactions` method of the ledgers created before the ICRC-3 standard.
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_canister::virtual_canister_call;
+use ic_exports::icrc_types::icrc::generic_value::ICRC3Value;
+use ic_exports::icrc_types::icrc1::account::{Account, Subaccount};
+use ic_exports::icrc_types::icrc1::transfer::Memo;
+use ic_exports::icrc_types::icrc3::blocks::{GetBlocksRequest, GetBlocksResult};
+use ic_exports::icrc_types::icrc3::transactions::{
+    GetTransactionsRequest, GetTransactionsResponse, Transaction, TransactionRange,
+};
+
+use crate::error::{InternalPaymentError, Result};
+use crate::token_terminal::TX_WINDOW;
+
+/// Maximum number of log entries requested from the token in one call.
+const LOG_PAGE_SIZE: u64 = 1000;
+
+/// Method used to read the transaction log of a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Deserialize)]
+pub enum TransactionLog {
+    /// ICRC-3 `icrc3_get_blocks` method.
+    Icrc3Blocks,
+
+    /// `get_transactions` method of the ledgers created before the ICRC-3 standard.
+    GetTransactions,
+}
+
+/// Transfer recorded in the transaction log.
+///
+/// Burn transactions are represented as transfers to the minting account of the token, and mint
+/// transactions as transfers from it, the same way as the terminal creates them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferRecord {
+    pub from: Account,
+    pub to: Account,
+    /// Amount received by the `to` account.
+    pub amount: Nat,
+    pub memo: Option<Memo>,
+    pub created_at_time: Option<u64>,
+}
+
+/// Entry of the transaction log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// Index of the entry in the log.
+    pub index: u64,
+
+    /// Time the entry was added to the log.
+    pub timestamp: u64,
+
+    /// The transfer, if the entry is a transfer transaction.
+    pub transfer: Option<TransferRecord>,
+}
+
+/// Returns the number of entries in the transaction log of the `token`.
+pub async fn get_log_length(token: Principal, log: TransactionLog) -> Result<u64> {
+    match log {
+        TransactionLog::Icrc3Blocks => {
+            let result = get_icrc3_blocks(token, 0, 0).await?;
+            to_u64(&result.log_length)
+        }
+        TransactionLog::GetTransactions => {
+            let response = get_icrc_transactions(token, 0, 0).await?;
+            to_u64(&response.log_length)
+        }
+    }
+}
+
+/// Returns `length` entries of the transaction log of the `token` starting from `start`, including
+/// the entries moved to the archive canisters.
+///
+/// `minting_account` of the token is used as the target of the burn transactions and the source
+/// of the mint transactions.
+pub async fn get_log_entries(
+    token: Principal,
+    log: TransactionLog,
+    minting_account: &Account,
+    start: u64,
+    length: u64,
+) -> Result<Vec<LogEntry>> {
+    match log {
+        TransactionLog::Icrc3Blocks => {
+            let result = get_icrc3_blocks(token, start, length).await?;
+
+            let mut blocks = vec![];
+            for archived in result.archived_blocks {
+                let archived_result = virtual_canister_call!(
+                    archived.callback.canister_id,
+                    &archived.callback.method,
+                    (archived.args,),
+                    GetBlocksResult
+                )
+                .await?;
+                blocks.extend(archived_result.blocks);
+            }
+            blocks.extend(result.blocks);
+            blocks.sort_by(|a, b| a.id.cmp(&b.id));
+
+            blocks
+                .into_iter()
+                .map(|block| parse_icrc3_block(to_u64(&block.id)?, block.block, minting_account))
+                .collect()
+        }
+        TransactionLog::GetTransactions => {
+            let response = get_icrc_transactions(token, start, length).await?;
+
+            // Archived transactions always precede the transactions stored in the ledger itself.
+            let mut entries = vec![];
+            for archived in response.archived_transactions {
+                let first_index = to_u64(&archived.start)?;
+                let args = GetTransactionsRequest {
+                    start: archived.start,
+                    length: archived.length,
+                };
+                let range = virtual_canister_call!(
+                    archived.callback.canister_id,
+                    &archived.callback.method,
+                    (args,),
+                    TransactionRange
+                )
+                .await?;
+                entries.extend(
+                    (first_index..)
+                        .zip(range.transactions)
+                        .map(|(index, tx)| parse_transaction(index, tx, minting_account)),
+                );
+            }
+
+            let first_index = to_u64(&response.first_index)?;
+            entries.extend(
+                (first_index..)
+                    .zip(response.transactions)
+                    .map(|(index, tx)| parse_transaction(index, tx, minting_account)),
+            );
+
+            Ok(entries)
+        }
+    }
+}
+
+/// Searches the transaction log of the `token` for the `transfer`. Returns the index of the log
+/// entry with the transfer, or `None` if the log doesn't have such a transfer.
+///
+/// Only the entries added within `search_period` nanoseconds since `transfer.created_at_time` are
+/// checked. The ledger accepts transfers created slightly ahead of its clock, so the search starts
+/// 5 minutes before `transfer.created_at_time`.
+pub async fn find_transfer(
+    token: Principal,
+    log: TransactionLog,
+    minting_account: &Account,
+    transfer: &TransferRecord,
+    search_period: u64,
+) -> Result<Option<u64>> {
+    let created_at_time = transfer.created_at_time.unwrap_or_default();
+    let search_since = created_at_time.saturating_sub(TX_WINDOW);
+    let search_until = created_at_time.saturating_add(search_period);
+    let log_length = get_log_length(token, log).await?;
+
+    // Entries are ordered by time, so the first entry the transfer can be in is found by binary
+    // search.
+    let (mut lo, mut hi) = (0, log_length);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let entry = get_log_entries(token, log, minting_account, mid, 1)
+            .await?
+            .pop();
+        match entry {
+            Some(entry) if entry.timestamp < search_since => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+
+    let mut start = lo;
+    while start < log_length {
+        let entries = get_log_entries(token, log, minting_account, start, LOG_PAGE_SIZE).await?;
+        let Some(last) = entries.last() else {
+            break;
+        };
+        start = last.index + 1;
+
+        for entry in entries {
+            if entry.timestamp > search_until {
+                return Ok(None);
+            }
+
+            if entry.transfer.as_ref() == Some(transfer) {
+                return Ok(Some(entry.index));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Requests blocks of the ICRC-3 `token`. Archived blocks are not requested.
+pub async fn get_icrc3_blocks(
+    token: Principal,
+    start: u64,
+    length: u64,
+) -> Result<GetBlocksResult> {
+    let args = vec![GetBlocksRequest {
+        start: start.into(),
+        length: length.into(),
+    }];
+    Ok(virtual_canister_call!(token, "icrc3_get_blocks", (args,), GetBlocksResult).await?)
+}
+
+/// Requests transactions of the `token` with `get_transactions` method. Archived transactions are
+/// not requested.
+pub async fn get_icrc_transactions(
+    token: Principal,
+    start: u64,
+    length: u64,
+) -> Result<GetTransactionsResponse> {
+    let args = GetTransactionsRequest {
+        start: start.into(),
+        length: length.into(),
+    };
+    Ok(virtual_canister_call!(token, "get_transactions", (args,), GetTransactionsResponse).await?)
+}
+
+fn parse_transaction(index: u64, tx: Transaction, minting_account: &Account) -> LogEntry {
+    let transfer = if let Some(transfer) = tx.transfer {
+        Some(TransferRecord {
+            from: transfer.from,
+            to: transfer.to,
+            amount: transfer.amount,
+            memo: transfer.memo,
+            created_at_time: transfer.created_at_time,
+        })
+    } else if let Some(burn) = tx.burn {
+        Some(TransferRecord {
+            from: burn.from,
+            to: *minting_account,
+            amount: burn.amount,
+            memo: burn.memo,
+            created_at_time: burn.created_at_time,
+        })
+    } else {
+        tx.mint.map(|mint| TransferRecord {
+            from: *minting_account,
+            to: mint.to,
+            amount: mint.amount,
+            memo: mint.memo,
+            created_at_time: mint.created_at_time,
+        })
+    };
+
+    LogEntry {
+        index,
+        timestamp: tx.timestamp,
+        transfer,
+    }
+}
+
+/// Parses the block in the format described by the ICRC-3 standard.
+fn parse_icrc3_block(index: u64, block: ICRC3Value, minting_account: &Account) -> Result<LogEntry> {
+    let ICRC3Value::Map(block) = block else {
+        return Err(InternalPaymentError::MaybeFailed);
+    };
+    let timestamp = block
+        .get("ts")
+        .and_then(value_u64)
+        .ok_or(InternalPaymentError::MaybeFailed)?;
+
+    let transfer = block
+        .get("tx")
+        .and_then(|tx| match tx {
+            ICRC3Value::Map(tx) => Some(tx),
+            _ => None,
+        })
+        .and_then(|tx| {
+            let op = tx
+                .get("op")
+                .or_else(|| block.get("btype"))
+                .and_then(value_text)?;
+            let (from, to) = match op {
+                "xfer" | "1xfer" | "2xfer" => (
+                    tx.get("from").and_then(value_account)?,
+                    tx.get("to").and_then(value_account)?,
+                ),
+                "burn" | "1burn" => (tx.get("from").and_then(value_account)?, *minting_account),
+                "mint" | "1mint" => (*minting_account, tx.get("to").and_then(value_account)?),
+                _ => return None,
+            };
+
+            Some(TransferRecord {
+                from,
+                to,
+                amount: tx.get("amt").and_then(value_nat)?,
+                memo: tx
+                    .get("memo")
+                    .and_then(value_blob)
+                    .map(|memo| Memo(memo.to_vec().into())),
+                created_at_time: tx.get("ts").and_then(value_u64),
+            })
+        });
+
+    Ok(LogEntry {
+        index,
+        timestamp,
+        transfer,
+    })
+}
+
+fn value_nat(value: &ICRC3Value) -> Option<Nat> {
+    match value {
+        ICRC3Value::Nat(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+fn value_u64(value: &ICRC3Value) -> Option<u64> {
+    value_nat(value).and_then(|value| u64::try_from(value.0).ok())
+}
+
+fn value_text(value: &ICRC3Value) -> Option<&str> {
+    match value {
+        ICRC3Value::Text(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn value_blob(value: &ICRC3Value) -> Option<&[u8]> {
+    match value {
+        ICRC3Value::Blob(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Accounts are encoded as an array of the owner principal and an optional subaccount.
+fn value_account(value: &ICRC3Value) -> Option<Account> {
+    let ICRC3Value::Array(parts) = value else {
+        return None;
+    };
+
+    let owner = Principal::try_from_slice(value_blob(parts.first()?)?).ok()?;
+    let subaccount = match parts.get(1) {
+        Some(subaccount) => Some(Subaccount::try_from(value_blob(subaccount)?).ok()?),
+        None => None,
+    };
+
+    Some(Account { owner, subaccount })
+}
+
+fn to_u64(value: &Nat) -> Result<u64> {
+    u64::try_from(value.0.clone()).map_err(|_| InternalPaymentError::Overflow)
+}
@@ -0,0 +1,459 @@
+//! Ready-made [`Balances`] implementation, stored in the stable memory.
+//!
+//! [`StableBalances`] keeps the balance of every account of the users together with the journal
+//! of all operations with the account, so every balance can be audited. Part of the balance can
+//! be locked, e.g. for the open orders of the user, so that it cannot be withdrawn until it is
+//! unlocked.
+//!
+//! The accounts are identified by ICRC-1 [`Account`], so a user can have several balances in
+//! different subaccounts. The [`Balances`] trait methods, called by the
+//! [`TokenTerminal`](crate::TokenTerminal), operate with the default subaccount of the user.
+//!
+//! [`StableBalances::reconcile`] compares the total amount owed to the users with the actual
+//! token balance of the canister.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Deserialize, Encode, Nat, Principal};
+use ic_exports::ic_kit::ic;
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_stable_structures::stable_structures::storable::Bound;
+use ic_stable_structures::stable_structures::DefaultMemoryImpl;
+use ic_stable_structures::{
+    BTreeMapStructure, IterableSortedMapStructure, StableBTreeMap, Storable, VirtualMemory,
+};
+
+use crate::error::PaymentError;
+use crate::ledger::LedgerClient;
+use crate::recovery_list::{with_stable_map, StableMaps};
+use crate::{BalanceError, Balances, Timestamp};
+
+/// Balance of an account.
+#[derive(Debug, Default, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct AccountBalance {
+    /// Whole amount owned by the account, including the locked amount.
+    pub total: Nat,
+
+    /// Amount that cannot be debited until it is unlocked.
+    pub locked: Nat,
+}
+
+impl AccountBalance {
+    /// Amount that can be debited from the account.
+    pub fn available(&self) -> Nat {
+        self.total.clone() - self.locked.clone()
+    }
+}
+
+/// Operation with an account balance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Deserialize)]
+pub enum JournalOperation {
+    Credit,
+    Debit,
+    Lock,
+    Unlock,
+
+    /// Debit of the locked amount, e.g. when the order is executed.
+    DebitLocked,
+}
+
+/// Record of the account journal.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct JournalEntry {
+    pub operation: JournalOperation,
+    pub amount: Nat,
+
+    /// Balance of the account after the operation.
+    pub balance: AccountBalance,
+    pub timestamp: Timestamp,
+}
+
+/// Result of comparison of the internal balances with the token balance of the canister.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Reconciliation {
+    /// Sum of the balances of all accounts.
+    pub liabilities: Nat,
+
+    /// Balance of the main account of the canister in the token.
+    pub token_balance: Nat,
+}
+
+impl Reconciliation {
+    /// Returns true if the canister owns enough tokens to pay all the liabilities.
+    pub fn is_covered(&self) -> bool {
+        self.token_balance >= self.liabilities
+    }
+
+    /// Amount of tokens owned by the canister above the liabilities.
+    pub fn surplus(&self) -> Nat {
+        match self.is_covered() {
+            true => self.token_balance.clone() - self.liabilities.clone(),
+            false => 0u64.into(),
+        }
+    }
+
+    /// Amount of tokens missing to pay all the liabilities.
+    pub fn deficit(&self) -> Nat {
+        match self.is_covered() {
+            true => 0u64.into(),
+            false => self.liabilities.clone() - self.token_balance.clone(),
+        }
+    }
+}
+
+thread_local! {
+    static BALANCES_STORAGE: StableMaps<AccountKey, AccountValue> = const { RefCell::new(BTreeMap::new()) };
+
+    static JOURNAL_STORAGE: StableMaps<JournalKey, JournalValue> = const { RefCell::new(BTreeMap::new()) };
+}
+
+const PRINCIPAL_MAX_LEN: usize = 29;
+const ACCOUNT_KEY_LEN: usize = 1 + PRINCIPAL_MAX_LEN + 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct AccountKey([u8; ACCOUNT_KEY_LEN]);
+
+/// Key of the record with the sums of all balances, kept up to date on every operation. Real
+/// principals are never this long, so the key cannot collide with an account.
+const TOTALS_KEY: AccountKey = AccountKey([u8::MAX; ACCOUNT_KEY_LEN]);
+
+impl AccountKey {
+    fn new(account: &Account) -> Self {
+        let principal = account.owner.as_slice();
+        let mut bytes = [0u8; ACCOUNT_KEY_LEN];
+        bytes[0] = principal.len() as u8;
+        bytes[1..1 + principal.len()].copy_from_slice(principal);
+        bytes[1 + PRINCIPAL_MAX_LEN..].copy_from_slice(account.effective_subaccount());
+        Self(bytes)
+    }
+
+    fn account(&self) -> Account {
+        let len = self.0[0] as usize;
+        let mut subaccount = [0u8; 32];
+        subaccount.copy_from_slice(&self.0[1 + PRINCIPAL_MAX_LEN..]);
+        Account {
+            owner: Principal::from_slice(&self.0[1..1 + len]),
+            subaccount: Some(subaccount),
+        }
+    }
+}
+
+impl Storable for AccountKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::from(&self.0[..])
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(bytes[..].try_into().expect("invalid account key"))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: ACCOUNT_KEY_LEN as u32,
+        is_fixed_size: true,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct JournalKey {
+    account: AccountKey,
+    index: u64,
+}
+
+impl Storable for JournalKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.account.0.to_vec();
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (account, index) = bytes.split_at(ACCOUNT_KEY_LEN);
+        Self {
+            account: AccountKey(account.try_into().expect("invalid journal key")),
+            index: u64::from_be_bytes(index.try_into().expect("invalid journal key")),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: ACCOUNT_KEY_LEN as u32 + 8,
+        is_fixed_size: true,
+    };
+}
+
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+struct AccountRecord {
+    balance: AccountBalance,
+    journal_len: u64,
+}
+
+#[derive(Clone)]
+struct AccountValue(AccountRecord);
+
+impl Storable for AccountValue {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = Encode!(&self.0).expect("serialization of account balance failed");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(candid::decode_one(&bytes).expect("deserialization of account balance failed"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(Clone)]
+struct JournalValue(JournalEntry);
+
+impl Storable for JournalValue {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = Encode!(&self.0).expect("serialization of journal entry failed");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(candid::decode_one(&bytes).expect("deserialization of journal entry failed"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// [`Balances`] implementation that stores account balances and their journals in the stable
+/// memory with the given ids. Instances with different memory ids keep separate balances, e.g. of
+/// different tokens.
+///
+/// Sums of all balances are updated on every operation, so [`StableBalances::reconcile`] doesn't
+/// iterate over the accounts.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StableBalances<const BALANCES_MEM_ID: u8, const JOURNAL_MEM_ID: u8>;
+
+impl<const BALANCES_MEM_ID: u8, const JOURNAL_MEM_ID: u8>
+    StableBalances<BALANCES_MEM_ID, JOURNAL_MEM_ID>
+{
+    fn with_balances<R>(
+        &self,
+        f: impl FnOnce(
+            &mut StableBTreeMap<AccountKey, AccountValue, VirtualMemory<DefaultMemoryImpl>>,
+        ) -> R,
+    ) -> R {
+        with_stable_map(&BALANCES_STORAGE, BALANCES_MEM_ID, f)
+    }
+
+    fn with_journal<R>(
+        &self,
+        f: impl FnOnce(
+            &mut StableBTreeMap<JournalKey, JournalValue, VirtualMemory<DefaultMemoryImpl>>,
+        ) -> R,
+    ) -> R {
+        with_stable_map(&JOURNAL_STORAGE, JOURNAL_MEM_ID, f)
+    }
+
+    fn totals(&self) -> AccountBalance {
+        self.with_balances(|m| m.get(&TOTALS_KEY))
+            .map(|v| v.0.balance)
+            .unwrap_or_default()
+    }
+
+    /// Current balance of the `account`.
+    pub fn balance(&self, account: &Account) -> AccountBalance {
+        self.with_balances(|m| m.get(&AccountKey::new(account)))
+            .map(|v| v.0.balance)
+            .unwrap_or_default()
+    }
+
+    /// All accounts with non-empty journal and their balances.
+    pub fn accounts(&self) -> Vec<(Account, AccountBalance)> {
+        self.with_balances(|m| {
+            m.iter()
+                .filter(|(key, _)| *key != TOTALS_KEY)
+                .map(|(key, value)| (key.account(), value.0.balance))
+                .collect()
+        })
+    }
+
+    /// Returns at most `limit` journal entries of the `account`, starting from the entry with
+    /// index `start`. The entries are indexed from 0 in the order they were added.
+    pub fn journal(&self, account: &Account, start: u64, limit: usize) -> Vec<JournalEntry> {
+        let account = AccountKey::new(account);
+        let from = JournalKey {
+            account,
+            index: start,
+        };
+        let to = JournalKey {
+            account,
+            index: u64::MAX,
+        };
+        self.with_journal(|m| m.range(from..=to).take(limit).map(|(_, v)| v.0).collect())
+    }
+
+    /// Sum of the balances of all accounts.
+    pub fn total_liabilities(&self) -> Nat {
+        self.totals().total
+    }
+
+    /// Sum of the locked balances of all accounts.
+    pub fn total_locked(&self) -> Nat {
+        self.totals().locked
+    }
+
+    /// Compares total liabilities with the balance of the main account of the canister in the
+    /// token, requested with the `ledger`.
+    ///
+    /// Note that the tokens of the transfers that are not finished yet, e.g. stored in the
+    /// recovery list, can be missing from the canister account.
+    pub async fn reconcile<L: LedgerClient>(
+        &self,
+        ledger: &L,
+    ) -> Result<Reconciliation, PaymentError> {
+        let token_balance = ledger.balance_of(ic::id().into()).await?;
+        Ok(Reconciliation {
+            liabilities: self.total_liabilities(),
+            token_balance,
+        })
+    }
+
+    /// Increases the balance of the `account` and returns its updated total balance.
+    pub fn credit_account(&mut self, account: &Account, amount: Nat) -> Result<Nat, BalanceError> {
+        self.update(
+            account,
+            JournalOperation::Credit,
+            amount,
+            |balance, amount| {
+                balance.total += amount;
+                Ok(())
+            },
+        )
+    }
+
+    /// Decreases the available balance of the `account` and returns its updated total balance.
+    pub fn debit_account(&mut self, account: &Account, amount: Nat) -> Result<Nat, BalanceError> {
+        self.update(
+            account,
+            JournalOperation::Debit,
+            amount,
+            |balance, amount| {
+                if balance.available() < amount {
+                    return Err(BalanceError::InsufficientFunds);
+                }
+
+                balance.total -= amount;
+                Ok(())
+            },
+        )
+    }
+
+    /// Locks the `amount` of the available balance of the `account`.
+    pub fn lock(&mut self, account: &Account, amount: Nat) -> Result<Nat, BalanceError> {
+        self.update(
+            account,
+            JournalOperation::Lock,
+            amount,
+            |balance, amount| {
+                if balance.available() < amount {
+                    return Err(BalanceError::InsufficientFunds);
+                }
+
+                balance.locked += amount;
+                Ok(())
+            },
+        )
+    }
+
+    /// Returns the `amount` of the locked balance of the `account` to the available balance.
+    pub fn unlock(&mut self, account: &Account, amount: Nat) -> Result<Nat, BalanceError> {
+        self.update(
+            account,
+            JournalOperation::Unlock,
+            amount,
+            |balance, amount| {
+                if balance.locked < amount {
+                    return Err(BalanceError::Fatal(
+                        "unlocked amount is larger than the locked balance".into(),
+                    ));
+                }
+
+                balance.locked -= amount;
+                Ok(())
+            },
+        )
+    }
+
+    /// Decreases the locked balance of the `account`.
+    pub fn debit_locked(&mut self, account: &Account, amount: Nat) -> Result<Nat, BalanceError> {
+        self.update(
+            account,
+            JournalOperation::DebitLocked,
+            amount,
+            |balance, amount| {
+                if balance.locked < amount {
+                    return Err(BalanceError::InsufficientFunds);
+                }
+
+                balance.locked -= amount.clone();
+                balance.total -= amount;
+                Ok(())
+            },
+        )
+    }
+
+    fn update(
+        &mut self,
+        account: &Account,
+        operation: JournalOperation,
+        amount: Nat,
+        f: impl FnOnce(&mut AccountBalance, Nat) -> Result<(), BalanceError>,
+    ) -> Result<Nat, BalanceError> {
+        let key = AccountKey::new(account);
+        let mut record = self
+            .with_balances(|m| m.get(&key))
+            .map(|v| v.0)
+            .unwrap_or_default();
+        let previous = record.balance.clone();
+        f(&mut record.balance, amount.clone())?;
+
+        let entry = JournalEntry {
+            operation,
+            amount,
+            balance: record.balance.clone(),
+            timestamp: ic::time(),
+        };
+        let journal_key = JournalKey {
+            account: key,
+            index: record.journal_len,
+        };
+        self.with_journal(|m| m.insert(journal_key, JournalValue(entry)));
+
+        let mut totals = self.totals();
+        totals.total = totals.total + record.balance.total.clone() - previous.total;
+        totals.locked = totals.locked + record.balance.locked.clone() - previous.locked;
+
+        record.journal_len += 1;
+        let total = record.balance.total.clone();
+        self.with_balances(|m| {
+            m.insert(key, AccountValue(record));
+            m.insert(
+                TOTALS_KEY,
+                AccountValue(AccountRecord {
+                    balance: totals,
+                    journal_len: 0,
+                }),
+            );
+        });
+
+        Ok(total)
+    }
+}
+
+impl<const BALANCES_MEM_ID: u8, const JOURNAL_MEM_ID: u8> Balances
+    for StableBalances<BALANCES_MEM_ID, JOURNAL_MEM_ID>
+{
+    fn credit(&mut self, account_owner: Principal, amount: Nat) -> Result<Nat, BalanceError> {
+        self.credit_account(&account_owner.into(), amount)
+    }
+
+    fn debit(&mut self, account_owner: Principal, amount: Nat) -> Result<Nat, BalanceError> {
+        self.debit_account(&account_owner.into(), amount)
+    }
+}
@@ -0,0 +1,77 @@
+use async_trait::async_trait;
+use candid::Nat;
+use ic_canister_client::{CanisterClient, CanisterClientError};
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_exports::icrc_types::icrc1::transfer::{TransferArg, TransferError};
+use ic_exports::icrc_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+
+use super::LedgerClient;
+use crate::error::{InternalPaymentError, Result};
+use crate::TxId;
+
+/// Ledger client that calls the token canister with a [`CanisterClient`], e.g. through an IC
+/// agent or PocketIC from outside of a canister.
+#[derive(Debug, Clone)]
+pub struct CanisterLedgerClient<C: CanisterClient> {
+    client: C,
+}
+
+impl<C: CanisterClient> CanisterLedgerClient<C> {
+    /// Creates a ledger client using the canister `client` of the token canister.
+    pub fn new(client: C) -> Self {
+        Self { client }
+    }
+
+    /// Canister client used to call the token.
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+}
+
+#[async_trait(?Send)]
+impl<C: CanisterClient> LedgerClient for CanisterLedgerClient<C> {
+    async fn balance_of(&self, account: Account) -> Result<Nat> {
+        self.client
+            .query("icrc1_balance_of", (account,))
+            .await
+            .map_err(map_error)
+    }
+
+    async fn transfer(&self, args: TransferArg) -> Result<TxId> {
+        let result: std::result::Result<TxId, TransferError> = self
+            .client
+            .update("icrc1_transfer", (args,))
+            .await
+            .map_err(map_error)?;
+        Ok(result?)
+    }
+
+    async fn transfer_from(&self, args: TransferFromArgs) -> Result<TxId> {
+        let result: std::result::Result<TxId, TransferFromError> = self
+            .client
+            .update("icrc2_transfer_from", (args,))
+            .await
+            .map_err(map_error)?;
+        Ok(result?)
+    }
+
+    async fn fee(&self) -> Result<Nat> {
+        self.client.query("icrc1_fee", ()).await.map_err(map_error)
+    }
+
+    async fn minting_account(&self) -> Result<Option<Account>> {
+        self.client
+            .query("icrc1_minting_account", ())
+            .await
+            .map_err(map_error)
+    }
+}
+
+fn map_error(err: CanisterClientError) -> InternalPaymentError {
+    match err {
+        CanisterClientError::CanisterError(ic_error) => ic_error.into(),
+        // The request could have been executed even if the client failed to deliver the response,
+        // so the result is unknown.
+        _ => InternalPaymentError::MaybeFailed,
+    }
+}
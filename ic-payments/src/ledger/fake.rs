@@ -0,0 +1,485 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use async_trait::async_trait;
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::{Nat, Principal};
+use ic_canister::register_raw_virtual_responder;
+use ic_exports::ic_cdk::api::call::{CallResult, RejectionCode};
+use ic_exports::ic_kit::ic;
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_exports::icrc_types::icrc1::transfer::{Memo, TransferArg, TransferError};
+use ic_exports::icrc_types::icrc2::allowance::{Allowance, AllowanceArgs};
+use ic_exports::icrc_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+
+use super::LedgerClient;
+use crate::error::Result;
+use crate::TxId;
+
+/// Deduplication window used by the fake ledger by default. This is the value used by the ICP
+/// and SNS ledgers.
+const DEFAULT_DEDUP_WINDOW: u64 = 10u64.pow(9) * 60 * 60 * 24;
+
+/// Difference between the `created_at_time` of a transaction and the ledger time the ledger
+/// tolerates, same as in the ICP ledger.
+const PERMITTED_DRIFT: u64 = 10u64.pow(9) * 60;
+
+/// Failure injected into the next transfer request to the [`FakeLedgerClient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerFailure {
+    /// The call is rejected before it reaches the ledger, so the request is not executed.
+    Reject(RejectionCode, String),
+
+    /// The request is executed by the ledger, but the caller receives the rejection instead of
+    /// the response.
+    ExecutedThenReject(RejectionCode, String),
+
+    /// The ledger returns the error without executing the request, e.g.
+    /// [`TransferError::TemporarilyUnavailable`].
+    Error(TransferError),
+}
+
+/// In-memory ICRC-1/ICRC-2 ledger for tests.
+///
+/// Implements transfers with fee checks, burning and minting through the minting account,
+/// transfers from allowance and deduplication of the requests with `created_at_time`. Requests
+/// older than the deduplication window are rejected with `TooOld` error, the same way the ICP and
+/// SNS ledgers do. Fees are burned.
+///
+/// The ledger can be used directly as a [`LedgerClient`], or it can serve the calls to a token
+/// principal made with `virtual_canister_call!`, after
+/// [`register_virtual_responders`](FakeLedgerClient::register_virtual_responders) is called. All
+/// clones of the client share the same ledger state.
+///
+/// The calling canister and the ledger time are taken from the mock context, so the tests must
+/// inject it.
+#[derive(Debug, Clone, Default)]
+pub struct FakeLedgerClient {
+    state: Rc<RefCell<FakeLedgerState>>,
+}
+
+#[derive(Debug, Default)]
+struct FakeLedgerState {
+    fee: Nat,
+    minting_account: Option<Account>,
+    dedup_window: u64,
+    total_supply: Nat,
+    balances: HashMap<Account, Nat>,
+    allowances: HashMap<(Account, Account), Nat>,
+    blocks: Vec<FakeBlock>,
+    failures: VecDeque<LedgerFailure>,
+}
+
+#[derive(Debug)]
+struct FakeBlock {
+    timestamp: u64,
+    transaction: FakeTransaction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FakeTransaction {
+    from: Account,
+    to: Account,
+    spender: Option<Account>,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Memo>,
+    created_at_time: Option<u64>,
+}
+
+impl FakeLedgerClient {
+    /// Creates an empty ledger with the given transaction `fee`.
+    pub fn new(fee: Nat) -> Self {
+        let client = Self::default();
+        {
+            let mut state = client.state.borrow_mut();
+            state.fee = fee;
+            state.dedup_window = DEFAULT_DEDUP_WINDOW;
+        }
+        client
+    }
+
+    /// Sets the minting account of the ledger.
+    pub fn with_minting_account(self, minting_account: Account) -> Self {
+        self.state.borrow_mut().minting_account = Some(minting_account);
+        self
+    }
+
+    /// Sets the period in nanoseconds the transactions are deduplicated for.
+    pub fn with_deduplication_window(self, window: u64) -> Self {
+        self.state.borrow_mut().dedup_window = window;
+        self
+    }
+
+    /// Makes the ledger serve the `virtual_canister_call!` calls to the `principal`.
+    ///
+    /// `icrc1_transfer`, `icrc2_transfer_from`, `icrc1_balance_of`, `icrc1_fee`,
+    /// `icrc1_minting_account`, `icrc1_total_supply` and `icrc2_allowance` methods are supported.
+    pub fn register_virtual_responders(&self, principal: Principal) {
+        let ledger = self.clone();
+        register_responder(
+            principal,
+            "icrc1_transfer",
+            move |(args,): (TransferArg,)| {
+                let result = ledger.transfer_call(ledger.transfer_tx(ic::caller(), args))?;
+                Ok(result.map_err(into_transfer_error))
+            },
+        );
+
+        let ledger = self.clone();
+        register_responder(
+            principal,
+            "icrc2_transfer_from",
+            move |(args,): (TransferFromArgs,)| {
+                ledger.transfer_call(ledger.transfer_from_tx(ic::caller(), args))
+            },
+        );
+
+        let ledger = self.clone();
+        register_responder(
+            principal,
+            "icrc1_balance_of",
+            move |(account,): (Account,)| Ok(ledger.balance(&account)),
+        );
+
+        let ledger = self.clone();
+        register_responder(principal, "icrc1_fee", move |()| {
+            Ok(ledger.state.borrow().fee.clone())
+        });
+
+        let ledger = self.clone();
+        register_responder(principal, "icrc1_minting_account", move |()| {
+            Ok(ledger.state.borrow().minting_account)
+        });
+
+        let ledger = self.clone();
+        register_responder(principal, "icrc1_total_supply", move |()| {
+            Ok(ledger.total_supply())
+        });
+
+        let ledger = self.clone();
+        register_responder(
+            principal,
+            "icrc2_allowance",
+            move |(args,): (AllowanceArgs,)| {
+                Ok(Allowance {
+                    allowance: ledger.allowance(&args.account, &args.spender),
+                    expires_at: None,
+                })
+            },
+        );
+    }
+
+    /// Changes the transaction fee of the ledger.
+    pub fn set_fee(&self, fee: Nat) {
+        self.state.borrow_mut().fee = fee;
+    }
+
+    /// Changes the minting account of the ledger.
+    pub fn set_minting_account(&self, minting_account: Option<Account>) {
+        self.state.borrow_mut().minting_account = minting_account;
+    }
+
+    /// Adds `amount` to the balance of the `account` without recording a transaction.
+    pub fn mint(&self, account: Account, amount: Nat) {
+        let mut state = self.state.borrow_mut();
+        state.total_supply += amount.clone();
+        let balance = state.balances.entry(account).or_default();
+        *balance += amount;
+    }
+
+    /// Sets the allowance the `from` account approved to the `spender`.
+    pub fn approve(&self, from: Account, spender: Account, amount: Nat) {
+        self.state
+            .borrow_mut()
+            .allowances
+            .insert((from, spender), amount);
+    }
+
+    /// Current balance of the `account`.
+    pub fn balance(&self, account: &Account) -> Nat {
+        self.state.borrow().balance(account)
+    }
+
+    /// Current allowance the `from` account approved to the `spender`.
+    pub fn allowance(&self, from: &Account, spender: &Account) -> Nat {
+        self.state
+            .borrow()
+            .allowances
+            .get(&(*from, *spender))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Total amount of tokens minted and not burned yet. Transfer fees are burned, so they are
+    /// subtracted from the supply.
+    pub fn total_supply(&self) -> Nat {
+        self.state.borrow().total_supply.clone()
+    }
+
+    /// Sum of balances of all accounts. Equals to the [total
+    /// supply](FakeLedgerClient::total_supply) unless the ledger is broken.
+    pub fn balances_sum(&self) -> Nat {
+        self.state
+            .borrow()
+            .balances
+            .values()
+            .fold(Nat::from(0u64), |acc, balance| acc + balance.clone())
+    }
+
+    /// Number of transactions executed by the ledger.
+    pub fn transactions_count(&self) -> usize {
+        self.state.borrow().blocks.len()
+    }
+
+    /// Makes the next transfer request fail with the `failure`. Several failures are applied to
+    /// the consequent requests in the order they were injected. Queries never fail.
+    pub fn inject_failure(&self, failure: LedgerFailure) {
+        self.state.borrow_mut().failures.push_back(failure);
+    }
+
+    /// Number of injected failures not applied yet.
+    pub fn pending_failures(&self) -> usize {
+        self.state.borrow().failures.len()
+    }
+
+    fn transfer_tx(&self, caller: Principal, args: TransferArg) -> FakeTransaction {
+        FakeTransaction {
+            from: Account {
+                owner: caller,
+                subaccount: args.from_subaccount,
+            },
+            to: args.to,
+            spender: None,
+            amount: args.amount,
+            fee: args.fee,
+            memo: args.memo,
+            created_at_time: args.created_at_time,
+        }
+    }
+
+    fn transfer_from_tx(&self, caller: Principal, args: TransferFromArgs) -> FakeTransaction {
+        FakeTransaction {
+            from: args.from,
+            to: args.to,
+            spender: Some(Account {
+                owner: caller,
+                subaccount: args.spender_subaccount,
+            }),
+            amount: args.amount,
+            fee: args.fee,
+            memo: args.memo,
+            created_at_time: args.created_at_time,
+        }
+    }
+
+    fn transfer_call(
+        &self,
+        tx: FakeTransaction,
+    ) -> CallResult<std::result::Result<TxId, TransferFromError>> {
+        let mut state = self.state.borrow_mut();
+        match state.failures.pop_front() {
+            Some(LedgerFailure::Reject(code, message)) => Err((code, message)),
+            Some(LedgerFailure::ExecutedThenReject(code, message)) => {
+                // The result of the execution is lost in the same way the response is.
+                let _ = state.execute(tx);
+                Err((code, message))
+            }
+            Some(LedgerFailure::Error(error)) => Ok(Err(into_transfer_from_error(error))),
+            None => Ok(state.execute(tx)),
+        }
+    }
+}
+
+impl FakeLedgerState {
+    fn balance(&self, account: &Account) -> Nat {
+        self.balances.get(account).cloned().unwrap_or_default()
+    }
+
+    fn execute(&mut self, tx: FakeTransaction) -> std::result::Result<TxId, TransferFromError> {
+        let now = ic::time();
+        let is_mint = Some(tx.from) == self.minting_account;
+        let is_burn = Some(tx.to) == self.minting_account;
+        let expected_fee: Nat = if is_mint || is_burn {
+            0u64.into()
+        } else {
+            self.fee.clone()
+        };
+
+        if let Some(created_at_time) = tx.created_at_time {
+            if created_at_time.saturating_add(self.dedup_window + PERMITTED_DRIFT) < now {
+                return Err(TransferFromError::TooOld);
+            }
+            if created_at_time > now.saturating_add(PERMITTED_DRIFT) {
+                return Err(TransferFromError::CreatedInFuture { ledger_time: now });
+            }
+        }
+
+        if tx.fee.as_ref().is_some_and(|fee| *fee != expected_fee) {
+            return Err(TransferFromError::BadFee { expected_fee });
+        }
+
+        if tx.created_at_time.is_some() {
+            let dedup_since = now.saturating_sub(self.dedup_window + PERMITTED_DRIFT);
+            let duplicate = self
+                .blocks
+                .iter()
+                .position(|block| block.timestamp >= dedup_since && block.transaction == tx);
+            if let Some(index) = duplicate {
+                return Err(TransferFromError::Duplicate {
+                    duplicate_of: index.into(),
+                });
+            }
+        }
+
+        let debit = tx.amount.clone() + expected_fee.clone();
+        if !is_mint {
+            let balance = self.balance(&tx.from);
+            if balance < debit {
+                return Err(TransferFromError::InsufficientFunds { balance });
+            }
+        }
+
+        if let Some(spender) = tx.spender {
+            let allowance = self
+                .allowances
+                .get(&(tx.from, spender))
+                .cloned()
+                .unwrap_or_default();
+            if allowance < debit {
+                return Err(TransferFromError::InsufficientAllowance { allowance });
+            }
+            self.allowances
+                .insert((tx.from, spender), allowance - debit.clone());
+        }
+
+        if is_mint {
+            self.total_supply += tx.amount.clone();
+        } else {
+            let balance = self.balance(&tx.from);
+            self.balances.insert(tx.from, balance - debit);
+            self.total_supply -= expected_fee;
+        }
+
+        if is_burn {
+            self.total_supply -= tx.amount.clone();
+        } else {
+            let balance = self.balance(&tx.to);
+            self.balances.insert(tx.to, balance + tx.amount.clone());
+        }
+
+        self.blocks.push(FakeBlock {
+            timestamp: now,
+            transaction: tx,
+        });
+        Ok((self.blocks.len() - 1).into())
+    }
+}
+
+#[async_trait(?Send)]
+impl LedgerClient for FakeLedgerClient {
+    async fn balance_of(&self, account: Account) -> Result<Nat> {
+        Ok(self.balance(&account))
+    }
+
+    async fn transfer(&self, args: TransferArg) -> Result<TxId> {
+        let result = self.transfer_call(self.transfer_tx(ic::id(), args))?;
+        Ok(result.map_err(into_transfer_error)?)
+    }
+
+    async fn transfer_from(&self, args: TransferFromArgs) -> Result<TxId> {
+        let result = self.transfer_call(self.transfer_from_tx(ic::id(), args))?;
+        Ok(result?)
+    }
+
+    async fn fee(&self) -> Result<Nat> {
+        Ok(self.state.borrow().fee.clone())
+    }
+
+    async fn minting_account(&self) -> Result<Option<Account>> {
+        Ok(self.state.borrow().minting_account)
+    }
+}
+
+/// Registers a responder which can reject the call, unlike the ones registered with
+/// `register_virtual_responder`.
+fn register_responder<T, R>(
+    principal: Principal,
+    method: &str,
+    responder: impl Fn(T) -> CallResult<R> + 'static,
+) where
+    for<'a> T: ArgumentDecoder<'a>,
+    (R,): ArgumentEncoder,
+{
+    register_raw_virtual_responder(principal, method, move |args| {
+        let args = candid::decode_args::<T>(&args).map_err(|e| {
+            (
+                RejectionCode::CanisterError,
+                format!("failed to decode args: {e}"),
+            )
+        })?;
+        let result = responder(args)?;
+        candid::encode_args((result,)).map_err(|e| {
+            (
+                RejectionCode::CanisterError,
+                format!("failed to encode response: {e}"),
+            )
+        })
+    });
+}
+
+fn into_transfer_error(error: TransferFromError) -> TransferError {
+    match error {
+        TransferFromError::BadFee { expected_fee } => TransferError::BadFee { expected_fee },
+        TransferFromError::BadBurn { min_burn_amount } => {
+            TransferError::BadBurn { min_burn_amount }
+        }
+        TransferFromError::InsufficientFunds { balance } => {
+            TransferError::InsufficientFunds { balance }
+        }
+        TransferFromError::TooOld => TransferError::TooOld,
+        TransferFromError::CreatedInFuture { ledger_time } => {
+            TransferError::CreatedInFuture { ledger_time }
+        }
+        TransferFromError::Duplicate { duplicate_of } => TransferError::Duplicate { duplicate_of },
+        TransferFromError::TemporarilyUnavailable => TransferError::TemporarilyUnavailable,
+        TransferFromError::GenericError {
+            error_code,
+            message,
+        } => TransferError::GenericError {
+            error_code,
+            message,
+        },
+        // Transfers without spender never check the allowance, but the conversion is kept total.
+        TransferFromError::InsufficientAllowance { allowance } => TransferError::GenericError {
+            error_code: 0u64.into(),
+            message: format!("insufficient allowance: {allowance}"),
+        },
+    }
+}
+
+fn into_transfer_from_error(error: TransferError) -> TransferFromError {
+    match error {
+        TransferError::BadFee { expected_fee } => TransferFromError::BadFee { expected_fee },
+        TransferError::BadBurn { min_burn_amount } => {
+            TransferFromError::BadBurn { min_burn_amount }
+        }
+        TransferError::InsufficientFunds { balance } => {
+            TransferFromError::InsufficientFunds { balance }
+        }
+        TransferError::TooOld => TransferFromError::TooOld,
+        TransferError::CreatedInFuture { ledger_time } => {
+            TransferFromError::CreatedInFuture { ledger_time }
+        }
+        TransferError::Duplicate { duplicate_of } => TransferFromError::Duplicate { duplicate_of },
+        TransferError::TemporarilyUnavailable => TransferFromError::TemporarilyUnavailable,
+        TransferError::GenericError {
+            error_code,
+            message,
+        } => TransferFromError::GenericError {
+            error_code,
+            message,
+        },
+    }
+}
@@ -0,0 +1,66 @@
+use async_trait::async_trait;
+use candid::{Nat, Principal};
+use ic_canister::virtual_canister_call;
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_exports::icrc_types::icrc1::transfer::{TransferArg, TransferError};
+use ic_exports::icrc_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+
+use super::LedgerClient;
+use crate::error::Result;
+use crate::{icrc1, TxId};
+
+/// Ledger client that calls the token canister with inter-canister calls.
+///
+/// In non-wasm builds the calls are dispatched to the responders registered with
+/// `ic_canister::register_virtual_responder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcLedgerClient {
+    principal: Principal,
+}
+
+impl IcLedgerClient {
+    /// Creates a client of the token canister with the given `principal`.
+    pub fn new(principal: Principal) -> Self {
+        Self { principal }
+    }
+
+    /// Principal of the token canister.
+    pub fn principal(&self) -> Principal {
+        self.principal
+    }
+}
+
+#[async_trait(?Send)]
+impl LedgerClient for IcLedgerClient {
+    async fn balance_of(&self, account: Account) -> Result<Nat> {
+        icrc1::get_icrc1_balance(self.principal, &account).await
+    }
+
+    async fn transfer(&self, args: TransferArg) -> Result<TxId> {
+        Ok(virtual_canister_call!(
+            self.principal,
+            "icrc1_transfer",
+            (args,),
+            std::result::Result<TxId, TransferError>
+        )
+        .await??)
+    }
+
+    async fn transfer_from(&self, args: TransferFromArgs) -> Result<TxId> {
+        Ok(virtual_canister_call!(
+            self.principal,
+            "icrc2_transfer_from",
+            (args,),
+            std::result::Result<TxId, TransferFromError>
+        )
+        .await??)
+    }
+
+    async fn fee(&self) -> Result<Nat> {
+        icrc1::get_icrc1_fee(self.principal).await
+    }
+
+    async fn minting_account(&self) -> Result<Option<Account>> {
+        icrc1::get_icrc1_minting_account(self.principal).await
+    }
+}
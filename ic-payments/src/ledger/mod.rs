@@ -0,0 +1,54 @@
+//! Clients used by the [`TokenTerminal`](crate::TokenTerminal) to call the token canister.
+//!
+//! [`LedgerClient`] abstracts the transport to the token, so the terminal can be used with the
+//! default [`IcLedgerClient`] inside a canister, with a
+//! `CanisterClient` off-chain (requires `canister-client`
+//! feature) or with the in-memory `FakeLedgerClient` in unit tests (requires `fake-ledger`
+//! feature). The fake ledger can also
+//! serve `virtual_canister_call!` calls to a token principal, so the code that calls the token
+//! directly can be tested with it as well.
+
+use async_trait::async_trait;
+use candid::Nat;
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_exports::icrc_types::icrc1::transfer::TransferArg;
+use ic_exports::icrc_types::icrc2::transfer_from::TransferFromArgs;
+
+use crate::error::Result;
+use crate::TxId;
+
+#[cfg(feature = "canister-client")]
+mod canister_client;
+#[cfg(feature = "fake-ledger")]
+mod fake;
+mod ic;
+
+#[cfg(feature = "canister-client")]
+pub use canister_client::CanisterLedgerClient;
+#[cfg(feature = "fake-ledger")]
+pub use fake::{FakeLedgerClient, LedgerFailure};
+pub use ic::IcLedgerClient;
+
+/// Interface to the ICRC-1/ICRC-2 token canister.
+///
+/// Errors returned by the token and by the transport are mapped into the
+/// [`InternalPaymentError`](crate::error::InternalPaymentError), the same way the functions of
+/// [`icrc1`](crate::icrc1) module do.
+#[async_trait(?Send)]
+pub trait LedgerClient {
+    /// Returns current balance of the `account`.
+    async fn balance_of(&self, account: Account) -> Result<Nat>;
+
+    /// Executes `icrc1_transfer` request from the account of the calling canister.
+    async fn transfer(&self, args: TransferArg) -> Result<TxId>;
+
+    /// Executes `icrc2_transfer_from` request using the allowance approved to the calling
+    /// canister.
+    async fn transfer_from(&self, args: TransferFromArgs) -> Result<TxId>;
+
+    /// Returns the transaction fee of the token.
+    async fn fee(&self) -> Result<Nat>;
+
+    /// Returns the minting account of the token, if any.
+    async fn minting_account(&self) -> Result<Option<Account>>;
+}
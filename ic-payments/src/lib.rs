@@ -14,10 +14,15 @@
 //!
 //! [`TokenTerminal`] class provides a generic methods to perform in and out transfers, dealing
 //! with all three issues explained above. To create it a canister has to provide an implementation
-//! for a [`Balances`] trait which stores the user balances in the canister.
+//! for a [`Balances`] trait which stores the user balances in the canister. A ready-made stable
+//! memory implementation with account journals is available in [`internal_ledger`] module.
 //!
 //! There are also convenience methods in [`icrc1`] module to call common operations of ICRC-1
-//! compatible tokens.
+//! compatible tokens, as well as ICRC-2 approve, allowance and transfer from operations.
+//!
+//! The terminal can also work with the legacy `AccountIdentifier` interface of the ICP ledger
+//! (see [`LedgerType::IcpLedger`]). Calls to this interface are available in [`icp_ledger`]
+//! module.
 //!
 //! # Transfer types
 //!
@@ -32,6 +37,10 @@
 //!   second step failed, the transfer is always kept in recovery list until it can be successfully
 //!   completed (since the tokens are already locked in the interim account).
 //!
+//! Deposits can also be done from an ICRC-2 allowance the user has approved to the canister (see
+//! [`TokenTerminal::deposit_from_allowance`]). Such transfers are single-step and are executed
+//! with `icrc2_transfer_from` call.
+//!
 //! # Performing a transfer
 //!
 //! General transfer execution is as follows:
@@ -57,7 +66,7 @@
 //! # Recovery
 //!
 //! Transfers stored in the recovery list can be recovered by calling
-//! [`TokenTerminal::recover_all()`] method. There are two ways to recover a transfer, result of
+//! [`TokenTerminal::recover_all()`] method. There are three ways to recover a transfer, result of
 //! which is not know to the terminal:
 //!
 //! 1. Using deduplication mechanism of ICRC-1 tokens. This mechanism is applied to all
@@ -65,6 +74,22 @@
 //!    token (typically 24 hours).
 //! 2. Using interim accounts of double-step transfers. This mechanism can only be applied to the
 //!    double-step transfers, and applied for transfers older than deduplication period.
+//! 3. Using the token transaction log. If the terminal is [configured to read the
+//!    log](TokenTerminal::with_transaction_log), single-step transfers older than deduplication
+//!    period are looked up in the log by their accounts, amount, memo and `created_at` time. If
+//!    the transfer is not found, it is considered failed.
+//!
+//! Transfers that the terminal removes from the recovery list without being able to either
+//! complete or reject them, are reported as [unresolvable](RecoveryStatus::Unresolvable) by
+//! [`TokenTerminal::recover_all_with_status()`]. The recovery can be run periodically with the
+//! [`RecoveryTask`](recovery_task::RecoveryTask) of `ic-task-scheduler`, which moves such
+//! transfers to the [`ManualAttentionList`].
+//!
+//! Every [entry](RecoveryEntry) of the recovery list records the time the transfer failed first,
+//! the number of failed attempts and the last error. Administrators of the canister can resolve
+//! an entry manually with [`TokenTerminal::force_resolve`], which executes the transfer operation
+//! as if the transfer had succeeded or failed, and records the resolution in a
+//! [`ResolutionLog`].
 //!
 //! ## Recovery through deduplication
 //!
@@ -103,7 +128,29 @@
 //!
 //! In such case terminal will automatically retrieve updated configuration and retry the failed
 //! transfer. The canister can also [set a callback](TokenTerminal::on_config_update) to be called
-//! to update the configuration stored in the state.
+//! to update the configuration stored in the state. The configuration can also be refreshed
+//! explicitly with [`TokenTerminal::refresh_token_config`].
+//!
+//! # Batch payouts
+//!
+//! Large numbers of withdrawals, e.g. reward distributions, can be executed with the
+//! [`BatchPayouts`](payout::BatchPayouts). The batch is kept in the stable memory and is
+//! processed in chunks of parallel withdrawals over many messages, reporting the status of each
+//! payout.
+//!
+//! # Many tokens
+//!
+//! Canisters that hold many tokens can use the [`TerminalRegistry`](registry::TerminalRegistry),
+//! which keeps configurations of all tokens, stores their recovery lists in a single stable
+//! memory and executes deposits and withdrawals by the token principal.
+//!
+//! # Ledger client
+//!
+//! The terminal calls the token through a [`LedgerClient`](ledger::LedgerClient). By default
+//! inter-canister calls are used, but the client can be replaced with
+//! [`TokenTerminal::with_ledger_client`], e.g. to use the terminal off-chain with a
+//! `CanisterClient` (`canister-client` feature), or with the in-memory
+//! `FakeLedgerClient` (`fake-ledger` feature) in tests.
 //!
 //! ```
 
@@ -111,8 +158,15 @@ use candid::{CandidType, Deserialize, Nat, Principal};
 
 mod balances;
 pub mod error;
+pub mod icp_ledger;
 pub mod icrc1;
+pub mod icrc3;
+pub mod internal_ledger;
+pub mod ledger;
+pub mod payout;
 pub mod recovery_list;
+pub mod recovery_task;
+pub mod registry;
 mod token_terminal;
 mod transfer;
 
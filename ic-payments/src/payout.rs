@@ -0,0 +1,562 @@
+//! Batch payouts, e.g. for reward distribution.
+//!
+//! A batch of [`PayoutItem`]s is saved into a [`PayoutStore`] and executed by [`BatchPayouts`] in
+//! chunks. Each call of [`BatchPayouts::process`] starts at most `max_parallel` withdrawals at
+//! the same time and waits until they are finished. The batch and the status of each item are
+//! kept in the store, so processing of a batch can be spread over many messages and continued
+//! after the canister upgrade.
+//!
+//! [`PayoutTask`] runs the processing with the `ic-task-scheduler` until the batch is finished.
+//! Since the scheduler keeps its tasks in the stable memory, the processing is resumed after
+//! the upgrade automatically. Canisters that drive the processing in other way can find the
+//! batches to resume with [`BatchPayouts::unfinished_batches`].
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use candid::{CandidType, Deserialize, Encode, Nat, Principal};
+use futures::future::join_all;
+use ic_exports::ic_kit::ic;
+use ic_stable_structures::stable_structures::storable::Bound;
+use ic_stable_structures::stable_structures::DefaultMemoryImpl;
+use ic_stable_structures::{
+    BTreeMapStructure, IterableSortedMapStructure, StableBTreeMap, Storable, VirtualMemory,
+};
+use ic_task_scheduler::scheduler::TaskScheduler;
+use ic_task_scheduler::task::{ScheduledTask, Task, TaskOptions};
+use ic_task_scheduler::SchedulerError;
+use serde::Serialize;
+
+use crate::error::{PaymentError, RecoveryDetails};
+use crate::ledger::LedgerClient;
+use crate::recovery_list::{with_stable_map, RecoveryList, StableMaps};
+use crate::{Balances, TokenTerminal, TxId};
+
+/// Id of a payout batch.
+pub type BatchId = u64;
+
+/// Delay before the next run of [`PayoutTask`] if no payouts could be started, e.g. because all
+/// parallel payouts are busy.
+const BUSY_DELAY_SECS: u64 = 10;
+
+/// Single payout of a batch.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct PayoutItem {
+    /// Principal to receive the payout.
+    pub recipient: Principal,
+
+    /// Amount to withdraw from the recipient's balance.
+    pub amount: Nat,
+}
+
+/// Status of a payout.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub enum PayoutStatus {
+    /// The payout is not started yet.
+    Pending,
+
+    /// The withdrawal is being executed.
+    InProgress,
+
+    /// The withdrawal is completed. `amount` is the amount received by the recipient.
+    Completed { tx_id: TxId, amount: Nat },
+
+    /// The withdrawal failed, and the amount is returned to the recipient's balance if it was
+    /// debited.
+    Failed(PaymentError),
+
+    /// The result of the withdrawal is unknown and the transfer is stored in the recovery list.
+    Recovering(RecoveryDetails),
+
+    /// The message executing the withdrawal was interrupted before the result was saved. The
+    /// transfer may or may not be executed, so it should be checked manually.
+    Interrupted,
+}
+
+/// Payout item together with its status.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct PayoutEntry {
+    pub item: PayoutItem,
+    pub status: PayoutStatus,
+}
+
+/// Number of the payouts of a batch in each status.
+#[derive(Debug, Default, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct BatchSummary {
+    pub total: u64,
+    pub pending: u64,
+    pub in_progress: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub recovering: u64,
+    pub interrupted: u64,
+}
+
+impl BatchSummary {
+    fn new(entries: &[PayoutEntry]) -> Self {
+        let mut summary = Self {
+            total: entries.len() as u64,
+            ..Default::default()
+        };
+        for entry in entries {
+            let counter = match entry.status {
+                PayoutStatus::Pending => &mut summary.pending,
+                PayoutStatus::InProgress => &mut summary.in_progress,
+                PayoutStatus::Completed { .. } => &mut summary.completed,
+                PayoutStatus::Failed(_) => &mut summary.failed,
+                PayoutStatus::Recovering(_) => &mut summary.recovering,
+                PayoutStatus::Interrupted => &mut summary.interrupted,
+            };
+            *counter += 1;
+        }
+
+        summary
+    }
+
+    /// Returns true if no payouts of the batch are pending or being executed.
+    pub fn is_finished(&self) -> bool {
+        self.pending == 0 && self.in_progress == 0
+    }
+}
+
+/// Storage of the payout batches.
+pub trait PayoutStore: Sync + Send {
+    /// Saves a new batch with all items [pending](PayoutStatus::Pending) and returns its id.
+    ///
+    /// Ids of the batches must never be reused, even after the batch is removed, so that the
+    /// tasks and payouts of the removed batch are never applied to a new one.
+    fn add_batch(&mut self, items: Vec<PayoutItem>) -> BatchId;
+
+    /// Ids of all stored batches.
+    fn batches(&self) -> Vec<BatchId>;
+
+    /// Items of the batch in the order they were added.
+    fn entries(&self, batch: BatchId) -> Vec<PayoutEntry>;
+
+    /// Sets the status of the item with the given `index` in the batch.
+    fn set_status(&mut self, batch: BatchId, index: u32, status: PayoutStatus);
+
+    /// Removes the batch from the store and returns its items.
+    fn remove_batch(&mut self, batch: BatchId) -> Vec<PayoutEntry>;
+
+    /// Payouts of the store executed at the moment, as `(batch, index)` pairs.
+    ///
+    /// Unlike the batches, this set must not be preserved over upgrades, so that the payouts
+    /// that are marked as in progress but are not in this set are known to be interrupted.
+    fn in_flight(&self) -> HashSet<(BatchId, u32)>;
+
+    /// Adds the payout to the [in-flight](PayoutStore::in_flight) set or removes it from the set.
+    fn set_in_flight(&mut self, batch: BatchId, index: u32, in_flight: bool);
+}
+
+thread_local! {
+    static PAYOUT_STORAGE: StableMaps<PayoutKey, PayoutValue> = const { RefCell::new(BTreeMap::new()) };
+
+    static IN_FLIGHT: RefCell<BTreeMap<u8, HashSet<(BatchId, u32)>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Key of the record with the id of the next batch. Batch ids are assigned from 0, so this key is
+/// never used by a batch.
+const NEXT_BATCH_KEY: PayoutKey = PayoutKey {
+    batch: BatchId::MAX,
+    index: u32::MAX,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct PayoutKey {
+    batch: BatchId,
+    index: u32,
+}
+
+impl Storable for PayoutKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.batch.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (batch, index) = bytes.split_at(8);
+        Self {
+            batch: BatchId::from_be_bytes(batch.try_into().expect("invalid payout key")),
+            index: u32::from_be_bytes(index.try_into().expect("invalid payout key")),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 12,
+        is_fixed_size: true,
+    };
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+enum PayoutRecord {
+    Entry(PayoutEntry),
+    NextBatch(BatchId),
+}
+
+#[derive(Clone)]
+struct PayoutValue(PayoutRecord);
+
+impl PayoutValue {
+    fn entry(self) -> Option<PayoutEntry> {
+        match self.0 {
+            PayoutRecord::Entry(entry) => Some(entry),
+            PayoutRecord::NextBatch(_) => None,
+        }
+    }
+}
+
+impl Storable for PayoutValue {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = Encode!(&self.0).expect("serialization of payout entry failed");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(candid::decode_one(&bytes).expect("deserialization of payout entry failed"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Default implementation of [`PayoutStore`], stored in the stable memory with the given id.
+#[derive(Debug)]
+pub struct StablePayoutStore<const MEM_ID: u8>;
+
+impl<const MEM_ID: u8> StablePayoutStore<MEM_ID> {
+    fn with_storage<R>(
+        &self,
+        f: impl FnOnce(
+            &mut StableBTreeMap<PayoutKey, PayoutValue, VirtualMemory<DefaultMemoryImpl>>,
+        ) -> R,
+    ) -> R {
+        with_stable_map(&PAYOUT_STORAGE, MEM_ID, f)
+    }
+}
+
+fn batch_range(batch: BatchId) -> std::ops::RangeInclusive<PayoutKey> {
+    PayoutKey { batch, index: 0 }..=PayoutKey {
+        batch,
+        index: u32::MAX,
+    }
+}
+
+impl<const MEM_ID: u8> PayoutStore for StablePayoutStore<MEM_ID> {
+    fn add_batch(&mut self, items: Vec<PayoutItem>) -> BatchId {
+        self.with_storage(|m| {
+            let batch = match m.get(&NEXT_BATCH_KEY).map(|v| v.0) {
+                Some(PayoutRecord::NextBatch(batch)) => batch,
+                _ => 0,
+            };
+            let next_batch = batch.checked_add(1).expect("batch ids are exhausted");
+            m.insert(
+                NEXT_BATCH_KEY,
+                PayoutValue(PayoutRecord::NextBatch(next_batch)),
+            );
+
+            for (index, item) in items.into_iter().enumerate() {
+                let key = PayoutKey {
+                    batch,
+                    index: index.try_into().expect("too many items in the batch"),
+                };
+                let status = PayoutStatus::Pending;
+                m.insert(
+                    key,
+                    PayoutValue(PayoutRecord::Entry(PayoutEntry { item, status })),
+                );
+            }
+
+            batch
+        })
+    }
+
+    fn batches(&self) -> Vec<BatchId> {
+        self.with_storage(|m| {
+            let mut batches = vec![];
+            let mut next = PayoutKey { batch: 0, index: 0 };
+            while let Some((key, _)) = m.range(next..NEXT_BATCH_KEY).next() {
+                batches.push(key.batch);
+                match key.batch.checked_add(1) {
+                    Some(batch) => next = PayoutKey { batch, index: 0 },
+                    None => break,
+                }
+            }
+
+            batches
+        })
+    }
+
+    fn entries(&self, batch: BatchId) -> Vec<PayoutEntry> {
+        self.with_storage(|m| {
+            m.range(batch_range(batch))
+                .filter_map(|(_, v)| v.entry())
+                .collect()
+        })
+    }
+
+    fn set_status(&mut self, batch: BatchId, index: u32, status: PayoutStatus) {
+        self.with_storage(|m| {
+            let key = PayoutKey { batch, index };
+            if let Some(mut entry) = m.get(&key).and_then(PayoutValue::entry) {
+                entry.status = status;
+                m.insert(key, PayoutValue(PayoutRecord::Entry(entry)));
+            }
+        })
+    }
+
+    fn remove_batch(&mut self, batch: BatchId) -> Vec<PayoutEntry> {
+        self.with_storage(|m| {
+            let entries: Vec<_> = m.range(batch_range(batch)).collect();
+            for (key, _) in &entries {
+                m.remove(key);
+            }
+
+            entries.into_iter().filter_map(|(_, v)| v.entry()).collect()
+        })
+    }
+
+    fn in_flight(&self) -> HashSet<(BatchId, u32)> {
+        IN_FLIGHT.with(|v| v.borrow().get(&MEM_ID).cloned().unwrap_or_default())
+    }
+
+    fn set_in_flight(&mut self, batch: BatchId, index: u32, in_flight: bool) {
+        IN_FLIGHT.with(|v| {
+            let mut v = v.borrow_mut();
+            let payouts = v.entry(MEM_ID).or_default();
+            match in_flight {
+                true => payouts.insert((batch, index)),
+                false => payouts.remove(&(batch, index)),
+            };
+        })
+    }
+}
+
+/// Executes payout batches with [`TokenTerminal::withdraw`].
+///
+/// Each payout withdraws the item amount from the recipient's balance to the recipient's
+/// account, so the canister must credit the payouts to the recipients' balances before adding
+/// the batch. The terminal is created by the given factory for every payout, so that the
+/// payouts can be executed in parallel.
+pub struct BatchPayouts<B, R, L, S>
+where
+    B: Balances,
+    R: RecoveryList,
+    L: LedgerClient,
+    S: PayoutStore,
+{
+    make_terminal: Rc<dyn Fn() -> TokenTerminal<B, R, L>>,
+    store: Rc<RefCell<S>>,
+    max_parallel: usize,
+}
+
+impl<B, R, L, S> BatchPayouts<B, R, L, S>
+where
+    B: Balances + 'static,
+    R: RecoveryList + 'static,
+    L: LedgerClient + 'static,
+    S: PayoutStore + 'static,
+{
+    /// Creates a new payout executor. At most `max_parallel` withdrawals of the `store` are
+    /// executed at the same time, including the withdrawals started by other messages.
+    ///
+    /// # Panics
+    /// If `max_parallel` is 0.
+    pub fn new(
+        make_terminal: impl Fn() -> TokenTerminal<B, R, L> + 'static,
+        store: S,
+        max_parallel: usize,
+    ) -> Self {
+        assert!(max_parallel > 0, "max_parallel must be positive");
+        Self {
+            make_terminal: Rc::new(make_terminal),
+            store: Rc::new(RefCell::new(store)),
+            max_parallel,
+        }
+    }
+
+    /// Saves a new batch to be processed.
+    pub fn add_batch(&self, items: Vec<PayoutItem>) -> BatchId {
+        self.store.borrow_mut().add_batch(items)
+    }
+
+    /// Items of the batch with their statuses.
+    pub fn entries(&self, batch: BatchId) -> Vec<PayoutEntry> {
+        self.store.borrow().entries(batch)
+    }
+
+    /// Progress of the batch.
+    pub fn summary(&self, batch: BatchId) -> BatchSummary {
+        BatchSummary::new(&self.entries(batch))
+    }
+
+    /// Ids of the batches that still have pending payouts.
+    pub fn unfinished_batches(&self) -> Vec<BatchId> {
+        let batches = self.store.borrow().batches();
+        batches
+            .into_iter()
+            .filter(|batch| !self.summary(*batch).is_finished())
+            .collect()
+    }
+
+    /// Removes the batch from the store and returns its items.
+    pub fn remove_batch(&self, batch: BatchId) -> Vec<PayoutEntry> {
+        self.store.borrow_mut().remove_batch(batch)
+    }
+
+    /// Executes the next chunk of pending payouts of the batch and returns the batch progress.
+    ///
+    /// Payouts left [in progress](PayoutStatus::InProgress) by a message that didn't finish,
+    /// e.g. before the canister upgrade, are marked as [interrupted](PayoutStatus::Interrupted).
+    pub async fn process(&self, batch: BatchId) -> BatchSummary {
+        let in_flight = self.store.borrow().in_flight();
+        let slots = self.max_parallel.saturating_sub(in_flight.len());
+
+        let mut chunk = vec![];
+        for (index, entry) in self.entries(batch).into_iter().enumerate() {
+            let index = index as u32;
+            match entry.status {
+                PayoutStatus::InProgress if !in_flight.contains(&(batch, index)) => {
+                    self.store
+                        .borrow_mut()
+                        .set_status(batch, index, PayoutStatus::Interrupted);
+                }
+                PayoutStatus::Pending if chunk.len() < slots => chunk.push((index, entry.item)),
+                _ => {}
+            }
+        }
+
+        // The payouts are marked before the first call to the token, so that other messages
+        // don't start them again.
+        for (index, _) in &chunk {
+            let mut store = self.store.borrow_mut();
+            store.set_status(batch, *index, PayoutStatus::InProgress);
+            store.set_in_flight(batch, *index, true);
+        }
+
+        let payouts = chunk.into_iter().map(|(index, item)| async move {
+            let mut terminal = (self.make_terminal)();
+            (index, terminal.withdraw(item.recipient, item.amount).await)
+        });
+
+        for (index, result) in join_all(payouts).await {
+            let status = match result {
+                Ok((tx_id, amount)) => PayoutStatus::Completed { tx_id, amount },
+                Err(PaymentError::Recoverable(details)) => PayoutStatus::Recovering(details),
+                Err(err) => PayoutStatus::Failed(err),
+            };
+            let mut store = self.store.borrow_mut();
+            store.set_status(batch, index, status);
+            store.set_in_flight(batch, index, false);
+        }
+
+        self.summary(batch)
+    }
+}
+
+impl<B, R, L, S> Clone for BatchPayouts<B, R, L, S>
+where
+    B: Balances,
+    R: RecoveryList,
+    L: LedgerClient,
+    S: PayoutStore,
+{
+    fn clone(&self) -> Self {
+        Self {
+            make_terminal: self.make_terminal.clone(),
+            store: self.store.clone(),
+            max_parallel: self.max_parallel,
+        }
+    }
+}
+
+/// Executes one chunk of a payout batch.
+pub trait PayoutRunner {
+    /// Current progress of the batch.
+    fn summary(&self, batch: BatchId) -> BatchSummary;
+
+    /// Processes the next chunk of the batch and returns the batch progress.
+    fn process(&self, batch: BatchId) -> Pin<Box<dyn Future<Output = BatchSummary>>>;
+}
+
+impl<B, R, L, S> PayoutRunner for BatchPayouts<B, R, L, S>
+where
+    B: Balances + 'static,
+    R: RecoveryList + 'static,
+    L: LedgerClient + 'static,
+    S: PayoutStore + 'static,
+{
+    fn summary(&self, batch: BatchId) -> BatchSummary {
+        BatchPayouts::summary(self, batch)
+    }
+
+    fn process(&self, batch: BatchId) -> Pin<Box<dyn Future<Output = BatchSummary>>> {
+        let payouts = self.clone();
+        Box::pin(async move { BatchPayouts::process(&payouts, batch).await })
+    }
+}
+
+/// Scheduler task that processes a payout batch with the [`PayoutRunner`] given as the task
+/// context. Every run processes one chunk of the batch and schedules the next run until the
+/// batch is finished. If the run could not start any payout, e.g. because all parallel payouts
+/// are busy, the next run is delayed.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct PayoutTask {
+    batch: BatchId,
+}
+
+impl PayoutTask {
+    /// Creates a task to process the `batch`.
+    pub fn new(batch: BatchId) -> Self {
+        Self { batch }
+    }
+
+    /// Batch processed by the task.
+    pub fn batch(&self) -> BatchId {
+        self.batch
+    }
+
+    /// Returns the task to be added to the scheduler.
+    pub fn schedule(self) -> ScheduledTask<Self> {
+        ScheduledTask::new(self)
+    }
+
+    /// Returns the task to be added to the scheduler, which is executed after `delay_secs`
+    /// seconds.
+    pub fn schedule_after(self, delay_secs: u64) -> ScheduledTask<Self> {
+        let options = TaskOptions::new()
+            .with_execute_after_timestamp_in_secs(ic::time() / 10u64.pow(9) + delay_secs);
+        ScheduledTask::with_options(self, options)
+    }
+}
+
+impl Task for PayoutTask {
+    type Ctx = Rc<dyn PayoutRunner>;
+
+    fn execute(
+        &self,
+        runner: Self::Ctx,
+        task_scheduler: Box<dyn 'static + TaskScheduler<Self>>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), SchedulerError>>>> {
+        let task = self.clone();
+        Box::pin(async move {
+            let pending = runner.summary(task.batch).pending;
+            let summary = runner.process(task.batch).await;
+            if summary.is_finished() {
+                return Ok(());
+            }
+
+            let next_run = match summary.pending < pending {
+                true => task.schedule(),
+                false => task.schedule_after(BUSY_DELAY_SECS),
+            };
+            task_scheduler.append_task(next_run);
+
+            Ok(())
+        })
+    }
+}
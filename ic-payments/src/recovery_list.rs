@@ -1,37 +1,160 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::thread::LocalKey;
 
-use candid::Encode;
+use candid::{CandidType, Deserialize, Encode, Principal};
+use ic_exports::ic_kit::ic;
 use ic_stable_structures::stable_structures::storable::Bound;
 use ic_stable_structures::stable_structures::DefaultMemoryImpl;
 use ic_stable_structures::{
-    BTreeMapStructure, IcMemoryManager, MemoryId, StableBTreeMap, Storable, VirtualMemory,
+    BTreeMapStructure, IcMemoryManager, IterableSortedMapStructure, MemoryId, StableBTreeMap,
+    Storable, VirtualMemory,
 };
 
-use crate::Transfer;
+use crate::error::{PaymentError, RecoveryDetails};
+use crate::{Timestamp, Transfer};
+
+/// Id of a [`RecoveryEntry`].
+pub type RecoveryEntryId = [u8; 32];
+
+/// Transfer stored in the recovery list, together with the history of its failures.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct RecoveryEntry {
+    /// Id of the entry. This is the id of the transfer that failed first, so it doesn't change
+    /// when the transfer is renewed or proceeds to the next step during recovery.
+    pub id: RecoveryEntryId,
+
+    /// The transfer to be recovered.
+    pub transfer: Transfer,
+
+    /// Time the transfer failed for the first time.
+    pub first_failed_at: Timestamp,
+
+    /// Number of times the transfer ended with an unknown result, including the first failure.
+    pub attempts: u32,
+
+    /// Error of the last failed attempt.
+    pub last_error: RecoveryDetails,
+}
+
+impl RecoveryEntry {
+    /// Creates an entry for a transfer that has just failed for the first time.
+    pub fn new(transfer: Transfer, error: RecoveryDetails) -> Self {
+        Self {
+            id: transfer.id(),
+            transfer,
+            first_failed_at: ic::time(),
+            attempts: 1,
+            last_error: error,
+        }
+    }
+}
 
 pub trait RecoveryList: Sync + Send {
-    fn push(&mut self, transfer: Transfer);
-    fn take_all(&mut self) -> Vec<Transfer>;
-    fn list(&self) -> Vec<Transfer>;
+    fn push(&mut self, entry: RecoveryEntry);
+    fn take_all(&mut self) -> Vec<RecoveryEntry>;
+    fn list(&self) -> Vec<RecoveryEntry>;
+
+    /// Removes the entry with the given `id` from the list.
+    fn remove(&mut self, id: &RecoveryEntryId) -> Option<RecoveryEntry>;
+}
+
+/// Transfer that cannot be recovered automatically and needs to be resolved manually.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ManualAttentionEntry {
+    /// The unresolved recovery entry.
+    pub entry: RecoveryEntry,
+
+    /// Error returned by the last recovery attempt.
+    pub reason: PaymentError,
+
+    /// Time the transfer was added to the list.
+    pub timestamp: Timestamp,
+}
+
+/// Storage of the transfers that need manual attention.
+pub trait ManualAttentionList: Sync + Send {
+    fn push(&mut self, entry: ManualAttentionEntry);
+    fn list(&self) -> Vec<ManualAttentionEntry>;
+
+    /// Removes the entry with the given recovery entry `id` from the list, e.g. after it was
+    /// resolved.
+    fn remove(&mut self, id: &RecoveryEntryId) -> Option<ManualAttentionEntry>;
+}
+
+/// Outcome of a transfer set manually by [`TokenTerminal::force_resolve`](crate::TokenTerminal::force_resolve).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ManualResolution {
+    /// The transfer is considered completed.
+    Succeeded,
+
+    /// The transfer is considered never executed.
+    Failed,
+}
+
+/// Record of the audit log of manual resolutions.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ResolutionRecord {
+    /// The resolved recovery entry.
+    pub entry: RecoveryEntry,
+
+    /// Outcome set for the transfer.
+    pub resolution: ManualResolution,
+
+    /// Principal that resolved the transfer.
+    pub resolved_by: Principal,
+
+    /// Time of the resolution.
+    pub timestamp: Timestamp,
+}
+
+/// Append-only audit log of manual resolutions.
+pub trait ResolutionLog: Sync + Send {
+    fn push(&mut self, record: ResolutionRecord);
+    fn list(&self) -> Vec<ResolutionRecord>;
 }
 
 thread_local! {
-    static MEMORY_MANAGER: IcMemoryManager<DefaultMemoryImpl> = IcMemoryManager::init(DefaultMemoryImpl::default());
+    pub(crate) static MEMORY_MANAGER: IcMemoryManager<DefaultMemoryImpl> = IcMemoryManager::init(DefaultMemoryImpl::default());
 
     static RECOVERY_LIST_STORAGE: RefCell<Option<StableBTreeMap<TransferKey, TransferValue, VirtualMemory<DefaultMemoryImpl>>>> =
         const { RefCell::new(None) };
+
+    static MANUAL_ATTENTION_STORAGE: StableMaps<TransferKey, ManualAttentionValue> = const { RefCell::new(BTreeMap::new()) };
+
+    static TOKEN_RECOVERY_LIST_STORAGE: StableMaps<TokenTransferKey, TransferValue> = const { RefCell::new(BTreeMap::new()) };
+
+    static RESOLUTION_LOG_STORAGE: StableMaps<u64, ResolutionValue> = const { RefCell::new(BTreeMap::new()) };
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-struct TransferKey([u8; 32]);
+/// Stable maps of the same type, opened in different memories and identified by the memory id.
+pub(crate) type StableMaps<K, V> =
+    RefCell<BTreeMap<u8, StableBTreeMap<K, V, VirtualMemory<DefaultMemoryImpl>>>>;
 
-impl TransferKey {
-    fn new(transfer: &Transfer) -> Self {
-        Self(transfer.id())
-    }
+/// Runs `f` with the map of the `storage` in the memory with the given `mem_id`, opening the map
+/// on first use.
+pub(crate) fn with_stable_map<K, V, R>(
+    storage: &'static LocalKey<StableMaps<K, V>>,
+    mem_id: u8,
+    f: impl FnOnce(&mut StableBTreeMap<K, V, VirtualMemory<DefaultMemoryImpl>>) -> R,
+) -> R
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    storage.with(|maps| {
+        let mut maps = maps.borrow_mut();
+        let map = maps.entry(mem_id).or_insert_with(|| {
+            StableBTreeMap::new(MEMORY_MANAGER.with(|mm| mm.get(MemoryId::new(mem_id))))
+        });
+        f(map)
+    })
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct TransferKey([u8; 32]);
+
 impl Storable for TransferKey {
     fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::from(&self.0[..])
@@ -50,16 +173,30 @@ impl Storable for TransferKey {
 }
 
 #[derive(Clone)]
-struct TransferValue(Transfer);
+struct TransferValue(RecoveryEntry);
 
 impl Storable for TransferValue {
     fn to_bytes(&self) -> Cow<'_, [u8]> {
-        let bytes = Encode!(&self.0).expect("serialization of transfer failed");
+        let bytes = Encode!(&self.0).expect("serialization of recovery entry failed");
         Cow::Owned(bytes)
     }
 
     fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
-        Self(candid::decode_one(&bytes).expect("deserialization of transfer failed"))
+        if let Ok(entry) = candid::decode_one(&bytes) {
+            return Self(entry);
+        }
+
+        // Before recovery entries were introduced, the list stored bare transfers. The time of
+        // the failure of such transfers is unknown, so their creation time is used instead.
+        let transfer: Transfer =
+            candid::decode_one(&bytes).expect("deserialization of recovery entry failed");
+        Self(RecoveryEntry {
+            id: transfer.id(),
+            first_failed_at: transfer.created_at(),
+            attempts: 1,
+            last_error: RecoveryDetails::IcError,
+            transfer,
+        })
     }
 
     const BOUND: Bound = Bound::Unbounded;
@@ -86,10 +223,10 @@ impl<const MEM_ID: u8> StableRecoveryList<MEM_ID> {
 }
 
 impl<const MEM_ID: u8> RecoveryList for StableRecoveryList<MEM_ID> {
-    fn push(&mut self, transfer: Transfer) {
+    fn push(&mut self, entry: RecoveryEntry) {
         self.with_storage(|m| {
-            let key = TransferKey::new(&transfer);
-            let value = TransferValue(transfer.clone());
+            let key = TransferKey(entry.id);
+            let value = TransferValue(entry.clone());
 
             // It is possible here that a transaction with exact same id is already added to the
             // recovery list. But we don't need to worry about this case, because that would mean
@@ -100,7 +237,7 @@ impl<const MEM_ID: u8> RecoveryList for StableRecoveryList<MEM_ID> {
         })
     }
 
-    fn take_all(&mut self) -> Vec<Transfer> {
+    fn take_all(&mut self) -> Vec<RecoveryEntry> {
         self.with_storage(|m| {
             let list = m.iter().map(|(_, v)| v.0).collect();
             m.clear();
@@ -108,7 +245,217 @@ impl<const MEM_ID: u8> RecoveryList for StableRecoveryList<MEM_ID> {
         })
     }
 
-    fn list(&self) -> Vec<Transfer> {
+    fn list(&self) -> Vec<RecoveryEntry> {
+        self.with_storage(|m| m.iter().map(|(_, v)| v.0).collect())
+    }
+
+    fn remove(&mut self, id: &RecoveryEntryId) -> Option<RecoveryEntry> {
+        self.with_storage(|m| m.remove(&TransferKey(*id)).map(|v| v.0))
+    }
+}
+
+const TOKEN_KEY_LEN: usize = 30;
+
+/// Key of a recovery entry in the list shared by many tokens. Entries of each token are stored
+/// in a continuous range of keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct TokenTransferKey {
+    token: [u8; TOKEN_KEY_LEN],
+    id: RecoveryEntryId,
+}
+
+impl TokenTransferKey {
+    fn new(token: &Principal, id: RecoveryEntryId) -> Self {
+        let principal = token.as_slice();
+        let mut token = [0u8; TOKEN_KEY_LEN];
+        token[0] = principal.len() as u8;
+        token[1..1 + principal.len()].copy_from_slice(principal);
+        Self { token, id }
+    }
+
+    fn range(token: &Principal) -> std::ops::RangeInclusive<Self> {
+        Self::new(token, [0; 32])..=Self::new(token, [u8::MAX; 32])
+    }
+}
+
+impl Storable for TokenTransferKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.token.to_vec();
+        bytes.extend_from_slice(&self.id);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (token, id) = bytes.split_at(TOKEN_KEY_LEN);
+        Self {
+            token: token.try_into().expect("invalid recovery entry key"),
+            id: id.try_into().expect("invalid recovery entry key"),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: (TOKEN_KEY_LEN + 32) as u32,
+        is_fixed_size: true,
+    };
+}
+
+/// Recovery list of a single token, stored together with the lists of other tokens in the
+/// stable memory with the given id.
+///
+/// This allows many terminals, e.g. of the [`TerminalRegistry`](crate::registry::TerminalRegistry),
+/// to use a single memory id for their recovery lists.
+#[derive(Debug, Clone, Copy)]
+pub struct StableTokenRecoveryList<const MEM_ID: u8> {
+    token: Principal,
+}
+
+impl<const MEM_ID: u8> StableTokenRecoveryList<MEM_ID> {
+    /// Creates the recovery list of the `token`.
+    pub fn new(token: Principal) -> Self {
+        Self { token }
+    }
+
+    /// Principal of the token of the list.
+    pub fn token(&self) -> Principal {
+        self.token
+    }
+
+    fn with_storage<R>(
+        &self,
+        f: impl FnOnce(
+            &mut StableBTreeMap<TokenTransferKey, TransferValue, VirtualMemory<DefaultMemoryImpl>>,
+        ) -> R,
+    ) -> R {
+        with_stable_map(&TOKEN_RECOVERY_LIST_STORAGE, MEM_ID, f)
+    }
+}
+
+impl<const MEM_ID: u8> RecoveryList for StableTokenRecoveryList<MEM_ID> {
+    fn push(&mut self, entry: RecoveryEntry) {
+        self.with_storage(|m| {
+            m.insert(
+                TokenTransferKey::new(&self.token, entry.id),
+                TransferValue(entry.clone()),
+            );
+        })
+    }
+
+    fn take_all(&mut self) -> Vec<RecoveryEntry> {
+        self.with_storage(|m| {
+            let entries: Vec<_> = m.range(TokenTransferKey::range(&self.token)).collect();
+            for (key, _) in &entries {
+                m.remove(key);
+            }
+
+            entries.into_iter().map(|(_, v)| v.0).collect()
+        })
+    }
+
+    fn list(&self) -> Vec<RecoveryEntry> {
+        self.with_storage(|m| {
+            m.range(TokenTransferKey::range(&self.token))
+                .map(|(_, v)| v.0)
+                .collect()
+        })
+    }
+
+    fn remove(&mut self, id: &RecoveryEntryId) -> Option<RecoveryEntry> {
+        self.with_storage(|m| {
+            m.remove(&TokenTransferKey::new(&self.token, *id))
+                .map(|v| v.0)
+        })
+    }
+}
+
+#[derive(Clone)]
+struct ManualAttentionValue(ManualAttentionEntry);
+
+impl Storable for ManualAttentionValue {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = Encode!(&self.0).expect("serialization of manual attention entry failed");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(candid::decode_one(&bytes).expect("deserialization of manual attention entry failed"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Default implementation of [`ManualAttentionList`], stored in the stable memory with the given
+/// id.
+#[derive(Debug)]
+pub struct StableManualAttentionList<const MEM_ID: u8>;
+
+impl<const MEM_ID: u8> StableManualAttentionList<MEM_ID> {
+    fn with_storage<R>(
+        &self,
+        f: impl FnOnce(
+            &mut StableBTreeMap<TransferKey, ManualAttentionValue, VirtualMemory<DefaultMemoryImpl>>,
+        ) -> R,
+    ) -> R {
+        with_stable_map(&MANUAL_ATTENTION_STORAGE, MEM_ID, f)
+    }
+}
+
+impl<const MEM_ID: u8> ManualAttentionList for StableManualAttentionList<MEM_ID> {
+    fn push(&mut self, entry: ManualAttentionEntry) {
+        self.with_storage(|m| {
+            m.insert(
+                TransferKey(entry.entry.id),
+                ManualAttentionValue(entry.clone()),
+            );
+        })
+    }
+
+    fn list(&self) -> Vec<ManualAttentionEntry> {
+        self.with_storage(|m| m.iter().map(|(_, v)| v.0).collect())
+    }
+
+    fn remove(&mut self, id: &RecoveryEntryId) -> Option<ManualAttentionEntry> {
+        self.with_storage(|m| m.remove(&TransferKey(*id)).map(|v| v.0))
+    }
+}
+
+#[derive(Clone)]
+struct ResolutionValue(ResolutionRecord);
+
+impl Storable for ResolutionValue {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = Encode!(&self.0).expect("serialization of resolution record failed");
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(candid::decode_one(&bytes).expect("deserialization of resolution record failed"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Default implementation of [`ResolutionLog`], stored in the stable memory with the given id.
+#[derive(Debug)]
+pub struct StableResolutionLog<const MEM_ID: u8>;
+
+impl<const MEM_ID: u8> StableResolutionLog<MEM_ID> {
+    fn with_storage<R>(
+        &self,
+        f: impl FnOnce(&mut StableBTreeMap<u64, ResolutionValue, VirtualMemory<DefaultMemoryImpl>>) -> R,
+    ) -> R {
+        with_stable_map(&RESOLUTION_LOG_STORAGE, MEM_ID, f)
+    }
+}
+
+impl<const MEM_ID: u8> ResolutionLog for StableResolutionLog<MEM_ID> {
+    fn push(&mut self, record: ResolutionRecord) {
+        self.with_storage(|m| {
+            let index = m.len();
+            m.insert(index, ResolutionValue(record.clone()));
+        })
+    }
+
+    fn list(&self) -> Vec<ResolutionRecord> {
         self.with_storage(|m| m.iter().map(|(_, v)| v.0).collect())
     }
 }
@@ -0,0 +1,221 @@
+//! Periodic recovery of the transfers stored in the recovery list, executed by the
+//! `ic-task-scheduler`.
+//!
+//! [`RecoveryTask`] runs a [`RecoveryRunner`] given as the scheduler context. If after the run
+//! some transfers are still pending, the task fails and the scheduler retries it according to the
+//! task backoff policy. Otherwise the task schedules its next run after the configured period.
+//!
+//! Canisters that use their own task type with the scheduler can call [`RecoveryRunner::run`]
+//! from their task directly.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_exports::ic_kit::ic;
+use ic_task_scheduler::retry::{BackoffPolicy, RetryPolicy};
+use ic_task_scheduler::scheduler::TaskScheduler;
+use ic_task_scheduler::task::{ScheduledTask, Task, TaskOptions};
+use ic_task_scheduler::SchedulerError;
+use serde::Serialize;
+
+use crate::error::{ParametersError, PaymentError};
+use crate::ledger::LedgerClient;
+use crate::recovery_list::{
+    ManualAttentionEntry, ManualAttentionList, ManualResolution, RecoveryEntry, RecoveryEntryId,
+    RecoveryList, ResolutionLog, ResolutionRecord,
+};
+use crate::{Balances, RecoveryStatus, TokenTerminal};
+
+type OutcomeCallback = dyn Fn(&RecoveryEntry, &RecoveryStatus);
+type RecoveryFuture = Pin<Box<dyn Future<Output = Vec<(RecoveryEntry, RecoveryStatus)>>>>;
+
+/// Executes one round of recovery.
+pub trait RecoveryRunner {
+    /// Recovers all transfers in the recovery list and returns status of each of them.
+    fn run(&self) -> RecoveryFuture;
+}
+
+/// [`RecoveryRunner`] for a [`TokenTerminal`].
+///
+/// The terminal is created by the given factory for every run, so the recovery doesn't hold the
+/// terminal between the calls of the canister. [Unresolvable](RecoveryStatus::Unresolvable)
+/// transfers are moved to the [`ManualAttentionList`].
+pub struct TerminalRecovery<B, R, L, M>
+where
+    B: Balances,
+    R: RecoveryList,
+    L: LedgerClient,
+    M: ManualAttentionList,
+{
+    make_terminal: Rc<dyn Fn() -> TokenTerminal<B, R, L>>,
+    manual_attention: Rc<RefCell<M>>,
+    on_outcome: Option<Rc<OutcomeCallback>>,
+}
+
+impl<B, R, L, M> TerminalRecovery<B, R, L, M>
+where
+    B: Balances + 'static,
+    R: RecoveryList + 'static,
+    L: LedgerClient + 'static,
+    M: ManualAttentionList + 'static,
+{
+    /// Creates a new recovery runner.
+    pub fn new(
+        make_terminal: impl Fn() -> TokenTerminal<B, R, L> + 'static,
+        manual_attention: M,
+    ) -> Self {
+        Self {
+            make_terminal: Rc::new(make_terminal),
+            manual_attention: Rc::new(RefCell::new(manual_attention)),
+            on_outcome: None,
+        }
+    }
+
+    /// Sets a callback to be run with the recovery status of every transfer.
+    pub fn on_outcome(self, callback: impl Fn(&RecoveryEntry, &RecoveryStatus) + 'static) -> Self {
+        Self {
+            on_outcome: Some(Rc::new(callback)),
+            ..self
+        }
+    }
+
+    /// Transfers that need manual attention.
+    pub fn manual_attention_list(&self) -> Vec<ManualAttentionEntry> {
+        self.manual_attention.borrow().list()
+    }
+
+    /// Resolves the entry of the manual attention list with the given `id` with
+    /// [`TokenTerminal::resolve_entry`] and removes it from the list.
+    pub fn resolve(
+        &self,
+        id: &RecoveryEntryId,
+        resolution: ManualResolution,
+        resolved_by: Principal,
+        log: &mut impl ResolutionLog,
+    ) -> Result<ResolutionRecord, PaymentError> {
+        let entry = self.manual_attention.borrow_mut().remove(id).ok_or(
+            PaymentError::InvalidParameters(ParametersError::UnknownRecoveryEntry),
+        )?;
+
+        let mut terminal = (self.make_terminal)();
+        terminal
+            .resolve_entry(entry.entry.clone(), resolution, resolved_by, log)
+            .inspect_err(|_| self.manual_attention.borrow_mut().push(entry))
+    }
+}
+
+impl<B, R, L, M> Clone for TerminalRecovery<B, R, L, M>
+where
+    B: Balances,
+    R: RecoveryList,
+    L: LedgerClient,
+    M: ManualAttentionList,
+{
+    fn clone(&self) -> Self {
+        Self {
+            make_terminal: self.make_terminal.clone(),
+            manual_attention: self.manual_attention.clone(),
+            on_outcome: self.on_outcome.clone(),
+        }
+    }
+}
+
+impl<B, R, L, M> RecoveryRunner for TerminalRecovery<B, R, L, M>
+where
+    B: Balances + 'static,
+    R: RecoveryList + 'static,
+    L: LedgerClient + 'static,
+    M: ManualAttentionList + 'static,
+{
+    fn run(&self) -> RecoveryFuture {
+        let recovery = self.clone();
+        Box::pin(async move {
+            let mut terminal = (recovery.make_terminal)();
+            let results = terminal.recover_all_with_status().await;
+
+            for (entry, status) in &results {
+                if let RecoveryStatus::Unresolvable(reason) = status {
+                    recovery
+                        .manual_attention
+                        .borrow_mut()
+                        .push(ManualAttentionEntry {
+                            entry: entry.clone(),
+                            reason: reason.clone(),
+                            timestamp: ic::time(),
+                        });
+                }
+
+                if let Some(callback) = &recovery.on_outcome {
+                    callback(entry, status);
+                }
+            }
+
+            results
+        })
+    }
+}
+
+/// Scheduler task that periodically runs the [`RecoveryRunner`] given as the task context.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct RecoveryTask {
+    period_secs: u64,
+    backoff: BackoffPolicy,
+}
+
+impl RecoveryTask {
+    /// Creates a task that runs the recovery every `period_secs` seconds. If some transfers are
+    /// still pending after the run, the recovery is retried with the `backoff` policy.
+    pub fn new(period_secs: u64, backoff: BackoffPolicy) -> Self {
+        Self {
+            period_secs,
+            backoff,
+        }
+    }
+
+    /// Period of the recovery runs.
+    pub fn period_secs(&self) -> u64 {
+        self.period_secs
+    }
+
+    /// Returns the task to be added to the scheduler, which is executed after `delay_secs`
+    /// seconds.
+    pub fn schedule(self, delay_secs: u64) -> ScheduledTask<Self> {
+        let options = TaskOptions::new()
+            .with_retry_policy(RetryPolicy::Infinite)
+            .with_backoff_policy(self.backoff.clone())
+            .with_execute_after_timestamp_in_secs(ic::time() / 10u64.pow(9) + delay_secs);
+        ScheduledTask::with_options(self, options)
+    }
+}
+
+impl Task for RecoveryTask {
+    type Ctx = Rc<dyn RecoveryRunner>;
+
+    fn execute(
+        &self,
+        runner: Self::Ctx,
+        task_scheduler: Box<dyn 'static + TaskScheduler<Self>>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), SchedulerError>>>> {
+        let task = self.clone();
+        Box::pin(async move {
+            let results = runner.run().await;
+            let pending = results
+                .iter()
+                .filter(|(_, status)| matches!(status, RecoveryStatus::Pending(_)))
+                .count();
+
+            if pending > 0 {
+                return Err(SchedulerError::TaskExecutionFailed(format!(
+                    "{pending} transfers are still pending recovery"
+                )));
+            }
+
+            let period_secs = task.period_secs;
+            task_scheduler.append_task(task.schedule(period_secs));
+            Ok(())
+        })
+    }
+}
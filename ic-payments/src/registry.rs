@@ -0,0 +1,233 @@
+//! Registry of the token terminals of a canister that works with many tokens.
+//!
+//! [`TerminalRegistry`] stores the configurations of the registered tokens and creates a
+//! [`TokenTerminal`] for the token on every operation. Recovery lists of all tokens are stored in
+//! a single [`StableTokenRecoveryList`] memory. Changes of the token configurations, detected by
+//! the terminals, are saved back into the registry.
+//!
+//! All operations take the registry by shared reference, so the registry can be kept in a
+//! `thread_local` and used by many concurrent calls of the canister.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use candid::{Nat, Principal};
+use ic_exports::icrc_types::icrc1::account::Account;
+
+use crate::error::{ParametersError, PaymentError};
+use crate::ledger::{IcLedgerClient, LedgerClient};
+use crate::recovery_list::{RecoveryEntry, StableTokenRecoveryList};
+use crate::{Balances, RecoveryStatus, TokenConfiguration, TokenTerminal, TxId};
+
+type ConfigUpdateCallback = dyn Fn(&TokenConfiguration);
+
+/// Token terminals for many tokens.
+///
+/// The token configurations are kept on the heap only and are lost on the canister upgrade.
+/// Canisters should save them into their stable state, e.g. with the callback set by
+/// [`TerminalRegistry::on_config_update`], and add them back with
+/// [`TerminalRegistry::add_token`] after the upgrade.
+///
+/// # Generic parameters
+/// * `B` - [`Balances`] storage, created for each token by the given factory.
+/// * `MEM_ID` - id of the stable memory of the recovery lists of all tokens.
+/// * `L` - [`LedgerClient`] used to call the tokens. By default inter-canister calls are used.
+pub struct TerminalRegistry<B: Balances, const MEM_ID: u8, L: LedgerClient = IcLedgerClient> {
+    configs: RefCell<BTreeMap<Principal, TokenConfiguration>>,
+    make_balances: Rc<dyn Fn(Principal) -> B>,
+    make_ledger: Rc<dyn Fn(Principal) -> L>,
+    on_config_update: Option<Rc<ConfigUpdateCallback>>,
+}
+
+impl<B: Balances, const MEM_ID: u8> TerminalRegistry<B, MEM_ID> {
+    /// Creates an empty registry. `make_balances` returns the balances storage of the given
+    /// token.
+    pub fn new(make_balances: impl Fn(Principal) -> B + 'static) -> Self {
+        Self {
+            configs: RefCell::new(BTreeMap::new()),
+            make_balances: Rc::new(make_balances),
+            make_ledger: Rc::new(IcLedgerClient::new),
+            on_config_update: None,
+        }
+    }
+}
+
+impl<B: Balances, const MEM_ID: u8, L: LedgerClient> TerminalRegistry<B, MEM_ID, L> {
+    /// Sets the factory of the clients used to call the tokens.
+    pub fn with_ledger_clients<L2: LedgerClient>(
+        self,
+        make_ledger: impl Fn(Principal) -> L2 + 'static,
+    ) -> TerminalRegistry<B, MEM_ID, L2> {
+        TerminalRegistry {
+            configs: self.configs,
+            make_balances: self.make_balances,
+            make_ledger: Rc::new(make_ledger),
+            on_config_update: self.on_config_update,
+        }
+    }
+
+    /// Sets a callback to be run when configuration of a token is changed, e.g. to save it into
+    /// the canister state.
+    pub fn on_config_update(self, callback: impl Fn(&TokenConfiguration) + 'static) -> Self {
+        Self {
+            on_config_update: Some(Rc::new(callback)),
+            ..self
+        }
+    }
+
+    /// Adds the token with the given configuration, replacing the previous configuration of the
+    /// token if any.
+    pub fn add_token(&self, config: TokenConfiguration) {
+        self.configs.borrow_mut().insert(config.principal, config);
+    }
+
+    /// Requests the configuration of the `token` from the token canister and adds the token to
+    /// the registry.
+    pub async fn register_token(
+        &self,
+        token: Principal,
+    ) -> Result<TokenConfiguration, PaymentError> {
+        let ledger = (self.make_ledger)(token);
+        let fee = ledger.fee().await?;
+        let minting_account = ledger.minting_account().await?.unwrap_or(Account {
+            owner: Principal::management_canister(),
+            subaccount: None,
+        });
+
+        let config = TokenConfiguration {
+            principal: token,
+            fee,
+            minting_account,
+        };
+        self.add_token(config.clone());
+
+        Ok(config)
+    }
+
+    /// Removes the token from the registry. Recovery list of the token is kept, so its transfers
+    /// are recovered if the token is registered again.
+    pub fn remove_token(&self, token: &Principal) -> Option<TokenConfiguration> {
+        self.configs.borrow_mut().remove(token)
+    }
+
+    /// Principals of the registered tokens.
+    pub fn tokens(&self) -> Vec<Principal> {
+        self.configs.borrow().keys().copied().collect()
+    }
+
+    /// Configuration of the `token`.
+    pub fn token_config(&self, token: &Principal) -> Option<TokenConfiguration> {
+        self.configs.borrow().get(token).cloned()
+    }
+
+    /// Creates the terminal of the `token`.
+    ///
+    /// Configuration changes made by the terminal are not saved into the registry, unless the
+    /// operation is executed through the registry methods.
+    pub fn terminal(
+        &self,
+        token: &Principal,
+    ) -> Result<TokenTerminal<B, StableTokenRecoveryList<MEM_ID>, L>, PaymentError> {
+        let config = self
+            .token_config(token)
+            .ok_or(PaymentError::InvalidParameters(
+                ParametersError::UnknownToken(*token),
+            ))?;
+
+        let terminal = TokenTerminal::new_with_recovery_list(
+            config,
+            (self.make_balances)(*token),
+            StableTokenRecoveryList::new(*token),
+        )
+        .with_ledger_client((self.make_ledger)(*token));
+
+        Ok(terminal)
+    }
+
+    /// [`TokenTerminal::deposit`] of the `token`.
+    pub async fn deposit(
+        &self,
+        token: &Principal,
+        caller: Principal,
+        amount: Nat,
+    ) -> Result<(TxId, Nat), PaymentError> {
+        let mut terminal = self.terminal(token)?;
+        let result = terminal.deposit(caller, amount).await;
+        self.update_config(terminal.token_config());
+
+        result
+    }
+
+    /// [`TokenTerminal::withdraw`] of the `token`.
+    pub async fn withdraw(
+        &self,
+        token: &Principal,
+        caller: Principal,
+        amount: Nat,
+    ) -> Result<(TxId, Nat), PaymentError> {
+        let mut terminal = self.terminal(token)?;
+        let result = terminal.withdraw(caller, amount).await;
+        self.update_config(terminal.token_config());
+
+        result
+    }
+
+    /// Refreshes configuration of all registered tokens with
+    /// [`TokenTerminal::refresh_token_config`] and returns the result for each token.
+    pub async fn refresh_all(&self) -> Vec<(Principal, Result<(), PaymentError>)> {
+        let mut results = vec![];
+        for token in self.tokens() {
+            let result = match self.terminal(&token) {
+                Ok(mut terminal) => {
+                    let result = terminal.refresh_token_config().await;
+                    self.update_config(terminal.token_config());
+                    result
+                }
+                Err(err) => Err(err),
+            };
+            results.push((token, result));
+        }
+
+        results
+    }
+
+    /// Recovers the transfers of all registered tokens with
+    /// [`TokenTerminal::recover_all_with_status`].
+    pub async fn recover_all(&self) -> Vec<(Principal, Vec<(RecoveryEntry, RecoveryStatus)>)> {
+        let mut results = vec![];
+        for token in self.tokens() {
+            let Ok(mut terminal) = self.terminal(&token) else {
+                continue;
+            };
+
+            let statuses = terminal.recover_all_with_status().await;
+            self.update_config(terminal.token_config());
+            if !statuses.is_empty() {
+                results.push((token, statuses));
+            }
+        }
+
+        results
+    }
+
+    fn update_config(&self, config: &TokenConfiguration) {
+        {
+            let mut configs = self.configs.borrow_mut();
+            let Some(current) = configs.get_mut(&config.principal) else {
+                return;
+            };
+
+            if current.fee == config.fee && current.minting_account == config.minting_account {
+                return;
+            }
+
+            *current = config.clone();
+        }
+
+        // The callback is run without the borrow, so that it can use the registry.
+        if let Some(callback) = &self.on_config_update {
+            callback(config);
+        }
+    }
+}
@@ -6,11 +6,18 @@ use ic_exports::ic_kit::ic;
 use ic_exports::icrc_types::icrc1::account::{Account, Subaccount};
 use ic_exports::icrc_types::icrc1::transfer::TransferError;
 
-use crate::error::{InternalPaymentError, PaymentError, RecoveryDetails, TransferFailReason};
-use crate::icrc1::{self, get_icrc1_balance, get_icrc1_minting_account, TokenTransferInfo};
-use crate::recovery_list::{RecoveryList, StableRecoveryList};
+use crate::error::{
+    InternalPaymentError, ParametersError, PaymentError, RecoveryDetails, TransferFailReason,
+};
+use crate::icrc1::TokenTransferInfo;
+use crate::icrc3::{self, TransactionLog, TransferRecord};
+use crate::ledger::{IcLedgerClient, LedgerClient};
+use crate::recovery_list::{
+    ManualResolution, RecoveryEntry, RecoveryEntryId, RecoveryList, ResolutionLog,
+    ResolutionRecord, StableRecoveryList,
+};
 use crate::transfer::{Operation, Stage, Transfer, TransferType};
-use crate::{Balances, TokenConfiguration, TxId};
+use crate::{icp_ledger, Balances, TokenConfiguration, TxId};
 
 /// Id that is used by the terminal to specify that the transaction ID is unknown, but it knows for
 /// sure that the transaction exists.
@@ -25,8 +32,9 @@ const N_RETRIES: usize = 3;
 const DEFAULT_DEDUP_PERIOD: u64 = 10u64.pow(9) * 60 * 60 * 24;
 
 /// Different IC nodes can have times not synchronized perfectly. We use 5 minute margin to make
-/// sure we don't try to deduplicate transactions when it's not possible already.
-const TX_WINDOW: u64 = 10u64.pow(9) * 60 * 5;
+/// sure we don't try to deduplicate transactions when it's not possible already, and to search
+/// the ledger for transfers stamped by the ledger before their `created_at_time`.
+pub(crate) const TX_WINDOW: u64 = 10u64.pow(9) * 60 * 5;
 
 // We use this counter to make every transfer created by the terminal unique, even if current
 // timestamp is the same. Since it's impossible to have timestamp repeat in operations before and
@@ -35,6 +43,43 @@ static TX_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 type ConfigChangePredicate = dyn Fn(&TokenConfiguration) + Send + Sync + 'static;
 
+/// Interface of the token canister used by the terminal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerType {
+    /// ICRC-1 compatible token.
+    #[default]
+    Icrc1,
+
+    /// ICP ledger, called with its legacy interface that identifies accounts by
+    /// `AccountIdentifier`.
+    ///
+    /// Transfers are deduplicated by `created_at_time` and `memo` the same way as ICRC-1
+    /// transfers. Single-step transfers older than deduplication period are recovered by searching
+    /// for them in the ledger blocks with `query_blocks` method.
+    IcpLedger,
+}
+
+/// Result of an attempt to recover a transfer from the recovery list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryStatus {
+    /// The transfer is completed and its operation is executed.
+    Completed(TxId),
+
+    /// The transfer is proven to be failed. Its operation is executed and the transfer is removed
+    /// from the recovery list.
+    Failed(PaymentError),
+
+    /// The terminal still cannot find out whether the transfer was successful, so it is returned
+    /// to the recovery list to be recovered later.
+    Pending(PaymentError),
+
+    /// The transfer is removed from the recovery list but the terminal could neither complete
+    /// nor reject it. This is the case for a single-step transfer older than deduplication period,
+    /// when the terminal has no way to search for it in the token transaction log. Such transfers
+    /// cannot be recovered automatically and must be resolved manually.
+    Unresolvable(PaymentError),
+}
+
 /// Bridge between an ICRC-1 token canister and the current canister. Provides safe and reliable
 /// token transfer methods to and from the canister.
 ///
@@ -84,17 +129,23 @@ type ConfigChangePredicate = dyn Fn(&TokenConfiguration) + Send + Sync + 'static
 /// # Generic parameters
 /// * `B` - [`Balances`] storage.
 /// * `R` - [`RecoveryList`] storage.
+/// * `L` - [`LedgerClient`] used to call the token canister. By default the terminal calls the
+///   token with inter-canister calls using [`IcLedgerClient`].
 ///
 /// Note that for all types that implement either of the traits above, `Rc<RefCell<T>>` also
 /// implement that trait. So to initiate an instance of `TokenTerminal` one can:
 /// * use static implementations that can be cloned and given to the token terminal by value
 /// * or give an `Rc<RefCell<T>>` of the value to the constructor.
-pub struct TokenTerminal<B: Balances, R: RecoveryList> {
+pub struct TokenTerminal<B: Balances, R: RecoveryList, L: LedgerClient = IcLedgerClient> {
     token_config: TokenConfiguration,
     balances: B,
     recovery_list: R,
     deduplication_period: u64,
     update_token_config: Option<Box<ConfigChangePredicate>>,
+    ledger_type: LedgerType,
+    transaction_log: Option<TransactionLog>,
+    ledger: L,
+    recovering: Option<RecoveryEntry>,
 }
 
 impl<T: Balances, const MEM_ID: u8> TokenTerminal<T, StableRecoveryList<MEM_ID>> {
@@ -103,11 +154,15 @@ impl<T: Balances, const MEM_ID: u8> TokenTerminal<T, StableRecoveryList<MEM_ID>>
     pub fn new(config: TokenConfiguration, balances: T) -> Self {
         let recovery_list = StableRecoveryList::<MEM_ID>;
         Self {
+            ledger: IcLedgerClient::new(config.principal),
             token_config: config,
             balances,
             recovery_list,
             deduplication_period: DEFAULT_DEDUP_PERIOD,
             update_token_config: None,
+            ledger_type: LedgerType::default(),
+            transaction_log: None,
+            recovering: None,
         }
     }
 }
@@ -120,16 +175,20 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
         recovery_list: R,
     ) -> Self {
         Self {
+            ledger: IcLedgerClient::new(config.principal),
             token_config: config,
             balances,
             recovery_list,
             deduplication_period: DEFAULT_DEDUP_PERIOD,
             update_token_config: None,
+            ledger_type: LedgerType::default(),
+            transaction_log: None,
+            recovering: None,
         }
     }
 }
 
-impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
+impl<T: Balances, R: RecoveryList, L: LedgerClient> TokenTerminal<T, R, L> {
     /// Sets a callback to be run in case the terminal detects that the token fee configuration is
     /// changed.
     ///
@@ -148,13 +207,69 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
         }
     }
 
+    /// Sets the interface used to call the token canister. By default the terminal uses
+    /// [ICRC-1](LedgerType::Icrc1) interface.
+    pub fn with_ledger_type(self, ledger_type: LedgerType) -> Self {
+        Self {
+            ledger_type,
+            ..self
+        }
+    }
+
+    /// Interface used by the terminal to call the token canister.
+    pub fn ledger_type(&self) -> LedgerType {
+        self.ledger_type
+    }
+
+    /// Sets the client used to call the token canister.
+    ///
+    /// With [`LedgerType::IcpLedger`] the client is used only for ICRC-2 transfers from allowance
+    /// and for requesting the token minting account, since the legacy ICP interface is not
+    /// covered by the [`LedgerClient`] trait.
+    pub fn with_ledger_client<L2: LedgerClient>(self, ledger: L2) -> TokenTerminal<T, R, L2> {
+        TokenTerminal {
+            token_config: self.token_config,
+            balances: self.balances,
+            recovery_list: self.recovery_list,
+            deduplication_period: self.deduplication_period,
+            update_token_config: self.update_token_config,
+            ledger_type: self.ledger_type,
+            transaction_log: self.transaction_log,
+            ledger,
+            recovering: self.recovering,
+        }
+    }
+
+    /// Client used by the terminal to call the token canister.
+    pub fn ledger_client(&self) -> &L {
+        &self.ledger
+    }
+
+    /// Enables recovery of single-step transfers older than deduplication period by searching for
+    /// them in the token transaction log, read with the given method.
+    ///
+    /// Without the transaction log such transfers are considered failed with
+    /// [`TransferFailReason::TooOld`] reason, since the terminal cannot find out whether they were
+    /// executed or not.
+    pub fn with_transaction_log(self, transaction_log: TransactionLog) -> Self {
+        Self {
+            transaction_log: Some(transaction_log),
+            ..self
+        }
+    }
+
+    /// Method used by the terminal to read the token transaction log, if any.
+    pub fn transaction_log(&self) -> Option<TransactionLog> {
+        self.transaction_log
+    }
+
     /// [`TokenTerminal::deposit`] for details.
     ///
     /// The amount the caller will receive on their balance is `interim_account_balance -
     /// transfer_fee`, where `transfer_fee` is the fee set by the token canister.
     pub async fn deposit_all(&mut self, caller: Principal) -> Result<(TxId, Nat), PaymentError> {
         let account = get_deposit_interim_account(caller);
-        let balance = get_icrc1_balance(self.token_config.principal, &account).await?;
+        let balance = self.get_balance(&account).await?;
         self.deposit(caller, balance).await
     }
 
@@ -202,6 +317,40 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
         Ok((tx_id, amount))
     }
 
+    /// Move the specified amount from the caller's main account into caller's balance, using the
+    /// ICRC-2 allowance the caller has approved to the main account of the `this` canister.
+    ///
+    /// This is an alternative to the interim account flow of [`TokenTerminal::deposit`]:
+    /// 1. Caller approves the canister to spend the tokens with `icrc2_approve` call.
+    /// 2. Caller calls a method in the canister to initiate the deposit.
+    /// 3. The canister transfers tokens from the caller account to its main account with
+    ///    `icrc2_transfer_from` call and credits the transferred amount to the caller's balance.
+    ///
+    /// The amount that the caller will receive on their balance is `amount - transfer_fee`, while
+    /// the approved allowance must be at least `amount`.
+    ///
+    /// The transfer is single-step, so it is retried and recovered the same way as the
+    /// [`TokenTerminal::deposit`] transfers.
+    pub async fn deposit_from_allowance(
+        &mut self,
+        caller: Principal,
+        amount: Nat,
+    ) -> Result<(TxId, Nat), PaymentError> {
+        let to = ic::id().into();
+        let memo = TX_COUNTER
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            .into();
+        let transfer =
+            Transfer::new_from_allowance(&self.token_config, caller, caller.into(), to, amount)
+                .with_operation(Operation::CreditOnSuccess)
+                .with_memo(memo);
+        let amount = transfer.final_amount()?;
+
+        let tx_id = self.transfer(transfer, N_RETRIES).await?;
+
+        Ok((tx_id, amount))
+    }
+
     /// Move the specified amount from the caller's balance to the caller's main account.
     ///
     /// This method creates a double-step transfer using a subaccount unique for the transfer. The
@@ -274,13 +423,12 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
     ) -> Result<TxId, PaymentError> {
         match transfer.r#type() {
             TransferType::DoubleStep(Stage::Second, _) => {
-                self.add_for_recovery(transfer);
-                match error {
-                    InternalPaymentError::WrongFee(fee) => {
-                        Err(PaymentError::Recoverable(RecoveryDetails::BadFee(fee)))
-                    }
-                    _ => Err(PaymentError::Recoverable(RecoveryDetails::IcError)),
-                }
+                let details = match error {
+                    InternalPaymentError::WrongFee(fee) => RecoveryDetails::BadFee(fee),
+                    _ => RecoveryDetails::IcError,
+                };
+                self.add_for_recovery(transfer, details.clone());
+                Err(PaymentError::Recoverable(details))
             }
             _ => {
                 if transfer.operation() == Operation::CreditOnError {
@@ -295,7 +443,7 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
     #[async_recursion(?Send)]
     async fn retry(&mut self, transfer: Transfer, n_retries: usize) -> Result<TxId, PaymentError> {
         if n_retries == 0 {
-            self.add_for_recovery(transfer);
+            self.add_for_recovery(transfer, RecoveryDetails::IcError);
             return Err(PaymentError::Recoverable(RecoveryDetails::IcError));
         }
 
@@ -336,10 +484,38 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
         self.update_recovery_fees();
     }
 
+    /// Requests current fee and minting account from the token canister and updates the terminal
+    /// configuration if they were changed. The callback set by [`TokenTerminal::on_config_update`]
+    /// is run in this case.
+    pub async fn refresh_token_config(&mut self) -> Result<(), PaymentError> {
+        let fee = self.ledger.fee().await?;
+        let minting_account = self.ledger.minting_account().await?.unwrap_or(Account {
+            owner: Principal::management_canister(),
+            subaccount: None,
+        });
+
+        if fee == self.token_config.fee && minting_account == self.token_config.minting_account {
+            return Ok(());
+        }
+
+        self.token_config.fee = fee;
+        self.token_config.minting_account = minting_account;
+        self.update_recovery_fees();
+
+        if let Some(f) = &self.update_token_config {
+            f(self.token_config());
+        }
+
+        Ok(())
+    }
+
     fn update_recovery_fees(&mut self) {
-        for tx in self.recovery_list.take_all() {
-            let fee = self.token_config.get_fee(&tx.from_acc(), &tx.to);
-            self.recovery_list.push(tx.with_fee(fee).reset_ts());
+        for mut entry in self.recovery_list.take_all() {
+            let fee = self
+                .token_config
+                .get_fee(&entry.transfer.from_acc(), &entry.transfer.to);
+            entry.transfer = entry.transfer.with_fee(fee).reset_ts();
+            self.recovery_list.push(entry);
         }
     }
 
@@ -347,8 +523,21 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
         Ok(self.balances.credit(recipient, amount)?)
     }
 
-    fn add_for_recovery(&mut self, transfer: Transfer) {
-        self.recovery_list.push(transfer);
+    fn add_for_recovery(&mut self, transfer: Transfer, error: RecoveryDetails) {
+        // If the transfer fails again while being recovered, it keeps the history of the entry
+        // it was recovered from.
+        let entry = match &self.recovering {
+            Some(recovered) => RecoveryEntry {
+                id: recovered.id,
+                transfer,
+                first_failed_at: recovered.first_failed_at,
+                attempts: recovered.attempts.saturating_add(1),
+                last_error: error,
+            },
+            None => RecoveryEntry::new(transfer, error),
+        };
+
+        self.recovery_list.push(entry);
     }
 
     /// Recover all transfers stored in the recovery list. Exact strategy of recovery depends for
@@ -360,28 +549,73 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
     /// the list. If the recovery was not successful, e.g. if the terminal has still no proof
     /// whether the transfer is successful or not, the transfer is returned to the recovery list.
     pub async fn recover_all(&mut self) -> Vec<Result<(TxId, Transfer), PaymentError>> {
+        self.recover_all_with_status()
+            .await
+            .into_iter()
+            .map(|(entry, status)| match status {
+                RecoveryStatus::Completed(tx_id) => Ok((tx_id, entry.transfer)),
+                RecoveryStatus::Failed(err)
+                | RecoveryStatus::Pending(err)
+                | RecoveryStatus::Unresolvable(err) => Err(err),
+            })
+            .collect()
+    }
+
+    /// Same as [`TokenTerminal::recover_all()`], but returns the [status](RecoveryStatus) of
+    /// each recovered entry, which tells whether the transfer is still in the recovery list.
+    pub async fn recover_all_with_status(&mut self) -> Vec<(RecoveryEntry, RecoveryStatus)> {
         let mut results = vec![];
-        for tx in self.recovery_list.take_all() {
-            if tx.token == self.token_config.principal {
-                results.push(self.recover_tx(tx).await);
-            } else {
+        for entry in self.recovery_list.take_all() {
+            if entry.transfer.token != self.token_config.principal {
                 // Return foreigh transfers to the recovery list
-                self.recovery_list.push(tx);
+                self.recovery_list.push(entry);
+                continue;
             }
+
+            self.recovering = Some(entry.clone());
+            let status = self.recover_tx(entry.transfer.clone()).await;
+            self.recovering = None;
+            results.push((entry, status));
         }
 
         results
     }
 
-    async fn recover_tx(&mut self, transfer: Transfer) -> Result<(TxId, Transfer), PaymentError> {
-        let tx_id = if self.can_deduplicate(&transfer) {
+    async fn recover_tx(&mut self, transfer: Transfer) -> RecoveryStatus {
+        let result = if self.can_deduplicate(&transfer) {
             self.execute_recovery_transfer(transfer.clone(), N_RETRIES)
-                .await?
+                .await
+        } else if !self.can_search_old_tx(&transfer) {
+            return RecoveryStatus::Unresolvable(PaymentError::TransferFailed(
+                TransferFailReason::TooOld,
+            ));
         } else {
-            self.recover_old_tx(transfer.clone()).await?
+            self.recover_old_tx(transfer.clone()).await
         };
 
-        Ok((tx_id, transfer))
+        match result {
+            Ok(tx_id) => RecoveryStatus::Completed(tx_id),
+            // Recoverable errors are returned only after the transfer is added to the recovery
+            // list again.
+            Err(err @ PaymentError::Recoverable(_)) => RecoveryStatus::Pending(err),
+            // The token rejected the transfer because of the fee, but the terminal could not
+            // update its configuration to retry it.
+            Err(PaymentError::BadFee(fee)) => {
+                self.add_for_recovery(transfer, RecoveryDetails::BadFee(fee.clone()));
+                RecoveryStatus::Pending(PaymentError::BadFee(fee))
+            }
+            Err(err) => RecoveryStatus::Failed(err),
+        }
+    }
+
+    /// Returns true if the terminal can find out the result of a transfer older than the
+    /// deduplication period.
+    fn can_search_old_tx(&self, tx: &Transfer) -> bool {
+        match tx.r#type() {
+            TransferType::DoubleStep(..) => true,
+            TransferType::SingleStep if self.ledger_type == LedgerType::IcpLedger => true,
+            _ => self.transaction_log.is_some(),
+        }
     }
 
     async fn execute_transfer(
@@ -389,7 +623,7 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
         transfer: Transfer,
         n_retries: usize,
     ) -> Result<TxId, PaymentError> {
-        match transfer.execute().await {
+        match self.execute(&transfer).await {
             Ok(TokenTransferInfo { token_tx_id, .. }) => {
                 Ok(self.complete(transfer, token_tx_id, n_retries).await?)
             }
@@ -409,7 +643,7 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
         transfer: Transfer,
         n_retries: usize,
     ) -> Result<TxId, PaymentError> {
-        match transfer.execute().await {
+        match self.execute(&transfer).await {
             Ok(TokenTransferInfo { token_tx_id, .. }) => {
                 Ok(self.complete(transfer, token_tx_id, n_retries).await?)
             }
@@ -457,7 +691,7 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
     }
 
     async fn get_minting_account(&self, expected_fee: Nat) -> Result<Account, PaymentError> {
-        match get_icrc1_minting_account(self.token_config.principal).await {
+        match self.ledger.minting_account().await {
             Ok(v) => Ok(v.unwrap_or(Account {
                 owner: Principal::management_canister(),
                 subaccount: None,
@@ -472,9 +706,15 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
 
     async fn recover_old_tx(&mut self, tx: Transfer) -> Result<TxId, PaymentError> {
         let TransferType::DoubleStep(stage, acc) = tx.r#type() else {
-            return Err(PaymentError::TransferFailed(TransferFailReason::TooOld));
+            return self.recover_old_single_step_tx(tx).await;
+        };
+        let interim_balance = match self.get_balance(acc).await {
+            Ok(balance) => balance,
+            Err(_) => {
+                self.add_for_recovery(tx, RecoveryDetails::IcError);
+                return Err(PaymentError::Recoverable(RecoveryDetails::IcError));
+            }
         };
-        let interim_balance = icrc1::get_icrc1_balance(self.token_config.principal, acc).await?;
 
         match stage {
             Stage::First if interim_balance == 0u64 => self.reject(
@@ -491,11 +731,165 @@ impl<T: Balances, R: RecoveryList> TokenTerminal<T, R> {
         }
     }
 
+    async fn recover_old_single_step_tx(&mut self, tx: Transfer) -> Result<TxId, PaymentError> {
+        // The ledger doesn't accept transactions older than deduplication period, so if the
+        // transfer is not found within this period, it was never executed.
+        let search_period = self.deduplication_period + TX_WINDOW;
+        let lookup = match (self.ledger_type, self.transaction_log, tx.r#type()) {
+            (LedgerType::IcpLedger, _, TransferType::SingleStep) => {
+                icp_ledger::find_icp_transfer(
+                    self.token_config.principal,
+                    &tx.from(),
+                    &tx.to(),
+                    &tx.amount_minus_fee(),
+                    tx.created_at(),
+                    tx.memo.as_ref(),
+                    search_period,
+                )
+                .await
+            }
+            (_, Some(log), _) => {
+                let record = TransferRecord {
+                    from: tx.from(),
+                    to: tx.to(),
+                    amount: tx.amount_minus_fee(),
+                    memo: tx.memo.clone(),
+                    created_at_time: Some(tx.created_at()),
+                };
+                icrc3::find_transfer(
+                    self.token_config.principal,
+                    log,
+                    &self.token_config.minting_account,
+                    &record,
+                    search_period,
+                )
+                .await
+            }
+            _ => return Err(PaymentError::TransferFailed(TransferFailReason::TooOld)),
+        };
+
+        match lookup {
+            Ok(Some(block_index)) => self.complete(tx, block_index.into(), N_RETRIES).await,
+            Ok(None) => self.reject(
+                tx,
+                InternalPaymentError::TransferFailed(TransferFailReason::Unknown),
+            ),
+            // The search can be repeated by the next recovery.
+            Err(_) => {
+                self.add_for_recovery(tx, RecoveryDetails::IcError);
+                Err(PaymentError::Recoverable(RecoveryDetails::IcError))
+            }
+        }
+    }
+
+    async fn execute(
+        &self,
+        transfer: &Transfer,
+    ) -> Result<TokenTransferInfo, InternalPaymentError> {
+        match (self.ledger_type, transfer.r#type()) {
+            (LedgerType::IcpLedger, TransferType::SingleStep | TransferType::DoubleStep(..)) => {
+                transfer.execute_icp().await
+            }
+            _ => transfer.execute_with(&self.ledger).await,
+        }
+    }
+
+    async fn get_balance(&self, account: &Account) -> Result<Nat, InternalPaymentError> {
+        match self.ledger_type {
+            LedgerType::Icrc1 => self.ledger.balance_of(*account).await,
+            LedgerType::IcpLedger => {
+                icp_ledger::get_icp_balance(self.token_config.principal, account).await
+            }
+        }
+    }
+
     /// Returns the list of transfers saved currently in the recovery list. These transfers can be
     /// recovered by calling [`TokenTerminal::recover_all()`] method.
     pub fn list_for_recovery(&self) -> Vec<Transfer> {
+        self.recovery_list
+            .list()
+            .into_iter()
+            .map(|entry| entry.transfer)
+            .collect()
+    }
+
+    /// Returns the entries of the recovery list with the history of their failures.
+    pub fn recovery_entries(&self) -> Vec<RecoveryEntry> {
         self.recovery_list.list()
     }
+
+    /// Resolves the entry of the recovery list with the given `id` manually, without calling the
+    /// token canister.
+    ///
+    /// This is intended for the canister administrators, who checked the outcome of the transfer
+    /// by other means. See [`TokenTerminal::resolve_entry`] for details.
+    pub fn force_resolve(
+        &mut self,
+        id: &RecoveryEntryId,
+        resolution: ManualResolution,
+        resolved_by: Principal,
+        log: &mut impl ResolutionLog,
+    ) -> Result<ResolutionRecord, PaymentError> {
+        let entry = self
+            .recovery_list
+            .list()
+            .into_iter()
+            .find(|entry| entry.id == *id)
+            .ok_or(PaymentError::InvalidParameters(
+                ParametersError::UnknownRecoveryEntry,
+            ))?;
+        self.resolve_entry(entry, resolution, resolved_by, log)
+    }
+
+    /// Resolves the given recovery entry manually, e.g. an entry taken from the
+    /// [`ManualAttentionList`](crate::recovery_list::ManualAttentionList).
+    ///
+    /// The entry is removed from the recovery list, and the transfer operation is executed the
+    /// same way as if the transfer was completed or rejected by the token:
+    /// * [`ManualResolution::Succeeded`] credits the amount received by the target account to the
+    ///   caller, if the operation is [`Operation::CreditOnSuccess`].
+    /// * [`ManualResolution::Failed`] returns the transfer amount to the caller, if the operation is
+    ///   [`Operation::CreditOnError`].
+    ///
+    /// The resolution is recorded in the `log`. If the operation fails, the entry is left in the
+    /// recovery list and nothing is recorded.
+    ///
+    /// The first step of a double-step transfer cannot be resolved as succeeded, since the
+    /// tokens are still in the interim account. Such entries are completed by the recovery.
+    pub fn resolve_entry(
+        &mut self,
+        entry: RecoveryEntry,
+        resolution: ManualResolution,
+        resolved_by: Principal,
+        log: &mut impl ResolutionLog,
+    ) -> Result<ResolutionRecord, PaymentError> {
+        let transfer = &entry.transfer;
+        match (resolution, transfer.r#type(), transfer.operation()) {
+            (ManualResolution::Succeeded, TransferType::DoubleStep(Stage::First, _), _) => {
+                return Err(PaymentError::InvalidParameters(
+                    ParametersError::IncompleteDoubleStep,
+                ));
+            }
+            (ManualResolution::Succeeded, _, Operation::CreditOnSuccess) => {
+                self.credit(transfer.caller(), transfer.final_amount()?)?;
+            }
+            (ManualResolution::Failed, _, Operation::CreditOnError) => {
+                self.credit(transfer.caller(), transfer.amount())?;
+            }
+            _ => {}
+        }
+
+        self.recovery_list.remove(&entry.id);
+        let record = ResolutionRecord {
+            entry,
+            resolution,
+            resolved_by,
+            timestamp: ic::time(),
+        };
+        log.push(record.clone());
+
+        Ok(record)
+    }
 }
 
 /// Returns the interim account for deposit transfers. This account belongs to the `this` canister
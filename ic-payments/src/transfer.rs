@@ -1,11 +1,13 @@
 use candid::{CandidType, Deserialize, Nat, Principal};
 use ic_exports::ic_kit::ic;
 use ic_exports::icrc_types::icrc1::account::{Account, Subaccount};
-use ic_exports::icrc_types::icrc1::transfer::Memo;
+use ic_exports::icrc_types::icrc1::transfer::{Memo, TransferArg};
+use ic_exports::icrc_types::icrc2::transfer_from::TransferFromArgs;
 
 use crate::error::{InternalPaymentError, ParametersError};
-use crate::icrc1::{self, TokenTransferInfo};
-use crate::{Timestamp, TokenConfiguration};
+use crate::icrc1::TokenTransferInfo;
+use crate::ledger::{IcLedgerClient, LedgerClient};
+use crate::{icp_ledger, Timestamp, TokenConfiguration};
 
 /// Transfer to be executed.
 #[derive(Debug, CandidType, Deserialize, Clone)]
@@ -66,6 +68,11 @@ pub enum TransferType {
 
     /// Transfer through an interim account, given as the second parameter.
     DoubleStep(Stage, Account),
+
+    /// Direct transfer from the given account to `to` account, using the ICRC-2 allowance the
+    /// account has approved to the `this` canister. The `from` subaccount of the transfer is used
+    /// as the spender subaccount.
+    FromAllowance(Account),
 }
 
 /// Current step of a double-step transfer.
@@ -122,16 +129,42 @@ impl Transfer {
         }
     }
 
+    /// Creates a new single-step transfer from the `from` account, executed with `icrc2_transfer_from`
+    /// call using the allowance `from` has approved to the main account of the `this` canister.
+    pub fn new_from_allowance(
+        token_config: &TokenConfiguration,
+        caller: Principal,
+        from: Account,
+        to: Account,
+        amount: Nat,
+    ) -> Self {
+        Self {
+            token: token_config.principal,
+            caller,
+            from: None,
+            to,
+            amount,
+            fee: token_config.get_fee(&from, &to),
+            operation: Operation::None,
+            r#type: TransferType::FromAllowance(from),
+            created_at: ic::time(),
+            memo: None,
+        }
+    }
+
     /// Sets the operation of the transfer to be the given one.
     pub fn with_operation(self, operation: Operation) -> Self {
         Self { operation, ..self }
     }
 
     /// Makes the transfer double-step.
+    ///
+    /// Transfers from an allowance are always single-step, so they are returned unchanged.
     pub fn double_step(self) -> Self {
         let interim_acc = match self.r#type {
             TransferType::SingleStep => self.generate_interim_acc(),
             TransferType::DoubleStep(_, interim_acc) => interim_acc,
+            TransferType::FromAllowance(_) => return self,
         };
 
         Self {
@@ -153,7 +186,60 @@ impl Transfer {
     /// This method does not consume the transfer since the caller might need to retry executing it
     /// in case of a transient error.
     pub async fn execute(&self) -> Result<TokenTransferInfo, InternalPaymentError> {
-        icrc1::transfer_icrc1(
+        self.execute_with(&IcLedgerClient::new(self.token)).await
+    }
+
+    /// Executes the transfer using the given ledger client.
+    pub async fn execute_with<L: LedgerClient>(
+        &self,
+        ledger: &L,
+    ) -> Result<TokenTransferInfo, InternalPaymentError> {
+        let amount = self.amount_minus_fee();
+        let token_tx_id = match self.r#type {
+            TransferType::FromAllowance(from) => {
+                ledger
+                    .transfer_from(TransferFromArgs {
+                        spender_subaccount: self.from,
+                        from,
+                        to: self.to(),
+                        amount: amount.clone(),
+                        fee: Some(self.fee.clone()),
+                        memo: self.memo.clone(),
+                        created_at_time: Some(self.created_at()),
+                    })
+                    .await?
+            }
+            _ => {
+                ledger
+                    .transfer(TransferArg {
+                        from_subaccount: self.from().subaccount,
+                        to: self.to(),
+                        fee: Some(self.fee.clone()),
+                        created_at_time: Some(self.created_at()),
+                        memo: self.memo.clone(),
+                        amount: amount.clone(),
+                    })
+                    .await?
+            }
+        };
+
+        Ok(TokenTransferInfo {
+            token_tx_id,
+            token_principal: self.token,
+            amount_transferred: amount,
+        })
+    }
+
+    /// Executes the transfer with the legacy `transfer` method of the ICP ledger.
+    ///
+    /// Transfers from an allowance are executed with `icrc2_transfer_from` method, since the
+    /// legacy interface doesn't support allowances.
+    pub async fn execute_icp(&self) -> Result<TokenTransferInfo, InternalPaymentError> {
+        if let TransferType::FromAllowance(_) = self.r#type {
+            return self.execute().await;
+        }
+
+        icp_ledger::transfer_icp(
             self.token,
             self.to(),
             self.amount_minus_fee(),
@@ -176,6 +262,10 @@ impl Transfer {
         hash.update(self.amount.0.to_bytes_le());
         hash.update(self.token.as_slice());
         hash.update(self.created_at.to_le_bytes());
+        if let TransferType::FromAllowance(from) = &self.r#type {
+            hash.update(from.owner.as_slice());
+            hash.update(from.effective_subaccount());
+        }
 
         let hash_result: [u8; 28] = hash.finalize().into();
         let mut subaccount = [0; 32];
@@ -188,6 +278,7 @@ impl Transfer {
     pub(crate) fn from(&self) -> Account {
         match &self.r#type {
             TransferType::SingleStep => self.from_acc(),
+            TransferType::FromAllowance(_) => self.from_acc(),
             TransferType::DoubleStep(Stage::First, _) => self.from_acc(),
             TransferType::DoubleStep(Stage::Second, acc) => *acc,
         }
@@ -195,16 +286,19 @@ impl Transfer {
 
     /// Source account of the transfer.
     pub fn from_acc(&self) -> Account {
-        Account {
-            owner: ic::id(),
-            subaccount: self.from,
+        match &self.r#type {
+            TransferType::FromAllowance(from) => *from,
+            _ => Account {
+                owner: ic::id(),
+                subaccount: self.from,
+            },
         }
     }
 
     /// Target account of the transfer.
     pub fn to(&self) -> Account {
         match &self.r#type {
-            TransferType::SingleStep => self.to,
+            TransferType::SingleStep | TransferType::FromAllowance(_) => self.to,
             TransferType::DoubleStep(Stage::First, acc) => *acc,
             TransferType::DoubleStep(Stage::Second, _) => self.to,
         }
@@ -0,0 +1,207 @@
+use candid::Nat;
+use common::*;
+use ic_canister::{register_raw_virtual_responder, register_virtual_responder};
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use ic_exports::ic_kit::mock_principals::{alice, bob};
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_exports::icrc_types::icrc2::allowance::{Allowance, AllowanceArgs};
+use ic_exports::icrc_types::icrc2::approve::{ApproveArgs, ApproveError};
+use ic_exports::icrc_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+use ic_payments::error::{InternalPaymentError, PaymentError, RecoveryDetails, TransferFailReason};
+use ic_payments::icrc1;
+use ic_payments::recovery_list::{RecoveryList, StableRecoveryList};
+
+pub mod common;
+
+fn setup_transfer_from_success(tx_id: u128) {
+    register_virtual_responder(
+        token_principal(),
+        "icrc2_transfer_from",
+        move |_: (TransferFromArgs,)| Ok::<Nat, TransferFromError>(tx_id.into()),
+    );
+}
+
+#[tokio::test]
+async fn deposit_from_allowance_args() {
+    let mut terminal = init_test();
+    register_virtual_responder(
+        token_principal(),
+        "icrc2_transfer_from",
+        move |(args,): (TransferFromArgs,)| {
+            assert_eq!(args.from, alice().into());
+            assert_eq!(args.to, this_principal().into());
+            assert_eq!(args.amount, 990u64);
+            assert_eq!(args.fee, Some(10u64.into()));
+            assert_eq!(args.spender_subaccount, None);
+            assert!(args.created_at_time.is_some());
+            assert!(args.memo.is_some());
+
+            Ok::<Nat, TransferFromError>(1u64.into())
+        },
+    );
+
+    let (tx_id, amount) = terminal
+        .deposit_from_allowance(alice(), 1000u64.into())
+        .await
+        .unwrap();
+    assert_eq!(tx_id, 1u64);
+    assert_eq!(amount, 990u64);
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+}
+
+#[tokio::test]
+async fn deposit_from_allowance_insufficient_allowance() {
+    let mut terminal = init_test();
+    register_virtual_responder(
+        token_principal(),
+        "icrc2_transfer_from",
+        move |_: (TransferFromArgs,)| {
+            Err::<Nat, TransferFromError>(TransferFromError::InsufficientAllowance {
+                allowance: 500u64.into(),
+            })
+        },
+    );
+
+    let err = terminal
+        .deposit_from_allowance(alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        PaymentError::TransferFailed(TransferFailReason::InsufficientAllowance(500u64.into()))
+    );
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn deposit_from_allowance_recovery() {
+    let mut terminal = init_test();
+    register_raw_virtual_responder(token_principal(), "icrc2_transfer_from", move |_| {
+        Err((RejectionCode::SysTransient, "recoverable".into()))
+    });
+
+    let err = terminal
+        .deposit_from_allowance(alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+    assert_eq!(err, PaymentError::Recoverable(RecoveryDetails::IcError));
+    assert_eq!(StableRecoveryList::<0>.list().len(), 1);
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+
+    // The first request was executed by the token, so the recovery attempt is deduplicated.
+    register_virtual_responder(
+        token_principal(),
+        "icrc2_transfer_from",
+        move |_: (TransferFromArgs,)| {
+            Err::<Nat, TransferFromError>(TransferFromError::Duplicate {
+                duplicate_of: 3u64.into(),
+            })
+        },
+    );
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, 3u64);
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn deposit_from_allowance_bad_fee() {
+    let mut terminal = init_test();
+    register_virtual_responder(
+        token_principal(),
+        "icrc2_transfer_from",
+        move |(args,): (TransferFromArgs,)| {
+            if args.fee == Some(10u64.into()) {
+                Err::<Nat, TransferFromError>(TransferFromError::BadFee {
+                    expected_fee: 20u64.into(),
+                })
+            } else {
+                Ok(1u64.into())
+            }
+        },
+    );
+
+    terminal
+        .deposit_from_allowance(alice(), 1000u64.into())
+        .await
+        .unwrap();
+    assert_eq!(terminal.fee(), 20u64);
+    assert_eq!(TestBalances::balance_of(alice()), 980u64);
+
+    setup_transfer_from_success(2);
+    terminal
+        .deposit_from_allowance(alice(), 1000u64.into())
+        .await
+        .unwrap();
+    assert_eq!(TestBalances::balance_of(alice()), 1960u64);
+}
+
+#[tokio::test]
+async fn approve_and_allowance() {
+    init_context();
+    register_virtual_responder(
+        token_principal(),
+        "icrc2_approve",
+        move |(args,): (ApproveArgs,)| match args.expected_allowance {
+            Some(current_allowance) => {
+                Err::<Nat, ApproveError>(ApproveError::AllowanceChanged { current_allowance })
+            }
+            None => Ok(5u64.into()),
+        },
+    );
+    register_virtual_responder(
+        token_principal(),
+        "icrc2_allowance",
+        move |(args,): (AllowanceArgs,)| {
+            assert_eq!(args.account, this_principal().into());
+            Allowance {
+                allowance: 100u64.into(),
+                expires_at: None,
+            }
+        },
+    );
+
+    let args = ApproveArgs {
+        from_subaccount: None,
+        spender: bob().into(),
+        amount: 100u64.into(),
+        expected_allowance: None,
+        expires_at: None,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    assert_eq!(
+        icrc1::approve_icrc2(token_principal(), args.clone())
+            .await
+            .unwrap(),
+        5u64
+    );
+    assert_eq!(
+        icrc1::approve_icrc2(
+            token_principal(),
+            ApproveArgs {
+                expected_allowance: Some(50u64.into()),
+                ..args
+            }
+        )
+        .await,
+        Err(InternalPaymentError::TransferFailed(
+            TransferFailReason::ApproveRejected(ApproveError::AllowanceChanged {
+                current_allowance: 50u64.into()
+            })
+        ))
+    );
+
+    let allowance = icrc1::get_icrc2_allowance(
+        token_principal(),
+        Account::from(this_principal()),
+        bob().into(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(allowance.allowance, 100u64);
+}
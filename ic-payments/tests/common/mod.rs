@@ -1,13 +1,20 @@
 use std::cell::RefCell;
+use std::rc::Rc;
 
 use candid::{Nat, Principal};
-use ic_canister::register_virtual_responder;
+use ic_canister::{register_raw_virtual_responder, register_virtual_responder};
+use ic_exports::ic_cdk::api::call::RejectionCode;
 use ic_exports::ic_kit::mock_principals::alice;
 use ic_exports::ic_kit::MockContext;
 use ic_exports::icrc_types::icrc1::account::Account;
 use ic_exports::icrc_types::icrc1::transfer::{TransferArg, TransferError};
+use ic_payments::ledger::FakeLedgerClient;
 use ic_payments::recovery_list::StableRecoveryList;
 use ic_payments::{BalanceError, Balances, TokenConfiguration, TokenTerminal, Transfer};
+use ic_task_scheduler::scheduler::TaskScheduler;
+use ic_task_scheduler::task::{InnerScheduledTask, ScheduledTask, Task, TaskOptions};
+
+pub const DAY: u64 = 10u64.pow(9) * 60 * 60 * 24;
 
 pub enum BalanceOperation {
     Credit(Principal, Nat),
@@ -116,6 +123,13 @@ pub fn init_test() -> TokenTerminal<TestBalances, StableRecoveryList<0>> {
     )
 }
 
+/// Fake ledger of the test token, which also serves the virtual calls to the token principal.
+pub fn fake_ledger() -> FakeLedgerClient {
+    let ledger = FakeLedgerClient::new(10u64.into()).with_minting_account(minting_account());
+    ledger.register_virtual_responders(token_principal());
+    ledger
+}
+
 pub fn setup_success(tx_id: u128) {
     register_virtual_responder(
         token_principal(),
@@ -135,3 +149,52 @@ pub fn setup_error() {
         },
     );
 }
+
+pub fn setup_maybe_failure() {
+    register_raw_virtual_responder(token_principal(), "icrc1_transfer", move |_| {
+        Err((RejectionCode::SysTransient, "recoverable".into()))
+    });
+}
+
+/// Scheduler which only records the appended tasks.
+pub struct TestScheduler<T: Task> {
+    pub appended: Rc<RefCell<Vec<ScheduledTask<T>>>>,
+}
+
+impl<T: Task> Default for TestScheduler<T> {
+    fn default() -> Self {
+        Self {
+            appended: Rc::default(),
+        }
+    }
+}
+
+impl<T: Task> Clone for TestScheduler<T> {
+    fn clone(&self) -> Self {
+        Self {
+            appended: self.appended.clone(),
+        }
+    }
+}
+
+impl<T: 'static + Task> TaskScheduler<T> for TestScheduler<T> {
+    fn append_task(&self, task: ScheduledTask<T>) -> u64 {
+        let mut appended = self.appended.borrow_mut();
+        appended.push(task);
+        appended.len() as u64
+    }
+
+    fn append_tasks(&self, tasks: Vec<ScheduledTask<T>>) -> Vec<u64> {
+        tasks.into_iter().map(|t| self.append_task(t)).collect()
+    }
+
+    fn get_task(&self, _task_id: u64) -> Option<InnerScheduledTask<T>> {
+        None
+    }
+
+    fn find_id(&self, _filter: &dyn Fn(T) -> bool) -> Option<u64> {
+        None
+    }
+
+    fn reschedule(&self, _task_id: u64, _options: TaskOptions) {}
+}
@@ -0,0 +1,276 @@
+use candid::Nat;
+use common::*;
+use ic_canister::{register_raw_virtual_responder, register_virtual_responder};
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use ic_exports::ic_kit::mock_principals::alice;
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_exports::ledger::{
+    Block, BlockIndex, GetBlocksArgs, Memo, Operation, QueryBlocksResponse, Timestamp, Tokens,
+    Transaction, TransferArgs, TransferError,
+};
+use ic_payments::error::{PaymentError, RecoveryDetails, TransferFailReason};
+use ic_payments::icp_ledger::{account_identifier, icp_memo};
+use ic_payments::recovery_list::{RecoveryList, StableRecoveryList};
+use ic_payments::{get_deposit_interim_account, Balances, LedgerType, TokenTerminal, Transfer};
+
+pub mod common;
+
+fn init_icp_test() -> TokenTerminal<TestBalances, StableRecoveryList<0>> {
+    init_test().with_ledger_type(LedgerType::IcpLedger)
+}
+
+fn setup_icp_success(block_index: BlockIndex) {
+    register_virtual_responder(token_principal(), "transfer", move |_: (TransferArgs,)| {
+        Ok::<BlockIndex, TransferError>(block_index)
+    });
+}
+
+fn setup_icp_maybe_failure() {
+    register_raw_virtual_responder(token_principal(), "transfer", move |_| {
+        Err((RejectionCode::SysTransient, "recoverable".into()))
+    });
+}
+
+fn setup_blocks(blocks: Vec<Block>) {
+    register_virtual_responder(
+        token_principal(),
+        "query_blocks",
+        move |(args,): (GetBlocksArgs,)| {
+            let start = (args.start as usize).min(blocks.len());
+            let end = (start + args.length as usize).min(blocks.len());
+            QueryBlocksResponse {
+                chain_length: blocks.len() as u64,
+                certificate: None,
+                blocks: blocks[start..end].to_vec(),
+                first_block_index: args.start,
+                archived_blocks: vec![],
+            }
+        },
+    );
+}
+
+fn block(timestamp: u64, transaction: Transaction) -> Block {
+    Block {
+        parent_hash: None,
+        transaction,
+        timestamp: Timestamp {
+            timestamp_nanos: timestamp,
+        },
+    }
+}
+
+fn other_block(timestamp: u64) -> Block {
+    block(
+        timestamp,
+        Transaction {
+            memo: Memo(0),
+            operation: Some(Operation::Mint {
+                to: account_identifier(&alice().into()),
+                amount: Tokens::from_e8s(1),
+            }),
+            created_at_time: Timestamp {
+                timestamp_nanos: timestamp,
+            },
+            icrc1_memo: None,
+        },
+    )
+}
+
+fn transfer_block(transfer: &Transfer) -> Block {
+    block(
+        transfer.created_at() + 1,
+        Transaction {
+            memo: icp_memo(transfer.memo.as_ref()),
+            operation: Some(Operation::Transfer {
+                from: account_identifier(&get_deposit_interim_account(alice())),
+                to: account_identifier(&this_principal().into()),
+                amount: Tokens::from_e8s(990),
+                fee: Tokens::from_e8s(10),
+            }),
+            created_at_time: Timestamp {
+                timestamp_nanos: transfer.created_at(),
+            },
+            icrc1_memo: None,
+        },
+    )
+}
+
+#[tokio::test]
+async fn deposit_args() {
+    let mut terminal = init_icp_test();
+    register_virtual_responder(
+        token_principal(),
+        "transfer",
+        move |(args,): (TransferArgs,)| {
+            assert_eq!(
+                args.to,
+                account_identifier(&Account::from(this_principal()))
+            );
+            assert_eq!(
+                args.from_subaccount.map(|s| s.0),
+                get_deposit_interim_account(alice()).subaccount
+            );
+            assert_eq!(args.amount, Tokens::from_e8s(990));
+            assert_eq!(args.fee, Tokens::from_e8s(10));
+            assert!(args.created_at_time.is_some());
+
+            Ok::<BlockIndex, TransferError>(7)
+        },
+    );
+
+    let (tx_id, amount) = terminal.deposit(alice(), 1000u64.into()).await.unwrap();
+    assert_eq!(tx_id, 7u64);
+    assert_eq!(amount, 990u64);
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+}
+
+#[tokio::test]
+async fn withdraw_with_success() {
+    let mut terminal = init_icp_test();
+    setup_icp_success(1);
+    TestBalances.credit(alice(), 3000u64.into()).unwrap();
+
+    let (tx_id, amount) = terminal.withdraw(alice(), 1000u64.into()).await.unwrap();
+    assert_eq!(tx_id, 1u64);
+    assert_eq!(amount, 980u64);
+    assert_eq!(TestBalances::balance_of(alice()), 2000u64);
+}
+
+#[tokio::test]
+async fn bad_fee_rerequest() {
+    let mut terminal = init_icp_test();
+    register_virtual_responder(
+        token_principal(),
+        "transfer",
+        move |(args,): (TransferArgs,)| {
+            if args.fee == Tokens::from_e8s(10) {
+                Err::<BlockIndex, TransferError>(TransferError::BadFee {
+                    expected_fee: Tokens::from_e8s(20),
+                })
+            } else {
+                Ok(1)
+            }
+        },
+    );
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap();
+    assert_eq!(terminal.fee(), 20u64);
+    assert_eq!(TestBalances::balance_of(alice()), 980u64);
+}
+
+#[tokio::test]
+async fn recent_transfer_recovered_by_deduplication() {
+    let mut terminal = init_icp_test();
+    setup_icp_maybe_failure();
+
+    let err = terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    assert_eq!(err, PaymentError::Recoverable(RecoveryDetails::IcError));
+
+    register_virtual_responder(token_principal(), "transfer", move |_: (TransferArgs,)| {
+        Err::<BlockIndex, TransferError>(TransferError::TxDuplicate { duplicate_of: 5 })
+    });
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, 5u64);
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+}
+
+#[tokio::test]
+async fn old_transfer_recovered_by_query_blocks() {
+    let mut terminal = init_icp_test();
+    setup_icp_maybe_failure();
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    let transfer = StableRecoveryList::<0>.list().pop().unwrap().transfer;
+    init_context().add_time(DAY);
+
+    let created_at = transfer.created_at();
+    setup_blocks(vec![
+        other_block(created_at - 10),
+        other_block(created_at - 5),
+        other_block(created_at),
+        transfer_block(&transfer),
+        other_block(created_at + 10),
+    ]);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, Nat::from(3u64));
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn old_transfer_found_in_block_stamped_before_creation() {
+    let mut terminal = init_icp_test();
+    setup_icp_maybe_failure();
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    let transfer = StableRecoveryList::<0>.list().pop().unwrap().transfer;
+    init_context().add_time(DAY);
+
+    // The ledger clock is behind the canister clock.
+    let minute = 10u64.pow(9) * 60;
+    let created_at = transfer.created_at();
+    let mut early_block = transfer_block(&transfer);
+    early_block.timestamp.timestamp_nanos = created_at - minute;
+    setup_blocks(vec![
+        other_block(created_at - 10 * minute),
+        early_block,
+        other_block(created_at + 10),
+    ]);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, Nat::from(1u64));
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn old_transfer_not_found_in_blocks() {
+    let mut terminal = init_icp_test();
+    setup_icp_maybe_failure();
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    let transfer = StableRecoveryList::<0>.list().pop().unwrap().transfer;
+    init_context().add_time(DAY);
+
+    let created_at = transfer.created_at();
+    setup_blocks(vec![
+        other_block(created_at - 10),
+        other_block(created_at + 10),
+        other_block(created_at + 2 * DAY),
+    ]);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].as_ref().unwrap_err(),
+        &PaymentError::TransferFailed(TransferFailReason::Unknown)
+    );
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn old_transfer_kept_if_blocks_unavailable() {
+    let mut terminal = init_icp_test();
+    setup_icp_maybe_failure();
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    init_context().add_time(DAY);
+    register_raw_virtual_responder(token_principal(), "query_blocks", move |_| {
+        Err((RejectionCode::SysTransient, "unavailable".into()))
+    });
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].as_ref().unwrap_err(),
+        &PaymentError::Recoverable(RecoveryDetails::IcError)
+    );
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 1);
+}
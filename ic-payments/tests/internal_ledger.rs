@@ -0,0 +1,149 @@
+use common::*;
+use ic_exports::ic_kit::mock_principals::{alice, bob};
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_payments::internal_ledger::{AccountBalance, JournalOperation, StableBalances};
+use ic_payments::recovery_list::StableRecoveryList;
+use ic_payments::{get_deposit_interim_account, BalanceError, Balances, TokenTerminal};
+
+pub mod common;
+
+type TestLedger = StableBalances<4, 5>;
+
+fn subaccount(owner: candid::Principal, id: u8) -> Account {
+    Account {
+        owner,
+        subaccount: Some([id; 32]),
+    }
+}
+
+fn balance(total: u64, locked: u64) -> AccountBalance {
+    AccountBalance {
+        total: total.into(),
+        locked: locked.into(),
+    }
+}
+
+#[tokio::test]
+async fn operations_recorded_in_journal() {
+    init_context();
+    let mut balances = TestLedger::default();
+
+    assert_eq!(balances.credit(alice(), 100u64.into()).unwrap(), 100u64);
+    assert_eq!(balances.debit(alice(), 30u64.into()).unwrap(), 70u64);
+    assert_eq!(
+        balances.debit(alice(), 100u64.into()),
+        Err(BalanceError::InsufficientFunds)
+    );
+
+    let journal = balances.journal(&alice().into(), 0, 10);
+    assert_eq!(journal.len(), 2);
+    assert_eq!(journal[0].operation, JournalOperation::Credit);
+    assert_eq!(journal[0].amount, 100u64);
+    assert_eq!(journal[1].operation, JournalOperation::Debit);
+    assert_eq!(journal[1].balance, balance(70, 0));
+
+    let page = balances.journal(&alice().into(), 1, 10);
+    assert_eq!(page, journal[1..]);
+    assert!(balances.journal(&bob().into(), 0, 10).is_empty());
+}
+
+#[tokio::test]
+async fn locked_balance_cannot_be_debited() {
+    init_context();
+    let mut balances = TestLedger::default();
+    let account = alice().into();
+
+    balances.credit_account(&account, 100u64.into()).unwrap();
+    balances.lock(&account, 60u64.into()).unwrap();
+    assert_eq!(balances.balance(&account), balance(100, 60));
+    assert_eq!(balances.balance(&account).available(), 40u64);
+
+    assert_eq!(
+        balances.debit_account(&account, 50u64.into()),
+        Err(BalanceError::InsufficientFunds)
+    );
+    assert_eq!(
+        balances.lock(&account, 50u64.into()),
+        Err(BalanceError::InsufficientFunds)
+    );
+
+    balances.debit_locked(&account, 20u64.into()).unwrap();
+    assert_eq!(balances.balance(&account), balance(80, 40));
+
+    balances.unlock(&account, 40u64.into()).unwrap();
+    balances.debit_account(&account, 80u64.into()).unwrap();
+    assert_eq!(balances.balance(&account), balance(0, 0));
+    assert!(balances.unlock(&account, 1u64.into()).is_err());
+    assert_eq!(balances.journal(&account, 0, 10).len(), 5);
+}
+
+#[tokio::test]
+async fn subaccounts_have_separate_balances() {
+    init_context();
+    let mut balances = TestLedger::default();
+
+    balances.credit(alice(), 100u64.into()).unwrap();
+    balances
+        .credit_account(&subaccount(alice(), 1), 200u64.into())
+        .unwrap();
+
+    assert_eq!(balances.balance(&alice().into()), balance(100, 0));
+    assert_eq!(balances.balance(&subaccount(alice(), 0)), balance(100, 0));
+    assert_eq!(balances.balance(&subaccount(alice(), 1)), balance(200, 0));
+    assert_eq!(balances.accounts().len(), 2);
+    assert_eq!(balances.total_liabilities(), 300u64);
+}
+
+#[tokio::test]
+async fn balances_stored_in_their_memories() {
+    init_context();
+    let mut balances = TestLedger::default();
+    let mut other = StableBalances::<6, 7>;
+
+    balances.credit(alice(), 100u64.into()).unwrap();
+    balances.lock(&alice().into(), 40u64.into()).unwrap();
+    other.credit(alice(), 300u64.into()).unwrap();
+    other.debit(alice(), 50u64.into()).unwrap();
+
+    assert_eq!(balances.balance(&alice().into()), balance(100, 40));
+    assert_eq!(other.balance(&alice().into()), balance(250, 0));
+    assert_eq!(balances.total_liabilities(), 100u64);
+    assert_eq!(balances.total_locked(), 40u64);
+    assert_eq!(other.total_liabilities(), 250u64);
+    assert_eq!(other.total_locked(), 0u64);
+    assert_eq!(other.journal(&alice().into(), 0, 10).len(), 2);
+}
+
+#[tokio::test]
+async fn reconciliation_with_token_balance() {
+    init_test();
+    let ledger = fake_ledger();
+    let mut terminal =
+        TokenTerminal::<_, StableRecoveryList<0>>::new(token_config(), TestLedger::default())
+            .with_ledger_client(ledger.clone());
+    terminal.set_fee(10u64.into());
+
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    terminal.deposit_all(alice()).await.unwrap();
+    assert_eq!(
+        terminal.balances().balance(&alice().into()),
+        balance(990, 0)
+    );
+
+    let reconciliation = terminal.balances().reconcile(&ledger).await.unwrap();
+    assert_eq!(reconciliation.liabilities, 990u64);
+    assert_eq!(reconciliation.token_balance, 990u64);
+    assert!(reconciliation.is_covered());
+
+    ledger.mint(this_principal().into(), 50u64.into());
+    let reconciliation = terminal.balances().reconcile(&ledger).await.unwrap();
+    assert_eq!(reconciliation.surplus(), 50u64);
+    assert_eq!(reconciliation.deficit(), 0u64);
+
+    // Balance credited without receiving the tokens.
+    TestLedger::default().credit(bob(), 100u64.into()).unwrap();
+    let reconciliation = terminal.balances().reconcile(&ledger).await.unwrap();
+    assert_eq!(reconciliation.liabilities, 1090u64);
+    assert!(!reconciliation.is_covered());
+    assert_eq!(reconciliation.deficit(), 50u64);
+}
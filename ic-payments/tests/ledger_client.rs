@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use common::*;
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use ic_exports::ic_kit::mock_principals::alice;
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_payments::error::{PaymentError, RecoveryDetails};
+use ic_payments::ledger::{FakeLedgerClient, LedgerFailure};
+use ic_payments::recovery_list::StableRecoveryList;
+use ic_payments::{get_deposit_interim_account, Balances, TokenTerminal};
+
+pub mod common;
+
+fn init_fake_test() -> (
+    TokenTerminal<TestBalances, StableRecoveryList<0>, FakeLedgerClient>,
+    FakeLedgerClient,
+) {
+    let ledger = fake_ledger();
+    let terminal = init_test().with_ledger_client(ledger.clone());
+    (terminal, ledger)
+}
+
+#[tokio::test]
+async fn deposit_all_and_withdraw() {
+    let (mut terminal, ledger) = init_fake_test();
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+
+    let (_, amount) = terminal.deposit_all(alice()).await.unwrap();
+    assert_eq!(amount, 990u64);
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(ledger.balance(&this_principal().into()), 990u64);
+
+    let (_, amount) = terminal.withdraw(alice(), 500u64.into()).await.unwrap();
+    assert_eq!(amount, 480u64);
+    assert_eq!(TestBalances::balance_of(alice()), 490u64);
+    assert_eq!(ledger.balance(&alice().into()), 480u64);
+    assert_eq!(ledger.balance(&this_principal().into()), 490u64);
+    assert_eq!(ledger.transactions_count(), 3);
+}
+
+#[tokio::test]
+async fn withdraw_to_minting_account_burns_without_fee() {
+    let (mut terminal, ledger) = init_fake_test();
+    ledger.mint(this_principal().into(), 1000u64.into());
+    TestBalances.credit(alice(), 1000u64.into()).unwrap();
+
+    let transfer = ic_payments::Transfer::new(
+        terminal.token_config(),
+        alice(),
+        minting_account(),
+        None,
+        1000u64.into(),
+    );
+    terminal.transfer(transfer, 3).await.unwrap();
+
+    assert_eq!(ledger.balance(&this_principal().into()), 0u64);
+    assert_eq!(ledger.balance(&minting_account()), 0u64);
+}
+
+#[tokio::test]
+async fn lost_response_recovered_by_deduplication() {
+    let (mut terminal, ledger) = init_fake_test();
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    for _ in 0..3 {
+        ledger.inject_failure(LedgerFailure::ExecutedThenReject(
+            RejectionCode::SysTransient,
+            "response lost".into(),
+        ));
+    }
+
+    let err = terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    assert_eq!(err, PaymentError::Recoverable(RecoveryDetails::IcError));
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(ledger.transactions_count(), 1);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, 0u64);
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(ledger.transactions_count(), 1);
+    assert_eq!(ledger.balance(&this_principal().into()), 990u64);
+}
+
+#[tokio::test]
+async fn rejected_call_retried() {
+    let (mut terminal, ledger) = init_fake_test();
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    ledger.inject_failure(LedgerFailure::Reject(
+        RejectionCode::SysTransient,
+        "not executed".into(),
+    ));
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap();
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(ledger.transactions_count(), 1);
+}
+
+#[tokio::test]
+async fn fee_change_detected() {
+    let (terminal, ledger) = init_fake_test();
+    let updated = Arc::new(AtomicUsize::new(0));
+    let counter = updated.clone();
+    let mut terminal = terminal.on_config_update(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    ledger.set_fee(20u64.into());
+
+    let (_, amount) = terminal.deposit(alice(), 1000u64.into()).await.unwrap();
+    assert_eq!(amount, 990u64);
+    assert_eq!(terminal.fee(), 20u64);
+    assert_eq!(TestBalances::balance_of(alice()), 980u64);
+    assert_eq!(updated.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn refresh_token_config() {
+    let (mut terminal, ledger) = init_fake_test();
+    let new_minter = Account {
+        owner: alice(),
+        subaccount: Some([1; 32]),
+    };
+    ledger.set_fee(30u64.into());
+    ledger.set_minting_account(Some(new_minter));
+
+    terminal.refresh_token_config().await.unwrap();
+    assert_eq!(terminal.fee(), 30u64);
+    assert_eq!(terminal.minting_account(), &new_minter);
+}
+
+#[tokio::test]
+async fn deposit_from_allowance() {
+    let (mut terminal, ledger) = init_fake_test();
+    ledger.mint(alice().into(), 2000u64.into());
+    ledger.approve(alice().into(), this_principal().into(), 1000u64.into());
+
+    terminal
+        .deposit_from_allowance(alice(), 1000u64.into())
+        .await
+        .unwrap();
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(ledger.balance(&alice().into()), 1000u64);
+    assert_eq!(
+        ledger.allowance(&alice().into(), &this_principal().into()),
+        0u64
+    );
+
+    let err = terminal
+        .deposit_from_allowance(alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, PaymentError::TransferFailed(_)));
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+}
@@ -0,0 +1,173 @@
+use common::*;
+use ic_exports::ic_kit::ic;
+use ic_exports::ic_kit::mock_principals::{alice, bob};
+use ic_payments::error::{ParametersError, PaymentError, RecoveryDetails};
+use ic_payments::recovery_list::{
+    ManualResolution, RecoveryList, ResolutionLog, StableRecoveryList, StableResolutionLog,
+};
+use ic_payments::{Balances, Operation, Transfer};
+
+pub mod common;
+
+#[tokio::test]
+async fn recovery_entry_tracks_failures() {
+    let mut terminal = init_test();
+    setup_maybe_failure();
+    let failed_at = ic::time();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+
+    let entries = terminal.recovery_entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].attempts, 1);
+    assert_eq!(entries[0].first_failed_at, failed_at);
+    assert_eq!(entries[0].last_error, RecoveryDetails::IcError);
+
+    terminal.recover_all().await;
+    terminal.recover_all().await;
+
+    let recovered = terminal.recovery_entries();
+    assert_eq!(recovered.len(), 1);
+    assert_eq!(recovered[0].id, entries[0].id);
+    assert_eq!(recovered[0].attempts, 3);
+    assert_eq!(recovered[0].first_failed_at, failed_at);
+}
+
+#[tokio::test]
+async fn force_resolve_succeeded_credits_on_success() {
+    let mut terminal = init_test();
+    setup_maybe_failure();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+
+    let id = terminal.recovery_entries()[0].id;
+    let record = terminal
+        .force_resolve(
+            &id,
+            ManualResolution::Succeeded,
+            bob(),
+            &mut StableResolutionLog::<2>,
+        )
+        .unwrap();
+
+    assert_eq!(record.entry.id, id);
+    assert_eq!(record.resolution, ManualResolution::Succeeded);
+    assert_eq!(record.resolved_by, bob());
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert!(StableRecoveryList::<0>.list().is_empty());
+}
+
+#[tokio::test]
+async fn force_resolve_failed_credits_on_error() {
+    let mut terminal = init_test();
+    setup_maybe_failure();
+    let transfer = Transfer {
+        fee: 10u64.into(),
+        operation: Operation::CreditOnError,
+        ..simple_transfer()
+    };
+    terminal.transfer(transfer, 1).await.unwrap_err();
+
+    let id = terminal.recovery_entries()[0].id;
+    terminal
+        .force_resolve(
+            &id,
+            ManualResolution::Failed,
+            bob(),
+            &mut StableResolutionLog::<2>,
+        )
+        .unwrap();
+
+    assert_eq!(TestBalances::balance_of(alice()), 1000u64);
+    assert!(StableRecoveryList::<0>.list().is_empty());
+}
+
+#[tokio::test]
+async fn force_resolve_skips_operation_for_other_outcome() {
+    let mut terminal = init_test();
+    setup_maybe_failure();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+
+    let id = terminal.recovery_entries()[0].id;
+    terminal
+        .force_resolve(
+            &id,
+            ManualResolution::Failed,
+            bob(),
+            &mut StableResolutionLog::<2>,
+        )
+        .unwrap();
+
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert!(StableRecoveryList::<0>.list().is_empty());
+}
+
+#[tokio::test]
+async fn force_resolve_unknown_entry() {
+    let mut terminal = init_test();
+
+    let err = terminal
+        .force_resolve(
+            &[0; 32],
+            ManualResolution::Succeeded,
+            bob(),
+            &mut StableResolutionLog::<2>,
+        )
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        PaymentError::InvalidParameters(ParametersError::UnknownRecoveryEntry)
+    );
+    assert!(StableResolutionLog::<2>.list().is_empty());
+}
+
+#[tokio::test]
+async fn resolutions_are_logged() {
+    let mut terminal = init_test();
+    setup_maybe_failure();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    terminal.deposit(alice(), 2000u64.into()).await.unwrap_err();
+
+    let entries = terminal.recovery_entries();
+    let resolutions = [ManualResolution::Succeeded, ManualResolution::Failed];
+    for (entry, resolution) in entries.iter().zip(resolutions) {
+        terminal
+            .force_resolve(&entry.id, resolution, bob(), &mut StableResolutionLog::<2>)
+            .unwrap();
+    }
+
+    let log = StableResolutionLog::<2>.list();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0].entry.id, entries[0].id);
+    assert_eq!(log[0].resolution, ManualResolution::Succeeded);
+    assert_eq!(log[1].entry.id, entries[1].id);
+    assert_eq!(log[1].resolution, ManualResolution::Failed);
+}
+
+#[tokio::test]
+async fn first_stage_cannot_be_resolved_as_succeeded() {
+    let mut terminal = init_test();
+    setup_maybe_failure();
+    TestBalances.credit(alice(), 1000u64.into()).unwrap();
+    terminal
+        .withdraw(alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+
+    let id = terminal.recovery_entries()[0].id;
+    let err = terminal
+        .force_resolve(
+            &id,
+            ManualResolution::Succeeded,
+            bob(),
+            &mut StableResolutionLog::<2>,
+        )
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        PaymentError::InvalidParameters(ParametersError::IncompleteDoubleStep)
+    );
+    assert_eq!(terminal.recovery_entries()[0].id, id);
+    assert!(StableResolutionLog::<2>.list().is_empty());
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+}
@@ -0,0 +1,230 @@
+use std::rc::Rc;
+
+use candid::{Nat, Principal};
+use common::*;
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use ic_exports::ic_kit::mock_principals::{alice, bob, john};
+use ic_payments::error::{ParametersError, PaymentError, RecoveryDetails};
+use ic_payments::ledger::{FakeLedgerClient, IcLedgerClient, LedgerFailure};
+use ic_payments::payout::{
+    BatchPayouts, PayoutItem, PayoutStatus, PayoutStore, PayoutTask, StablePayoutStore,
+};
+use ic_payments::recovery_list::StableRecoveryList;
+use ic_payments::{Balances, TokenTerminal};
+use ic_task_scheduler::task::Task;
+
+pub mod common;
+
+type TestPayouts =
+    BatchPayouts<TestBalances, StableRecoveryList<0>, IcLedgerClient, StablePayoutStore<3>>;
+
+fn init_payouts(max_parallel: usize) -> (TestPayouts, FakeLedgerClient) {
+    let terminal = init_test();
+    let config = terminal.token_config().clone();
+    let ledger = fake_ledger();
+    ledger.mint(this_principal().into(), 10_000u64.into());
+
+    let payouts = BatchPayouts::new(
+        move || TokenTerminal::new(config.clone(), TestBalances),
+        StablePayoutStore::<3>,
+        max_parallel,
+    );
+
+    (payouts, ledger)
+}
+
+fn payout(recipient: Principal, amount: u64) -> PayoutItem {
+    TestBalances.credit(recipient, amount.into()).unwrap();
+    PayoutItem {
+        recipient,
+        amount: amount.into(),
+    }
+}
+
+#[tokio::test]
+async fn batch_processed_in_chunks() {
+    let (payouts, ledger) = init_payouts(2);
+    let batch = payouts.add_batch(vec![
+        payout(alice(), 100),
+        payout(bob(), 200),
+        payout(john(), 300),
+    ]);
+
+    let summary = payouts.process(batch).await;
+    assert_eq!(summary.total, 3);
+    assert_eq!(summary.completed, 2);
+    assert_eq!(summary.pending, 1);
+    assert!(!summary.is_finished());
+    assert_eq!(payouts.unfinished_batches(), vec![batch]);
+
+    let summary = payouts.process(batch).await;
+    assert_eq!(summary.completed, 3);
+    assert!(summary.is_finished());
+    assert!(payouts.unfinished_batches().is_empty());
+
+    assert_eq!(ledger.balance(&alice().into()), 80u64);
+    assert_eq!(ledger.balance(&bob().into()), 180u64);
+    assert_eq!(ledger.balance(&john().into()), 280u64);
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(TestBalances::balance_of(john()), 0u64);
+}
+
+#[tokio::test]
+async fn per_item_status_reported() {
+    let (payouts, ledger) = init_payouts(1);
+    let batch = payouts.add_batch(vec![
+        payout(alice(), 100),
+        payout(bob(), 15),
+        payout(john(), 300),
+    ]);
+
+    payouts.process(batch).await;
+    payouts.process(batch).await;
+    for _ in 0..3 {
+        ledger.inject_failure(LedgerFailure::Reject(
+            RejectionCode::SysTransient,
+            "unknown".into(),
+        ));
+    }
+    payouts.process(batch).await;
+
+    let entries = payouts.entries(batch);
+    assert_eq!(
+        entries[0].status,
+        PayoutStatus::Completed {
+            tx_id: Nat::from(1u64),
+            amount: 80u64.into(),
+        }
+    );
+    assert_eq!(
+        entries[1].status,
+        PayoutStatus::Failed(PaymentError::InvalidParameters(
+            ParametersError::AmountTooSmall {
+                minimum_required: 21u64.into(),
+                actual: 15u64.into(),
+            }
+        ))
+    );
+    assert_eq!(
+        entries[2].status,
+        PayoutStatus::Recovering(RecoveryDetails::IcError)
+    );
+    assert_eq!(TestBalances::balance_of(bob()), 15u64);
+
+    let summary = payouts.summary(batch);
+    assert_eq!(summary.completed, 1);
+    assert_eq!(summary.failed, 1);
+    assert_eq!(summary.recovering, 1);
+    assert!(summary.is_finished());
+}
+
+#[tokio::test]
+async fn interrupted_payouts_marked_on_resume() {
+    let (payouts, ledger) = init_payouts(10);
+    let batch = payouts.add_batch(vec![payout(alice(), 100), payout(bob(), 200)]);
+
+    // Payout that was in progress when the previous message was interrupted.
+    StablePayoutStore::<3>.set_status(batch, 0, PayoutStatus::InProgress);
+
+    let summary = payouts.process(batch).await;
+    assert_eq!(summary.interrupted, 1);
+    assert_eq!(summary.completed, 1);
+    assert_eq!(payouts.entries(batch)[0].status, PayoutStatus::Interrupted);
+    assert_eq!(ledger.balance(&alice().into()), 0u64);
+    assert_eq!(ledger.balance(&bob().into()), 180u64);
+}
+
+#[tokio::test]
+async fn batches_stored_separately() {
+    let (payouts, _) = init_payouts(10);
+    let first = payouts.add_batch(vec![payout(alice(), 100)]);
+    let second = payouts.add_batch(vec![payout(bob(), 200), payout(john(), 300)]);
+    assert_ne!(first, second);
+    assert_eq!(StablePayoutStore::<3>.batches(), vec![first, second]);
+
+    payouts.process(second).await;
+    assert_eq!(payouts.summary(first).pending, 1);
+    assert_eq!(payouts.summary(second).completed, 2);
+
+    let removed = payouts.remove_batch(first);
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].item.recipient, alice());
+    assert_eq!(StablePayoutStore::<3>.batches(), vec![second]);
+    assert!(payouts.entries(first).is_empty());
+}
+
+#[tokio::test]
+async fn payout_task_runs_until_finished() {
+    let (payouts, ledger) = init_payouts(1);
+    let batch = payouts.add_batch(vec![payout(alice(), 100), payout(bob(), 200)]);
+
+    let scheduler = TestScheduler::<PayoutTask>::default();
+    let runner = Rc::new(payouts.clone());
+    let task = PayoutTask::new(batch);
+    task.execute(runner.clone(), Box::new(scheduler.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        scheduler.appended.borrow().as_slice(),
+        &[PayoutTask::new(batch).schedule()]
+    );
+
+    task.execute(runner, Box::new(scheduler.clone()))
+        .await
+        .unwrap();
+    assert_eq!(scheduler.appended.borrow().len(), 1);
+    assert!(payouts.summary(batch).is_finished());
+    assert_eq!(ledger.balance(&bob().into()), 180u64);
+}
+
+#[tokio::test]
+async fn batch_ids_not_reused() {
+    let (payouts, _) = init_payouts(10);
+    let first = payouts.add_batch(vec![payout(alice(), 100)]);
+    payouts.remove_batch(first);
+
+    let second = payouts.add_batch(vec![payout(bob(), 200)]);
+    assert_ne!(first, second);
+    assert_eq!(StablePayoutStore::<3>.batches(), vec![second]);
+    assert!(payouts.entries(first).is_empty());
+}
+
+#[tokio::test]
+async fn stores_kept_separately() {
+    let (payouts, _) = init_payouts(10);
+    let batch = payouts.add_batch(vec![payout(alice(), 100)]);
+    StablePayoutStore::<3>.set_in_flight(batch, 0, true);
+
+    let mut other = StablePayoutStore::<4>;
+    let other_batch = other.add_batch(vec![payout(bob(), 200), payout(john(), 300)]);
+    assert_eq!(other.entries(other_batch).len(), 2);
+    assert_eq!(StablePayoutStore::<3>.entries(batch).len(), 1);
+    assert!(other.in_flight().is_empty());
+}
+
+#[tokio::test]
+async fn payout_task_delayed_if_no_slots() {
+    let (payouts, ledger) = init_payouts(1);
+    let batch = payouts.add_batch(vec![payout(alice(), 100)]);
+
+    // Payout of another batch executed by another message.
+    StablePayoutStore::<3>.set_in_flight(batch + 1, 0, true);
+
+    let scheduler = TestScheduler::<PayoutTask>::default();
+    let task = PayoutTask::new(batch);
+    task.execute(Rc::new(payouts.clone()), Box::new(scheduler.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        scheduler.appended.borrow().as_slice(),
+        &[PayoutTask::new(batch).schedule_after(10)]
+    );
+    assert_eq!(payouts.summary(batch).pending, 1);
+    assert_eq!(ledger.balance(&alice().into()), 0u64);
+}
+
+#[test]
+#[should_panic(expected = "max_parallel must be positive")]
+fn zero_parallel_payouts_rejected() {
+    init_payouts(0);
+}
@@ -0,0 +1,200 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use candid::Nat;
+use common::*;
+use ic_canister::register_raw_virtual_responder;
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use ic_exports::ic_kit::mock_principals::alice;
+use ic_payments::error::{PaymentError, TransferFailReason};
+use ic_payments::ledger::IcLedgerClient;
+use ic_payments::recovery_list::{
+    ManualResolution, RecoveryEntry, RecoveryList, ResolutionLog, StableManualAttentionList,
+    StableRecoveryList, StableResolutionLog,
+};
+use ic_payments::recovery_task::{RecoveryRunner, RecoveryTask, TerminalRecovery};
+use ic_payments::{Balances, RecoveryStatus, TokenTerminal};
+use ic_task_scheduler::retry::BackoffPolicy;
+use ic_task_scheduler::task::Task;
+use ic_task_scheduler::SchedulerError;
+
+pub mod common;
+
+const PERIOD_SECS: u64 = 600;
+
+type TestRecovery = TerminalRecovery<
+    TestBalances,
+    StableRecoveryList<0>,
+    IcLedgerClient,
+    StableManualAttentionList<1>,
+>;
+
+type Outcomes = Rc<RefCell<Vec<(RecoveryEntry, RecoveryStatus)>>>;
+
+fn init_recovery() -> (
+    TokenTerminal<TestBalances, StableRecoveryList<0>>,
+    TestRecovery,
+    Outcomes,
+) {
+    let terminal = init_test();
+    let config = terminal.token_config().clone();
+    let outcomes = Outcomes::default();
+    let outcomes_clone = outcomes.clone();
+    let recovery = TerminalRecovery::new(
+        move || TokenTerminal::new(config.clone(), TestBalances),
+        StableManualAttentionList::<1>,
+    )
+    .on_outcome(move |entry, status| {
+        outcomes_clone
+            .borrow_mut()
+            .push((entry.clone(), status.clone()))
+    });
+
+    (terminal, recovery, outcomes)
+}
+
+async fn execute_task(
+    recovery: TestRecovery,
+    scheduler: &TestScheduler<RecoveryTask>,
+) -> Result<(), SchedulerError> {
+    let task = RecoveryTask::new(PERIOD_SECS, BackoffPolicy::Fixed { secs: 10 });
+    task.execute(Rc::new(recovery), Box::new(scheduler.clone()))
+        .await
+}
+
+#[tokio::test]
+async fn recovered_transfers_reported_and_task_rescheduled() {
+    let (mut terminal, recovery, outcomes) = init_recovery();
+    setup_maybe_failure();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+
+    setup_success(7);
+    let scheduler = TestScheduler::default();
+    execute_task(recovery.clone(), &scheduler).await.unwrap();
+
+    let outcomes = outcomes.borrow();
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].1, RecoveryStatus::Completed(Nat::from(7u64)));
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert!(StableRecoveryList::<0>.list().is_empty());
+
+    let appended = scheduler.appended.borrow();
+    assert_eq!(appended.len(), 1);
+    assert_eq!(
+        appended[0],
+        RecoveryTask::new(PERIOD_SECS, BackoffPolicy::Fixed { secs: 10 }).schedule(PERIOD_SECS)
+    );
+}
+
+#[tokio::test]
+async fn pending_transfers_fail_the_task() {
+    let (mut terminal, recovery, outcomes) = init_recovery();
+    setup_maybe_failure();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+
+    let scheduler = TestScheduler::default();
+    let err = execute_task(recovery.clone(), &scheduler)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, SchedulerError::TaskExecutionFailed(_)));
+    assert!(matches!(outcomes.borrow()[0].1, RecoveryStatus::Pending(_)));
+    assert_eq!(StableRecoveryList::<0>.list().len(), 1);
+    assert!(scheduler.appended.borrow().is_empty());
+    assert!(recovery.manual_attention_list().is_empty());
+}
+
+#[tokio::test]
+async fn unresolvable_transfers_moved_to_manual_attention() {
+    let (mut terminal, recovery, outcomes) = init_recovery();
+    setup_maybe_failure();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    init_context().add_time(DAY);
+
+    let scheduler = TestScheduler::default();
+    execute_task(recovery.clone(), &scheduler).await.unwrap();
+
+    let expected_error = PaymentError::TransferFailed(TransferFailReason::TooOld);
+    assert_eq!(
+        outcomes.borrow()[0].1,
+        RecoveryStatus::Unresolvable(expected_error.clone())
+    );
+    assert!(StableRecoveryList::<0>.list().is_empty());
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+
+    let manual_attention = recovery.manual_attention_list();
+    assert_eq!(manual_attention.len(), 1);
+    assert_eq!(manual_attention[0].reason, expected_error);
+    assert_eq!(manual_attention[0].entry.transfer.caller(), alice());
+    assert_eq!(scheduler.appended.borrow().len(), 1);
+}
+
+#[tokio::test]
+async fn transport_errors_keep_old_transfers_pending() {
+    let (mut terminal, recovery, outcomes) = init_recovery();
+    setup_maybe_failure();
+    TestBalances.credit(alice(), 1000u64.into()).unwrap();
+    terminal
+        .withdraw(alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+    init_context().add_time(DAY);
+    register_raw_virtual_responder(token_principal(), "icrc1_balance_of", move |_| {
+        Err((RejectionCode::SysTransient, "unavailable".into()))
+    });
+
+    recovery.run().await;
+
+    assert!(matches!(outcomes.borrow()[0].1, RecoveryStatus::Pending(_)));
+    assert_eq!(StableRecoveryList::<0>.list().len(), 1);
+    assert!(recovery.manual_attention_list().is_empty());
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+}
+
+#[tokio::test]
+async fn failed_transfers_not_moved_to_manual_attention() {
+    let (mut terminal, recovery, outcomes) = init_recovery();
+    setup_maybe_failure();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+
+    setup_error();
+    recovery.run().await;
+
+    assert!(matches!(outcomes.borrow()[0].1, RecoveryStatus::Failed(_)));
+    assert!(StableRecoveryList::<0>.list().is_empty());
+    assert!(recovery.manual_attention_list().is_empty());
+}
+
+#[tokio::test]
+async fn manual_attention_entry_resolved() {
+    let (mut terminal, recovery, _) = init_recovery();
+    setup_maybe_failure();
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    init_context().add_time(DAY);
+    recovery.run().await;
+
+    let id = recovery.manual_attention_list()[0].entry.id;
+    let record = recovery
+        .resolve(
+            &id,
+            ManualResolution::Succeeded,
+            this_principal(),
+            &mut StableResolutionLog::<2>,
+        )
+        .unwrap();
+
+    assert_eq!(record.entry.id, id);
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert!(recovery.manual_attention_list().is_empty());
+    assert_eq!(StableResolutionLog::<2>.list().len(), 1);
+
+    let err = recovery
+        .resolve(
+            &id,
+            ManualResolution::Succeeded,
+            this_principal(),
+            &mut StableResolutionLog::<2>,
+        )
+        .unwrap_err();
+    assert!(matches!(err, PaymentError::InvalidParameters(_)));
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+}
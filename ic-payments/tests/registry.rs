@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use candid::{Nat, Principal};
+use common::*;
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use ic_exports::ic_kit::mock_principals::{alice, bob};
+use ic_payments::error::{ParametersError, PaymentError};
+use ic_payments::ledger::{FakeLedgerClient, LedgerFailure};
+use ic_payments::recovery_list::{RecoveryList, StableTokenRecoveryList};
+use ic_payments::registry::TerminalRegistry;
+use ic_payments::{get_deposit_interim_account, RecoveryStatus, TokenConfiguration};
+
+pub mod common;
+
+type TestRegistry = TerminalRegistry<TestBalances, 0, FakeLedgerClient>;
+
+fn other_token() -> Principal {
+    Principal::from_slice(&[4; 29])
+}
+
+async fn init_registry() -> (TestRegistry, FakeLedgerClient, FakeLedgerClient) {
+    init_test();
+    let first = fake_ledger();
+    let second = FakeLedgerClient::new(20u64.into());
+
+    let ledgers = [
+        (token_principal(), first.clone()),
+        (other_token(), second.clone()),
+    ];
+    let registry = TerminalRegistry::new(|_| TestBalances).with_ledger_clients(move |token| {
+        ledgers
+            .iter()
+            .find(|(principal, _)| *principal == token)
+            .map(|(_, ledger)| ledger.clone())
+            .unwrap()
+    });
+    registry.register_token(token_principal()).await.unwrap();
+    registry.register_token(other_token()).await.unwrap();
+
+    (registry, first, second)
+}
+
+#[tokio::test]
+async fn tokens_registered_with_their_configuration() {
+    let (registry, _, _) = init_registry().await;
+
+    assert_eq!(registry.tokens(), vec![token_principal(), other_token()]);
+    let config = registry.token_config(&token_principal()).unwrap();
+    assert_eq!(config.fee, 10u64);
+    assert_eq!(config.minting_account, minting_account());
+    let config = registry.token_config(&other_token()).unwrap();
+    assert_eq!(config.fee, 20u64);
+    assert_eq!(
+        config.minting_account.owner,
+        Principal::management_canister()
+    );
+}
+
+#[tokio::test]
+async fn deposit_and_withdraw_by_token() {
+    let (registry, first, second) = init_registry().await;
+    first.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    second.mint(get_deposit_interim_account(bob()), 1000u64.into());
+
+    let (_, amount) = registry
+        .deposit(&token_principal(), alice(), 1000u64.into())
+        .await
+        .unwrap();
+    assert_eq!(amount, 990u64);
+    let (_, amount) = registry
+        .deposit(&other_token(), bob(), 1000u64.into())
+        .await
+        .unwrap();
+    assert_eq!(amount, 980u64);
+    assert_eq!(first.balance(&this_principal().into()), 990u64);
+    assert_eq!(second.balance(&this_principal().into()), 980u64);
+
+    let (_, amount) = registry
+        .withdraw(&other_token(), bob(), 500u64.into())
+        .await
+        .unwrap();
+    assert_eq!(amount, 460u64);
+    assert_eq!(second.balance(&bob().into()), 460u64);
+    assert_eq!(first.balance(&bob().into()), 0u64);
+    assert_eq!(TestBalances::balance_of(bob()), 480u64);
+}
+
+#[tokio::test]
+async fn concurrent_deposits_for_different_tokens() {
+    let (registry, first, second) = init_registry().await;
+    first.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    second.mint(get_deposit_interim_account(bob()), 1000u64.into());
+
+    let (first_token, second_token) = (token_principal(), other_token());
+    let (first_result, second_result) = futures::join!(
+        registry.deposit(&first_token, alice(), 1000u64.into()),
+        registry.deposit(&second_token, bob(), 1000u64.into()),
+    );
+    assert_eq!(first_result.unwrap().1, 990u64);
+    assert_eq!(second_result.unwrap().1, 980u64);
+}
+
+#[tokio::test]
+async fn unknown_token_rejected() {
+    let (registry, _, _) = init_registry().await;
+    let token = Principal::from_slice(&[5; 29]);
+
+    let err = registry
+        .deposit(&token, alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        PaymentError::InvalidParameters(ParametersError::UnknownToken(token))
+    );
+
+    registry.remove_token(&other_token());
+    assert!(registry.terminal(&other_token()).is_err());
+    assert_eq!(registry.tokens(), vec![token_principal()]);
+}
+
+#[tokio::test]
+async fn configuration_refreshed_for_all_tokens() {
+    let (registry, first, second) = init_registry().await;
+    let updates = Rc::new(RefCell::new(Vec::<TokenConfiguration>::new()));
+    let updates_clone = updates.clone();
+    let registry =
+        registry.on_config_update(move |config| updates_clone.borrow_mut().push(config.clone()));
+
+    first.set_fee(15u64.into());
+    second.set_minting_account(Some(minting_account()));
+    let results = registry.refresh_all().await;
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+    assert_eq!(
+        registry.token_config(&token_principal()).unwrap().fee,
+        15u64
+    );
+    assert_eq!(
+        registry
+            .token_config(&other_token())
+            .unwrap()
+            .minting_account,
+        minting_account()
+    );
+    assert_eq!(updates.borrow().len(), 2);
+
+    registry.refresh_all().await;
+    assert_eq!(updates.borrow().len(), 2);
+}
+
+#[tokio::test]
+async fn bad_fee_saved_into_registry() {
+    let (registry, first, _) = init_registry().await;
+    first.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    first.set_fee(25u64.into());
+
+    registry
+        .deposit(&token_principal(), alice(), 1000u64.into())
+        .await
+        .unwrap();
+    assert_eq!(TestBalances::balance_of(alice()), 975u64);
+    assert_eq!(
+        registry.token_config(&token_principal()).unwrap().fee,
+        25u64
+    );
+}
+
+#[tokio::test]
+async fn recovery_lists_shared_by_tokens() {
+    let (registry, first, second) = init_registry().await;
+    first.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    second.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    for _ in 0..3 {
+        let failure = LedgerFailure::ExecutedThenReject(RejectionCode::SysTransient, "lost".into());
+        first.inject_failure(failure.clone());
+        second.inject_failure(failure);
+    }
+
+    registry
+        .deposit(&token_principal(), alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        StableTokenRecoveryList::<0>::new(token_principal())
+            .list()
+            .len(),
+        1
+    );
+    assert!(StableTokenRecoveryList::<0>::new(other_token())
+        .list()
+        .is_empty());
+
+    registry
+        .deposit(&other_token(), alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        StableTokenRecoveryList::<0>::new(other_token())
+            .list()
+            .len(),
+        1
+    );
+
+    let results = registry.recover_all().await;
+    assert_eq!(results.len(), 2);
+    for (token, statuses) in results {
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].0.transfer.token, token);
+        assert!(matches!(statuses[0].1, RecoveryStatus::Completed(_)));
+    }
+
+    assert_eq!(
+        TestBalances::balance_of(alice()),
+        Nat::from(990u64 + 980u64)
+    );
+    assert!(StableTokenRecoveryList::<0>::new(token_principal())
+        .list()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn token_recovery_lists_stored_in_their_memories() {
+    let (registry, first, _) = init_registry().await;
+    first.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    for _ in 0..3 {
+        first.inject_failure(LedgerFailure::Reject(
+            RejectionCode::SysTransient,
+            "lost".into(),
+        ));
+    }
+
+    registry
+        .deposit(&token_principal(), alice(), 1000u64.into())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        StableTokenRecoveryList::<0>::new(token_principal())
+            .list()
+            .len(),
+        1
+    );
+    assert!(StableTokenRecoveryList::<1>::new(token_principal())
+        .list()
+        .is_empty());
+}
@@ -0,0 +1,169 @@
+use candid::{Nat, Principal};
+use common::*;
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use ic_exports::ic_kit::mock_principals::{alice, bob, john};
+use ic_exports::icrc_types::icrc1::transfer::TransferError;
+use ic_payments::error::{PaymentError, TransferFailReason};
+use ic_payments::ledger::{FakeLedgerClient, LedgerFailure};
+use ic_payments::recovery_list::{RecoveryList, StableRecoveryList};
+use ic_payments::{get_deposit_interim_account, TokenTerminal, Transfer};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+pub mod common;
+
+fn init_simulated_test() -> (
+    TokenTerminal<TestBalances, StableRecoveryList<0>>,
+    FakeLedgerClient,
+) {
+    let terminal = init_test();
+    let ledger = fake_ledger();
+    (terminal, ledger)
+}
+
+fn lost_response() -> LedgerFailure {
+    LedgerFailure::ExecutedThenReject(RejectionCode::SysTransient, "response lost".into())
+}
+
+async fn drain_recovery_list(terminal: &mut TokenTerminal<TestBalances, StableRecoveryList<0>>) {
+    while !terminal.list_for_recovery().is_empty() {
+        terminal.recover_all().await;
+    }
+}
+
+#[tokio::test]
+async fn deposit_and_withdraw() {
+    let (mut terminal, ledger) = init_simulated_test();
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+
+    terminal.deposit_all(alice()).await.unwrap();
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+
+    terminal.withdraw(alice(), 990u64.into()).await.unwrap();
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(ledger.balance(&alice().into()), 970u64);
+    assert_eq!(ledger.balance(&this_principal().into()), 0u64);
+    assert_eq!(ledger.total_supply(), 970u64);
+}
+
+#[tokio::test]
+async fn lost_response_recovered() {
+    let (mut terminal, ledger) = init_simulated_test();
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    for _ in 0..3 {
+        ledger.inject_failure(lost_response());
+    }
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    assert_eq!(StableRecoveryList::<0>.list().len(), 1);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results[0].as_ref().unwrap().0, 0u64);
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(ledger.transactions_count(), 1);
+}
+
+#[tokio::test]
+async fn bad_fee_updates_config() {
+    let (mut terminal, ledger) = init_simulated_test();
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    ledger.set_fee(25u64.into());
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap();
+    assert_eq!(terminal.fee(), 25u64);
+    assert_eq!(TestBalances::balance_of(alice()), 975u64);
+}
+
+#[tokio::test]
+async fn transfer_older_than_dedup_window_rejected() {
+    let (mut terminal, ledger) = init_simulated_test();
+    ledger.mint(this_principal().into(), 1000u64.into());
+
+    let transfer = Transfer::new(
+        terminal.token_config(),
+        alice(),
+        alice().into(),
+        None,
+        1000u64.into(),
+    );
+    init_context().add_time(2 * DAY);
+
+    let err = terminal.transfer(transfer, 3).await.unwrap_err();
+    assert_eq!(
+        err,
+        PaymentError::TransferFailed(TransferFailReason::Rejected(TransferError::TooOld))
+    );
+    assert_eq!(ledger.transactions_count(), 0);
+}
+
+#[tokio::test]
+async fn ledger_error_not_executed() {
+    let (mut terminal, ledger) = init_simulated_test();
+    ledger.mint(get_deposit_interim_account(alice()), 1000u64.into());
+    ledger.inject_failure(LedgerFailure::Error(TransferError::TemporarilyUnavailable));
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(ledger.transactions_count(), 0);
+    assert_eq!(
+        ledger.balance(&get_deposit_interim_account(alice())),
+        1000u64
+    );
+}
+
+/// Runs random deposits and withdrawals with random ledger faults. After all transfers are
+/// recovered, the terminal account must hold exactly the amount credited to the users, and the
+/// users funds must change only by the burned fees.
+#[tokio::test]
+async fn funds_are_conserved() {
+    let (mut terminal, ledger) = init_simulated_test();
+    let users = [alice(), bob(), john()];
+    let mut rng = StdRng::seed_from_u64(42);
+
+    let user_funds = |user: Principal| {
+        ledger.balance(&user.into())
+            + ledger.balance(&get_deposit_interim_account(user))
+            + TestBalances::balance_of(user)
+    };
+
+    for _ in 0..200 {
+        let user = users[rng.gen_range(0..users.len())];
+        let amount: Nat = rng.gen_range(1u64..2000).into();
+
+        for _ in 0..rng.gen_range(0..4) {
+            let failure = match rng.gen_range(0..3) {
+                0 => lost_response(),
+                1 => LedgerFailure::Reject(RejectionCode::SysTransient, "not executed".into()),
+                _ => LedgerFailure::Error(TransferError::TemporarilyUnavailable),
+            };
+            ledger.inject_failure(failure);
+        }
+
+        let funds_before = user_funds(user);
+        let supply_before = ledger.total_supply();
+        let mut minted = Nat::from(0u64);
+
+        if rng.gen_bool(0.5) {
+            ledger.mint(get_deposit_interim_account(user), amount.clone());
+            minted = amount.clone();
+            let _ = terminal.deposit(user, amount).await;
+        } else if TestBalances::balance_of(user) >= amount {
+            let _ = terminal.withdraw(user, amount).await;
+        }
+
+        drain_recovery_list(&mut terminal).await;
+        init_context().add_time(rng.gen_range(0..10u64.pow(9)));
+
+        let internal_total = users.iter().fold(Nat::from(0u64), |acc, user| {
+            acc + TestBalances::balance_of(*user)
+        });
+        assert_eq!(ledger.balance(&this_principal().into()), internal_total);
+        assert_eq!(ledger.total_supply(), ledger.balances_sum());
+
+        let burned_fees = supply_before + minted.clone() - ledger.total_supply();
+        assert_eq!(user_funds(user) + burned_fees, funds_before + minted);
+    }
+
+    // Make sure the faults didn't prevent the transfers from being executed.
+    assert!(ledger.transactions_count() > 100);
+}
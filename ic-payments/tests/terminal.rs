@@ -1,7 +1,8 @@
 use common::*;
 use ic_exports::ic_kit::mock_principals::alice;
 use ic_exports::icrc_types::icrc1::account::Account;
-use ic_payments::recovery_list::{RecoveryList, StableRecoveryList};
+use ic_payments::error::RecoveryDetails;
+use ic_payments::recovery_list::{RecoveryEntry, RecoveryList, StableRecoveryList};
 use ic_payments::{Balances, TokenConfiguration, Transfer};
 
 pub mod common;
@@ -56,11 +57,11 @@ fn update_fees() {
     let mut terminal = init_test();
     let transfer = simple_transfer().with_fee(15u64.into());
 
-    StableRecoveryList::<0>.push(transfer);
+    StableRecoveryList::<0>.push(RecoveryEntry::new(transfer, RecoveryDetails::IcError));
 
     terminal.set_fee(20u64.into());
 
-    assert_eq!(StableRecoveryList::<0>.list()[0].fee, 20u64);
+    assert_eq!(StableRecoveryList::<0>.list()[0].transfer.fee, 20u64);
 }
 
 #[test]
@@ -83,13 +84,16 @@ fn update_minting_account() {
     assert_eq!(transfer.fee, 0u64);
     assert_eq!(transfer.effective_fee(), 0u64);
 
-    StableRecoveryList::<0>.push(transfer);
+    StableRecoveryList::<0>.push(RecoveryEntry::new(transfer, RecoveryDetails::IcError));
 
     terminal.set_minting_account(Account {
         owner: alice(),
         subaccount: Some([12; 32]),
     });
 
-    assert_eq!(StableRecoveryList::<0>.list()[0].fee, 10u64);
-    assert_eq!(StableRecoveryList::<0>.list()[0].effective_fee(), 10u64);
+    assert_eq!(StableRecoveryList::<0>.list()[0].transfer.fee, 10u64);
+    assert_eq!(
+        StableRecoveryList::<0>.list()[0].transfer.effective_fee(),
+        10u64
+    );
 }
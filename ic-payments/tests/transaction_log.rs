@@ -0,0 +1,371 @@
+use candid::{Nat, Principal};
+use common::*;
+use ic_canister::{register_raw_virtual_responder, register_virtual_responder};
+use ic_exports::ic_cdk::api::call::RejectionCode;
+use ic_exports::ic_kit::mock_principals::alice;
+use ic_exports::icrc_types::icrc::generic_value::ICRC3Value;
+use ic_exports::icrc_types::icrc1::account::Account;
+use ic_exports::icrc_types::icrc3::archive::{ArchivedRange, QueryArchiveFn};
+use ic_exports::icrc_types::icrc3::blocks::{BlockWithId, GetBlocksRequest, GetBlocksResult};
+use ic_exports::icrc_types::icrc3::transactions::{
+    self, GetTransactionsRequest, GetTransactionsResponse, Transaction, TransactionRange,
+};
+use ic_payments::error::{PaymentError, RecoveryDetails, TransferFailReason};
+use ic_payments::icrc3::TransactionLog;
+use ic_payments::recovery_list::{RecoveryList, StableRecoveryList};
+use ic_payments::{get_deposit_interim_account, Operation, TokenTerminal, Transfer};
+
+pub mod common;
+
+fn archive_principal() -> Principal {
+    Principal::from_slice(&[5; 29])
+}
+
+/// Makes a deposit which result is unknown to the terminal and moves the time past the
+/// deduplication period.
+async fn make_old_deposit(
+    log: TransactionLog,
+) -> (TokenTerminal<TestBalances, StableRecoveryList<0>>, Transfer) {
+    let mut terminal = init_test().with_transaction_log(log);
+    register_raw_virtual_responder(token_principal(), "icrc1_transfer", move |_| {
+        Err((RejectionCode::SysTransient, "recoverable".into()))
+    });
+
+    terminal.deposit(alice(), 1000u64.into()).await.unwrap_err();
+    let transfer = StableRecoveryList::<0>.list().pop().unwrap().transfer;
+    init_context().add_time(DAY);
+
+    (terminal, transfer)
+}
+
+fn icrc3_account(account: &Account) -> ICRC3Value {
+    let mut parts = vec![ICRC3Value::Blob(account.owner.as_slice().to_vec().into())];
+    if let Some(subaccount) = account.subaccount {
+        parts.push(ICRC3Value::Blob(subaccount.to_vec().into()));
+    }
+    ICRC3Value::Array(parts)
+}
+
+fn icrc3_block(timestamp: u64, transfer: Option<&Transfer>) -> ICRC3Value {
+    let mut block = vec![("ts".to_string(), ICRC3Value::Nat(timestamp.into()))];
+    match transfer {
+        Some(transfer) => {
+            let mut tx = vec![
+                ("op".to_string(), ICRC3Value::Text("xfer".into())),
+                (
+                    "from".to_string(),
+                    icrc3_account(&get_deposit_interim_account(alice())),
+                ),
+                ("to".to_string(), icrc3_account(&this_principal().into())),
+                ("amt".to_string(), ICRC3Value::Nat(990u64.into())),
+                (
+                    "ts".to_string(),
+                    ICRC3Value::Nat(transfer.created_at().into()),
+                ),
+            ];
+            if let Some(memo) = &transfer.memo {
+                tx.push(("memo".to_string(), ICRC3Value::Blob(memo.0.clone())));
+            }
+            block.push(("tx".to_string(), ICRC3Value::Map(tx.into_iter().collect())));
+        }
+        None => {
+            let tx = [
+                ("op".to_string(), ICRC3Value::Text("mint".into())),
+                ("to".to_string(), icrc3_account(&alice().into())),
+                ("amt".to_string(), ICRC3Value::Nat(1u64.into())),
+            ];
+            block.push(("tx".to_string(), ICRC3Value::Map(tx.into_iter().collect())));
+        }
+    }
+
+    ICRC3Value::Map(block.into_iter().collect())
+}
+
+fn setup_icrc3_blocks(blocks: Vec<ICRC3Value>) {
+    register_virtual_responder(
+        token_principal(),
+        "icrc3_get_blocks",
+        move |(args,): (Vec<GetBlocksRequest>,)| {
+            let (start, length) = args[0].as_start_and_length().unwrap();
+            let start = (start as usize).min(blocks.len());
+            let end = (start + length as usize).min(blocks.len());
+            GetBlocksResult {
+                log_length: blocks.len().into(),
+                blocks: (start..end)
+                    .map(|id| BlockWithId {
+                        id: id.into(),
+                        block: blocks[id].clone(),
+                    })
+                    .collect(),
+                archived_blocks: vec![],
+            }
+        },
+    );
+}
+
+fn legacy_transaction(timestamp: u64, transfer: Option<&Transfer>) -> Transaction {
+    Transaction {
+        kind: "transfer".into(),
+        mint: None,
+        burn: None,
+        transfer: transfer.map(|transfer| transactions::Transfer {
+            amount: 990u64.into(),
+            from: get_deposit_interim_account(alice()),
+            to: this_principal().into(),
+            spender: None,
+            memo: transfer.memo.clone(),
+            fee: Some(10u64.into()),
+            created_at_time: Some(transfer.created_at()),
+        }),
+        approve: None,
+        fee_collector: None,
+        timestamp,
+    }
+}
+
+/// Sets up `get_transactions` responses, with first `n_archived` transactions moved to the archive.
+fn setup_transactions(transactions: Vec<Transaction>, n_archived: usize) {
+    let archived = transactions[..n_archived].to_vec();
+    register_virtual_responder(
+        archive_principal(),
+        "get_archived_transactions",
+        move |(args,): (GetTransactionsRequest,)| {
+            let (start, length) = args.as_start_and_length().unwrap();
+            let start = (start as usize).min(archived.len());
+            let end = (start + length as usize).min(archived.len());
+            TransactionRange {
+                transactions: archived[start..end].to_vec(),
+            }
+        },
+    );
+
+    register_virtual_responder(
+        token_principal(),
+        "get_transactions",
+        move |(args,): (GetTransactionsRequest,)| {
+            let (start, length) = args.as_start_and_length().unwrap();
+            let end = (start + length).min(transactions.len() as u64);
+            let archived_end = end.min(n_archived as u64);
+            let first_index = start.max(n_archived as u64).min(end);
+
+            let archived_transactions = if start < archived_end {
+                vec![ArchivedRange {
+                    start: start.into(),
+                    length: (archived_end - start).into(),
+                    callback: QueryArchiveFn::new(archive_principal(), "get_archived_transactions"),
+                }]
+            } else {
+                vec![]
+            };
+
+            GetTransactionsResponse {
+                log_length: transactions.len().into(),
+                first_index: first_index.into(),
+                transactions: transactions[first_index as usize..end as usize].to_vec(),
+                archived_transactions,
+            }
+        },
+    );
+}
+
+#[tokio::test]
+async fn old_transfer_found_in_icrc3_blocks() {
+    let (mut terminal, transfer) = make_old_deposit(TransactionLog::Icrc3Blocks).await;
+    let created_at = transfer.created_at();
+    setup_icrc3_blocks(vec![
+        icrc3_block(created_at - 10, None),
+        icrc3_block(created_at, None),
+        icrc3_block(created_at + 5, Some(&transfer)),
+        icrc3_block(created_at + 10, None),
+    ]);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, Nat::from(2u64));
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn old_transfer_found_in_block_stamped_before_creation() {
+    let (mut terminal, transfer) = make_old_deposit(TransactionLog::Icrc3Blocks).await;
+    // The transfer was created ahead of the ledger clock.
+    let minute = 10u64.pow(9) * 60;
+    let created_at = transfer.created_at();
+    setup_icrc3_blocks(vec![
+        icrc3_block(created_at - 10 * minute, None),
+        icrc3_block(created_at - 2 * minute, None),
+        icrc3_block(created_at - minute, Some(&transfer)),
+        icrc3_block(created_at + 10, None),
+    ]);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, Nat::from(2u64));
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn old_transfer_not_found_in_icrc3_blocks() {
+    let (mut terminal, transfer) = make_old_deposit(TransactionLog::Icrc3Blocks).await;
+    let created_at = transfer.created_at();
+    setup_icrc3_blocks(vec![
+        icrc3_block(created_at - 10, None),
+        icrc3_block(created_at + 5, None),
+        icrc3_block(created_at + 2 * DAY, Some(&transfer)),
+    ]);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].as_ref().unwrap_err(),
+        &PaymentError::TransferFailed(TransferFailReason::Unknown)
+    );
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn old_transfer_kept_if_log_unavailable() {
+    let (mut terminal, transfer) = make_old_deposit(TransactionLog::Icrc3Blocks).await;
+    register_raw_virtual_responder(token_principal(), "icrc3_get_blocks", move |_| {
+        Err((RejectionCode::SysTransient, "unavailable".into()))
+    });
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].as_ref().unwrap_err(),
+        &PaymentError::Recoverable(RecoveryDetails::IcError)
+    );
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+
+    let entries = StableRecoveryList::<0>.list();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].transfer.created_at(), transfer.created_at());
+    assert_eq!(entries[0].attempts, 2);
+}
+
+#[tokio::test]
+async fn old_transfer_found_in_archived_transactions() {
+    let (mut terminal, transfer) = make_old_deposit(TransactionLog::GetTransactions).await;
+    let created_at = transfer.created_at();
+    setup_transactions(
+        vec![
+            legacy_transaction(created_at - 10, None),
+            legacy_transaction(created_at + 5, Some(&transfer)),
+            legacy_transaction(created_at + 10, None),
+            legacy_transaction(created_at + 20, None),
+        ],
+        2,
+    );
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, Nat::from(1u64));
+    assert_eq!(TestBalances::balance_of(alice()), 990u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+/// Makes a burn which result is unknown to the terminal and moves the time past the
+/// deduplication period.
+async fn make_old_burn(
+    log: TransactionLog,
+) -> (TokenTerminal<TestBalances, StableRecoveryList<0>>, Transfer) {
+    let mut terminal = init_test().with_transaction_log(log);
+    register_raw_virtual_responder(token_principal(), "icrc1_transfer", move |_| {
+        Err((RejectionCode::SysTransient, "recoverable".into()))
+    });
+
+    let transfer = Transfer::new(
+        terminal.token_config(),
+        alice(),
+        minting_account(),
+        None,
+        1000u64.into(),
+    )
+    .with_operation(Operation::CreditOnError);
+    terminal.transfer(transfer, 1).await.unwrap_err();
+    let transfer = StableRecoveryList::<0>.list().pop().unwrap().transfer;
+    init_context().add_time(DAY);
+
+    (terminal, transfer)
+}
+
+#[tokio::test]
+async fn old_burn_found_in_icrc3_blocks() {
+    let (mut terminal, transfer) = make_old_burn(TransactionLog::Icrc3Blocks).await;
+    let created_at = transfer.created_at();
+    let mut tx = vec![
+        ("op".to_string(), ICRC3Value::Text("burn".into())),
+        ("from".to_string(), icrc3_account(&this_principal().into())),
+        ("amt".to_string(), ICRC3Value::Nat(1000u64.into())),
+        ("ts".to_string(), ICRC3Value::Nat(created_at.into())),
+    ];
+    if let Some(memo) = &transfer.memo {
+        tx.push(("memo".to_string(), ICRC3Value::Blob(memo.0.clone())));
+    }
+    let burn = ICRC3Value::Map(
+        [
+            ("ts".to_string(), ICRC3Value::Nat((created_at + 5).into())),
+            ("tx".to_string(), ICRC3Value::Map(tx.into_iter().collect())),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    setup_icrc3_blocks(vec![icrc3_block(created_at - 10, None), burn]);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, Nat::from(1u64));
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn old_burn_found_in_transactions() {
+    let (mut terminal, transfer) = make_old_burn(TransactionLog::GetTransactions).await;
+    let created_at = transfer.created_at();
+    let burn = Transaction {
+        kind: "burn".into(),
+        mint: None,
+        burn: Some(transactions::Burn {
+            amount: 1000u64.into(),
+            from: this_principal().into(),
+            spender: None,
+            memo: transfer.memo.clone(),
+            created_at_time: Some(created_at),
+            fee: None,
+        }),
+        transfer: None,
+        approve: None,
+        fee_collector: None,
+        timestamp: created_at + 5,
+    };
+    setup_transactions(vec![legacy_transaction(created_at - 10, None), burn], 0);
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap().0, Nat::from(1u64));
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+    assert_eq!(StableRecoveryList::<0>.list().len(), 0);
+}
+
+#[tokio::test]
+async fn old_transfer_without_transaction_log() {
+    let (terminal, _) = make_old_deposit(TransactionLog::Icrc3Blocks).await;
+    let mut terminal = TokenTerminal::new_with_recovery_list(
+        terminal.token_config().clone(),
+        TestBalances,
+        StableRecoveryList::<0>,
+    );
+
+    let results = terminal.recover_all().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].as_ref().unwrap_err(),
+        &PaymentError::TransferFailed(TransferFailReason::TooOld)
+    );
+    assert_eq!(TestBalances::balance_of(alice()), 0u64);
+}
@@ -0,0 +1,88 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Result};
+
+pub fn derive_bounded(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Builds a value with all the fields set to `MIN` or `MAX`.
+fn bounded_value(path: TokenStream2, fields: &Fields, bound: &TokenStream2) -> TokenStream2 {
+    let values = fields.iter().map(|field| {
+        let ty = &field.ty;
+        quote! { <#ty as ::ic_stable_structures::Bounded>::#bound }
+    });
+
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote! { #path { #(#names: #values),* } }
+        }
+        Fields::Unnamed(_) => quote! { #path ( #(#values),* ) },
+        Fields::Unit => quote! { #path },
+    }
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let min = quote! { MIN };
+    let max = quote! { MAX };
+
+    // Bounds are lexicographic, the same as for the derived `Ord`:
+    // structs compare field by field, enums compare by variant first.
+    let (min_value, max_value) = match &input.data {
+        Data::Struct(data) => (
+            bounded_value(quote! { Self }, &data.fields, &min),
+            bounded_value(quote! { Self }, &data.fields, &max),
+        ),
+        Data::Enum(data) => {
+            let (Some(first), Some(last)) = (data.variants.first(), data.variants.last()) else {
+                return Err(Error::new(
+                    name.span(),
+                    "Bounded cannot be derived for an enum without variants",
+                ));
+            };
+            let first_ident = &first.ident;
+            let last_ident = &last.ident;
+
+            (
+                bounded_value(quote! { Self::#first_ident }, &first.fields, &min),
+                bounded_value(quote! { Self::#last_ident }, &last.fields, &max),
+            )
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                name.span(),
+                "Bounded cannot be derived for unions",
+            ))
+        }
+    };
+
+    let mut generics = input.generics.clone();
+    let type_params: Vec<_> = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    if !type_params.is_empty() {
+        let where_clause = generics.make_where_clause();
+        for param in type_params {
+            where_clause
+                .predicates
+                .push(parse_quote! { #param: ::ic_stable_structures::Bounded });
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl #impl_generics ::ic_stable_structures::Bounded for #name #ty_generics #where_clause {
+            const MIN: Self = #min_value;
+            const MAX: Self = #max_value;
+        }
+    })
+}
@@ -0,0 +1,55 @@
+use proc_macro::TokenStream;
+
+mod bounded;
+mod storable;
+
+/// Derives `Storable` for a struct or an enum.
+///
+/// The encoding is selected with the `#[storable(encoding = "...")]` attribute:
+///
+/// * `fixed` - fields are concatenated using fixed-size little-endian encoding.
+///   All the fields must implement `FixedSizeCodec`. The `Bound` is computed automatically
+///   and is always fixed-size, so the type can be used as a `StableVec` element.
+/// * `ordered` - order-preserving encoding: the byte representations of two values compare
+///   the same way as the values themselves (field by field). All the fields must implement
+///   `OrderedCodec`. Use it for `StableBTreeMap` keys.
+/// * `varint` - compact encoding with LEB128 variable-length integers.
+///   All the fields must implement `VarintCodec`.
+/// * `candid` - the whole value is encoded with candid.
+/// * `bincode` - the whole value is encoded with bincode. Requires the `bincode` feature
+///   of the `ic-stable-structures` crate.
+///
+/// For the `candid` and `bincode` encodings the value is `Bound::Unbounded` unless
+/// `max_size = N` is specified in the attribute.
+///
+/// ```ignore
+/// #[derive(Storable)]
+/// #[storable(encoding = "ordered")]
+/// struct BalanceKey {
+///     owner: Principal,
+///     nonce: u64,
+/// }
+/// ```
+#[proc_macro_derive(Storable, attributes(storable))]
+pub fn derive_storable(input: TokenStream) -> TokenStream {
+    storable::derive_storable(input)
+}
+
+/// Derives `Bounded` for a struct or an enum.
+///
+/// The bounds are lexicographic, the same way as the derived `Ord` compares values:
+/// for a struct `MIN` and `MAX` have all the fields set to their `MIN` and `MAX` values,
+/// for an enum `MIN` is the first variant with the minimal fields and `MAX` is the last
+/// variant with the maximal fields. All the fields must implement `Bounded`.
+///
+/// ```ignore
+/// #[derive(Bounded)]
+/// struct TokenKey {
+///     token: Principal,
+///     nonce: u64,
+/// }
+/// ```
+#[proc_macro_derive(Bounded)]
+pub fn derive_bounded(input: TokenStream) -> TokenStream {
+    bounded::derive_bounded(input)
+}
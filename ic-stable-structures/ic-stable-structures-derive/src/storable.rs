@@ -0,0 +1,454 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Ident, LitInt, LitStr,
+    Result, Type,
+};
+
+pub fn derive_storable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Fixed,
+    Ordered,
+    Varint,
+    Candid,
+    Bincode,
+}
+
+struct Options {
+    encoding: Encoding,
+    max_size: Option<LitInt>,
+}
+
+fn parse_options(input: &DeriveInput) -> Result<Options> {
+    let mut encoding = None;
+    let mut max_size = None;
+
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("storable")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("encoding") {
+                let value: LitStr = meta.value()?.parse()?;
+                encoding = Some(match value.value().as_str() {
+                    "fixed" => Encoding::Fixed,
+                    "ordered" => Encoding::Ordered,
+                    "varint" => Encoding::Varint,
+                    "candid" => Encoding::Candid,
+                    "bincode" => Encoding::Bincode,
+                    other => {
+                        return Err(Error::new(
+                            value.span(),
+                            format!(
+                                "unknown encoding `{other}`, expected one of: fixed, ordered, varint, candid, bincode"
+                            ),
+                        ))
+                    }
+                });
+                Ok(())
+            } else if meta.path.is_ident("max_size") {
+                max_size = Some(meta.value()?.parse::<LitInt>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported storable attribute"))
+            }
+        })?;
+    }
+
+    let encoding = encoding.ok_or_else(|| {
+        Error::new(
+            input.ident.span(),
+            "missing `#[storable(encoding = \"...\")]` attribute",
+        )
+    })?;
+
+    if let Some(max_size) = &max_size {
+        if !matches!(encoding, Encoding::Candid | Encoding::Bincode) {
+            return Err(Error::new(
+                max_size.span(),
+                "`max_size` is only supported for candid and bincode encodings, other encodings compute the bound automatically",
+            ));
+        }
+    }
+
+    Ok(Options { encoding, max_size })
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let options = parse_options(&input)?;
+
+    match options.encoding {
+        Encoding::Candid => Ok(expand_serde(
+            &input,
+            quote! { encode_candid },
+            quote! { decode_candid },
+            options.max_size,
+        )),
+        Encoding::Bincode => Ok(expand_serde(
+            &input,
+            quote! { encode_bincode },
+            quote! { decode_bincode },
+            options.max_size,
+        )),
+        Encoding::Fixed | Encoding::Ordered | Encoding::Varint => {
+            expand_codec(&input, Codec::new(options.encoding))
+        }
+    }
+}
+
+/// Implements `Storable` by serializing the whole value with one of the serde-based formats.
+fn expand_serde(
+    input: &DeriveInput,
+    encode: TokenStream2,
+    decode: TokenStream2,
+    max_size: Option<LitInt>,
+) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let bound = match max_size {
+        Some(max_size) => quote! {
+            ::ic_stable_structures::Bound::Bounded {
+                max_size: #max_size,
+                is_fixed_size: false,
+            }
+        },
+        None => quote! { ::ic_stable_structures::Bound::Unbounded },
+    };
+
+    quote! {
+        #[automatically_derived]
+        impl #impl_generics ::ic_stable_structures::Storable for #name #ty_generics #where_clause {
+            fn to_bytes(&self) -> ::std::borrow::Cow<'_, [u8]> {
+                ::std::borrow::Cow::Owned(::ic_stable_structures::codec::#encode(self))
+            }
+
+            fn from_bytes(bytes: ::std::borrow::Cow<'_, [u8]>) -> Self {
+                ::ic_stable_structures::codec::#decode(&bytes)
+            }
+
+            const BOUND: ::ic_stable_structures::Bound = #bound;
+        }
+    }
+}
+
+/// Names of the codec trait items used by a field-by-field encoding.
+struct Codec {
+    encoding: Encoding,
+    trait_name: Ident,
+    encode: Ident,
+    decode: Ident,
+}
+
+impl Codec {
+    fn new(encoding: Encoding) -> Self {
+        let (trait_name, suffix) = match encoding {
+            Encoding::Fixed => ("FixedSizeCodec", "fixed"),
+            Encoding::Ordered => ("OrderedCodec", "ordered"),
+            Encoding::Varint => ("VarintCodec", "varint"),
+            Encoding::Candid | Encoding::Bincode => {
+                unreachable!("serde-based encodings are not field codecs")
+            }
+        };
+
+        Self {
+            encoding,
+            trait_name: format_ident!("{trait_name}"),
+            encode: format_ident!("encode_{suffix}"),
+            decode: format_ident!("decode_{suffix}"),
+        }
+    }
+
+    fn trait_path(&self) -> TokenStream2 {
+        let trait_name = &self.trait_name;
+        quote! { ::ic_stable_structures::codec::#trait_name }
+    }
+
+    /// Size of the encoded value of the given type.
+    /// It is `usize` for the fixed encoding and `CodecBound` for the others.
+    fn size_of(&self, ty: &Type) -> TokenStream2 {
+        let trait_path = self.trait_path();
+        match self.encoding {
+            Encoding::Fixed => quote! { <#ty as #trait_path>::FIXED_SIZE },
+            Encoding::Ordered => quote! { <#ty as #trait_path>::ORDERED_BOUND },
+            _ => quote! { <#ty as #trait_path>::VARINT_BOUND },
+        }
+    }
+
+    /// Size of a sequence of values with the given types.
+    fn size_of_all(&self, types: &[&Type]) -> TokenStream2 {
+        let sizes = types.iter().map(|ty| self.size_of(ty));
+        match self.encoding {
+            Encoding::Fixed => quote! { 0 #(+ #sizes)* },
+            _ => quote! {
+                ::ic_stable_structures::codec::CodecBound::fixed(0) #(.then(#sizes))*
+            },
+        }
+    }
+}
+
+/// Pattern and constructor helpers for a set of fields.
+struct FieldsInfo<'a> {
+    bindings: Vec<Ident>,
+    types: Vec<&'a Type>,
+    fields: &'a Fields,
+}
+
+impl<'a> FieldsInfo<'a> {
+    fn new(fields: &'a Fields) -> Self {
+        let bindings = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match &field.ident {
+                Some(ident) => format_ident!("__{ident}"),
+                None => format_ident!("__field{i}"),
+            })
+            .collect();
+        let types = fields.iter().map(|field| &field.ty).collect();
+
+        Self {
+            bindings,
+            types,
+            fields,
+        }
+    }
+
+    fn pattern(&self, path: &TokenStream2) -> TokenStream2 {
+        let bindings = &self.bindings;
+        match self.fields {
+            Fields::Named(named) => {
+                let names = named.named.iter().map(|field| &field.ident);
+                quote! { #path { #(#names: #bindings),* } }
+            }
+            Fields::Unnamed(_) => quote! { #path ( #(#bindings),* ) },
+            Fields::Unit => quote! { #path },
+        }
+    }
+
+    fn constructor(&self, path: &TokenStream2, values: Vec<TokenStream2>) -> TokenStream2 {
+        match self.fields {
+            Fields::Named(named) => {
+                let names = named.named.iter().map(|field| &field.ident);
+                quote! { #path { #(#names: #values),* } }
+            }
+            Fields::Unnamed(_) => quote! { #path ( #(#values),* ) },
+            Fields::Unit => quote! { #path },
+        }
+    }
+}
+
+/// Implements the codec trait for the type field by field and `Storable` on top of it.
+fn expand_codec(input: &DeriveInput, codec: Codec) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let trait_path = codec.trait_path();
+    let encode = &codec.encode;
+    let decode = &codec.decode;
+
+    let mut generics = input.generics.clone();
+    let mut field_types: Vec<&Type> = vec![];
+
+    let (size, encode_body, decode_body) = match &input.data {
+        Data::Struct(data) => {
+            let fields = FieldsInfo::new(&data.fields);
+            field_types.extend(fields.types.iter().copied());
+
+            let size = codec.size_of_all(&fields.types);
+            let pattern = fields.pattern(&quote! { Self });
+            let bindings = &fields.bindings;
+            let encode_body = quote! {
+                let #pattern = self;
+                #( #trait_path::#encode(#bindings, buf); )*
+            };
+            let values = fields
+                .types
+                .iter()
+                .map(|ty| quote! { <#ty as #trait_path>::#decode(bytes) })
+                .collect();
+            let decode_body = fields.constructor(&quote! { Self }, values);
+
+            (size, encode_body, decode_body)
+        }
+        Data::Enum(data) => {
+            if data.variants.is_empty() {
+                return Err(Error::new(
+                    name.span(),
+                    "Storable cannot be derived for an enum without variants",
+                ));
+            }
+            if data.variants.len() > u8::MAX as usize + 1 {
+                return Err(Error::new(
+                    name.span(),
+                    "Storable can be derived only for enums with at most 256 variants",
+                ));
+            }
+
+            let mut variant_sizes = vec![];
+            let mut encode_arms = vec![];
+            let mut decode_arms = vec![];
+            for (tag, variant) in data.variants.iter().enumerate() {
+                let tag = tag as u8;
+                let ident = &variant.ident;
+                let path = quote! { Self::#ident };
+                let fields = FieldsInfo::new(&variant.fields);
+                field_types.extend(fields.types.iter().copied());
+
+                variant_sizes.push(codec.size_of_all(&fields.types));
+
+                let pattern = fields.pattern(&path);
+                let bindings = &fields.bindings;
+                encode_arms.push(quote! {
+                    #pattern => {
+                        <u8 as #trait_path>::#encode(&#tag, buf);
+                        #( #trait_path::#encode(#bindings, buf); )*
+                    }
+                });
+
+                let values = fields
+                    .types
+                    .iter()
+                    .map(|ty| quote! { <#ty as #trait_path>::#decode(bytes) })
+                    .collect();
+                let constructor = fields.constructor(&path, values);
+                decode_arms.push(quote! { #tag => #constructor, });
+            }
+
+            let size = match codec.encoding {
+                Encoding::Fixed => quote! {
+                    1 + ::ic_stable_structures::codec::max_size_of(&[#(#variant_sizes),*])
+                },
+                _ => {
+                    let first = &variant_sizes[0];
+                    let rest = &variant_sizes[1..];
+                    quote! {
+                        ::ic_stable_structures::codec::CodecBound::fixed(1)
+                            .then(#first #(.or(#rest))*)
+                    }
+                }
+            };
+
+            let encode_body = quote! {
+                match self {
+                    #(#encode_arms)*
+                }
+            };
+            let decode_body = quote! {
+                match <u8 as #trait_path>::#decode(bytes) {
+                    #(#decode_arms)*
+                    tag => panic!("invalid variant tag {} for {}", tag, stringify!(#name)),
+                }
+            };
+
+            (size, encode_body, decode_body)
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                name.span(),
+                "Storable cannot be derived for unions",
+            ))
+        }
+    };
+
+    if generics.type_params().next().is_some() {
+        let where_clause = generics.make_where_clause();
+        for ty in field_types {
+            where_clause
+                .predicates
+                .push(parse_quote! { #ty: #trait_path });
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let codec_impl = match codec.encoding {
+        // Fixed-size values are padded up to the size of the largest enum variant,
+        // so the decoder always consumes exactly `FIXED_SIZE` bytes.
+        Encoding::Fixed => quote! {
+            #[automatically_derived]
+            impl #impl_generics #trait_path for #name #ty_generics #where_clause {
+                const FIXED_SIZE: usize = #size;
+
+                #[allow(unused_variables, unused_mut)]
+                fn encode_fixed(&self, buf: &mut ::std::vec::Vec<u8>) {
+                    let start = buf.len();
+                    #encode_body
+                    buf.resize(start + <Self as #trait_path>::FIXED_SIZE, 0);
+                }
+
+                #[allow(unused_variables, unused_mut)]
+                fn decode_fixed(bytes: &mut &[u8]) -> Self {
+                    let (mut value_bytes, rest) = bytes.split_at(<Self as #trait_path>::FIXED_SIZE);
+                    *bytes = rest;
+                    let bytes = &mut value_bytes;
+                    #decode_body
+                }
+            }
+        },
+        Encoding::Ordered => quote! {
+            #[automatically_derived]
+            impl #impl_generics #trait_path for #name #ty_generics #where_clause {
+                const ORDERED_BOUND: ::ic_stable_structures::codec::CodecBound = #size;
+
+                #[allow(unused_variables, unused_mut)]
+                fn encode_ordered(&self, buf: &mut ::std::vec::Vec<u8>) {
+                    #encode_body
+                }
+
+                #[allow(unused_variables, unused_mut)]
+                fn decode_ordered(bytes: &mut &[u8]) -> Self {
+                    #decode_body
+                }
+            }
+        },
+        _ => quote! {
+            #[automatically_derived]
+            impl #impl_generics #trait_path for #name #ty_generics #where_clause {
+                const VARINT_BOUND: ::ic_stable_structures::codec::CodecBound = #size;
+
+                #[allow(unused_variables, unused_mut)]
+                fn encode_varint(&self, buf: &mut ::std::vec::Vec<u8>) {
+                    #encode_body
+                }
+
+                #[allow(unused_variables, unused_mut)]
+                fn decode_varint(bytes: &mut &[u8]) -> Self {
+                    #decode_body
+                }
+            }
+        },
+    };
+
+    let bound = match codec.encoding {
+        Encoding::Fixed => quote! {
+            ::ic_stable_structures::codec::CodecBound::fixed(
+                <Self as #trait_path>::FIXED_SIZE
+            ).to_bound()
+        },
+        Encoding::Ordered => quote! { <Self as #trait_path>::ORDERED_BOUND.to_bound() },
+        _ => quote! { <Self as #trait_path>::VARINT_BOUND.to_bound() },
+    };
+
+    Ok(quote! {
+        #codec_impl
+
+        #[automatically_derived]
+        impl #impl_generics ::ic_stable_structures::Storable for #name #ty_generics #where_clause {
+            fn to_bytes(&self) -> ::std::borrow::Cow<'_, [u8]> {
+                let mut buf = ::std::vec::Vec::new();
+                <Self as #trait_path>::#encode(self, &mut buf);
+                ::std::borrow::Cow::Owned(buf)
+            }
+
+            fn from_bytes(bytes: ::std::borrow::Cow<'_, [u8]>) -> Self {
+                let mut bytes: &[u8] = &bytes;
+                <Self as #trait_path>::#decode(&mut bytes)
+            }
+
+            const BOUND: ::ic_stable_structures::Bound = #bound;
+        }
+    })
+}
@@ -0,0 +1,156 @@
+//! Command line tool to inspect the stable structures offline.
+//!
+//! ```text
+//! stable-structures-inspector --dir ./memories list
+//! stable-structures-inspector --image stable_memory.bin dump --structure btreemap \
+//!     --memory-id 0 --key-size 8 --key-decoder u64 --value-decoder candid
+//! stable-structures-inspector --dir ./memories export --structure log \
+//!     --memory-id 1 --data-memory-id 2 --output log.json
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use ic_stable_structures::inspector::{
+    entries_to_json, Decoder, InspectedMemory, InspectorResult, InspectorSource, RawStructure,
+};
+use serde_json::Value;
+
+#[derive(Parser)]
+#[command(about = "Inspect stable structures offline", version)]
+#[command(group(ArgGroup::new("source").required(true).args(["dir", "image"])))]
+struct Cli {
+    /// Memory-mapped files directory, one file per memory id.
+    #[arg(long)]
+    dir: Option<PathBuf>,
+    /// The directory files were created in the durable mode.
+    #[arg(long, requires = "dir")]
+    durable: bool,
+    /// IC stable memory image managed by the memory manager.
+    #[arg(long)]
+    image: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the memory ids and their sizes.
+    List,
+    /// Print the entries of a structure.
+    Dump(StructureArgs),
+    /// Print the number of entries of a structure.
+    Count(StructureArgs),
+    /// Write the entries of a structure to a JSON file.
+    Export {
+        #[command(flatten)]
+        structure: StructureArgs,
+        /// Output JSON file.
+        #[arg(long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct StructureArgs {
+    #[arg(long, value_enum)]
+    structure: StructureKind,
+    /// Memory of the structure, the index memory for a log.
+    #[arg(long)]
+    memory_id: u8,
+    /// Data memory of a log.
+    #[arg(long, required_if_eq("structure", "log"))]
+    data_memory_id: Option<u8>,
+    /// Size of the fixed-size btreemap keys, e.g. 8 for `u64`. Omit for the other keys.
+    #[arg(long)]
+    key_size: Option<u32>,
+    /// Key decoder: hex, utf8, u64, candid or bincode:<u64|i64|u128|i128|bool|string|bytes>.
+    #[arg(long, default_value = "hex")]
+    key_decoder: Decoder,
+    /// Value decoder, same as the key decoder.
+    #[arg(long, default_value = "hex")]
+    value_decoder: Decoder,
+    /// Maximum number of entries to read.
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StructureKind {
+    Btreemap,
+    Log,
+    Vec,
+    Cell,
+}
+
+impl StructureArgs {
+    fn open(&self, source: &InspectorSource) -> InspectorResult<RawStructure<InspectedMemory>> {
+        let memory = source.memory(self.memory_id)?;
+        Ok(match self.structure {
+            StructureKind::Btreemap => RawStructure::BTreeMap {
+                memory,
+                fixed_key_size: self.key_size,
+            },
+            StructureKind::Log => RawStructure::Log {
+                index_memory: memory,
+                data_memory: source.memory(
+                    self.data_memory_id
+                        .expect("data memory id is required for a log"),
+                )?,
+            },
+            StructureKind::Vec => RawStructure::Vec { memory },
+            StructureKind::Cell => RawStructure::Cell { memory },
+        })
+    }
+
+    fn read_json(&self, source: &InspectorSource) -> InspectorResult<Value> {
+        let entries = self.open(source)?.entries(self.limit)?;
+        entries_to_json(&entries, self.key_decoder, self.value_decoder)
+    }
+}
+
+fn run(cli: Cli) -> InspectorResult<()> {
+    let source = match (cli.dir, cli.image) {
+        (Some(dir), _) => InspectorSource::open_directory(dir, cli.durable)?,
+        (None, Some(image)) => InspectorSource::open_image(image)?,
+        (None, None) => unreachable!("clap requires one of the sources"),
+    };
+
+    let mut stdout = io::stdout().lock();
+    match cli.command {
+        Command::List => {
+            for info in source.memories()? {
+                writeln!(stdout, "{}\t{} pages", info.id, info.size_in_pages)?;
+            }
+        }
+        Command::Dump(args) => {
+            for entry in args.read_json(&source)?.as_array().into_iter().flatten() {
+                writeln!(stdout, "{entry}")?;
+            }
+        }
+        Command::Count(args) => {
+            writeln!(stdout, "{}", args.open(&source)?.len()?)?;
+        }
+        Command::Export { structure, output } => {
+            let json = structure.read_json(&source)?;
+            let mut file = BufWriter::new(File::create(output)?);
+            serde_json::to_writer_pretty(&mut file, &json)?;
+            file.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use candid::Principal;
+
+use super::take_bytes;
+
+/// Fixed-size little-endian encoding.
+///
+/// Every value of the type is encoded into exactly `FIXED_SIZE` bytes.
+pub trait FixedSizeCodec: Sized {
+    /// Size of the encoded value in bytes.
+    const FIXED_SIZE: usize;
+
+    /// Appends exactly `FIXED_SIZE` bytes to the buffer.
+    fn encode_fixed(&self, buf: &mut Vec<u8>);
+
+    /// Decodes the value from the first `FIXED_SIZE` bytes and advances the input.
+    fn decode_fixed(bytes: &mut &[u8]) -> Self;
+}
+
+macro_rules! impl_fixed_for_number {
+    ($($ty:ty),*) => {
+        $(
+            impl FixedSizeCodec for $ty {
+                const FIXED_SIZE: usize = std::mem::size_of::<$ty>();
+
+                fn encode_fixed(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode_fixed(bytes: &mut &[u8]) -> Self {
+                    let bytes = take_bytes(bytes, Self::FIXED_SIZE);
+                    <$ty>::from_le_bytes(bytes.try_into().expect("slice has correct length"))
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_for_number!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl FixedSizeCodec for bool {
+    const FIXED_SIZE: usize = 1;
+
+    fn encode_fixed(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    fn decode_fixed(bytes: &mut &[u8]) -> Self {
+        match take_bytes(bytes, 1)[0] {
+            0 => false,
+            1 => true,
+            other => panic!("invalid bool value: {other}"),
+        }
+    }
+}
+
+impl FixedSizeCodec for () {
+    const FIXED_SIZE: usize = 0;
+
+    fn encode_fixed(&self, _buf: &mut Vec<u8>) {}
+
+    fn decode_fixed(_bytes: &mut &[u8]) -> Self {}
+}
+
+impl<const N: usize> FixedSizeCodec for [u8; N] {
+    const FIXED_SIZE: usize = N;
+
+    fn encode_fixed(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode_fixed(bytes: &mut &[u8]) -> Self {
+        take_bytes(bytes, N)
+            .try_into()
+            .expect("slice has correct length")
+    }
+}
+
+/// Principal is stored as the length byte followed by the principal bytes padded with zeros.
+impl FixedSizeCodec for Principal {
+    const FIXED_SIZE: usize = 1 + Principal::MAX_LENGTH_IN_BYTES;
+
+    fn encode_fixed(&self, buf: &mut Vec<u8>) {
+        let slice = self.as_slice();
+        buf.push(slice.len() as u8);
+        buf.extend_from_slice(slice);
+        buf.resize(buf.len() + Principal::MAX_LENGTH_IN_BYTES - slice.len(), 0);
+    }
+
+    fn decode_fixed(bytes: &mut &[u8]) -> Self {
+        let bytes = take_bytes(bytes, Self::FIXED_SIZE);
+        let len = bytes[0] as usize;
+        Principal::from_slice(&bytes[1..=len])
+    }
+}
+
+/// Option is stored as the `0`/`1` tag followed by the value.
+/// `None` is padded with zeros up to the size of the value.
+impl<T: FixedSizeCodec> FixedSizeCodec for Option<T> {
+    const FIXED_SIZE: usize = 1 + T::FIXED_SIZE;
+
+    fn encode_fixed(&self, buf: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                buf.push(1);
+                value.encode_fixed(buf);
+            }
+            None => buf.resize(buf.len() + Self::FIXED_SIZE, 0),
+        }
+    }
+
+    fn decode_fixed(bytes: &mut &[u8]) -> Self {
+        let mut value_bytes = take_bytes(bytes, Self::FIXED_SIZE);
+        match take_bytes(&mut value_bytes, 1)[0] {
+            0 => None,
+            1 => Some(T::decode_fixed(&mut value_bytes)),
+            other => panic!("invalid option tag: {other}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+
+    fn check_roundtrip<T: FixedSizeCodec + PartialEq + Debug>(value: T) {
+        let mut buf = vec![];
+        value.encode_fixed(&mut buf);
+        assert_eq!(buf.len(), T::FIXED_SIZE);
+
+        // Trailing bytes should not be consumed.
+        buf.push(42);
+        let mut bytes = buf.as_slice();
+        assert_eq!(T::decode_fixed(&mut bytes), value);
+        assert_eq!(bytes, &[42]);
+    }
+
+    #[test]
+    fn should_encode_numbers_as_little_endian() {
+        let mut buf = vec![];
+        0x0102_0304u32.encode_fixed(&mut buf);
+        assert_eq!(buf, vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn should_roundtrip_values() {
+        check_roundtrip(42u8);
+        check_roundtrip(u64::MAX);
+        check_roundtrip(-42i32);
+        check_roundtrip(i128::MIN);
+        check_roundtrip(1.5f64);
+        check_roundtrip(true);
+        check_roundtrip(());
+        check_roundtrip([1u8, 2, 3]);
+        check_roundtrip(Principal::anonymous());
+        check_roundtrip(Principal::management_canister());
+        check_roundtrip(Principal::from_slice(&[7; 29]));
+        check_roundtrip(Some(10u16));
+        check_roundtrip(None::<u16>);
+    }
+}
@@ -0,0 +1,232 @@
+//! Building blocks for the `#[derive(Storable)]` macro.
+//!
+//! Every field-by-field encoding is described by a trait implemented for the primitive
+//! types and for the types deriving `Storable` with the corresponding encoding:
+//!
+//! * [`FixedSizeCodec`] - fixed-size little-endian encoding;
+//! * [`OrderedCodec`] - order-preserving encoding suitable for `StableBTreeMap` keys;
+//! * [`VarintCodec`] - compact encoding with LEB128 variable-length integers.
+//!
+//! Whole-value encodings are implemented by the [`encode_candid`]/[`decode_candid`] and
+//! [`encode_bincode`]/[`decode_bincode`] helpers.
+
+mod fixed;
+mod ordered;
+mod varint;
+
+use candid::{CandidType, Deserialize};
+use dfinity_stable_structures::storable::Bound;
+pub use fixed::FixedSizeCodec;
+pub use ordered::{KeyPrefix, OrderedCodec, OrderedKey};
+pub use varint::VarintCodec;
+
+/// Size bounds of an encoded value.
+///
+/// Unlike [`Bound`] this type is `Copy`, so it can be combined in constant expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecBound {
+    /// The encoded value has no size bounds.
+    Unbounded,
+    /// The encoded value is at most `max_size` bytes long.
+    Bounded {
+        max_size: usize,
+        is_fixed_size: bool,
+    },
+}
+
+impl CodecBound {
+    /// Bound of a value that is always encoded into `size` bytes.
+    pub const fn fixed(size: usize) -> Self {
+        Self::Bounded {
+            max_size: size,
+            is_fixed_size: true,
+        }
+    }
+
+    /// Bound of a value that is encoded into at most `max_size` bytes.
+    pub const fn bounded(max_size: usize) -> Self {
+        Self::Bounded {
+            max_size,
+            is_fixed_size: false,
+        }
+    }
+
+    /// Bound of `self` followed by `next`.
+    pub const fn then(self, next: Self) -> Self {
+        match (self, next) {
+            (
+                Self::Bounded {
+                    max_size: a,
+                    is_fixed_size: a_fixed,
+                },
+                Self::Bounded {
+                    max_size: b,
+                    is_fixed_size: b_fixed,
+                },
+            ) => Self::Bounded {
+                max_size: a + b,
+                is_fixed_size: a_fixed && b_fixed,
+            },
+            _ => Self::Unbounded,
+        }
+    }
+
+    /// Bound of a value that is encoded either as `self` or as `other`.
+    pub const fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (
+                Self::Bounded {
+                    max_size: a,
+                    is_fixed_size: a_fixed,
+                },
+                Self::Bounded {
+                    max_size: b,
+                    is_fixed_size: b_fixed,
+                },
+            ) => Self::Bounded {
+                max_size: if a > b { a } else { b },
+                is_fixed_size: a_fixed && b_fixed && a == b,
+            },
+            _ => Self::Unbounded,
+        }
+    }
+
+    /// Bound of `count` values, each of them bounded by `self`.
+    pub const fn repeat(self, count: usize) -> Self {
+        match self {
+            Self::Bounded {
+                max_size,
+                is_fixed_size,
+            } => Self::Bounded {
+                max_size: max_size * count,
+                is_fixed_size,
+            },
+            Self::Unbounded => Self::Unbounded,
+        }
+    }
+
+    /// Converts to the `Storable::BOUND` value.
+    pub const fn to_bound(self) -> Bound {
+        match self {
+            Self::Bounded {
+                max_size,
+                is_fixed_size,
+            } => Bound::Bounded {
+                max_size: max_size as u32,
+                is_fixed_size,
+            },
+            Self::Unbounded => Bound::Unbounded,
+        }
+    }
+}
+
+/// Returns the largest of the given sizes.
+pub const fn max_size_of(sizes: &[usize]) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < sizes.len() {
+        if sizes[i] > max {
+            max = sizes[i];
+        }
+        i += 1;
+    }
+    max
+}
+
+/// Splits the first `len` bytes from the input.
+///
+/// # Panics
+///
+/// Panics if the input is shorter than `len` bytes.
+pub(crate) fn take_bytes<'a>(bytes: &mut &'a [u8], len: usize) -> &'a [u8] {
+    assert!(
+        bytes.len() >= len,
+        "unexpected end of input: expected {len} bytes, got {}",
+        bytes.len()
+    );
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    head
+}
+
+/// Encodes the value with candid.
+pub fn encode_candid<T: CandidType>(value: &T) -> Vec<u8> {
+    candid::encode_one(value).expect("failed to encode value with candid")
+}
+
+/// Decodes a candid-encoded value.
+pub fn decode_candid<T>(bytes: &[u8]) -> T
+where
+    T: CandidType + for<'de> Deserialize<'de>,
+{
+    candid::decode_one(bytes).expect("failed to decode value with candid")
+}
+
+/// Encodes the value with bincode.
+#[cfg(feature = "bincode")]
+pub fn encode_bincode<T: serde::Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("failed to encode value with bincode")
+}
+
+/// Decodes a bincode-encoded value.
+#[cfg(feature = "bincode")]
+pub fn decode_bincode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> T {
+    bincode::deserialize(bytes).expect("failed to decode value with bincode")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_combine_bounds() {
+        let fixed = CodecBound::fixed(4).then(CodecBound::fixed(8));
+        assert_eq!(fixed, CodecBound::fixed(12));
+
+        let bounded = CodecBound::fixed(4).then(CodecBound::bounded(8));
+        assert_eq!(bounded, CodecBound::bounded(12));
+
+        let unbounded = CodecBound::fixed(4).then(CodecBound::Unbounded);
+        assert_eq!(unbounded, CodecBound::Unbounded);
+
+        assert_eq!(
+            CodecBound::fixed(4).or(CodecBound::fixed(4)),
+            CodecBound::fixed(4)
+        );
+        assert_eq!(
+            CodecBound::fixed(4).or(CodecBound::fixed(2)),
+            CodecBound::bounded(4)
+        );
+        assert_eq!(
+            CodecBound::fixed(4).or(CodecBound::Unbounded),
+            CodecBound::Unbounded
+        );
+
+        assert_eq!(CodecBound::fixed(3).repeat(3), CodecBound::fixed(9));
+    }
+
+    #[test]
+    fn should_convert_to_storable_bound() {
+        assert_eq!(
+            CodecBound::fixed(4).to_bound(),
+            Bound::Bounded {
+                max_size: 4,
+                is_fixed_size: true
+            }
+        );
+        assert_eq!(CodecBound::Unbounded.to_bound(), Bound::Unbounded);
+    }
+
+    #[test]
+    fn should_find_max_size() {
+        assert_eq!(max_size_of(&[]), 0);
+        assert_eq!(max_size_of(&[1, 5, 3]), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected end of input")]
+    fn take_bytes_should_panic_on_short_input() {
+        let mut bytes: &[u8] = &[1, 2];
+        take_bytes(&mut bytes, 3);
+    }
+}
@@ -0,0 +1,484 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::marker::PhantomData;
+
+use candid::Principal;
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+use super::{take_bytes, CodecBound};
+
+/// Order-preserving encoding.
+///
+/// For any two values `a` and `b` the byte-wise (lexicographic) comparison of the encoded
+/// values gives the same result as `a.cmp(&b)`. Variable-length values are self-delimiting,
+/// so the encoding of a tuple or a struct preserves the field-by-field order as well.
+pub trait OrderedCodec: Sized {
+    /// Size bounds of the encoded value.
+    const ORDERED_BOUND: CodecBound;
+
+    /// Appends the encoded value to the buffer.
+    fn encode_ordered(&self, buf: &mut Vec<u8>);
+
+    /// Decodes the value and advances the input.
+    fn decode_ordered(bytes: &mut &[u8]) -> Self;
+}
+
+macro_rules! impl_ordered_for_unsigned {
+    ($($ty:ty),*) => {
+        $(
+            impl OrderedCodec for $ty {
+                const ORDERED_BOUND: CodecBound = CodecBound::fixed(std::mem::size_of::<$ty>());
+
+                fn encode_ordered(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn decode_ordered(bytes: &mut &[u8]) -> Self {
+                    let bytes = take_bytes(bytes, std::mem::size_of::<$ty>());
+                    <$ty>::from_be_bytes(bytes.try_into().expect("slice has correct length"))
+                }
+            }
+        )*
+    };
+}
+
+impl_ordered_for_unsigned!(u8, u16, u32, u64, u128);
+
+/// Signed integers are stored as big-endian with the sign bit flipped,
+/// so negative values go before the positive ones.
+macro_rules! impl_ordered_for_signed {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl OrderedCodec for $ty {
+                const ORDERED_BOUND: CodecBound = CodecBound::fixed(std::mem::size_of::<$ty>());
+
+                fn encode_ordered(&self, buf: &mut Vec<u8>) {
+                    let flipped = (*self as $unsigned) ^ (1 << (<$unsigned>::BITS - 1));
+                    flipped.encode_ordered(buf);
+                }
+
+                fn decode_ordered(bytes: &mut &[u8]) -> Self {
+                    let flipped = <$unsigned>::decode_ordered(bytes);
+                    (flipped ^ (1 << (<$unsigned>::BITS - 1))) as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_ordered_for_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+/// Floats are ordered according to `total_cmp`: negative values have all the bits flipped,
+/// positive values have only the sign bit flipped.
+macro_rules! impl_ordered_for_float {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl OrderedCodec for $ty {
+                const ORDERED_BOUND: CodecBound = CodecBound::fixed(std::mem::size_of::<$ty>());
+
+                fn encode_ordered(&self, buf: &mut Vec<u8>) {
+                    let bits = self.to_bits();
+                    let sign = 1 << (<$unsigned>::BITS - 1);
+                    let flipped = if bits & sign != 0 { !bits } else { bits | sign };
+                    flipped.encode_ordered(buf);
+                }
+
+                fn decode_ordered(bytes: &mut &[u8]) -> Self {
+                    let flipped = <$unsigned>::decode_ordered(bytes);
+                    let sign = 1 << (<$unsigned>::BITS - 1);
+                    let bits = if flipped & sign != 0 { flipped & !sign } else { !flipped };
+                    <$ty>::from_bits(bits)
+                }
+            }
+        )*
+    };
+}
+
+impl_ordered_for_float!(f32 => u32, f64 => u64);
+
+impl OrderedCodec for bool {
+    const ORDERED_BOUND: CodecBound = CodecBound::fixed(1);
+
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    fn decode_ordered(bytes: &mut &[u8]) -> Self {
+        match take_bytes(bytes, 1)[0] {
+            0 => false,
+            1 => true,
+            other => panic!("invalid bool value: {other}"),
+        }
+    }
+}
+
+impl OrderedCodec for () {
+    const ORDERED_BOUND: CodecBound = CodecBound::fixed(0);
+
+    fn encode_ordered(&self, _buf: &mut Vec<u8>) {}
+
+    fn decode_ordered(_bytes: &mut &[u8]) -> Self {}
+}
+
+impl<const N: usize> OrderedCodec for [u8; N] {
+    const ORDERED_BOUND: CodecBound = CodecBound::fixed(N);
+
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode_ordered(bytes: &mut &[u8]) -> Self {
+        take_bytes(bytes, N)
+            .try_into()
+            .expect("slice has correct length")
+    }
+}
+
+/// Principals are ordered by length first and then by bytes,
+/// so they are stored as the length byte followed by the bytes padded with zeros.
+impl OrderedCodec for Principal {
+    const ORDERED_BOUND: CodecBound = CodecBound::fixed(1 + Principal::MAX_LENGTH_IN_BYTES);
+
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        let slice = self.as_slice();
+        buf.push(slice.len() as u8);
+        buf.extend_from_slice(slice);
+        buf.resize(buf.len() + Principal::MAX_LENGTH_IN_BYTES - slice.len(), 0);
+    }
+
+    fn decode_ordered(bytes: &mut &[u8]) -> Self {
+        let bytes = take_bytes(bytes, 1 + Principal::MAX_LENGTH_IN_BYTES);
+        let len = bytes[0] as usize;
+        Principal::from_slice(&bytes[1..=len])
+    }
+}
+
+/// Byte strings are escaped to stay self-delimiting: every `0x00` byte is stored as `0x00 0xFF`
+/// and the string is terminated with `0x00 0x00`. A shorter prefix always goes first.
+fn encode_escaped(value: &[u8], buf: &mut Vec<u8>) {
+    for byte in value {
+        buf.push(*byte);
+        if *byte == 0 {
+            buf.push(0xFF);
+        }
+    }
+    buf.extend_from_slice(&[0, 0]);
+}
+
+fn decode_escaped(bytes: &mut &[u8]) -> Vec<u8> {
+    let mut value = vec![];
+    loop {
+        let byte = take_bytes(bytes, 1)[0];
+        if byte != 0 {
+            value.push(byte);
+            continue;
+        }
+
+        match take_bytes(bytes, 1)[0] {
+            0 => return value,
+            0xFF => value.push(0),
+            other => panic!("invalid escape sequence: 0x00 0x{other:02X}"),
+        }
+    }
+}
+
+impl OrderedCodec for Vec<u8> {
+    const ORDERED_BOUND: CodecBound = CodecBound::Unbounded;
+
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        encode_escaped(self, buf);
+    }
+
+    fn decode_ordered(bytes: &mut &[u8]) -> Self {
+        decode_escaped(bytes)
+    }
+}
+
+impl OrderedCodec for String {
+    const ORDERED_BOUND: CodecBound = CodecBound::Unbounded;
+
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        encode_escaped(self.as_bytes(), buf);
+    }
+
+    fn decode_ordered(bytes: &mut &[u8]) -> Self {
+        String::from_utf8(decode_escaped(bytes)).expect("invalid utf-8 string")
+    }
+}
+
+/// `None` goes before any `Some` value.
+impl<T: OrderedCodec> OrderedCodec for Option<T> {
+    const ORDERED_BOUND: CodecBound =
+        CodecBound::fixed(1).then(T::ORDERED_BOUND.or(CodecBound::fixed(0)));
+
+    fn encode_ordered(&self, buf: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                buf.push(1);
+                value.encode_ordered(buf);
+            }
+            None => buf.push(0),
+        }
+    }
+
+    fn decode_ordered(bytes: &mut &[u8]) -> Self {
+        match take_bytes(bytes, 1)[0] {
+            0 => None,
+            1 => Some(T::decode_ordered(bytes)),
+            other => panic!("invalid option tag: {other}"),
+        }
+    }
+}
+
+macro_rules! impl_ordered_for_tuple {
+    ($($name:ident: $idx:tt),*) => {
+        impl<$($name: OrderedCodec),*> OrderedCodec for ($($name,)*) {
+            const ORDERED_BOUND: CodecBound = CodecBound::fixed(0)$(.then($name::ORDERED_BOUND))*;
+
+            fn encode_ordered(&self, buf: &mut Vec<u8>) {
+                $(self.$idx.encode_ordered(buf);)*
+            }
+
+            fn decode_ordered(bytes: &mut &[u8]) -> Self {
+                ($($name::decode_ordered(bytes),)*)
+            }
+        }
+    };
+}
+
+impl_ordered_for_tuple!(A: 0);
+impl_ordered_for_tuple!(A: 0, B: 1);
+impl_ordered_for_tuple!(A: 0, B: 1, C: 2);
+impl_ordered_for_tuple!(A: 0, B: 1, C: 2, D: 3);
+impl_ordered_for_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_ordered_for_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+
+/// Marks the tuples that are prefixes of the tuple `K`: `(A,)`, `(A, B)`, ... and `K` itself.
+///
+/// Since the ordered encoding of a tuple is the concatenation of the encodings of its elements,
+/// the encoding of a prefix is a byte prefix of the encoding of the whole key.
+pub trait KeyPrefix<K: OrderedCodec>: OrderedCodec {}
+
+macro_rules! impl_key_prefix {
+    ($key:tt => $($prefix:tt),*) => {
+        $(impl_key_prefix!(@impl $key $prefix);)*
+    };
+    (@impl ($($key:ident),*) ($($prefix:ident),*)) => {
+        impl<$($key: OrderedCodec),*> KeyPrefix<($($key,)*)> for ($($prefix,)*) {}
+    };
+}
+
+impl_key_prefix!((A) => (A));
+impl_key_prefix!((A, B) => (A), (A, B));
+impl_key_prefix!((A, B, C) => (A), (A, B), (A, B, C));
+impl_key_prefix!((A, B, C, D) => (A), (A, B), (A, B, C), (A, B, C, D));
+impl_key_prefix!((A, B, C, D, E) => (A), (A, B), (A, B, C), (A, B, C, D), (A, B, C, D, E));
+impl_key_prefix!(
+    (A, B, C, D, E, F) => (A), (A, B), (A, B, C), (A, B, C, D), (A, B, C, D, E), (A, B, C, D, E, F)
+);
+
+/// `Storable` key that keeps the value in the order-preserving encoding.
+///
+/// Keys are compared by their bytes, which is the same as comparing the decoded values,
+/// so `OrderedKey<K>` can be used as a `StableBTreeMap` key for any `K: OrderedCodec`.
+pub struct OrderedKey<K> {
+    bytes: Vec<u8>,
+    _key: PhantomData<fn() -> K>,
+}
+
+impl<K: OrderedCodec> OrderedKey<K> {
+    /// Encodes the key.
+    pub fn new(key: &K) -> Self {
+        let mut bytes = vec![];
+        key.encode_ordered(&mut bytes);
+        Self::from_raw(bytes)
+    }
+
+    /// Encodes the key prefix. The result is less or equal to any key starting with the prefix.
+    pub fn prefix<P: KeyPrefix<K>>(prefix: &P) -> Self {
+        let mut bytes = vec![];
+        prefix.encode_ordered(&mut bytes);
+        Self::from_raw(bytes)
+    }
+
+    /// Decodes the key.
+    pub fn decode(&self) -> K {
+        K::decode_ordered(&mut self.bytes.as_slice())
+    }
+
+    /// Returns the encoded key.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn from_raw(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<K> Clone for OrderedKey<K> {
+    fn clone(&self) -> Self {
+        Self {
+            bytes: self.bytes.clone(),
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<K> fmt::Debug for OrderedKey<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OrderedKey").field(&self.bytes).finish()
+    }
+}
+
+impl<K> PartialEq for OrderedKey<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl<K> Eq for OrderedKey<K> {}
+
+impl<K> PartialOrd for OrderedKey<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> Ord for OrderedKey<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bytes.cmp(&other.bytes)
+    }
+}
+
+impl<K: OrderedCodec> Storable for OrderedKey<K> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self::from_raw(bytes.into_owned())
+    }
+
+    const BOUND: Bound = K::ORDERED_BOUND.to_bound();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+
+    fn encode<T: OrderedCodec>(value: &T) -> Vec<u8> {
+        let mut buf = vec![];
+        value.encode_ordered(&mut buf);
+        buf
+    }
+
+    /// Checks the roundtrip and that the encoded values are sorted in the same order as the values.
+    fn check_sorted<T: OrderedCodec + Ord + Debug + Clone>(mut values: Vec<T>) {
+        values.sort();
+        let encoded: Vec<_> = values.iter().map(encode).collect();
+
+        for (value, bytes) in values.iter().zip(&encoded) {
+            let mut input = bytes.as_slice();
+            assert_eq!(&T::decode_ordered(&mut input), value);
+            assert!(input.is_empty());
+        }
+
+        for pair in encoded.windows(2) {
+            assert!(pair[0] <= pair[1], "{:?} > {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn should_preserve_integer_order() {
+        check_sorted(vec![0u32, 1, 255, 256, u32::MAX]);
+        check_sorted(vec![i64::MIN, -256, -1, 0, 1, 255, i64::MAX]);
+        check_sorted(vec![i8::MIN, -1, 0, 1, i8::MAX]);
+    }
+
+    #[test]
+    fn should_preserve_float_order() {
+        let values = [f64::MIN, -1.5, -0.0, 0.0, 1e-10, 2.0, f64::MAX];
+        let encoded: Vec<_> = values.iter().map(encode).collect();
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+        for (value, bytes) in values.iter().zip(&encoded) {
+            assert_eq!(f64::decode_ordered(&mut bytes.as_slice()), *value);
+        }
+    }
+
+    #[test]
+    fn should_preserve_string_order() {
+        check_sorted(vec![
+            String::new(),
+            "\0".to_string(),
+            "\0\0".to_string(),
+            "a".to_string(),
+            "a\0".to_string(),
+            "ab".to_string(),
+            "b".to_string(),
+        ]);
+        check_sorted(vec![vec![], vec![0u8], vec![0, 0xFF], vec![1], vec![0xFF]]);
+    }
+
+    #[test]
+    fn should_preserve_principal_order() {
+        check_sorted(vec![
+            Principal::management_canister(),
+            Principal::anonymous(),
+            Principal::from_slice(&[0, 0]),
+            Principal::from_slice(&[0xFF; 2]),
+            Principal::from_slice(&[1; 29]),
+        ]);
+    }
+
+    #[test]
+    fn should_preserve_tuple_order() {
+        check_sorted(vec![
+            ("a".to_string(), 2u8),
+            ("a".to_string(), 10),
+            ("ab".to_string(), 1),
+            ("b".to_string(), 0),
+        ]);
+        check_sorted(vec![
+            (None, -1i16, true),
+            (Some(0u64), -5, false),
+            (Some(0), -5, true),
+            (Some(1), i16::MIN, false),
+        ]);
+    }
+
+    #[test]
+    fn prefix_should_be_byte_prefix_of_key() {
+        let key = (1u32, "abc".to_string(), -5i64);
+        let encoded = OrderedKey::new(&key);
+
+        let prefix = OrderedKey::<(u32, String, i64)>::prefix(&(1u32,));
+        assert!(encoded.as_bytes().starts_with(prefix.as_bytes()));
+        assert!(prefix < encoded);
+
+        let prefix = OrderedKey::<(u32, String, i64)>::prefix(&(1u32, "abc".to_string()));
+        assert!(encoded.as_bytes().starts_with(prefix.as_bytes()));
+
+        let other_prefix = OrderedKey::<(u32, String, i64)>::prefix(&(1u32, "ab".to_string()));
+        assert!(!encoded.as_bytes().starts_with(other_prefix.as_bytes()));
+
+        assert_eq!(encoded.decode(), key);
+        assert_eq!(OrderedKey::from_bytes(encoded.to_bytes()), encoded);
+    }
+
+    #[test]
+    fn should_compute_bounds() {
+        assert_eq!(<(u32, u64)>::ORDERED_BOUND, CodecBound::fixed(12));
+        assert_eq!(<Option<u32>>::ORDERED_BOUND, CodecBound::bounded(5));
+        assert_eq!(<(u32, String)>::ORDERED_BOUND, CodecBound::Unbounded);
+    }
+}
@@ -0,0 +1,278 @@
+use candid::Principal;
+
+use super::{take_bytes, CodecBound};
+
+/// Compact encoding: integers are stored as LEB128 variable-length numbers,
+/// signed integers are zigzag-encoded before that.
+pub trait VarintCodec: Sized {
+    /// Size bounds of the encoded value.
+    const VARINT_BOUND: CodecBound;
+
+    /// Appends the encoded value to the buffer.
+    fn encode_varint(&self, buf: &mut Vec<u8>);
+
+    /// Decodes the value and advances the input.
+    fn decode_varint(bytes: &mut &[u8]) -> Self;
+}
+
+/// Max number of bytes of LEB128-encoded number with the given number of bits.
+const fn leb128_max_size(bits: u32) -> usize {
+    bits.div_ceil(7) as usize
+}
+
+fn encode_leb128(mut value: u128, buf: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn decode_leb128(bytes: &mut &[u8], bits: u32) -> u128 {
+    let mut value = 0u128;
+    let mut shift = 0;
+    loop {
+        let byte = take_bytes(bytes, 1)[0];
+        assert!(shift < bits, "varint is too long for {bits}-bit number");
+        value |= ((byte & 0x7F) as u128) << shift;
+        if byte & 0x80 == 0 {
+            assert!(
+                bits == u128::BITS || value >> bits == 0,
+                "varint overflows {bits}-bit number"
+            );
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+macro_rules! impl_varint_for_unsigned {
+    ($($ty:ty),*) => {
+        $(
+            impl VarintCodec for $ty {
+                const VARINT_BOUND: CodecBound = CodecBound::bounded(leb128_max_size(<$ty>::BITS));
+
+                fn encode_varint(&self, buf: &mut Vec<u8>) {
+                    encode_leb128(*self as u128, buf);
+                }
+
+                fn decode_varint(bytes: &mut &[u8]) -> Self {
+                    decode_leb128(bytes, <$ty>::BITS) as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_varint_for_unsigned!(u16, u32, u64, u128, usize);
+
+macro_rules! impl_varint_for_signed {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl VarintCodec for $ty {
+                const VARINT_BOUND: CodecBound = CodecBound::bounded(leb128_max_size(<$ty>::BITS));
+
+                fn encode_varint(&self, buf: &mut Vec<u8>) {
+                    let zigzag = ((*self << 1) ^ (*self >> (<$ty>::BITS - 1))) as $unsigned;
+                    zigzag.encode_varint(buf);
+                }
+
+                fn decode_varint(bytes: &mut &[u8]) -> Self {
+                    let zigzag = <$unsigned>::decode_varint(bytes);
+                    ((zigzag >> 1) as $ty) ^ -((zigzag & 1) as $ty)
+                }
+            }
+        )*
+    };
+}
+
+impl_varint_for_signed!(i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize);
+
+/// Single-byte and floating point numbers gain nothing from LEB128, so they are stored as is.
+macro_rules! impl_varint_as_raw {
+    ($($ty:ty),*) => {
+        $(
+            impl VarintCodec for $ty {
+                const VARINT_BOUND: CodecBound = CodecBound::fixed(std::mem::size_of::<$ty>());
+
+                fn encode_varint(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode_varint(bytes: &mut &[u8]) -> Self {
+                    let bytes = take_bytes(bytes, std::mem::size_of::<$ty>());
+                    <$ty>::from_le_bytes(bytes.try_into().expect("slice has correct length"))
+                }
+            }
+        )*
+    };
+}
+
+impl_varint_as_raw!(u8, i8, f32, f64);
+
+impl VarintCodec for bool {
+    const VARINT_BOUND: CodecBound = CodecBound::fixed(1);
+
+    fn encode_varint(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    fn decode_varint(bytes: &mut &[u8]) -> Self {
+        match take_bytes(bytes, 1)[0] {
+            0 => false,
+            1 => true,
+            other => panic!("invalid bool value: {other}"),
+        }
+    }
+}
+
+impl VarintCodec for () {
+    const VARINT_BOUND: CodecBound = CodecBound::fixed(0);
+
+    fn encode_varint(&self, _buf: &mut Vec<u8>) {}
+
+    fn decode_varint(_bytes: &mut &[u8]) -> Self {}
+}
+
+impl<const N: usize> VarintCodec for [u8; N] {
+    const VARINT_BOUND: CodecBound = CodecBound::fixed(N);
+
+    fn encode_varint(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode_varint(bytes: &mut &[u8]) -> Self {
+        take_bytes(bytes, N)
+            .try_into()
+            .expect("slice has correct length")
+    }
+}
+
+impl VarintCodec for Principal {
+    const VARINT_BOUND: CodecBound = CodecBound::bounded(1 + Principal::MAX_LENGTH_IN_BYTES);
+
+    fn encode_varint(&self, buf: &mut Vec<u8>) {
+        let slice = self.as_slice();
+        buf.push(slice.len() as u8);
+        buf.extend_from_slice(slice);
+    }
+
+    fn decode_varint(bytes: &mut &[u8]) -> Self {
+        let len = take_bytes(bytes, 1)[0] as usize;
+        Principal::from_slice(take_bytes(bytes, len))
+    }
+}
+
+impl VarintCodec for String {
+    const VARINT_BOUND: CodecBound = CodecBound::Unbounded;
+
+    fn encode_varint(&self, buf: &mut Vec<u8>) {
+        self.len().encode_varint(buf);
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode_varint(bytes: &mut &[u8]) -> Self {
+        let len = usize::decode_varint(bytes);
+        String::from_utf8(take_bytes(bytes, len).to_vec()).expect("invalid utf-8 string")
+    }
+}
+
+impl<T: VarintCodec> VarintCodec for Vec<T> {
+    const VARINT_BOUND: CodecBound = CodecBound::Unbounded;
+
+    fn encode_varint(&self, buf: &mut Vec<u8>) {
+        self.len().encode_varint(buf);
+        for item in self {
+            item.encode_varint(buf);
+        }
+    }
+
+    fn decode_varint(bytes: &mut &[u8]) -> Self {
+        let len = usize::decode_varint(bytes);
+        (0..len).map(|_| T::decode_varint(bytes)).collect()
+    }
+}
+
+impl<T: VarintCodec> VarintCodec for Option<T> {
+    const VARINT_BOUND: CodecBound =
+        CodecBound::fixed(1).then(T::VARINT_BOUND.or(CodecBound::fixed(0)));
+
+    fn encode_varint(&self, buf: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                buf.push(1);
+                value.encode_varint(buf);
+            }
+            None => buf.push(0),
+        }
+    }
+
+    fn decode_varint(bytes: &mut &[u8]) -> Self {
+        match take_bytes(bytes, 1)[0] {
+            0 => None,
+            1 => Some(T::decode_varint(bytes)),
+            other => panic!("invalid option tag: {other}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+
+    fn encode<T: VarintCodec>(value: &T) -> Vec<u8> {
+        let mut buf = vec![];
+        value.encode_varint(&mut buf);
+        buf
+    }
+
+    fn check_roundtrip<T: VarintCodec + PartialEq + Debug>(value: T) {
+        let encoded = encode(&value);
+        if let CodecBound::Bounded { max_size, .. } = T::VARINT_BOUND {
+            assert!(encoded.len() <= max_size);
+        }
+
+        let mut input = encoded.as_slice();
+        assert_eq!(T::decode_varint(&mut input), value);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn should_encode_small_numbers_compactly() {
+        assert_eq!(encode(&0u64), vec![0]);
+        assert_eq!(encode(&127u64), vec![0x7F]);
+        assert_eq!(encode(&128u64), vec![0x80, 0x01]);
+        assert_eq!(encode(&300u32), vec![0xAC, 0x02]);
+        assert_eq!(encode(&-1i64), vec![0x01]);
+        assert_eq!(encode(&1i64), vec![0x02]);
+    }
+
+    #[test]
+    fn should_roundtrip_values() {
+        check_roundtrip(u16::MAX);
+        check_roundtrip(u64::MAX);
+        check_roundtrip(u128::MAX);
+        check_roundtrip(i32::MIN);
+        check_roundtrip(i128::MAX);
+        check_roundtrip(-42isize);
+        check_roundtrip(1.25f32);
+        check_roundtrip(200u8);
+        check_roundtrip(Principal::anonymous());
+        check_roundtrip("hello".to_string());
+        check_roundtrip(vec![1u64, 300, u64::MAX]);
+        check_roundtrip(Some(-5i64));
+        check_roundtrip(None::<String>);
+    }
+
+    #[test]
+    #[should_panic(expected = "varint overflows")]
+    fn should_reject_overflowing_varint() {
+        u16::decode_varint(&mut [0xFF, 0xFF, 0x07].as_slice());
+    }
+}
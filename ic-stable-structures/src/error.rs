@@ -15,6 +15,18 @@ pub enum Error {
     IncompatibleElementType,
     #[error("bad magic number: actual: {actual:?}, expected: {expected:?}")]
     BadMagic { actual: [u8; 3], expected: [u8; 3] },
+    #[error("fixed-size value is expected to be {expected} bytes long, but it's {actual} bytes")]
+    UnexpectedFixedSize { expected: u32, actual: u64 },
+    #[error("stable memory has reached the soft quota of {max_pages} pages")]
+    SoftQuotaExceeded { max_pages: u64 },
+    #[error("entry is not migrated to the schema version {0} yet")]
+    EntryNotMigrated(u32),
+    #[error("migration from the schema version {from_version} to {to_version} is in progress")]
+    MigrationInProgress { from_version: u32, to_version: u32 },
+    #[error("no migrations from the schema version {0}")]
+    UnsupportedSchemaVersion(u32),
+    #[error("structure was modified: expected generation {expected}, actual {actual}")]
+    StructureModified { expected: u64, actual: u64 },
 }
 
 impl From<cell::InitError> for Error {
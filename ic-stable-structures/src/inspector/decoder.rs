@@ -0,0 +1,215 @@
+use std::fmt;
+use std::str::FromStr;
+
+use candid::IDLArgs;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::error::{InspectorError, InspectorResult};
+
+/// Decoder of the raw key and value bytes into JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decoder {
+    /// Bytes as a hex string.
+    #[default]
+    Hex,
+    /// Bytes as an UTF-8 string, e.g. `String` keys.
+    Utf8,
+    /// Big-endian `u64`, the `Storable` encoding of the integers.
+    U64,
+    /// Candid encoded value printed in the Candid text format.
+    Candid,
+    /// Bincode encoded value of the given type.
+    ///
+    /// Bincode is not self-describing, so only the primitive types can be decoded
+    /// without the schema.
+    Bincode(BincodeType),
+}
+
+/// Types decodable by [`Decoder::Bincode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BincodeType {
+    U64,
+    I64,
+    U128,
+    I128,
+    Bool,
+    String,
+    Bytes,
+}
+
+impl Decoder {
+    /// Decodes the bytes into a JSON value.
+    pub fn decode(&self, bytes: &[u8]) -> InspectorResult<Value> {
+        match self {
+            Decoder::Hex => Ok(Value::String(hex::encode(bytes))),
+            Decoder::Utf8 => std::str::from_utf8(bytes)
+                .map(|s| Value::String(s.to_owned()))
+                .map_err(|e| self.error(e)),
+            Decoder::U64 => {
+                let bytes: [u8; 8] = bytes
+                    .try_into()
+                    .map_err(|_| self.error(format!("expected 8 bytes, got {}", bytes.len())))?;
+                Ok(u64::from_be_bytes(bytes).into())
+            }
+            Decoder::Candid => IDLArgs::from_bytes(bytes)
+                .map(|args| Value::String(args.to_string()))
+                .map_err(|e| self.error(e)),
+            Decoder::Bincode(ty) => match ty {
+                BincodeType::U64 => self.decode_bincode::<u64>(bytes).map(Into::into),
+                BincodeType::I64 => self.decode_bincode::<i64>(bytes).map(Into::into),
+                // JSON numbers can't hold 128-bit integers without losing precision.
+                BincodeType::U128 => self
+                    .decode_bincode::<u128>(bytes)
+                    .map(|v| Value::String(v.to_string())),
+                BincodeType::I128 => self
+                    .decode_bincode::<i128>(bytes)
+                    .map(|v| Value::String(v.to_string())),
+                BincodeType::Bool => self.decode_bincode::<bool>(bytes).map(Into::into),
+                BincodeType::String => self.decode_bincode::<String>(bytes).map(Into::into),
+                BincodeType::Bytes => self
+                    .decode_bincode::<Vec<u8>>(bytes)
+                    .map(|v| Value::String(hex::encode(v))),
+            },
+        }
+    }
+
+    fn decode_bincode<T: DeserializeOwned>(&self, bytes: &[u8]) -> InspectorResult<T> {
+        bincode::deserialize(bytes).map_err(|e| self.error(e))
+    }
+
+    fn error(&self, reason: impl fmt::Display) -> InspectorError {
+        InspectorError::Decode {
+            decoder: self.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoder::Hex => f.write_str("hex"),
+            Decoder::Utf8 => f.write_str("utf8"),
+            Decoder::U64 => f.write_str("u64"),
+            Decoder::Candid => f.write_str("candid"),
+            Decoder::Bincode(ty) => write!(f, "bincode:{ty}"),
+        }
+    }
+}
+
+/// Parses `hex`, `utf8`, `u64`, `candid` or `bincode:<type>`, e.g. `bincode:string`.
+impl FromStr for Decoder {
+    type Err = InspectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(Decoder::Hex),
+            "utf8" => Ok(Decoder::Utf8),
+            "u64" => Ok(Decoder::U64),
+            "candid" => Ok(Decoder::Candid),
+            _ => s
+                .strip_prefix("bincode:")
+                .and_then(|ty| BincodeType::from_str(ty).ok())
+                .map(Decoder::Bincode)
+                .ok_or_else(|| InspectorError::UnknownDecoder(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for BincodeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BincodeType::U64 => "u64",
+            BincodeType::I64 => "i64",
+            BincodeType::U128 => "u128",
+            BincodeType::I128 => "i128",
+            BincodeType::Bool => "bool",
+            BincodeType::String => "string",
+            BincodeType::Bytes => "bytes",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for BincodeType {
+    type Err = InspectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u64" => Ok(BincodeType::U64),
+            "i64" => Ok(BincodeType::I64),
+            "u128" => Ok(BincodeType::U128),
+            "i128" => Ok(BincodeType::I128),
+            "bool" => Ok(BincodeType::Bool),
+            "string" => Ok(BincodeType::String),
+            "bytes" => Ok(BincodeType::Bytes),
+            _ => Err(InspectorError::UnknownDecoder(format!("bincode:{s}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Encode;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn should_parse_decoders() {
+        for decoder in [
+            Decoder::Hex,
+            Decoder::Utf8,
+            Decoder::U64,
+            Decoder::Candid,
+            Decoder::Bincode(BincodeType::String),
+            Decoder::Bincode(BincodeType::I128),
+        ] {
+            assert_eq!(decoder.to_string().parse::<Decoder>().unwrap(), decoder);
+        }
+
+        assert!("bincode".parse::<Decoder>().is_err());
+        assert!("bincode:f32".parse::<Decoder>().is_err());
+        assert!("json".parse::<Decoder>().is_err());
+    }
+
+    #[test]
+    fn should_decode_values() {
+        assert_eq!(Decoder::Hex.decode(&[0xAB, 1]).unwrap(), json!("ab01"));
+        assert_eq!(Decoder::Utf8.decode(b"key").unwrap(), json!("key"));
+        assert_eq!(
+            Decoder::U64.decode(&42u64.to_be_bytes()).unwrap(),
+            json!(42)
+        );
+        assert_eq!(
+            Decoder::Candid
+                .decode(&Encode!(&"hello", &7u8).unwrap())
+                .unwrap(),
+            json!("(\"hello\", 7 : nat8)")
+        );
+
+        let bytes = bincode::serialize("value").unwrap();
+        assert_eq!(
+            Decoder::Bincode(BincodeType::String)
+                .decode(&bytes)
+                .unwrap(),
+            json!("value")
+        );
+        let bytes = bincode::serialize(&u128::MAX).unwrap();
+        assert_eq!(
+            Decoder::Bincode(BincodeType::U128).decode(&bytes).unwrap(),
+            json!(u128::MAX.to_string())
+        );
+    }
+
+    #[test]
+    fn should_fail_on_invalid_bytes() {
+        assert!(matches!(
+            Decoder::U64.decode(&[1, 2, 3]),
+            Err(InspectorError::Decode { .. })
+        ));
+        assert!(Decoder::Utf8.decode(&[0xFF]).is_err());
+        assert!(Decoder::Candid.decode(b"not candid").is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use thiserror::Error;
+
+use crate::MemMapError;
+
+#[derive(Debug, Error)]
+pub enum InspectorError {
+    #[error("memory error: {0}")]
+    MemMap(#[from] MemMapError),
+    #[error("file error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("memory {0} not found")]
+    MemoryNotFound(u8),
+    #[error("memory is empty, the {0} is not initialized")]
+    NotInitialized(&'static str),
+    #[error("bad {structure} magic number: {actual:?}")]
+    BadMagic {
+        structure: &'static str,
+        actual: [u8; 3],
+    },
+    #[error("unsupported {structure} layout version: {version}")]
+    UnsupportedVersion {
+        structure: &'static str,
+        version: u8,
+    },
+    #[error("{structure} is corrupted: {reason}")]
+    Corrupted {
+        structure: &'static str,
+        reason: String,
+    },
+    #[error("unsupported fixed key size: {0}")]
+    UnsupportedKeySize(u32),
+    #[error("unknown decoder: {0}")]
+    UnknownDecoder(String),
+    #[error("failed to decode value with the {decoder} decoder: {reason}")]
+    Decode { decoder: String, reason: String },
+}
+
+pub type InspectorResult<T> = Result<T, InspectorError>;
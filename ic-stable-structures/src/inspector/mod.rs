@@ -0,0 +1,62 @@
+//! Offline inspection of the stable structures stored in a memory-mapped files directory
+//! or in an IC stable memory image. Used by the `stable-structures-inspector` binary.
+
+mod decoder;
+mod error;
+mod raw;
+mod source;
+
+pub use decoder::{BincodeType, Decoder};
+pub use error::{InspectorError, InspectorResult};
+pub use raw::{EntryKey, RawEntry, RawStructure};
+use serde_json::{json, Value};
+pub use source::{InspectedMemory, InspectorSource, MemoryInfo};
+
+/// Decodes the entries into a JSON array of `{"key": .., "value": ..}` objects,
+/// or `{"index": .., "value": ..}` for the structures indexed by position.
+pub fn entries_to_json(
+    entries: &[RawEntry],
+    key_decoder: Decoder,
+    value_decoder: Decoder,
+) -> InspectorResult<Value> {
+    entries
+        .iter()
+        .map(|entry| {
+            let value = value_decoder.decode(&entry.value)?;
+            Ok(match &entry.key {
+                EntryKey::Index(index) => json!({ "index": index, "value": value }),
+                EntryKey::Bytes(key) => json!({ "key": key_decoder.decode(key)?, "value": value }),
+            })
+        })
+        .collect::<InspectorResult<Vec<_>>>()
+        .map(Value::Array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_convert_entries_to_json() {
+        let entries = vec![
+            RawEntry {
+                key: EntryKey::Bytes(b"key".to_vec()),
+                value: 1u64.to_be_bytes().to_vec(),
+            },
+            RawEntry {
+                key: EntryKey::Index(3),
+                value: vec![0xFF],
+            },
+        ];
+
+        assert_eq!(
+            entries_to_json(&entries[..1], Decoder::Utf8, Decoder::U64).unwrap(),
+            json!([{ "key": "key", "value": 1 }])
+        );
+        assert_eq!(
+            entries_to_json(&entries[1..], Decoder::Utf8, Decoder::Hex).unwrap(),
+            json!([{ "index": 3, "value": "ff" }])
+        );
+        assert!(entries_to_json(&entries, Decoder::Hex, Decoder::U64).is_err());
+    }
+}
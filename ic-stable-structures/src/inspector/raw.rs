@@ -0,0 +1,481 @@
+use std::borrow::Cow;
+
+use dfinity_stable_structures::{btreemap, Memory, Storable};
+
+use super::error::{InspectorError, InspectorResult};
+use crate::Bound;
+
+const WASM_PAGE_SIZE_IN_BYTES: u64 = 65536;
+
+const BTREEMAP_MAGIC: &[u8; 3] = b"BTR";
+const VEC_MAGIC: &[u8; 3] = b"SVC";
+const CELL_MAGIC: &[u8; 3] = b"SCL";
+const LOG_INDEX_MAGIC: &[u8; 3] = b"GLI";
+const LOG_DATA_MAGIC: &[u8; 3] = b"GLD";
+
+/// Offset of the vector elements.
+const VEC_DATA_OFFSET: u64 = 64;
+/// Size of the cell header: magic, version and value length.
+const CELL_HEADER_SIZE: u64 = 8;
+/// Size of the log index and data headers.
+const LOG_HEADER_SIZE: u64 = 32;
+
+/// Stable structure whose keys and values are read as raw bytes,
+/// so it can be inspected without knowing the element types.
+pub enum RawStructure<M> {
+    /// `StableBTreeMap`. The layout of the map depends on whether the key type is fixed size,
+    /// so `fixed_key_size` must be set to the key size for such keys, e.g. `Some(8)` for `u64`.
+    BTreeMap {
+        memory: M,
+        fixed_key_size: Option<u32>,
+    },
+    /// `StableLog` stored in the index and data memories.
+    Log { index_memory: M, data_memory: M },
+    /// `StableVec`.
+    Vec { memory: M },
+    /// `StableCell`, it has a single entry.
+    Cell { memory: M },
+}
+
+/// Key of a [`RawEntry`]: the map key or the element index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKey {
+    Index(u64),
+    Bytes(Vec<u8>),
+}
+
+/// Raw key-value pair of a stable structure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEntry {
+    pub key: EntryKey,
+    pub value: Vec<u8>,
+}
+
+/// Dispatches the fixed key size known at runtime to the `RawKey` size known at compile time.
+macro_rules! read_btreemap_with_key_size {
+    ($size:expr, $memory:expr, $limit:expr, [$($n:literal)*]) => {
+        match $size {
+            None => Ok(read_btreemap::<_, 0>($memory, $limit)),
+            $(Some($n) => Ok(read_btreemap::<_, $n>($memory, $limit)),)*
+            Some(size) => Err(InspectorError::UnsupportedKeySize(size)),
+        }
+    };
+}
+
+impl<M: Memory> RawStructure<M> {
+    /// Name of the structure for the error messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BTreeMap { .. } => "btreemap",
+            Self::Log { .. } => "log",
+            Self::Vec { .. } => "vec",
+            Self::Cell { .. } => "cell",
+        }
+    }
+
+    /// Number of entries in the structure.
+    pub fn len(&self) -> InspectorResult<u64> {
+        match self {
+            Self::BTreeMap { memory, .. } => {
+                check_header(memory, BTREEMAP_MAGIC, &[1, 2], "btreemap")?;
+                // The length is at the same offset in all the layout versions.
+                Ok(read_u64(memory, 20))
+            }
+            Self::Log {
+                index_memory,
+                data_memory,
+            } => {
+                check_log_headers(index_memory, data_memory)?;
+                Ok(read_u64(index_memory, LOG_HEADER_SIZE))
+            }
+            Self::Vec { memory } => Ok(VecHeader::read(memory)?.len),
+            Self::Cell { memory } => {
+                check_header(memory, CELL_MAGIC, &[1], "cell")?;
+                Ok(1)
+            }
+        }
+    }
+
+    /// Is the structure empty.
+    pub fn is_empty(&self) -> InspectorResult<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Reads up to `limit` first entries of the structure.
+    pub fn entries(&self, limit: Option<usize>) -> InspectorResult<Vec<RawEntry>>
+    where
+        M: Clone,
+    {
+        let limit = limit.unwrap_or(usize::MAX);
+        match self {
+            Self::BTreeMap {
+                memory,
+                fixed_key_size,
+            } => {
+                check_header(memory, BTREEMAP_MAGIC, &[1, 2], "btreemap")?;
+                read_btreemap_with_key_size!(
+                    *fixed_key_size,
+                    memory.clone(),
+                    limit,
+                    [1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28
+                     29 30 31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51 52 53
+                     54 55 56 57 58 59 60 61 62 63 64]
+                )
+            }
+            Self::Log {
+                index_memory,
+                data_memory,
+            } => {
+                check_log_headers(index_memory, data_memory)?;
+                let len = read_u64(index_memory, LOG_HEADER_SIZE);
+                let mut start = 0;
+                let mut entries = vec![];
+                for index in 0..len.min(limit as u64) {
+                    let end = read_u64(index_memory, LOG_HEADER_SIZE + 8 + index * 8);
+                    if end < start {
+                        return Err(corrupted(self, "entry offsets are not increasing"));
+                    }
+                    let value = read_bytes(data_memory, LOG_HEADER_SIZE + start, end - start)
+                        .ok_or_else(|| corrupted(self, "entry is out of the data memory"))?;
+                    entries.push(RawEntry {
+                        key: EntryKey::Index(index),
+                        value,
+                    });
+                    start = end;
+                }
+
+                Ok(entries)
+            }
+            Self::Vec { memory } => {
+                let header = VecHeader::read(memory)?;
+                let mut entries = vec![];
+                for index in 0..header.len.min(limit as u64) {
+                    let offset = VEC_DATA_OFFSET + header.slot_size() * index;
+                    let (value_offset, size) = header.read_entry_size(memory, offset);
+                    if size > header.max_size as u64 {
+                        return Err(corrupted(self, "element is larger than the max size"));
+                    }
+                    let value = read_bytes(memory, value_offset, size)
+                        .ok_or_else(|| corrupted(self, "element is out of the memory"))?;
+                    entries.push(RawEntry {
+                        key: EntryKey::Index(index),
+                        value,
+                    });
+                }
+
+                Ok(entries)
+            }
+            Self::Cell { memory } => {
+                check_header(memory, CELL_MAGIC, &[1], "cell")?;
+                if limit == 0 {
+                    return Ok(vec![]);
+                }
+
+                let size = read_u32(memory, 4) as u64;
+                let value = read_bytes(memory, CELL_HEADER_SIZE, size)
+                    .ok_or_else(|| corrupted(self, "value is out of the memory"))?;
+                Ok(vec![RawEntry {
+                    key: EntryKey::Index(0),
+                    value,
+                }])
+            }
+        }
+    }
+}
+
+fn read_btreemap<M: Memory, const N: usize>(memory: M, limit: usize) -> Vec<RawEntry> {
+    btreemap::BTreeMap::<RawKey<N>, Vec<u8>, M>::load(memory)
+        .iter()
+        .take(limit)
+        .map(|(key, value)| RawEntry {
+            key: EntryKey::Bytes(key.0),
+            value,
+        })
+        .collect()
+}
+
+/// Key bytes of a `StableBTreeMap`. `N` is the size of the fixed-size keys or zero for the other keys.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RawKey<const N: usize>(Vec<u8>);
+
+impl<const N: usize> Storable for RawKey<N> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+
+    const BOUND: Bound = if N == 0 {
+        Bound::Unbounded
+    } else {
+        Bound::Bounded {
+            max_size: N as u32,
+            is_fixed_size: true,
+        }
+    };
+}
+
+struct VecHeader {
+    len: u64,
+    max_size: u32,
+    is_fixed_size: bool,
+}
+
+impl VecHeader {
+    fn read(memory: &impl Memory) -> InspectorResult<Self> {
+        check_header(memory, VEC_MAGIC, &[1], "vec")?;
+        let mut is_fixed_size = [0];
+        memory.read(16, &mut is_fixed_size);
+
+        Ok(Self {
+            len: read_u64(memory, 4),
+            max_size: read_u32(memory, 12),
+            is_fixed_size: is_fixed_size[0] != 0,
+        })
+    }
+
+    /// Number of bytes used to store the element size.
+    fn size_prefix_len(&self) -> u64 {
+        if self.is_fixed_size {
+            0
+        } else if self.max_size <= u8::MAX as u32 {
+            1
+        } else if self.max_size <= u16::MAX as u32 {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn slot_size(&self) -> u64 {
+        self.max_size as u64 + self.size_prefix_len()
+    }
+
+    /// Returns the offset and the size of the element stored in the slot at `offset`.
+    fn read_entry_size(&self, memory: &impl Memory, offset: u64) -> (u64, u64) {
+        let prefix_len = self.size_prefix_len();
+        if prefix_len == 0 {
+            return (offset, self.max_size as u64);
+        }
+
+        let mut size = [0; 4];
+        memory.read(offset, &mut size[..prefix_len as usize]);
+        (offset + prefix_len, u32::from_le_bytes(size) as u64)
+    }
+}
+
+fn check_log_headers(index_memory: &impl Memory, data_memory: &impl Memory) -> InspectorResult<()> {
+    check_header(index_memory, LOG_INDEX_MAGIC, &[1], "log index")?;
+    check_header(data_memory, LOG_DATA_MAGIC, &[1], "log data")
+}
+
+fn check_header(
+    memory: &impl Memory,
+    magic: &[u8; 3],
+    versions: &[u8],
+    structure: &'static str,
+) -> InspectorResult<()> {
+    if memory.size() == 0 {
+        return Err(InspectorError::NotInitialized(structure));
+    }
+
+    let mut header = [0; 4];
+    memory.read(0, &mut header);
+    let actual = [header[0], header[1], header[2]];
+    if &actual != magic {
+        return Err(InspectorError::BadMagic { structure, actual });
+    }
+    if !versions.contains(&header[3]) {
+        return Err(InspectorError::UnsupportedVersion {
+            structure,
+            version: header[3],
+        });
+    }
+
+    Ok(())
+}
+
+fn read_bytes(memory: &impl Memory, offset: u64, len: u64) -> Option<Vec<u8>> {
+    let end = offset.checked_add(len)?;
+    if end > memory.size() * WASM_PAGE_SIZE_IN_BYTES {
+        return None;
+    }
+
+    let mut bytes = vec![0; len as usize];
+    memory.read(offset, &mut bytes);
+    Some(bytes)
+}
+
+fn read_u32(memory: &impl Memory, offset: u64) -> u32 {
+    let mut bytes = [0; 4];
+    memory.read(offset, &mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(memory: &impl Memory, offset: u64) -> u64 {
+    let mut bytes = [0; 8];
+    memory.read(offset, &mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+fn corrupted<M: Memory>(structure: &RawStructure<M>, reason: &str) -> InspectorError {
+    InspectorError::Corrupted {
+        structure: structure.name(),
+        reason: reason.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::{
+        BTreeMapStructure, CellStructure, LogStructure, StableBTreeMap, StableCell, StableLog,
+        StableVec, VecStructure,
+    };
+
+    fn bytes_entry(key: impl Storable, value: impl Storable) -> RawEntry {
+        RawEntry {
+            key: EntryKey::Bytes(key.to_bytes().into_owned()),
+            value: value.to_bytes().into_owned(),
+        }
+    }
+
+    fn index_entry(index: u64, value: impl Storable) -> RawEntry {
+        RawEntry {
+            key: EntryKey::Index(index),
+            value: value.to_bytes().into_owned(),
+        }
+    }
+
+    #[test]
+    fn should_read_btreemap() {
+        let memory = VectorMemory::default();
+        let mut map = StableBTreeMap::<u64, String, _>::new(memory.clone());
+        for i in 0..100u64 {
+            map.insert(i, format!("value {i}"));
+        }
+
+        let raw = RawStructure::BTreeMap {
+            memory: memory.clone(),
+            fixed_key_size: Some(8),
+        };
+        assert_eq!(raw.len().unwrap(), 100);
+        let entries = raw.entries(Some(3)).unwrap();
+        assert_eq!(
+            entries,
+            (0..3u64)
+                .map(|i| bytes_entry(i, format!("value {i}")))
+                .collect::<Vec<_>>()
+        );
+
+        let raw = RawStructure::BTreeMap {
+            memory,
+            fixed_key_size: Some(65),
+        };
+        assert!(matches!(
+            raw.entries(None),
+            Err(InspectorError::UnsupportedKeySize(65))
+        ));
+    }
+
+    #[test]
+    fn should_read_btreemap_with_unbounded_keys() {
+        let memory = VectorMemory::default();
+        let mut map = StableBTreeMap::<String, u64, _>::new(memory.clone());
+        map.insert("b".to_string(), 2);
+        map.insert("a".to_string(), 1);
+
+        let raw = RawStructure::BTreeMap {
+            memory,
+            fixed_key_size: None,
+        };
+        assert_eq!(
+            raw.entries(None).unwrap(),
+            vec![
+                bytes_entry("a".to_string(), 1u64),
+                bytes_entry("b".to_string(), 2u64)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_read_vec() {
+        let memory = VectorMemory::default();
+        let mut vec = StableVec::<u64, _>::new(memory.clone()).unwrap();
+        for i in 0..10u64 {
+            vec.push(&(i * 10)).unwrap();
+        }
+
+        let raw = RawStructure::Vec { memory };
+        assert_eq!(raw.len().unwrap(), 10);
+        let entries = raw.entries(None).unwrap();
+        assert_eq!(entries.len(), 10);
+        assert_eq!(entries[9], index_entry(9, 90u64));
+    }
+
+    #[test]
+    fn should_read_log() {
+        let index_memory = VectorMemory::default();
+        let data_memory = VectorMemory::default();
+        let mut log =
+            StableLog::<String, _>::new(index_memory.clone(), data_memory.clone()).unwrap();
+        for value in ["first", "", "third"] {
+            log.append(value.to_string()).unwrap();
+        }
+
+        let raw = RawStructure::Log {
+            index_memory,
+            data_memory,
+        };
+        assert_eq!(raw.len().unwrap(), 3);
+        assert_eq!(
+            raw.entries(None).unwrap(),
+            vec![
+                index_entry(0, "first".to_string()),
+                index_entry(1, String::new()),
+                index_entry(2, "third".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_read_cell() {
+        let memory = VectorMemory::default();
+        let mut cell = StableCell::new(memory.clone(), 1u64).unwrap();
+        cell.set(5).unwrap();
+
+        let raw = RawStructure::Cell { memory };
+        assert_eq!(raw.len().unwrap(), 1);
+        assert_eq!(raw.entries(None).unwrap(), vec![index_entry(0, 5u64)]);
+        assert_eq!(raw.entries(Some(0)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn should_check_structure_type() {
+        let memory = VectorMemory::default();
+        assert!(matches!(
+            RawStructure::Cell {
+                memory: memory.clone()
+            }
+            .len(),
+            Err(InspectorError::NotInitialized("cell"))
+        ));
+
+        StableVec::<u64, _>::new(memory.clone()).unwrap();
+        assert!(matches!(
+            RawStructure::BTreeMap {
+                memory,
+                fixed_key_size: None
+            }
+            .entries(None),
+            Err(InspectorError::BadMagic {
+                structure: "btreemap",
+                actual: [b'S', b'V', b'C']
+            })
+        ));
+    }
+}
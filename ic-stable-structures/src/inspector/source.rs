@@ -0,0 +1,180 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use dfinity_stable_structures::Memory;
+
+use super::error::{InspectorError, InspectorResult};
+use crate::{
+    IcStableMemoryImage, IcStableMemoryImageMemory, MemoryMappedFileMemory,
+    MemoryMappedFileMemoryManager, SyncPolicy,
+};
+
+const WASM_PAGE_SIZE_IN_BYTES: u64 = 65536;
+
+/// Memories to inspect: a [`MemoryMappedFileMemoryManager`] directory or an IC stable memory image.
+///
+/// Both are opened read-only.
+pub enum InspectorSource {
+    Directory {
+        base_path: PathBuf,
+        manager: MemoryMappedFileMemoryManager,
+    },
+    Image(IcStableMemoryImage),
+}
+
+/// Memory id and its size in Wasm pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryInfo {
+    pub id: u8,
+    pub size_in_pages: u64,
+}
+
+impl InspectorSource {
+    /// Opens the memory-mapped files directory. Set `is_durable` for the files created in the durable mode.
+    pub fn open_directory(base_path: PathBuf, is_durable: bool) -> InspectorResult<Self> {
+        // Read-only files never grow, so the mapping needs only the space of the largest file.
+        // The default reservation may not fit into the address space limits.
+        let mut max_file_length = WASM_PAGE_SIZE_IN_BYTES;
+        for entry in fs::read_dir(&base_path)? {
+            max_file_length = max_file_length.max(entry?.metadata()?.len());
+        }
+
+        let mut manager = MemoryMappedFileMemoryManager::new_read_only(base_path.clone())
+            .with_max_memory_size_per_id(max_file_length as usize);
+        if is_durable {
+            manager = manager.with_durable_mode(SyncPolicy::Explicit);
+        }
+
+        Ok(Self::Directory { base_path, manager })
+    }
+
+    /// Opens the IC stable memory image.
+    pub fn open_image(path: impl AsRef<Path>) -> InspectorResult<Self> {
+        Ok(Self::Image(IcStableMemoryImage::open(path)?))
+    }
+
+    /// Returns the available memories ordered by id.
+    pub fn memories(&self) -> InspectorResult<Vec<MemoryInfo>> {
+        match self {
+            Self::Directory { base_path, .. } => {
+                let mut ids = vec![];
+                for entry in fs::read_dir(base_path)? {
+                    let entry = entry?;
+                    // Skip the files which are not memories, e.g. the backup manifests.
+                    let id = entry
+                        .file_name()
+                        .to_str()
+                        .and_then(|name| name.parse().ok());
+                    if let Some(id) = id.filter(|_| entry.path().is_file()) {
+                        ids.push(id);
+                    }
+                }
+                ids.sort_unstable();
+
+                ids.into_iter()
+                    .map(|id| {
+                        Ok(MemoryInfo {
+                            id,
+                            size_in_pages: self.memory(id)?.size(),
+                        })
+                    })
+                    .collect()
+            }
+            Self::Image(image) => Ok(image
+                .memory_ids()
+                .into_iter()
+                .map(|id| MemoryInfo {
+                    id,
+                    size_in_pages: image.memory_size_in_pages(id),
+                })
+                .collect()),
+        }
+    }
+
+    /// Opens the memory with the given id.
+    pub fn memory(&self, id: u8) -> InspectorResult<InspectedMemory> {
+        match self {
+            Self::Directory { base_path, manager } => {
+                let name = id.to_string();
+                if !base_path.join(&name).is_file() {
+                    return Err(InspectorError::MemoryNotFound(id));
+                }
+
+                Ok(InspectedMemory::File(manager.open(name)?))
+            }
+            Self::Image(image) => image
+                .memory(id)
+                .map(InspectedMemory::Image)
+                .ok_or(InspectorError::MemoryNotFound(id)),
+        }
+    }
+}
+
+/// Read-only memory of an [`InspectorSource`].
+#[derive(Clone)]
+pub enum InspectedMemory {
+    File(MemoryMappedFileMemory),
+    Image(IcStableMemoryImageMemory),
+}
+
+impl Memory for InspectedMemory {
+    fn size(&self) -> u64 {
+        match self {
+            Self::File(memory) => memory.size(),
+            Self::Image(memory) => memory.size(),
+        }
+    }
+
+    fn grow(&self, pages: u64) -> i64 {
+        match self {
+            Self::File(memory) => memory.grow(pages),
+            Self::Image(memory) => memory.grow(pages),
+        }
+    }
+
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        match self {
+            Self::File(memory) => memory.read(offset, dst),
+            Self::Image(memory) => memory.read(offset, dst),
+        }
+    }
+
+    fn write(&self, offset: u64, src: &[u8]) {
+        match self {
+            Self::File(memory) => memory.write(offset, src),
+            Self::Image(memory) => memory.write(offset, src),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{MemoryManager, StableCell};
+
+    #[test]
+    fn should_list_directory_memories() {
+        let base_dir = TempDir::new().unwrap();
+        {
+            let manager = MemoryMappedFileMemoryManager::new(base_dir.path().to_path_buf(), true);
+            StableCell::new(manager.get(7), 1u64).unwrap();
+            StableCell::new(manager.get(2), 2u64).unwrap();
+        }
+        fs::write(base_dir.path().join("manifest.json"), b"{}").unwrap();
+
+        let source = InspectorSource::open_directory(base_dir.path().to_path_buf(), false).unwrap();
+        let memories = source.memories().unwrap();
+        assert_eq!(
+            memories.iter().map(|info| info.id).collect::<Vec<_>>(),
+            vec![2, 7]
+        );
+        assert!(memories.iter().all(|info| info.size_in_pages == 1));
+
+        assert!(matches!(
+            source.memory(3),
+            Err(InspectorError::MemoryNotFound(3))
+        ));
+    }
+}
@@ -1,18 +1,24 @@
+pub mod codec;
 mod structure;
 
 mod error;
+#[cfg(feature = "inspector")]
+pub mod inspector;
 mod memory;
 #[cfg(feature = "memory-mapped-files-memory")]
 mod memory_mapped_files;
+mod quota;
 
 #[cfg(test)]
 mod test_utils;
 
 pub use dfinity_stable_structures as stable_structures;
 pub use error::{Error, Result};
+pub use ic_stable_structures_derive::{Bounded, Storable};
 pub use memory::*;
 #[cfg(feature = "memory-mapped-files-memory")]
 pub use memory_mapped_files::*;
+pub use quota::*;
 pub use stable_structures::memory_manager::{
     MemoryId, MemoryManager as IcMemoryManager, VirtualMemory,
 };
@@ -0,0 +1,258 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::error::{MemMapError, MemMapResult};
+use super::memory_mapped_file::{MemoryMappedFile, PAGE_SIZE};
+
+/// Name of the manifest file in a backup directory.
+pub const BACKUP_MANIFEST_FILE_NAME: &str = "manifest.json";
+
+const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// Which data to include into a backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupKind {
+    /// All the memories with all their data.
+    Full,
+    /// Only the pages modified since the previous backup made by the same manager.
+    Incremental,
+}
+
+/// Compression of the backup data files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackupCompression {
+    None,
+    Deflate,
+}
+
+/// Description of a backup, stored as `manifest.json` in the backup directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    /// Unique id of the backup.
+    pub backup_id: u64,
+    /// Id of the backup this one is based on, `None` for a full backup.
+    pub base_backup_id: Option<u64>,
+    /// Creation time, nanoseconds since the Unix epoch.
+    pub created_at: u64,
+    pub compression: BackupCompression,
+    pub page_size: u64,
+    pub files: Vec<BackupFile>,
+}
+
+/// Backed up data of a single memory file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupFile {
+    /// Name of the memory file in the manager directory.
+    pub name: String,
+    /// Memory length in bytes at the moment of the backup.
+    pub length: u64,
+    /// Byte ranges `[start, end)` stored in the data file, in order.
+    pub ranges: Vec<(u64, u64)>,
+    /// Name of the data file in the backup directory.
+    pub data_file: String,
+    /// Hex-encoded SHA-256 of the uncompressed data.
+    pub sha256: String,
+}
+
+impl BackupManifest {
+    /// Loads the manifest from the backup directory.
+    pub fn load(backup_path: impl AsRef<Path>) -> MemMapResult<Self> {
+        let file = File::open(backup_path.as_ref().join(BACKUP_MANIFEST_FILE_NAME))?;
+        let manifest: Self = serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| invalid_backup(format!("failed to parse manifest: {err}")))?;
+
+        if manifest.format_version != BACKUP_FORMAT_VERSION {
+            return Err(invalid_backup(format!(
+                "unsupported backup format version {}",
+                manifest.format_version
+            )));
+        }
+        if manifest.page_size != PAGE_SIZE as u64 {
+            return Err(invalid_backup(format!(
+                "unsupported page size {}",
+                manifest.page_size
+            )));
+        }
+
+        Ok(manifest)
+    }
+
+    fn save(&self, backup_path: &Path) -> MemMapResult<()> {
+        let file = File::create(backup_path.join(BACKUP_MANIFEST_FILE_NAME))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|err| MemMapError::FileOpenError(err.into()))?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Checks that the backups form a chain: a full backup followed by the increments,
+/// each of them based on the previous one.
+pub(super) fn check_backup_chain(manifests: &[BackupManifest]) -> MemMapResult<()> {
+    let Some(first) = manifests.first() else {
+        return Err(invalid_backup("no backups to restore".to_owned()));
+    };
+    if first.base_backup_id.is_some() {
+        return Err(invalid_backup(format!(
+            "backup {} is incremental, the chain should start with a full backup",
+            first.backup_id
+        )));
+    }
+
+    for pair in manifests.windows(2) {
+        if pair[1].base_backup_id != Some(pair[0].backup_id) {
+            return Err(invalid_backup(format!(
+                "backup {} is not based on backup {}",
+                pair[1].backup_id, pair[0].backup_id
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns an id for a new backup: the current time in nanoseconds, but always
+/// greater than the previous id.
+pub(super) fn next_backup_id(previous_id: Option<u64>) -> u64 {
+    let now = now_nanos();
+    match previous_id {
+        Some(previous_id) if previous_id >= now => previous_id + 1,
+        _ => now,
+    }
+}
+
+/// Writes the backup of the memory files to an empty `backup_path` directory.
+pub(super) fn write_backup(
+    backup_path: &Path,
+    files: &[(String, &MemoryMappedFile)],
+    kind: BackupKind,
+    compression: BackupCompression,
+    backup_id: u64,
+    base_backup_id: Option<u64>,
+) -> MemMapResult<BackupManifest> {
+    fs::create_dir_all(backup_path)?;
+    if backup_path.join(BACKUP_MANIFEST_FILE_NAME).exists() {
+        return Err(invalid_backup(format!(
+            "backup directory {} is not empty",
+            backup_path.display()
+        )));
+    }
+
+    let mut backup_files = Vec::with_capacity(files.len());
+    for (name, file) in files {
+        let ranges = file.backup_ranges(kind == BackupKind::Full);
+        let data_file = format!("{name}.pages");
+
+        let mut hasher = Sha256::new();
+        let writer = BufWriter::new(File::create(backup_path.join(&data_file))?);
+        let mut writer: Box<dyn Write> = match compression {
+            BackupCompression::None => Box::new(writer),
+            BackupCompression::Deflate => {
+                Box::new(DeflateEncoder::new(writer, Compression::default()))
+            }
+        };
+
+        let mut buf = vec![0; PAGE_SIZE];
+        for range in &ranges {
+            for chunk_start in range.clone().step_by(PAGE_SIZE) {
+                let chunk = &mut buf[..(range.end - chunk_start).min(PAGE_SIZE)];
+                file.read(chunk_start, chunk)?;
+                hasher.update(&chunk);
+                writer.write_all(chunk)?;
+            }
+        }
+        writer.flush()?;
+        drop(writer);
+
+        backup_files.push(BackupFile {
+            name: name.clone(),
+            length: file.len() as u64,
+            ranges: ranges
+                .iter()
+                .map(|range| (range.start as u64, range.end as u64))
+                .collect(),
+            data_file,
+            sha256: hex::encode(hasher.finalize()),
+        });
+    }
+
+    let manifest = BackupManifest {
+        format_version: BACKUP_FORMAT_VERSION,
+        backup_id,
+        base_backup_id,
+        created_at: now_nanos(),
+        compression,
+        page_size: PAGE_SIZE as u64,
+        files: backup_files,
+    };
+    manifest.save(backup_path)?;
+
+    Ok(manifest)
+}
+
+/// Reads the data of the backed up file and passes it to `apply` in chunks
+/// together with their offsets in the memory.
+///
+/// The checksum is verified after all the data is read, so `apply` may receive
+/// the data of a corrupted backup before the error is returned.
+pub(super) fn read_backup_file(
+    backup_path: &Path,
+    manifest: &BackupManifest,
+    file: &BackupFile,
+    mut apply: impl FnMut(usize, &[u8]) -> MemMapResult<()>,
+) -> MemMapResult<()> {
+    let reader = BufReader::new(File::open(backup_path.join(&file.data_file))?);
+    let mut reader: Box<dyn Read> = match manifest.compression {
+        BackupCompression::None => Box::new(reader),
+        BackupCompression::Deflate => Box::new(DeflateDecoder::new(reader)),
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; PAGE_SIZE];
+    for &(start, end) in &file.ranges {
+        if start > end || end > file.length {
+            return Err(invalid_backup(format!(
+                "invalid range {start}..{end} of {}",
+                file.name
+            )));
+        }
+
+        for chunk_start in (start as usize..end as usize).step_by(PAGE_SIZE) {
+            let chunk = &mut buf[..(end as usize - chunk_start).min(PAGE_SIZE)];
+            reader.read_exact(chunk)?;
+            hasher.update(&chunk);
+            apply(chunk_start, chunk)?;
+        }
+    }
+
+    if hex::encode(hasher.finalize()) != file.sha256 {
+        return Err(invalid_backup(format!(
+            "checksum mismatch of {} in backup {}",
+            file.name, manifest.backup_id
+        )));
+    }
+
+    Ok(())
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn invalid_backup(reason: String) -> MemMapError {
+    MemMapError::InvalidBackup(reason)
+}
@@ -12,6 +12,16 @@ pub enum MemMapError {
     SizeShouldBePageSizeMultiple,
     #[error("invalid source file name")]
     InvalidSourceFileName,
+    #[error("file {path} is inconsistent: {reason}")]
+    InconsistentFile { path: String, reason: String },
+    #[error("invalid stable memory image: {0}")]
+    InvalidStableMemoryImage(String),
+    #[error("memory is read-only")]
+    ReadOnly,
+    #[error("invalid backup: {0}")]
+    InvalidBackup(String),
+    #[error("incremental backup requires a previous backup made by the same manager")]
+    NoBaseBackup,
 }
 
 pub type MemMapResult<T> = Result<T, MemMapError>;
@@ -0,0 +1,294 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use dfinity_stable_structures::Memory;
+use memmap2::{Mmap, MmapOptions};
+
+use super::error::{MemMapError, MemMapResult};
+use super::memory::MemoryMappedFileMemoryManager;
+
+const WASM_PAGE_SIZE_IN_BYTES: u64 = 65536;
+
+/// Layout constants of the `dfinity-stable-structures` memory manager.
+const MAGIC: &[u8; 3] = b"MGR";
+const LAYOUT_VERSION: u8 = 1;
+const MAX_NUM_MEMORIES: usize = 255;
+const MAX_NUM_BUCKETS: usize = 32768;
+const UNALLOCATED_BUCKET_MARKER: u8 = MAX_NUM_MEMORIES as u8;
+const HEADER_RESERVED_BYTES: usize = 32;
+/// Header: magic, version, number of allocated buckets, bucket size, reserved bytes, memory sizes.
+const HEADER_SIZE: usize = 3 + 1 + 2 + 2 + HEADER_RESERVED_BYTES + MAX_NUM_MEMORIES * 8;
+const BUCKETS_OFFSET_IN_BYTES: u64 = WASM_PAGE_SIZE_IN_BYTES;
+
+/// Raw IC stable memory image (e.g. a stable memory dump of a canister snapshot)
+/// managed by the `dfinity-stable-structures` memory manager.
+///
+/// The virtual memories of the image can be extracted into a [`MemoryMappedFileMemoryManager`]
+/// directory, so they're available by the same memory ids the canister used.
+pub struct IcStableMemoryImage {
+    image: Arc<Mmap>,
+    bucket_size_in_pages: u16,
+    memory_sizes_in_pages: Vec<u64>,
+    /// Buckets allocated to each memory, in order.
+    memory_buckets: Vec<Vec<u16>>,
+}
+
+impl IcStableMemoryImage {
+    /// Opens the image file and decodes the memory manager header.
+    pub fn open(path: impl AsRef<Path>) -> MemMapResult<Self> {
+        let file = File::open(path)?;
+        // Safety: the image is mapped read-only, it should not be modified while it's open.
+        let image = unsafe { MmapOptions::new().map(&file) }?;
+
+        if image.len() < HEADER_SIZE + MAX_NUM_BUCKETS {
+            return Err(invalid_image(
+                "image is shorter than the memory manager header",
+            ));
+        }
+        if &image[..3] != MAGIC {
+            return Err(invalid_image("memory manager magic not found"));
+        }
+        if image[3] != LAYOUT_VERSION {
+            return Err(invalid_image("unsupported memory manager layout version"));
+        }
+
+        let num_allocated_buckets = u16::from_le_bytes([image[4], image[5]]);
+        let bucket_size_in_pages = u16::from_le_bytes([image[6], image[7]]);
+        if bucket_size_in_pages == 0 {
+            return Err(invalid_image("zero bucket size"));
+        }
+
+        let sizes_offset = HEADER_SIZE - MAX_NUM_MEMORIES * 8;
+        let memory_sizes_in_pages = image[sizes_offset..HEADER_SIZE]
+            .chunks_exact(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().expect("valid chunk length")))
+            .collect::<Vec<_>>();
+
+        let mut memory_buckets = vec![vec![]; MAX_NUM_MEMORIES];
+        let allocations = &image[HEADER_SIZE..HEADER_SIZE + MAX_NUM_BUCKETS];
+        for (bucket, &memory_id) in allocations.iter().enumerate() {
+            if memory_id == UNALLOCATED_BUCKET_MARKER {
+                continue;
+            }
+            if bucket >= num_allocated_buckets as usize {
+                return Err(invalid_image(
+                    "bucket is allocated beyond the allocated buckets count",
+                ));
+            }
+            memory_buckets[memory_id as usize].push(bucket as u16);
+        }
+
+        let image = Self {
+            image: Arc::new(image),
+            bucket_size_in_pages,
+            memory_sizes_in_pages,
+            memory_buckets,
+        };
+        image.check_bounds()?;
+
+        Ok(image)
+    }
+
+    /// Ids of the non-empty memories.
+    pub fn memory_ids(&self) -> Vec<u8> {
+        (0..MAX_NUM_MEMORIES)
+            .filter(|&id| self.memory_sizes_in_pages[id] > 0)
+            .map(|id| id as u8)
+            .collect()
+    }
+
+    /// Size of the memory in Wasm pages.
+    pub fn memory_size_in_pages(&self, id: u8) -> u64 {
+        self.memory_sizes_in_pages
+            .get(id as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Size of a bucket in Wasm pages.
+    pub fn bucket_size_in_pages(&self) -> u16 {
+        self.bucket_size_in_pages
+    }
+
+    /// Returns a read-only view of the virtual memory with the given id, so it can be
+    /// read in place without extracting the image. Returns `None` for an empty memory.
+    pub fn memory(&self, id: u8) -> Option<IcStableMemoryImageMemory> {
+        let size_in_pages = self.memory_size_in_pages(id);
+        if size_in_pages == 0 {
+            return None;
+        }
+
+        Some(IcStableMemoryImageMemory {
+            image: self.image.clone(),
+            bucket_offsets: self.memory_buckets[id as usize]
+                .iter()
+                .map(|&bucket| self.bucket_offset(bucket))
+                .collect(),
+            bucket_size_in_bytes: self.bucket_size_in_bytes(),
+            size_in_pages,
+        })
+    }
+
+    /// Writes each non-empty virtual memory into the `base_path` folder as a file named
+    /// by the memory id and returns the manager of these files.
+    ///
+    /// The files are persistent, so the image is not needed after the extraction.
+    pub fn extract_to(&self, base_path: PathBuf) -> MemMapResult<MemoryMappedFileMemoryManager> {
+        fs::create_dir_all(&base_path)?;
+
+        for id in self.memory_ids() {
+            let mut remaining = self.memory_size_in_pages(id) * WASM_PAGE_SIZE_IN_BYTES;
+            let mut file = BufWriter::new(File::create(base_path.join(id.to_string()))?);
+            for &bucket in &self.memory_buckets[id as usize] {
+                if remaining == 0 {
+                    break;
+                }
+
+                let len = remaining.min(self.bucket_size_in_bytes());
+                let start = self.bucket_offset(bucket) as usize;
+                file.write_all(&self.image[start..start + len as usize])?;
+                remaining -= len;
+            }
+            file.flush()?;
+        }
+
+        Ok(MemoryMappedFileMemoryManager::new(base_path, true))
+    }
+
+    /// Checks that the memories fit into their buckets and the buckets fit into the image.
+    fn check_bounds(&self) -> MemMapResult<()> {
+        for (id, buckets) in self.memory_buckets.iter().enumerate() {
+            let capacity = buckets.len() as u64 * self.bucket_size_in_pages as u64;
+            if self.memory_sizes_in_pages[id] > capacity {
+                return Err(invalid_image(&format!(
+                    "memory {id} is larger than its allocated buckets"
+                )));
+            }
+        }
+
+        if let Some(&last_bucket) = self.memory_buckets.iter().flatten().max() {
+            let end = self.bucket_offset(last_bucket) + self.bucket_size_in_bytes();
+            if end > self.image.len() as u64 {
+                return Err(invalid_image("image is truncated"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn bucket_size_in_bytes(&self) -> u64 {
+        self.bucket_size_in_pages as u64 * WASM_PAGE_SIZE_IN_BYTES
+    }
+
+    fn bucket_offset(&self, bucket: u16) -> u64 {
+        BUCKETS_OFFSET_IN_BYTES + self.bucket_size_in_bytes() * bucket as u64
+    }
+}
+
+/// Read-only virtual memory of an [`IcStableMemoryImage`].
+///
+/// The memory can't be modified, `write` and `grow` panic.
+#[derive(Clone)]
+pub struct IcStableMemoryImageMemory {
+    image: Arc<Mmap>,
+    bucket_offsets: Vec<u64>,
+    bucket_size_in_bytes: u64,
+    size_in_pages: u64,
+}
+
+impl Memory for IcStableMemoryImageMemory {
+    fn size(&self) -> u64 {
+        self.size_in_pages
+    }
+
+    fn grow(&self, _pages: u64) -> i64 {
+        panic!("stable memory image is read-only")
+    }
+
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        let end = offset
+            .checked_add(dst.len() as u64)
+            .expect("read address overflow");
+        assert!(
+            end <= self.size_in_pages * WASM_PAGE_SIZE_IN_BYTES,
+            "invalid stable memory image read"
+        );
+
+        // The read may span several buckets which are not adjacent in the image.
+        let mut offset = offset;
+        let mut dst = dst;
+        while !dst.is_empty() {
+            let bucket = (offset / self.bucket_size_in_bytes) as usize;
+            let offset_in_bucket = offset % self.bucket_size_in_bytes;
+            let len = (self.bucket_size_in_bytes - offset_in_bucket).min(dst.len() as u64) as usize;
+            let start = (self.bucket_offsets[bucket] + offset_in_bucket) as usize;
+
+            let (head, tail) = dst.split_at_mut(len);
+            head.copy_from_slice(&self.image[start..start + len]);
+            dst = tail;
+            offset += len as u64;
+        }
+    }
+
+    fn write(&self, _offset: u64, _src: &[u8]) {
+        panic!("stable memory image is read-only")
+    }
+}
+
+fn invalid_image(reason: &str) -> MemMapError {
+    MemMapError::InvalidStableMemoryImage(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn header_size_should_match_memory_manager_layout() {
+        assert_eq!(HEADER_SIZE, 2080);
+    }
+
+    fn open_image(bytes: &[u8]) -> MemMapResult<IcStableMemoryImage> {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        IcStableMemoryImage::open(file.path())
+    }
+
+    fn assert_invalid(result: MemMapResult<IcStableMemoryImage>, expected_reason: &str) {
+        match result {
+            Err(MemMapError::InvalidStableMemoryImage(reason)) => {
+                assert!(
+                    reason.contains(expected_reason),
+                    "unexpected reason: {reason}"
+                )
+            }
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("invalid image was opened"),
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_images() {
+        assert_invalid(open_image(b"MGR"), "shorter");
+
+        let mut image = vec![0; WASM_PAGE_SIZE_IN_BYTES as usize];
+        assert_invalid(open_image(&image), "magic");
+
+        image[..4].copy_from_slice(b"MGR\x02");
+        assert_invalid(open_image(&image), "version");
+
+        // A memory which claims a bucket that is not in the image.
+        image[3] = LAYOUT_VERSION;
+        image[4..6].copy_from_slice(&1u16.to_le_bytes());
+        image[6..8].copy_from_slice(&1u16.to_le_bytes());
+        image[HEADER_SIZE..HEADER_SIZE + MAX_NUM_BUCKETS].fill(UNALLOCATED_BUCKET_MARKER);
+        image[HEADER_SIZE] = 0;
+        assert_invalid(open_image(&image), "truncated");
+    }
+}
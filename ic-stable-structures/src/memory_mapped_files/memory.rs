@@ -4,10 +4,15 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use dfinity_stable_structures::Memory;
-use parking_lot::{RwLock, RwLockReadGuard};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+use super::backup::{
+    check_backup_chain, next_backup_id, read_backup_file, write_backup, BackupCompression,
+    BackupKind, BackupManifest,
+};
 use super::error::{MemMapError, MemMapResult};
-use super::memory_mapped_file::MemoryMappedFile;
+use super::memory_mapped_file::{MemoryMappedFile, SyncPolicy};
+use super::snapshot::{MemoryMappedFileManagerSnapshot, MemoryMappedFileSnapshot};
 use crate::memory::MemoryManager;
 
 const WASM_PAGE_SIZE_IN_BYTES: u64 = 65536;
@@ -26,6 +31,10 @@ pub struct MemoryMappedFileMemoryManager {
     is_persistent: bool,
     created_memory_resources: RwLock<BTreeMap<PathBuf, MemoryMappedFileMemory>>,
     max_memory_length: usize,
+    sync_policy: Option<SyncPolicy>,
+    is_read_only: bool,
+    /// Id of the last backup made by this manager, the base for the next incremental backup.
+    last_backup_id: Mutex<Option<u64>>,
 }
 
 impl MemoryMappedFileMemoryManager {
@@ -37,6 +46,73 @@ impl MemoryMappedFileMemoryManager {
             is_persistent,
             created_memory_resources: Default::default(),
             max_memory_length: DEFAULT_MEM_MAP_RESERVED_LENGTH,
+            sync_policy: None,
+            is_read_only: false,
+            last_backup_id: Mutex::new(None),
+        }
+    }
+
+    /// Create a manager which opens the existing files in the `base_path` folder in the read-only mode.
+    ///
+    /// Any number of read-only managers may use the folder together with a writer.
+    /// The memories can't be modified: `write` and `grow` panic. Opening a memory which
+    /// doesn't exist fails. Use [`Self::with_durable_mode`] to read the files created in the durable mode.
+    pub fn new_read_only(base_path: PathBuf) -> Self {
+        Self {
+            is_read_only: true,
+            ..Self::new(base_path, true)
+        }
+    }
+
+    /// Flush all the opened memories to disk. In the durable mode also marks
+    /// the files as consistent.
+    pub fn sync(&self) -> MemMapResult<()> {
+        for memory in self.created_memory_resources.read().values() {
+            memory.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Open the memory for the given id or return the error if the file can't be opened,
+    /// e.g. when it's inconsistent in the durable mode.
+    ///
+    /// `MemoryManager::get` panics in this case.
+    pub fn open(&self, id: impl AsRef<Path>) -> MemMapResult<MemoryMappedFileMemory> {
+        let mut created_memory_resources = self.created_memory_resources.write();
+        let file_path = self.base_path.join(id.as_ref());
+        match created_memory_resources.entry(file_path) {
+            Entry::Vacant(entry) => {
+                let file_path = entry
+                    .key()
+                    .to_str()
+                    .ok_or(MemMapError::InvalidSourceFileName)?;
+                let memory = if self.is_read_only {
+                    MemoryMappedFileMemory::new_read_only(
+                        file_path.to_owned(),
+                        self.max_memory_length,
+                        self.sync_policy.is_some(),
+                    )?
+                } else if let Some(sync_policy) = self.sync_policy {
+                    MemoryMappedFileMemory::new_durable(
+                        file_path.to_owned(),
+                        self.max_memory_length,
+                        self.is_persistent,
+                        sync_policy,
+                    )?
+                } else {
+                    MemoryMappedFileMemory::new(
+                        file_path.to_owned(),
+                        self.max_memory_length,
+                        self.is_persistent,
+                    )?
+                };
+
+                entry.insert(memory.clone());
+
+                Ok(memory)
+            }
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
         }
     }
 
@@ -48,6 +124,20 @@ impl MemoryMappedFileMemoryManager {
         self
     }
 
+    /// Open the files in the durable mode.
+    ///
+    /// Each file gets a header page with the data size and checksum, which is updated when
+    /// the file is synced according to the `sync_policy` or by [`Self::sync`].
+    /// A file which wasn't synced after the last write, e.g. because the process crashed,
+    /// fails to open with [`MemMapError::InconsistentFile`].
+    ///
+    /// Files created without the durable mode can't be opened in the durable mode and vice versa.
+    pub fn with_durable_mode(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = Some(sync_policy);
+
+        self
+    }
+
     /// Flush and save the memory-mapped files to the given path.
     /// Note that this function should be executed at the point when the stable storage state in consistent in order
     /// to save a consistent backup.
@@ -74,32 +164,127 @@ impl MemoryMappedFileMemoryManager {
         Ok(())
     }
 
-    fn get_impl(&self, id: impl AsRef<Path>) -> MemoryMappedFileMemory {
-        let mut created_memory_resources = self.created_memory_resources.write();
-        let file_path = self.base_path.join(id.as_ref());
-        match created_memory_resources.entry(file_path) {
-            Entry::Vacant(entry) => {
-                let file_path = entry.key().to_str().unwrap_or_else(|| {
-                    panic!("Cannot extract path from {}", entry.key().display())
-                });
-                let result = MemoryMappedFileMemory::new(
-                    file_path.to_owned(),
-                    self.max_memory_length,
-                    self.is_persistent,
-                )
-                .unwrap_or_else(|_| {
-                    panic!(
-                        "failed to initialize MemoryMappedFileMemory with path: {}",
-                        file_path
-                    )
-                });
-
-                entry.insert(result.clone());
-
-                result
+    /// Create copy-on-write snapshots of all the memories opened by this manager.
+    ///
+    /// The memories are locked only while the files are mapped, the writes
+    /// after that copy the modified pages into the snapshots, so the snapshots
+    /// keep showing the state at the moment of the call.
+    pub fn snapshot(&self) -> MemMapResult<MemoryMappedFileManagerSnapshot> {
+        let created_memory_resources = self.created_memory_resources.read();
+        let mut locks = Vec::with_capacity(created_memory_resources.len());
+        for (file_path, memory) in created_memory_resources.iter() {
+            let file_name = file_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or(MemMapError::InvalidSourceFileName)?;
+            locks.push((file_name.to_owned(), memory.write_lock()));
+        }
+
+        let memories = locks
+            .iter_mut()
+            .map(|(name, memory)| Ok((name.clone(), MemoryMappedFileSnapshot(memory.snapshot()?))))
+            .collect::<MemMapResult<_>>()?;
+
+        Ok(MemoryMappedFileManagerSnapshot { memories })
+    }
+
+    /// Save a backup of the memories opened by this manager to the `path` directory.
+    ///
+    /// A full backup contains all the data. An incremental backup contains only the pages
+    /// modified since the previous backup made by this manager (and all the data of the memories
+    /// opened after it), so it can be restored only on top of the previous backups of the chain.
+    /// Returns [`MemMapError::NoBaseBackup`] if there is no previous backup.
+    ///
+    /// All the memories are locked for writing during the backup, so the backup is consistent
+    /// if the stable storage state is consistent at the moment of the call.
+    pub fn save_backup(
+        &self,
+        path: impl AsRef<Path>,
+        kind: BackupKind,
+        compression: BackupCompression,
+    ) -> MemMapResult<BackupManifest> {
+        let mut last_backup_id = self.last_backup_id.lock();
+        let base_backup_id = match kind {
+            BackupKind::Full => None,
+            BackupKind::Incremental => Some(last_backup_id.ok_or(MemMapError::NoBaseBackup)?),
+        };
+
+        let created_memory_resources = self.created_memory_resources.read();
+        let mut locks = Vec::with_capacity(created_memory_resources.len());
+        for (file_path, memory) in created_memory_resources.iter() {
+            let file_name = file_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or(MemMapError::InvalidSourceFileName)?;
+            locks.push((file_name.to_owned(), memory.write_lock()));
+        }
+
+        let backup_id = next_backup_id(*last_backup_id);
+        let files = locks
+            .iter()
+            .map(|(name, memory)| (name.clone(), &**memory))
+            .collect::<Vec<_>>();
+        let manifest = write_backup(
+            path.as_ref(),
+            &files,
+            kind,
+            compression,
+            backup_id,
+            base_backup_id,
+        )?;
+
+        for (_, memory) in &mut locks {
+            memory.mark_backed_up();
+        }
+        *last_backup_id = Some(backup_id);
+
+        Ok(manifest)
+    }
+
+    /// Restore the memories from the backup directories: a full backup followed by
+    /// the chain of incremental backups based on it, in order.
+    ///
+    /// The manager directory should not contain the backed up memories, since they
+    /// can't be shrunk to the backed up size. If the backup is corrupted,
+    /// [`MemMapError::InvalidBackup`] is returned and the memories may be partially restored.
+    pub fn restore_backups(&self, backup_paths: &[impl AsRef<Path>]) -> MemMapResult<()> {
+        let manifests = backup_paths
+            .iter()
+            .map(BackupManifest::load)
+            .collect::<MemMapResult<Vec<_>>>()?;
+        check_backup_chain(&manifests)?;
+
+        for (backup_path, manifest) in backup_paths.iter().zip(&manifests) {
+            for file in &manifest.files {
+                let memory = self.open(&file.name)?;
+                let mut memory = memory.write_lock();
+
+                let length = file.length as usize;
+                if memory.len() > length {
+                    return Err(MemMapError::InvalidBackup(format!(
+                        "memory {} is larger than in backup {}",
+                        file.name, manifest.backup_id
+                    )));
+                }
+                memory.resize(length)?;
+
+                read_backup_file(backup_path.as_ref(), manifest, file, |offset, data| {
+                    memory.write(offset, data)
+                })?;
+                memory.sync()?;
             }
-            Entry::Occupied(entry) => entry.get().clone(),
         }
+
+        Ok(())
+    }
+
+    fn get_impl(&self, id: impl AsRef<Path>) -> MemoryMappedFileMemory {
+        self.open(&id).unwrap_or_else(|err| {
+            panic!(
+                "failed to initialize MemoryMappedFileMemory with path: {}: {err}",
+                self.base_path.join(id.as_ref()).display()
+            )
+        })
     }
 }
 
@@ -133,6 +318,38 @@ impl MemoryMappedFileMemory {
         )?))))
     }
 
+    /// Create a memory in the durable mode, see [`MemoryMappedFileMemoryManager::with_durable_mode`].
+    pub fn new_durable(
+        path: String,
+        max_size: usize,
+        is_persistent: bool,
+        sync_policy: SyncPolicy,
+    ) -> MemMapResult<Self> {
+        Ok(Self(Arc::new(RwLock::new(MemoryMappedFile::new_durable(
+            path,
+            max_size,
+            is_persistent,
+            sync_policy,
+        )?))))
+    }
+
+    /// Open an existing file in the read-only mode, see [`MemoryMappedFileMemoryManager::new_read_only`].
+    pub fn new_read_only(path: String, max_size: usize, is_durable: bool) -> MemMapResult<Self> {
+        Ok(Self(Arc::new(RwLock::new(
+            MemoryMappedFile::new_read_only(path, max_size, is_durable)?,
+        ))))
+    }
+
+    /// Create a copy-on-write snapshot of the memory, see [`MemoryMappedFileMemoryManager::snapshot`].
+    pub fn snapshot(&self) -> MemMapResult<MemoryMappedFileSnapshot> {
+        Ok(MemoryMappedFileSnapshot(self.0.write().snapshot()?))
+    }
+
+    /// Flush the changes to disk. In the durable mode also marks the file as consistent.
+    pub fn sync(&self) -> MemMapResult<()> {
+        self.0.write().sync()
+    }
+
     pub fn set_is_persistent(&self, is_persistent: bool) {
         self.0.write().set_is_persistent(is_persistent)
     }
@@ -140,6 +357,10 @@ impl MemoryMappedFileMemory {
     pub(super) fn read_lock(&self) -> RwLockReadGuard<'_, MemoryMappedFile> {
         self.0.read()
     }
+
+    pub(super) fn write_lock(&self) -> RwLockWriteGuard<'_, MemoryMappedFile> {
+        self.0.write()
+    }
 }
 
 impl Memory for MemoryMappedFileMemory {
@@ -1,25 +1,166 @@
+use std::collections::BTreeSet;
 use std::fs::{copy, remove_file, File, OpenOptions};
+use std::io::Write;
+use std::ops::{Deref, Range};
 use std::path::Path;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
-use memmap2::{MmapMut, MmapOptions};
+use memmap2::{Mmap, MmapMut, MmapOptions};
+use sha2::{Digest, Sha256};
 
 use super::error::{MemMapError, MemMapResult};
+use super::snapshot::SnapshotState;
 
 /// By default we use chunk size equal to the default page size.
 /// Since our structures are usually pretty small it doesn't seem
 /// that we will benefit from using huge page size (2 MB or 1 GB)
-const PAGE_SIZE: usize = 4096;
+pub(super) const PAGE_SIZE: usize = 4096;
+
+/// Durable files start with a header page, the data follows it.
+const HEADER_SIZE: usize = PAGE_SIZE;
+const HEADER_MAGIC: &[u8; 8] = b"ICMMFDUR";
+const HEADER_VERSION: u8 = 1;
+const STATE_CLEAN: u8 = 0;
+const STATE_DIRTY: u8 = 1;
+/// Size of the data chunks with separate checksums, so a sync rehashes only the modified chunks.
+const CHECKSUM_CHUNK_SIZE: usize = 16 * PAGE_SIZE;
+
+/// Defines when the changes of a durable memory-mapped file are synced to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync only on explicit `sync()` calls and on drop.
+    Explicit,
+    /// Additionally sync on write if at least the given interval passed since the last sync.
+    Periodic(Duration),
+}
+
+/// Header page of a durable file.
+///
+/// Layout (little-endian): magic (8 bytes), version (1 byte), state (1 byte),
+/// padding up to 16 bytes, data length (8 bytes), data checksum (32 bytes).
+///
+/// The data checksum is the SHA-256 of the concatenated SHA-256 checksums of the
+/// data chunks of [`CHECKSUM_CHUNK_SIZE`] bytes.
+///
+/// The state is set to dirty before the first write after a sync and is reset
+/// to clean together with the new checksum when the data is synced, so a file
+/// which wasn't synced before a crash is detected on open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    is_dirty: bool,
+    data_length: u64,
+    checksum: [u8; 32],
+}
+
+impl Header {
+    const ENCODED_SIZE: usize = 56;
+
+    fn encode(&self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        bytes[..8].copy_from_slice(HEADER_MAGIC);
+        bytes[8] = HEADER_VERSION;
+        bytes[9] = if self.is_dirty {
+            STATE_DIRTY
+        } else {
+            STATE_CLEAN
+        };
+        bytes[16..24].copy_from_slice(&self.data_length.to_le_bytes());
+        bytes[24..].copy_from_slice(&self.checksum);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, &'static str> {
+        if &bytes[..8] != HEADER_MAGIC {
+            return Err("header is missing");
+        }
+        if bytes[8] != HEADER_VERSION {
+            return Err("unsupported header version");
+        }
+        let is_dirty = match bytes[9] {
+            STATE_CLEAN => false,
+            STATE_DIRTY => true,
+            _ => return Err("invalid header state"),
+        };
+
+        Ok(Self {
+            is_dirty,
+            data_length: u64::from_le_bytes(bytes[16..24].try_into().expect("valid slice length")),
+            checksum: bytes[24..Self::ENCODED_SIZE]
+                .try_into()
+                .expect("valid slice length"),
+        })
+    }
+}
+
+/// State of a file opened in the durable mode.
+struct Durability {
+    policy: SyncPolicy,
+    last_sync: Instant,
+    is_dirty: bool,
+    /// Checksums of the data chunks as of the last sync.
+    chunk_checksums: Vec<[u8; 32]>,
+    /// Chunks modified since their checksums were computed.
+    stale_chunks: BTreeSet<usize>,
+}
+
+/// Shared mapping of the file, read-only for the files opened in the read-only mode.
+enum Mapping {
+    Writable(MmapMut),
+    ReadOnly(Mmap),
+}
+
+impl Mapping {
+    fn as_mut(&mut self) -> MemMapResult<&mut MmapMut> {
+        match self {
+            Mapping::Writable(mapping) => Ok(mapping),
+            Mapping::ReadOnly(_) => Err(MemMapError::ReadOnly),
+        }
+    }
+
+    fn flush(&self) -> MemMapResult<()> {
+        if let Mapping::Writable(mapping) = self {
+            mapping.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Mapping::Writable(mapping) => mapping,
+            Mapping::ReadOnly(mapping) => mapping,
+        }
+    }
+}
 
 /// Memory mapped file implementation.
 /// If `is_persistent` flag is true then after the
 /// structure is dropped all the changes are saved to file.
+///
+/// In the durable mode the file starts with a header page holding the data size
+/// and checksum, see [`SyncPolicy`] for when the data is synced to disk.
+///
+/// In the read-only mode the file is mapped read-only and all the modifications are rejected.
 pub(super) struct MemoryMappedFile {
     file: File,
     path: String,
     length: usize,
     max_length: usize,
     is_persistent: bool,
-    mapping: MmapMut,
+    mapping: Mapping,
+    durability: Option<Durability>,
+    /// Pages modified since the last backup.
+    dirty_pages: BTreeSet<usize>,
+    /// Whether the file was backed up since it was opened. If not, all the pages are considered dirty.
+    is_backed_up: bool,
+    is_read_only: bool,
+    /// Copy-on-write snapshots of the file, see [`MemoryMappedFile::snapshot`].
+    snapshots: Vec<Weak<SnapshotState>>,
 }
 
 impl MemoryMappedFile {
@@ -28,30 +169,102 @@ impl MemoryMappedFile {
     /// `max_length` is used to reserve the memory address space to allow resizing the memory
     /// without flushing data and re-mapping it again.
     pub fn new(path: String, max_length: usize, is_persistent: bool) -> MemMapResult<Self> {
+        Self::open(path, max_length, is_persistent, None, false)
+    }
+
+    /// Same as [`MemoryMappedFile::new`], but opens the file in the durable mode.
+    ///
+    /// Returns [`MemMapError::InconsistentFile`] if the existing file has no valid header,
+    /// wasn't synced before the previous process stopped, or its data doesn't match the checksum.
+    pub fn new_durable(
+        path: String,
+        max_length: usize,
+        is_persistent: bool,
+        policy: SyncPolicy,
+    ) -> MemMapResult<Self> {
+        Self::open(path, max_length, is_persistent, Some(policy), false)
+    }
+
+    /// Opens an existing file in the read-only mode, `is_durable` tells whether the file
+    /// was created in the durable mode.
+    ///
+    /// Unlike the writable mode, the file may be opened by any number of readers while
+    /// a writer modifies it. The readers see the changes of the writer, but not the
+    /// growth of the file after it was opened. The durable file header is validated,
+    /// but its state and checksum are not, since the writer may be in the middle of a change.
+    pub fn new_read_only(path: String, max_length: usize, is_durable: bool) -> MemMapResult<Self> {
+        let policy = is_durable.then_some(SyncPolicy::Explicit);
+        Self::open(path, max_length, true, policy, true)
+    }
+
+    fn open(
+        path: String,
+        max_length: usize,
+        is_persistent: bool,
+        policy: Option<SyncPolicy>,
+        is_read_only: bool,
+    ) -> MemMapResult<Self> {
         if !is_persistent {
             _ = remove_file(&path);
         }
 
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .read(true)
-            .open(&path)?;
-        let length = file.metadata()?.len() as usize;
+        let file = if is_read_only {
+            OpenOptions::new().read(true).open(&path)?
+        } else {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .read(true)
+                .open(&path)?
+        };
+        let file_length = file.metadata()?.len() as usize;
+
+        let header_size = if policy.is_some() { HEADER_SIZE } else { 0 };
+        let is_new_durable_file = policy.is_some() && file_length == 0 && !is_read_only;
+        if is_new_durable_file {
+            file.set_len(HEADER_SIZE as u64)?;
+        } else if file_length < header_size {
+            return Err(inconsistent(&path, "file is shorter than the header"));
+        }
 
         let mut mmap_opts = MmapOptions::new();
+        mmap_opts.len(max_length + header_size);
         // Safety: function preconditions should guarantee the safety of the operation:
         // mapping to a file is safe if the file isn't modified concurrently by this and other processes.
-        let mapping = unsafe { mmap_opts.len(max_length).map_mut(&file) }?;
+        // Readers map the file read-only, so they never modify it.
+        let mapping = if is_read_only {
+            Mapping::ReadOnly(unsafe { mmap_opts.map(&file) }?)
+        } else {
+            Mapping::Writable(unsafe { mmap_opts.map_mut(&file) }?)
+        };
 
-        Ok(Self {
+        let mut memory_file = Self {
             file,
             path,
             is_persistent,
             max_length,
-            length,
+            length: file_length.saturating_sub(header_size),
             mapping,
-        })
+            durability: policy.map(|policy| Durability {
+                policy,
+                last_sync: Instant::now(),
+                is_dirty: false,
+                chunk_checksums: vec![],
+                stale_chunks: (0..chunk_count(file_length.saturating_sub(header_size))).collect(),
+            }),
+            dirty_pages: BTreeSet::new(),
+            is_backed_up: false,
+            is_read_only,
+            snapshots: vec![],
+        };
+
+        if is_new_durable_file {
+            memory_file.write_header(false)?;
+        } else if memory_file.durability.is_some() {
+            memory_file.check_consistency()?;
+        }
+
+        Ok(memory_file)
     }
 
     /// Returns the current length in bytes
@@ -64,6 +277,8 @@ impl MemoryMappedFile {
     /// If `new_length` is less or equal than the current length
     /// nothing happens.
     pub fn resize(&mut self, new_length: usize) -> MemMapResult<usize> {
+        self.check_writable()?;
+
         if new_length % PAGE_SIZE != 0 {
             return Err(MemMapError::SizeShouldBePageSizeMultiple);
         }
@@ -79,9 +294,14 @@ impl MemoryMappedFile {
             });
         }
 
+        self.mark_dirty()?;
+        let previous_length = self.length;
+
         // There is no need to remap after changing the size
-        self.file.set_len(new_length as u64)?;
+        self.file
+            .set_len((self.header_size() + new_length) as u64)?;
         self.length = new_length;
+        self.mark_chunks_stale(previous_length, new_length - previous_length);
 
         Ok(self.length)
     }
@@ -92,6 +312,7 @@ impl MemoryMappedFile {
             return Err(MemMapError::AccessOutOfBounds);
         }
 
+        let offset = self.header_size() + offset;
         dst.copy_from_slice(&self.mapping[offset..offset + dst.len()]);
 
         Ok(())
@@ -99,51 +320,341 @@ impl MemoryMappedFile {
 
     /// Write data from `src` to the memory starting at `offset`.
     pub fn write(&mut self, offset: usize, src: &[u8]) -> MemMapResult<()> {
+        self.check_writable()?;
+
         if offset + src.len() > self.len() {
             return Err(MemMapError::AccessOutOfBounds);
         }
 
-        self.mapping[offset..offset + src.len()].copy_from_slice(src);
+        self.mark_dirty()?;
+        self.mark_pages_dirty(offset, src.len());
+        self.mark_chunks_stale(offset, src.len());
+        self.preserve_for_snapshots(offset, src.len());
 
-        Ok(())
+        let offset = self.header_size() + offset;
+        self.mapping.as_mut()?[offset..offset + src.len()].copy_from_slice(src);
+
+        self.sync_if_due()
     }
 
     /// Fill range with zeros.
     pub fn zero_range(&mut self, offset: usize, count: usize) -> MemMapResult<()> {
+        self.check_writable()?;
+
         if offset + count > self.length {
             return Err(MemMapError::AccessOutOfBounds);
         }
 
-        self.mapping[offset..(offset + count)].fill(0);
+        self.mark_dirty()?;
+        self.mark_pages_dirty(offset, count);
+        self.mark_chunks_stale(offset, count);
+        self.preserve_for_snapshots(offset, count);
 
-        Ok(())
+        let offset = self.header_size() + offset;
+        self.mapping.as_mut()?[offset..(offset + count)].fill(0);
+
+        self.sync_if_due()
     }
 
     /// Flush all the changes to the underlying file.
     pub fn flush(&self) -> MemMapResult<()> {
-        self.mapping.flush()?;
+        self.mapping.flush()
+    }
+
+    /// Flush all the changes to the underlying file and, in the durable mode,
+    /// mark the file as consistent by writing the data size and checksum to the header.
+    ///
+    /// Only the checksums of the chunks modified since the previous sync are recomputed.
+    pub fn sync(&mut self) -> MemMapResult<()> {
+        if self.is_read_only {
+            return Ok(());
+        }
+
+        self.flush()?;
+
+        if self.durability.is_some() {
+            self.update_chunk_checksums();
+            self.write_header(false)?;
+            if let Some(durability) = &mut self.durability {
+                durability.is_dirty = false;
+                durability.last_sync = Instant::now();
+            }
+        }
 
         Ok(())
     }
 
     /// Save the copy to a file at the specified path.
+    ///
+    /// In the durable mode the copy gets a clean header with the current checksum
+    /// even if the original file has unsynced changes.
     pub fn save_copy(&self, path: impl AsRef<Path>) -> MemMapResult<()> {
         self.flush()?;
-        copy(&self.path, path)?;
+        copy(&self.path, &path)?;
+
+        if self.durability.is_some() {
+            let mut copy = OpenOptions::new().write(true).open(&path)?;
+            copy.write_all(&self.header(false).encode())?;
+            copy.sync_all()?;
+        }
 
         Ok(())
     }
 
+    /// Byte ranges to include into a backup: the whole data for a full backup or if
+    /// the file wasn't backed up yet, otherwise the pages modified since the last backup.
+    pub fn backup_ranges(&self, is_full: bool) -> Vec<Range<usize>> {
+        if is_full || !self.is_backed_up {
+            let mut ranges = vec![];
+            if self.length > 0 {
+                ranges.push(0..self.length);
+            }
+            return ranges;
+        }
+
+        let mut ranges: Vec<Range<usize>> = vec![];
+        for &page in &self.dirty_pages {
+            let start = page * PAGE_SIZE;
+            let end = (start + PAGE_SIZE).min(self.length);
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        ranges
+    }
+
+    /// Resets the dirty pages tracking after a successful backup.
+    pub fn mark_backed_up(&mut self) {
+        self.dirty_pages.clear();
+        self.is_backed_up = true;
+    }
+
+    /// Creates a copy-on-write snapshot of the current data.
+    ///
+    /// The snapshot privately maps the file, and before a page visible to the snapshot is
+    /// modified for the first time, its current content is copied into the snapshot mapping.
+    /// So creating a snapshot is cheap and the following writes pay only for the pages
+    /// they modify while the snapshot is alive.
+    pub fn snapshot(&mut self) -> MemMapResult<Arc<SnapshotState>> {
+        let mut mmap_opts = MmapOptions::new();
+        mmap_opts.len(self.header_size() + self.length);
+        // Safety: the mapping is private, so it never modifies the file.
+        let mapping = unsafe { mmap_opts.map_copy(&self.file) }?;
+
+        let snapshot = Arc::new(SnapshotState::new(mapping, self.header_size(), self.length));
+        self.snapshots
+            .retain(|snapshot| snapshot.strong_count() > 0);
+        self.snapshots.push(Arc::downgrade(&snapshot));
+
+        Ok(snapshot)
+    }
+
     /// Set `is_persistent` flag.
     pub fn set_is_persistent(&mut self, is_persistent: bool) {
         self.is_persistent = is_persistent;
     }
+
+    fn check_writable(&self) -> MemMapResult<()> {
+        if self.is_read_only {
+            return Err(MemMapError::ReadOnly);
+        }
+
+        Ok(())
+    }
+
+    /// Copies the pages of the range into the snapshots before they are modified.
+    fn preserve_for_snapshots(&mut self, offset: usize, count: usize) {
+        if self.snapshots.is_empty() {
+            return;
+        }
+
+        self.snapshots
+            .retain(|snapshot| snapshot.strong_count() > 0);
+        for snapshot in self.snapshots.iter().filter_map(Weak::upgrade) {
+            snapshot.preserve(offset, count, &self.mapping);
+        }
+    }
+
+    fn mark_pages_dirty(&mut self, offset: usize, count: usize) {
+        // Until the first backup all the pages are considered dirty.
+        if !self.is_backed_up || count == 0 {
+            return;
+        }
+        let first_page = offset / PAGE_SIZE;
+        let last_page = (offset + count - 1) / PAGE_SIZE;
+        self.dirty_pages.extend(first_page..=last_page);
+    }
+
+    fn mark_chunks_stale(&mut self, offset: usize, count: usize) {
+        let Some(durability) = &mut self.durability else {
+            return;
+        };
+        if count == 0 {
+            return;
+        }
+        let first_chunk = offset / CHECKSUM_CHUNK_SIZE;
+        let last_chunk = (offset + count - 1) / CHECKSUM_CHUNK_SIZE;
+        durability.stale_chunks.extend(first_chunk..=last_chunk);
+    }
+
+    /// Recomputes the checksums of the stale chunks.
+    fn update_chunk_checksums(&mut self) {
+        let Some(durability) = &mut self.durability else {
+            return;
+        };
+        let data = &self.mapping[HEADER_SIZE..HEADER_SIZE + self.length];
+
+        durability
+            .chunk_checksums
+            .resize(chunk_count(self.length), [0; 32]);
+        for chunk in std::mem::take(&mut durability.stale_chunks) {
+            durability.chunk_checksums[chunk] = chunk_checksum(data, chunk);
+        }
+    }
+
+    fn header_size(&self) -> usize {
+        if self.durability.is_some() {
+            HEADER_SIZE
+        } else {
+            0
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.mapping[self.header_size()..self.header_size() + self.length]
+    }
+
+    fn header(&self, is_dirty: bool) -> Header {
+        Header {
+            is_dirty,
+            data_length: self.length as u64,
+            checksum: self.data_checksum(),
+        }
+    }
+
+    /// Checksum of the data, the stale chunks are hashed without updating their checksums.
+    fn data_checksum(&self) -> [u8; 32] {
+        let data = self.data();
+        let mut hasher = Sha256::new();
+        for chunk in 0..chunk_count(self.length) {
+            let cached = self.durability.as_ref().and_then(|durability| {
+                if durability.stale_chunks.contains(&chunk) {
+                    None
+                } else {
+                    durability.chunk_checksums.get(chunk)
+                }
+            });
+            match cached {
+                Some(checksum) => hasher.update(checksum),
+                None => hasher.update(chunk_checksum(data, chunk)),
+            }
+        }
+        hasher.finalize().into()
+    }
+
+    /// Writes the header and syncs it to disk.
+    /// The data should be synced before writing a clean header.
+    fn write_header(&mut self, is_dirty: bool) -> MemMapResult<()> {
+        let header = if is_dirty {
+            // The checksum isn't checked for the dirty files, no need to compute it.
+            Header {
+                is_dirty,
+                data_length: self.length as u64,
+                checksum: [0; 32],
+            }
+        } else {
+            self.header(false)
+        };
+
+        let mapping = self.mapping.as_mut()?;
+        mapping[..Header::ENCODED_SIZE].copy_from_slice(&header.encode());
+        mapping.flush_range(0, HEADER_SIZE)?;
+
+        Ok(())
+    }
+
+    /// Marks the durable file as dirty before the first modification after a sync.
+    fn mark_dirty(&mut self) -> MemMapResult<()> {
+        match &self.durability {
+            Some(durability) if !durability.is_dirty => {
+                self.write_header(true)?;
+                if let Some(durability) = &mut self.durability {
+                    durability.is_dirty = true;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn sync_if_due(&mut self) -> MemMapResult<()> {
+        match &self.durability {
+            Some(Durability {
+                policy: SyncPolicy::Periodic(interval),
+                last_sync,
+                ..
+            }) if last_sync.elapsed() >= *interval => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_consistency(&mut self) -> MemMapResult<()> {
+        let header = Header::decode(&self.mapping[..Header::ENCODED_SIZE])
+            .map_err(|reason| inconsistent(&self.path, reason))?;
+
+        if self.is_read_only {
+            return Ok(());
+        }
+
+        if header.is_dirty {
+            return Err(inconsistent(
+                &self.path,
+                "file wasn't synced after the last write, data may be torn",
+            ));
+        }
+        if header.data_length != self.length as u64 {
+            return Err(inconsistent(
+                &self.path,
+                "data size doesn't match the header",
+            ));
+        }
+        self.update_chunk_checksums();
+        if header.checksum != self.data_checksum() {
+            return Err(inconsistent(&self.path, "data checksum mismatch"));
+        }
+
+        Ok(())
+    }
+}
+
+fn chunk_count(length: usize) -> usize {
+    length.div_ceil(CHECKSUM_CHUNK_SIZE)
+}
+
+fn chunk_checksum(data: &[u8], chunk: usize) -> [u8; 32] {
+    let start = chunk * CHECKSUM_CHUNK_SIZE;
+    let end = (start + CHECKSUM_CHUNK_SIZE).min(data.len());
+    Sha256::digest(&data[start..end]).into()
+}
+
+fn inconsistent(path: &str, reason: &str) -> MemMapError {
+    MemMapError::InconsistentFile {
+        path: path.to_owned(),
+        reason: reason.to_owned(),
+    }
 }
 
 impl Drop for MemoryMappedFile {
     fn drop(&mut self) {
+        if self.is_read_only {
+            return;
+        }
+
         if self.is_persistent {
-            self.flush().expect("failed to flush data to file")
+            if let Err(err) = self.sync() {
+                log::error!("failed to sync memory-mapped file {}: {err}", self.path);
+            }
         } else {
             _ = remove_file(&self.path);
         }
@@ -152,7 +663,7 @@ impl Drop for MemoryMappedFile {
 
 #[cfg(test)]
 mod tests {
-    use std::io::Read;
+    use std::io::{Read, Seek, SeekFrom};
 
     use tempfile::NamedTempFile;
 
@@ -369,4 +880,284 @@ mod tests {
             assert!(!Path::new(&path).exists());
         });
     }
+
+    fn new_durable(path: String) -> MemMapResult<MemoryMappedFile> {
+        MemoryMappedFile::new_durable(path, DEFAULT_MAX_LENGTH * 2, true, SyncPolicy::Explicit)
+    }
+
+    fn assert_inconsistent(result: MemMapResult<MemoryMappedFile>, expected_reason: &str) {
+        match result {
+            Err(MemMapError::InconsistentFile { reason, .. }) => {
+                assert!(
+                    reason.contains(expected_reason),
+                    "unexpected reason: {reason}"
+                )
+            }
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("inconsistent file was opened"),
+        }
+    }
+
+    #[test]
+    fn should_reopen_synced_durable_file() {
+        with_temp_file(|path| {
+            let mut file_memory = new_durable(path.clone()).unwrap();
+            assert_eq!(file_memory.len(), 0);
+            file_memory.resize(PAGE_SIZE).unwrap();
+            file_memory.write(0, &create_data()).unwrap();
+            drop(file_memory);
+
+            assert_eq!(
+                File::open(&path).unwrap().metadata().unwrap().len(),
+                (HEADER_SIZE + PAGE_SIZE) as u64
+            );
+
+            let file_memory = new_durable(path).unwrap();
+            assert_eq!(file_memory.len(), PAGE_SIZE);
+            let mut data = vec![0; PAGE_SIZE];
+            file_memory.read(0, &mut data).unwrap();
+            check_data(&data);
+        });
+    }
+
+    #[test]
+    fn should_detect_unsynced_durable_file() {
+        with_temp_file(|path| {
+            let mut file_memory = new_durable(path.clone()).unwrap();
+            file_memory.resize(PAGE_SIZE).unwrap();
+            file_memory.write(0, &[1, 2, 3]).unwrap();
+            file_memory.sync().unwrap();
+            file_memory.write(0, &[4, 5, 6]).unwrap();
+
+            // Simulate a crash: the memory is never synced.
+            std::mem::forget(file_memory);
+
+            assert_inconsistent(new_durable(path), "wasn't synced");
+        });
+    }
+
+    #[test]
+    fn should_detect_corrupted_durable_file() {
+        with_temp_file(|path| {
+            let mut file_memory = new_durable(path.clone()).unwrap();
+            file_memory.resize(PAGE_SIZE).unwrap();
+            file_memory.write(0, &create_data()).unwrap();
+            drop(file_memory);
+
+            let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start((HEADER_SIZE + 10) as u64))
+                .unwrap();
+            file.write_all(&[0xFF]).unwrap();
+            drop(file);
+
+            assert_inconsistent(new_durable(path.clone()), "checksum mismatch");
+
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.set_len((HEADER_SIZE + 2 * PAGE_SIZE) as u64).unwrap();
+            drop(file);
+
+            assert_inconsistent(new_durable(path), "size doesn't match");
+        });
+    }
+
+    #[test]
+    fn should_update_checksums_of_modified_chunks() {
+        with_temp_file(|path| {
+            let length = CHECKSUM_CHUNK_SIZE * 2 + PAGE_SIZE;
+            let open = |path: String| {
+                MemoryMappedFile::new_durable(path, length, true, SyncPolicy::Explicit)
+            };
+
+            let mut file_memory = open(path.clone()).unwrap();
+            file_memory.resize(length).unwrap();
+            file_memory.write(0, &[1, 2, 3]).unwrap();
+            drop(file_memory);
+
+            let mut file_memory = open(path.clone()).unwrap();
+            file_memory
+                .write(CHECKSUM_CHUNK_SIZE * 2, &[4, 5, 6])
+                .unwrap();
+            file_memory.sync().unwrap();
+            assert_eq!(
+                file_memory.header(false),
+                Header::decode(&file_memory.mapping).unwrap()
+            );
+            drop(file_memory);
+
+            let file_memory = open(path.clone()).unwrap();
+            let slice = &mut [0; 3];
+            file_memory.read(CHECKSUM_CHUNK_SIZE * 2, slice).unwrap();
+            assert_eq!(slice, &[4, 5, 6]);
+            drop(file_memory);
+
+            let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(
+                (HEADER_SIZE + CHECKSUM_CHUNK_SIZE + 1) as u64,
+            ))
+            .unwrap();
+            file.write_all(&[0xFF]).unwrap();
+            drop(file);
+
+            assert_inconsistent(open(path), "checksum mismatch");
+        });
+    }
+
+    #[test]
+    fn should_reject_file_without_header() {
+        with_temp_file(|path| {
+            let mut file_memory =
+                MemoryMappedFile::new(path.clone(), DEFAULT_MAX_LENGTH, true).unwrap();
+            file_memory.resize(PAGE_SIZE).unwrap();
+            file_memory.write(0, &[1, 2, 3]).unwrap();
+            drop(file_memory);
+
+            assert_inconsistent(new_durable(path), "header is missing");
+        });
+    }
+
+    #[test]
+    fn should_sync_periodically() {
+        with_temp_file(|path| {
+            let mut file_memory = MemoryMappedFile::new_durable(
+                path.clone(),
+                DEFAULT_MAX_LENGTH,
+                true,
+                SyncPolicy::Periodic(Duration::ZERO),
+            )
+            .unwrap();
+            file_memory.resize(PAGE_SIZE).unwrap();
+            file_memory.write(0, &[1, 2, 3]).unwrap();
+
+            // Every write is synced, so even an abrupt stop leaves the file consistent.
+            std::mem::forget(file_memory);
+
+            let file_memory = new_durable(path).unwrap();
+            let slice = &mut [0; 3];
+            file_memory.read(0, slice).unwrap();
+            assert_eq!(slice, &[1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn should_save_consistent_copy_of_dirty_durable_file() {
+        with_temp_file(|path| {
+            let mut file_memory = new_durable(path).unwrap();
+            file_memory.resize(PAGE_SIZE).unwrap();
+            file_memory.write(0, &create_data()).unwrap();
+
+            let copy_path = NamedTempFile::new().unwrap().into_temp_path();
+            file_memory.save_copy(&copy_path).unwrap();
+
+            let copy = new_durable(copy_path.to_str().unwrap().to_owned()).unwrap();
+            let mut data = vec![0; PAGE_SIZE];
+            copy.read(0, &mut data).unwrap();
+            check_data(&data);
+        });
+    }
+
+    #[test]
+    fn should_track_dirty_pages_after_backup() {
+        with_temp_file(|path| {
+            let mut file_memory = MemoryMappedFile::new(path, PAGE_SIZE * 8, true).unwrap();
+            file_memory.resize(PAGE_SIZE * 4).unwrap();
+            file_memory.write(10, &[1]).unwrap();
+
+            let whole_file = 0..PAGE_SIZE * 4;
+            assert_eq!(file_memory.backup_ranges(false), vec![whole_file.clone()]);
+            file_memory.mark_backed_up();
+            assert!(file_memory.backup_ranges(false).is_empty());
+            assert_eq!(file_memory.backup_ranges(true), vec![whole_file]);
+
+            file_memory.write(PAGE_SIZE - 1, &[1, 2]).unwrap();
+            file_memory.zero_range(PAGE_SIZE * 3, 5).unwrap();
+            assert_eq!(
+                file_memory.backup_ranges(false),
+                vec![0..PAGE_SIZE * 2, PAGE_SIZE * 3..PAGE_SIZE * 4]
+            );
+
+            file_memory.mark_backed_up();
+            assert!(file_memory.backup_ranges(false).is_empty());
+        });
+    }
+
+    #[test]
+    fn should_reject_modifications_in_read_only_mode() {
+        with_temp_file(|path| {
+            let mut writer = MemoryMappedFile::new(path.clone(), DEFAULT_MAX_LENGTH, true).unwrap();
+            writer.resize(PAGE_SIZE).unwrap();
+            writer.write(0, &[1, 2, 3]).unwrap();
+
+            let mut reader =
+                MemoryMappedFile::new_read_only(path.clone(), DEFAULT_MAX_LENGTH, false).unwrap();
+            let other_reader =
+                MemoryMappedFile::new_read_only(path, DEFAULT_MAX_LENGTH, false).unwrap();
+
+            assert!(matches!(reader.write(0, &[0]), Err(MemMapError::ReadOnly)));
+            assert!(matches!(
+                reader.zero_range(0, 1),
+                Err(MemMapError::ReadOnly)
+            ));
+            assert!(matches!(
+                reader.resize(PAGE_SIZE * 2),
+                Err(MemMapError::ReadOnly)
+            ));
+
+            // Readers see the changes of the writer.
+            writer.write(1, &[42]).unwrap();
+            for reader in [&reader, &other_reader] {
+                let slice = &mut [0; 3];
+                reader.read(0, slice).unwrap();
+                assert_eq!(slice, &[1, 42, 3]);
+            }
+
+            drop(reader);
+            drop(other_reader);
+            let slice = &mut [0; 3];
+            writer.read(0, slice).unwrap();
+            assert_eq!(slice, &[1, 42, 3]);
+        });
+    }
+
+    #[test]
+    fn should_not_create_file_in_read_only_mode() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("missing").to_str().unwrap().to_owned();
+
+        assert!(matches!(
+            MemoryMappedFile::new_read_only(path.clone(), DEFAULT_MAX_LENGTH, false),
+            Err(MemMapError::FileOpenError(_))
+        ));
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn should_keep_snapshot_unchanged() {
+        with_temp_file(|path| {
+            let mut file_memory = new_durable(path).unwrap();
+            file_memory.resize(PAGE_SIZE).unwrap();
+            file_memory.write(0, &create_data()).unwrap();
+
+            let snapshot = file_memory.snapshot().unwrap();
+
+            file_memory.write(10, &[0xAA; 100]).unwrap();
+            file_memory.zero_range(PAGE_SIZE - 10, 10).unwrap();
+            file_memory.resize(PAGE_SIZE * 2).unwrap();
+            file_memory.write(PAGE_SIZE, &[1]).unwrap();
+
+            let mut data = vec![0; PAGE_SIZE];
+            snapshot.read(0, &mut data).unwrap();
+            check_data(&data);
+            assert!(matches!(
+                snapshot.read(PAGE_SIZE, &mut [0]),
+                Err(MemMapError::AccessOutOfBounds)
+            ));
+
+            file_memory.read(0, &mut data).unwrap();
+            assert_eq!(&data[10..110], &[0xAA; 100]);
+
+            drop(snapshot);
+            file_memory.write(0, &[1]).unwrap();
+            assert!(file_memory.snapshots.is_empty());
+        });
+    }
 }
@@ -1,5 +1,15 @@
+mod backup;
 mod error;
+mod ic_image;
 mod memory;
 mod memory_mapped_file;
+mod snapshot;
 
+pub use backup::{
+    BackupCompression, BackupFile, BackupKind, BackupManifest, BACKUP_MANIFEST_FILE_NAME,
+};
+pub use error::{MemMapError, MemMapResult};
+pub use ic_image::{IcStableMemoryImage, IcStableMemoryImageMemory};
 pub use memory::{MemoryMappedFileMemory, MemoryMappedFileMemoryManager};
+pub use memory_mapped_file::SyncPolicy;
+pub use snapshot::{MemoryMappedFileManagerSnapshot, MemoryMappedFileSnapshot};
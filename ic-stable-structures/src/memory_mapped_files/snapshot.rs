@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use dfinity_stable_structures::Memory;
+use memmap2::MmapMut;
+use parking_lot::RwLock;
+
+use super::error::{MemMapError, MemMapResult};
+use super::memory_mapped_file::PAGE_SIZE;
+use crate::memory::MemoryManager;
+
+const WASM_PAGE_SIZE_IN_BYTES: u64 = 65536;
+
+/// Private mapping of a memory-mapped file frozen at the moment of the snapshot.
+pub(super) struct SnapshotState {
+    data: RwLock<SnapshotData>,
+    /// Offset of the data in the file, non-zero for the durable files.
+    data_offset: usize,
+    length: usize,
+}
+
+struct SnapshotData {
+    mapping: MmapMut,
+    /// Pages copied into the mapping before the file was modified.
+    preserved_pages: BTreeSet<usize>,
+}
+
+impl SnapshotState {
+    pub(super) fn new(mapping: MmapMut, data_offset: usize, length: usize) -> Self {
+        Self {
+            data: RwLock::new(SnapshotData {
+                mapping,
+                preserved_pages: BTreeSet::new(),
+            }),
+            data_offset,
+            length,
+        }
+    }
+
+    /// Copies the not yet preserved pages of the range from the `live_mapping` of the file
+    /// into the snapshot. Should be called before the range is modified.
+    ///
+    /// Until a page of a private mapping is written, it shows the current content of the file,
+    /// so the write makes the page private and freezes its content.
+    pub(super) fn preserve(&self, offset: usize, count: usize, live_mapping: &[u8]) {
+        let end = (offset + count).min(self.length);
+        if offset >= end {
+            return;
+        }
+
+        let mut data = self.data.write();
+        for page in offset / PAGE_SIZE..=(end - 1) / PAGE_SIZE {
+            if !data.preserved_pages.insert(page) {
+                continue;
+            }
+
+            let start = self.data_offset + page * PAGE_SIZE;
+            let end = (start + PAGE_SIZE).min(self.data_offset + self.length);
+            data.mapping[start..end].copy_from_slice(&live_mapping[start..end]);
+        }
+    }
+
+    pub(super) fn read(&self, offset: usize, dst: &mut [u8]) -> MemMapResult<()> {
+        if offset + dst.len() > self.length {
+            return Err(MemMapError::AccessOutOfBounds);
+        }
+
+        let offset = self.data_offset + offset;
+        dst.copy_from_slice(&self.data.read().mapping[offset..offset + dst.len()]);
+
+        Ok(())
+    }
+}
+
+/// Read-only point-in-time view of a [`MemoryMappedFileMemory`](super::MemoryMappedFileMemory).
+///
+/// The memory can't be modified, `write` and `grow` panic.
+#[derive(Clone)]
+pub struct MemoryMappedFileSnapshot(pub(super) Arc<SnapshotState>);
+
+impl Memory for MemoryMappedFileSnapshot {
+    fn size(&self) -> u64 {
+        self.0.length as u64 / WASM_PAGE_SIZE_IN_BYTES
+    }
+
+    fn grow(&self, _pages: u64) -> i64 {
+        panic!("memory-mapped file snapshot is read-only")
+    }
+
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        self.0
+            .read(offset as usize, dst)
+            .expect("invalid memory-mapped file snapshot read")
+    }
+
+    fn write(&self, _offset: u64, _src: &[u8]) {
+        panic!("memory-mapped file snapshot is read-only")
+    }
+}
+
+/// Snapshots of all the memories of a
+/// [`MemoryMappedFileMemoryManager`](super::MemoryMappedFileMemoryManager) made at the same moment.
+pub struct MemoryMappedFileManagerSnapshot {
+    pub(super) memories: BTreeMap<String, MemoryMappedFileSnapshot>,
+}
+
+impl MemoryMappedFileManagerSnapshot {
+    /// Returns the snapshot of the memory with the given id if it was opened at the moment of the snapshot.
+    pub fn try_get(&self, id: &str) -> Option<MemoryMappedFileSnapshot> {
+        self.memories.get(id).cloned()
+    }
+
+    /// Ids of the memories in the snapshot.
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.memories.keys().map(String::as_str)
+    }
+}
+
+impl MemoryManager<MemoryMappedFileSnapshot, &str> for MemoryMappedFileManagerSnapshot {
+    fn get(&self, id: &str) -> MemoryMappedFileSnapshot {
+        self.try_get(id)
+            .unwrap_or_else(|| panic!("memory {id} is not in the snapshot"))
+    }
+}
+
+impl MemoryManager<MemoryMappedFileSnapshot, u8> for MemoryMappedFileManagerSnapshot {
+    fn get(&self, id: u8) -> MemoryMappedFileSnapshot {
+        self.get(id.to_string().as_str())
+    }
+}
@@ -0,0 +1,498 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use candid::CandidType;
+use dfinity_stable_structures::Memory;
+
+use crate::MemoryManager;
+
+/// Usage percents at which the quota reports [`QuotaEvent::ThresholdReached`] by default.
+pub const DEFAULT_WARNING_THRESHOLDS: [u8; 2] = [80, 95];
+
+/// Budget the quota event refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, CandidType)]
+pub enum QuotaScope {
+    /// Budget of all the memories wrapped by the quota.
+    Global,
+    /// Budget of the memory with the given id.
+    Memory(u8),
+}
+
+/// Event reported by a [`MemoryQuota`] to the log and to the registered callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType)]
+pub enum QuotaEvent {
+    /// Usage reached the warning threshold for the first time.
+    ThresholdReached {
+        scope: QuotaScope,
+        threshold_percent: u8,
+        used_pages: u64,
+        budget_pages: u64,
+    },
+    /// Memory was not grown, because it would exceed the budget.
+    GrowDenied {
+        scope: QuotaScope,
+        requested_pages: u64,
+        used_pages: u64,
+        budget_pages: u64,
+    },
+}
+
+/// Usage of a single memory wrapped by a [`MemoryQuota`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType)]
+pub struct MemoryQuotaUsage {
+    pub memory_id: u8,
+    pub used_pages: u64,
+    pub budget_pages: Option<u64>,
+}
+
+/// Snapshot of the [`MemoryQuota`] usage, e.g. to expose it next to the `ic-metrics` data.
+#[derive(Debug, Clone, Default, PartialEq, Eq, CandidType)]
+pub struct QuotaUsage {
+    /// Pages used by all the wrapped memories.
+    pub total_pages: u64,
+    pub global_budget_pages: Option<u64>,
+    /// Usage of the wrapped memories ordered by id.
+    pub memories: Vec<MemoryQuotaUsage>,
+}
+
+type QuotaCallback = Rc<dyn Fn(&QuotaEvent)>;
+
+struct QuotaState {
+    global_budget: Option<u64>,
+    budgets: BTreeMap<u8, u64>,
+    used: BTreeMap<u8, u64>,
+    /// Sorted usage percents to warn at.
+    thresholds: Vec<u8>,
+    /// The highest threshold reported for each scope.
+    reported: BTreeMap<QuotaScope, u8>,
+    callbacks: Vec<QuotaCallback>,
+}
+
+impl QuotaState {
+    fn budget(&self, scope: QuotaScope) -> Option<u64> {
+        match scope {
+            QuotaScope::Global => self.global_budget,
+            QuotaScope::Memory(id) => self.budgets.get(&id).copied(),
+        }
+    }
+
+    fn used(&self, scope: QuotaScope) -> u64 {
+        match scope {
+            QuotaScope::Global => self.used.values().sum(),
+            QuotaScope::Memory(id) => self.used.get(&id).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Returns the event for the threshold crossed by the current usage of the scope, if any.
+    fn crossed_threshold(&mut self, scope: QuotaScope) -> Option<QuotaEvent> {
+        let budget_pages = self.budget(scope)?;
+        let used_pages = self.used(scope);
+        let used_percent = used_pages
+            .saturating_mul(100)
+            .checked_div(budget_pages)
+            .unwrap_or(100);
+
+        let threshold_percent = self
+            .thresholds
+            .iter()
+            .rev()
+            .copied()
+            .find(|&threshold| used_percent >= threshold as u64)?;
+        if self
+            .reported
+            .get(&scope)
+            .is_some_and(|&reported| reported >= threshold_percent)
+        {
+            return None;
+        }
+
+        self.reported.insert(scope, threshold_percent);
+        Some(QuotaEvent::ThresholdReached {
+            scope,
+            threshold_percent,
+            used_pages,
+            budget_pages,
+        })
+    }
+}
+
+/// Page budgets for the memories of a canister.
+///
+/// Memories wrapped by the quota fail to grow once their own budget or the global budget
+/// is exhausted, so the structures return `Error::OutOfStableMemory` well before the
+/// subnet limits are hit. Usage growth is reported at the configured thresholds with
+/// `log` warnings and the callbacks registered by [`MemoryQuota::on_event`].
+///
+/// The quota is a cheap handle: clones share the same budgets and usage.
+#[derive(Clone)]
+pub struct MemoryQuota(Rc<RefCell<QuotaState>>);
+
+impl Default for MemoryQuota {
+    fn default() -> Self {
+        Self(Rc::new(RefCell::new(QuotaState {
+            global_budget: None,
+            budgets: BTreeMap::new(),
+            used: BTreeMap::new(),
+            thresholds: DEFAULT_WARNING_THRESHOLDS.to_vec(),
+            reported: BTreeMap::new(),
+            callbacks: vec![],
+        })))
+    }
+}
+
+impl MemoryQuota {
+    /// Creates a quota without budgets and with the [`DEFAULT_WARNING_THRESHOLDS`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the total size of all the wrapped memories.
+    pub fn with_global_budget(self, pages: u64) -> Self {
+        self.set_global_budget(Some(pages));
+        self
+    }
+
+    /// Limits the size of the memory with the given id.
+    pub fn with_memory_budget(self, memory_id: u8, pages: u64) -> Self {
+        self.set_memory_budget(memory_id, Some(pages));
+        self
+    }
+
+    /// Replaces the usage percents at which the warnings are reported.
+    pub fn with_warning_thresholds(self, percents: &[u8]) -> Self {
+        {
+            let mut state = self.0.borrow_mut();
+            state.thresholds = percents.to_vec();
+            state.thresholds.sort_unstable();
+            state.thresholds.dedup();
+            state.reported.clear();
+        }
+        self
+    }
+
+    /// Registers a callback invoked on every [`QuotaEvent`].
+    pub fn on_event(self, callback: impl Fn(&QuotaEvent) + 'static) -> Self {
+        self.0.borrow_mut().callbacks.push(Rc::new(callback));
+        self
+    }
+
+    /// Changes the global budget. `None` removes the limit.
+    pub fn set_global_budget(&self, pages: Option<u64>) {
+        let mut state = self.0.borrow_mut();
+        state.global_budget = pages;
+        state.reported.remove(&QuotaScope::Global);
+    }
+
+    /// Changes the budget of the memory with the given id. `None` removes the limit.
+    pub fn set_memory_budget(&self, memory_id: u8, pages: Option<u64>) {
+        let mut state = self.0.borrow_mut();
+        match pages {
+            Some(pages) => state.budgets.insert(memory_id, pages),
+            None => state.budgets.remove(&memory_id),
+        };
+        state.reported.remove(&QuotaScope::Memory(memory_id));
+    }
+
+    /// Wraps the memory with the given id into the quota.
+    pub fn wrap<M: Memory>(&self, memory_id: u8, memory: M) -> QuotaMemory<M> {
+        self.record_size(memory_id, memory.size());
+        QuotaMemory {
+            memory_id,
+            memory,
+            quota: self.clone(),
+        }
+    }
+
+    /// Returns the current usage of the wrapped memories.
+    pub fn usage(&self) -> QuotaUsage {
+        let state = self.0.borrow();
+        QuotaUsage {
+            total_pages: state.used(QuotaScope::Global),
+            global_budget_pages: state.global_budget,
+            memories: state
+                .used
+                .iter()
+                .map(|(&memory_id, &used_pages)| MemoryQuotaUsage {
+                    memory_id,
+                    used_pages,
+                    budget_pages: state.budgets.get(&memory_id).copied(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Checks that the memory of `current_size` pages may grow by `pages`.
+    fn check_grow(&self, memory_id: u8, current_size: u64, pages: u64) -> Option<QuotaEvent> {
+        let state = self.0.borrow();
+        let memory_used = current_size;
+        let global_used = state.used(QuotaScope::Global)
+            - state.used(QuotaScope::Memory(memory_id))
+            + current_size;
+
+        [
+            (QuotaScope::Memory(memory_id), memory_used),
+            (QuotaScope::Global, global_used),
+        ]
+        .into_iter()
+        .find_map(|(scope, used_pages)| {
+            let budget_pages = state.budget(scope)?;
+            (used_pages.saturating_add(pages) > budget_pages).then_some(QuotaEvent::GrowDenied {
+                scope,
+                requested_pages: pages,
+                used_pages,
+                budget_pages,
+            })
+        })
+    }
+
+    fn record_size(&self, memory_id: u8, size: u64) {
+        let events = {
+            let mut state = self.0.borrow_mut();
+            state.used.insert(memory_id, size);
+            [QuotaScope::Memory(memory_id), QuotaScope::Global]
+                .into_iter()
+                .filter_map(|scope| state.crossed_threshold(scope))
+                .collect::<Vec<_>>()
+        };
+
+        for event in events {
+            self.notify(&event);
+        }
+    }
+
+    fn notify(&self, event: &QuotaEvent) {
+        match event {
+            QuotaEvent::ThresholdReached {
+                scope,
+                threshold_percent,
+                used_pages,
+                budget_pages,
+            } => log::warn!(
+                "stable memory quota {scope:?} reached {threshold_percent}%: {used_pages} of {budget_pages} pages used"
+            ),
+            QuotaEvent::GrowDenied {
+                scope,
+                requested_pages,
+                used_pages,
+                budget_pages,
+            } => log::error!(
+                "stable memory quota {scope:?} denied growing by {requested_pages} pages: {used_pages} of {budget_pages} pages used"
+            ),
+        }
+
+        // Callbacks may use the quota, so it must not be borrowed while they run.
+        let callbacks = self.0.borrow().callbacks.clone();
+        for callback in callbacks {
+            callback(event);
+        }
+    }
+}
+
+/// Memory which grows only within the budgets of its [`MemoryQuota`].
+#[derive(Clone)]
+pub struct QuotaMemory<M: Memory> {
+    memory_id: u8,
+    memory: M,
+    quota: MemoryQuota,
+}
+
+impl<M: Memory> QuotaMemory<M> {
+    /// Id of the memory in the quota.
+    pub fn memory_id(&self) -> u8 {
+        self.memory_id
+    }
+
+    /// Returns the wrapped memory.
+    pub fn inner(&self) -> &M {
+        &self.memory
+    }
+}
+
+impl<M: Memory> Memory for QuotaMemory<M> {
+    fn size(&self) -> u64 {
+        self.memory.size()
+    }
+
+    fn grow(&self, pages: u64) -> i64 {
+        let current_size = self.memory.size();
+        if let Some(event) = self.quota.check_grow(self.memory_id, current_size, pages) {
+            self.quota.notify(&event);
+            return -1;
+        }
+
+        let result = self.memory.grow(pages);
+        if result >= 0 {
+            self.quota.record_size(self.memory_id, self.memory.size());
+        }
+        result
+    }
+
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        self.memory.read(offset, dst)
+    }
+
+    fn write(&self, offset: u64, src: &[u8]) {
+        self.memory.write(offset, src)
+    }
+}
+
+/// Memory manager which wraps the memories of another manager into a [`MemoryQuota`].
+pub struct QuotaMemoryManager<MM> {
+    manager: MM,
+    quota: MemoryQuota,
+}
+
+impl<MM> QuotaMemoryManager<MM> {
+    pub fn new(manager: MM, quota: MemoryQuota) -> Self {
+        Self { manager, quota }
+    }
+
+    pub fn quota(&self) -> &MemoryQuota {
+        &self.quota
+    }
+}
+
+impl<M: Memory, MM: MemoryManager<M, u8>> MemoryManager<QuotaMemory<M>, u8>
+    for QuotaMemoryManager<MM>
+{
+    fn get(&self, id: u8) -> QuotaMemory<M> {
+        self.quota.wrap(id, self.manager.get(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::{default_ic_memory_manager, Error, StableVec, VecStructure};
+
+    fn recording_quota(quota: MemoryQuota) -> (MemoryQuota, Rc<RefCell<Vec<QuotaEvent>>>) {
+        let events = Rc::new(RefCell::new(vec![]));
+        let recorded = events.clone();
+        let quota = quota.on_event(move |event| recorded.borrow_mut().push(*event));
+        (quota, events)
+    }
+
+    #[test]
+    fn should_deny_growing_over_memory_budget() {
+        let (quota, events) = recording_quota(MemoryQuota::new().with_memory_budget(1, 3));
+        let memory = quota.wrap(1, VectorMemory::default());
+
+        assert_eq!(memory.grow(2), 0);
+        assert_eq!(memory.grow(2), -1);
+        assert_eq!(memory.size(), 2);
+        assert_eq!(memory.grow(1), 2);
+
+        let unlimited = quota.wrap(2, VectorMemory::default());
+        assert_eq!(unlimited.grow(10), 0);
+
+        assert_eq!(
+            events.borrow()[..],
+            [
+                QuotaEvent::GrowDenied {
+                    scope: QuotaScope::Memory(1),
+                    requested_pages: 2,
+                    used_pages: 2,
+                    budget_pages: 3,
+                },
+                QuotaEvent::ThresholdReached {
+                    scope: QuotaScope::Memory(1),
+                    threshold_percent: 95,
+                    used_pages: 3,
+                    budget_pages: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_deny_growing_over_global_budget() {
+        let quota = MemoryQuota::new().with_global_budget(5);
+        let first = quota.wrap(1, VectorMemory::default());
+        let second = quota.wrap(2, VectorMemory::default());
+
+        assert_eq!(first.grow(3), 0);
+        assert_eq!(second.grow(3), -1);
+        assert_eq!(second.grow(2), 0);
+        assert_eq!(first.grow(1), -1);
+    }
+
+    #[test]
+    fn should_report_each_threshold_once() {
+        let (quota, events) = recording_quota(
+            MemoryQuota::new()
+                .with_global_budget(10)
+                .with_warning_thresholds(&[50, 90]),
+        );
+        let memory = quota.wrap(0, VectorMemory::default());
+
+        memory.grow(4);
+        assert!(events.borrow().is_empty());
+
+        memory.grow(1);
+        memory.grow(1);
+        memory.grow(3);
+        assert_eq!(
+            events.borrow()[..],
+            [
+                QuotaEvent::ThresholdReached {
+                    scope: QuotaScope::Global,
+                    threshold_percent: 50,
+                    used_pages: 5,
+                    budget_pages: 10,
+                },
+                QuotaEvent::ThresholdReached {
+                    scope: QuotaScope::Global,
+                    threshold_percent: 90,
+                    used_pages: 9,
+                    budget_pages: 10,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_count_existing_memory_size() {
+        let existing = VectorMemory::default();
+        existing.grow(4);
+
+        let quota = MemoryQuota::new().with_memory_budget(3, 5);
+        let memory = quota.wrap(3, existing);
+        assert_eq!(memory.grow(2), -1);
+
+        assert_eq!(
+            quota.usage(),
+            QuotaUsage {
+                total_pages: 4,
+                global_budget_pages: None,
+                memories: vec![MemoryQuotaUsage {
+                    memory_id: 3,
+                    used_pages: 4,
+                    budget_pages: Some(5),
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn should_fail_structure_writes_over_budget() {
+        let manager = QuotaMemoryManager::new(
+            default_ic_memory_manager(),
+            MemoryQuota::new().with_memory_budget(0, 1),
+        );
+        let mut vec = StableVec::<u64, _>::new(manager.get(0)).unwrap();
+
+        let mut result = Ok(());
+        for i in 0..100_000 {
+            result = vec.push(&i);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        assert!(matches!(result, Err(Error::OutOfStableMemory)));
+        assert_eq!(manager.quota().usage().total_pages, 1);
+    }
+}
@@ -129,6 +129,10 @@ where
     fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_> {
         self.inner.iter_upper_bound(bound)
     }
+
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,355 @@
+use std::ops::Range;
+
+use candid::{CandidType, Deserialize};
+use dfinity_stable_structures::{Memory, Storable};
+
+use super::{set_certified_data, sha256, Hash};
+use crate::structure::{LogStructure, StableLog, StableVec, VecStructure};
+use crate::Result;
+
+/// Append-only log with a Merkle tree over its entries.
+///
+/// The tree follows RFC 6962: leaves are `H(0x00 || entry bytes)` and nodes are
+/// `H(0x01 || left || right)`. Hashes of the complete subtrees are stored in a separate
+/// memory in post-order, so appending an entry and computing the root are `O(log n)`.
+///
+/// The root hash is not certified automatically: call [`CertifiedLog::certify`] after updates,
+/// or, if the canister certifies other structures too, combine their root hashes with
+/// [`CertifiedLog::root_hash`] and certify the result, since the canister has a single
+/// certified data slot.
+pub struct CertifiedLog<T: Storable, M: Memory> {
+    log: StableLog<T, M>,
+    tree: StableVec<Hash, M>,
+}
+
+impl<T: Storable, M: Memory> CertifiedLog<T, M> {
+    /// Loads the log from the memories or creates an empty one.
+    ///
+    /// The tree is rebuilt if it doesn't match the entries of the log.
+    pub fn new(index_memory: M, data_memory: M, tree_memory: M) -> Result<Self> {
+        let mut certified_log = Self {
+            log: StableLog::new(index_memory, data_memory)?,
+            tree: StableVec::new(tree_memory)?,
+        };
+
+        if certified_log.tree.len() != tree_size(certified_log.log.len()) {
+            certified_log.tree.clear()?;
+            for index in 0..certified_log.log.len() {
+                let entry = certified_log.log.get(index).expect("entry is in the log");
+                certified_log.push_leaf(index, &entry)?;
+            }
+        }
+
+        Ok(certified_log)
+    }
+
+    /// Root hash of the Merkle tree. The root of an empty log is the hash of an empty string.
+    pub fn root_hash(&self) -> Hash {
+        match self.log.len() {
+            0 => sha256(&[]),
+            len => self.subtree_hash(0, len),
+        }
+    }
+
+    /// Sets the root hash as the canister certified data.
+    pub fn certify(&self) {
+        set_certified_data(&self.root_hash());
+    }
+
+    /// Returns the proof of inclusion of the entries in the `range`,
+    /// or `None` if the range is empty or out of bounds.
+    pub fn proof(&self, range: Range<u64>) -> Option<RangeProof> {
+        let tree_size = self.log.len();
+        if range.start >= range.end || range.end > tree_size {
+            return None;
+        }
+
+        let mut proof = RangeProof {
+            tree_size,
+            start: range.start,
+            end: range.end,
+            hashes: vec![],
+        };
+        self.collect_proof_hashes(0, tree_size, &range, &mut proof.hashes);
+
+        Some(proof)
+    }
+
+    fn collect_proof_hashes(&self, lo: u64, hi: u64, range: &Range<u64>, hashes: &mut Vec<Hash>) {
+        if hi <= range.start || lo >= range.end {
+            hashes.push(self.subtree_hash(lo, hi));
+        } else if hi - lo > 1 {
+            let split = lo + split_size(hi - lo);
+            self.collect_proof_hashes(lo, split, range, hashes);
+            self.collect_proof_hashes(split, hi, range, hashes);
+        }
+    }
+
+    /// Hash of the subtree over the leaves `lo..hi`.
+    fn subtree_hash(&self, lo: u64, hi: u64) -> Hash {
+        let size = hi - lo;
+        if size.is_power_of_two() {
+            let height = size.trailing_zeros();
+            return self
+                .tree
+                .get(node_position(height, lo >> height))
+                .expect("complete subtree hash is stored");
+        }
+
+        let split = lo + split_size(size);
+        node_hash(&self.subtree_hash(lo, split), &self.subtree_hash(split, hi))
+    }
+
+    /// Stores the hash of the leaf and of the subtrees it completes.
+    fn push_leaf(&mut self, index: u64, entry: &T) -> Result<()> {
+        let mut hash = leaf_hash(&entry.to_bytes());
+        self.tree.push(&hash)?;
+
+        let (mut height, mut node_index) = (0, index);
+        while node_index % 2 == 1 {
+            let left = self
+                .tree
+                .get(node_position(height, node_index - 1))
+                .expect("left sibling is stored");
+            hash = node_hash(&left, &hash);
+            self.tree.push(&hash)?;
+
+            height += 1;
+            node_index /= 2;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Storable, M: Memory> LogStructure<T> for CertifiedLog<T, M> {
+    fn get(&self, index: u64) -> Option<T> {
+        self.log.get(index)
+    }
+
+    fn append(&mut self, value: T) -> Result<u64> {
+        let index = self.log.len();
+        let result = self
+            .push_leaf(index, &value)
+            .and_then(|_| self.log.append(value));
+        if result.is_err() {
+            // Drop the hashes of the entry which is not in the log.
+            while self.tree.len() > tree_size(index) {
+                self.tree.pop();
+            }
+        }
+        result?;
+
+        Ok(index)
+    }
+
+    fn len(&self) -> u64 {
+        self.log.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.log.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.log.clear();
+        self.tree
+            .clear()
+            .expect("clearing a vector doesn't allocate");
+    }
+}
+
+/// Proof of inclusion of the `start..end` entries in the log of `tree_size` entries.
+///
+/// Contains the hashes of the subtrees outside of the range, in the order of
+/// the depth-first traversal of the tree.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct RangeProof {
+    pub tree_size: u64,
+    pub start: u64,
+    pub end: u64,
+    pub hashes: Vec<Hash>,
+}
+
+impl RangeProof {
+    /// Computes the root hash of the tree from the entries of the range,
+    /// or returns `None` if the proof doesn't match the entries.
+    pub fn root_hash<T: Storable>(&self, entries: &[T]) -> Option<Hash> {
+        if self.start >= self.end
+            || self.end > self.tree_size
+            || entries.len() as u64 != self.end - self.start
+        {
+            return None;
+        }
+
+        let leaves = entries
+            .iter()
+            .map(|entry| leaf_hash(&entry.to_bytes()))
+            .collect::<Vec<_>>();
+        let mut hashes = self.hashes.iter();
+        let root = self.reconstruct(0, self.tree_size, &leaves, &mut hashes)?;
+
+        // All the hashes must be consumed by a valid proof.
+        hashes.next().is_none().then_some(root)
+    }
+
+    /// Checks that the entries of the range are included in the tree with the given root hash,
+    /// e.g. the certified data of the canister.
+    pub fn verify<T: Storable>(&self, entries: &[T], root_hash: &Hash) -> bool {
+        self.root_hash(entries).as_ref() == Some(root_hash)
+    }
+
+    fn reconstruct<'a>(
+        &self,
+        lo: u64,
+        hi: u64,
+        leaves: &[Hash],
+        hashes: &mut impl Iterator<Item = &'a Hash>,
+    ) -> Option<Hash> {
+        if hi <= self.start || lo >= self.end {
+            hashes.next().copied()
+        } else if hi - lo == 1 {
+            Some(leaves[(lo - self.start) as usize])
+        } else {
+            let split = lo + split_size(hi - lo);
+            let left = self.reconstruct(lo, split, leaves, hashes)?;
+            let right = self.reconstruct(split, hi, leaves, hashes)?;
+            Some(node_hash(&left, &right))
+        }
+    }
+}
+
+fn leaf_hash(bytes: &[u8]) -> Hash {
+    sha256(&[&[0], bytes])
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    sha256(&[&[1], left, right])
+}
+
+/// The largest power of two less than `size`, the size of the left subtree.
+fn split_size(size: u64) -> u64 {
+    debug_assert!(size > 1);
+    1 << (63 - (size - 1).leading_zeros())
+}
+
+/// Number of the stored hashes of the log with `len` entries.
+fn tree_size(len: u64) -> u64 {
+    2 * len - len.count_ones() as u64
+}
+
+/// Post-order position of the complete subtree of `height` with the given index on its level.
+fn node_position(height: u32, index: u64) -> u64 {
+    (1 << (height + 1)) * (index + 1) - index.count_ones() as u64 - 2
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn new_log() -> CertifiedLog<u64, VectorMemory> {
+        CertifiedLog::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+        .unwrap()
+    }
+
+    /// Root hash computed by the recursive definition of RFC 6962.
+    fn reference_root(entries: &[u64]) -> Hash {
+        match entries.len() {
+            0 => sha256(&[]),
+            1 => leaf_hash(&entries[0].to_bytes()),
+            len => {
+                let split = split_size(len as u64) as usize;
+                node_hash(
+                    &reference_root(&entries[..split]),
+                    &reference_root(&entries[split..]),
+                )
+            }
+        }
+    }
+
+    #[test]
+    fn should_compute_root_hash_incrementally() {
+        let mut log = new_log();
+        let mut entries = vec![];
+        assert_eq!(log.root_hash(), reference_root(&entries));
+
+        for value in 0..33u64 {
+            assert_eq!(log.append(value * 7).unwrap(), value);
+            entries.push(value * 7);
+            assert_eq!(log.root_hash(), reference_root(&entries));
+        }
+    }
+
+    #[test]
+    fn should_verify_range_proofs() {
+        let mut log = new_log();
+        let entries = (0..13u64).collect::<Vec<_>>();
+        for &entry in &entries {
+            log.append(entry).unwrap();
+        }
+        let root = log.root_hash();
+
+        for start in 0..13 {
+            for end in start + 1..=13 {
+                let proof = log.proof(start..end).unwrap();
+                let range = &entries[start as usize..end as usize];
+                assert!(proof.verify(range, &root), "{start}..{end}");
+            }
+        }
+
+        let proof = log.proof(2..5).unwrap();
+        assert!(!proof.verify(&[2u64, 3, 42], &root));
+        assert!(!proof.verify(&[2u64, 3], &root));
+        assert!(log.proof(5..5).is_none());
+        assert!(log.proof(10..14).is_none());
+    }
+
+    #[test]
+    fn should_restore_tree_from_memory() {
+        let index_memory = VectorMemory::default();
+        let data_memory = VectorMemory::default();
+        let tree_memory = VectorMemory::default();
+
+        let root = {
+            let mut log = CertifiedLog::<u64, _>::new(
+                index_memory.clone(),
+                data_memory.clone(),
+                tree_memory.clone(),
+            )
+            .unwrap();
+            for value in 0..10 {
+                log.append(value).unwrap();
+            }
+            log.root_hash()
+        };
+
+        let log =
+            CertifiedLog::<u64, _>::new(index_memory.clone(), data_memory.clone(), tree_memory)
+                .unwrap();
+        assert_eq!(log.len(), 10);
+        assert_eq!(log.root_hash(), root);
+
+        // The tree is rebuilt from the entries if its memory is lost.
+        let log = CertifiedLog::<u64, _>::new(index_memory, data_memory, VectorMemory::default())
+            .unwrap();
+        assert_eq!(log.root_hash(), root);
+    }
+
+    #[test]
+    fn should_clear_log() {
+        let mut log = new_log();
+        log.append(1).unwrap();
+        log.clear();
+
+        assert!(log.is_empty());
+        assert_eq!(log.root_hash(), sha256(&[]));
+        log.append(2).unwrap();
+        assert_eq!(log.root_hash(), reference_root(&[2]));
+    }
+}
@@ -0,0 +1,560 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::{Bound as RangeBound, RangeBounds};
+
+use dfinity_stable_structures::{btreemap, Memory, Storable};
+use ic_certification::{
+    empty, fork, fork_hash, labeled, labeled_hash, leaf, leaf_hash, pruned, HashTree,
+};
+
+use super::{set_certified_data, sha256, Hash};
+use crate::structure::{
+    BTreeMapStructure, CellStructure, IterableSortedMapStructure, StableBTreeMap, StableCell,
+};
+use crate::{Bound, Result};
+
+/// Key-value map with a hash tree in the IC certification format.
+///
+/// The entries are kept in a treap, a binary search tree with the node priorities derived
+/// from the key hashes, so the shape of the tree depends only on the set of keys.
+/// Every node stores the hash of its subtree, and inserts and removals recompute only
+/// the hashes on the path to the changed node.
+///
+/// The subtree of a node is `fork(left, fork(labeled(key, leaf(value)), right))`
+/// without the empty children, as in the `ic-certified-map` crate. The labels are
+/// the `Storable` bytes of the keys, so the bytes must be ordered the same way as the keys
+/// for the witnesses to prove absence of the keys.
+///
+/// The root hash is not certified automatically: call [`CertifiedBTreeMap::certify`]
+/// or include [`CertifiedBTreeMap::root_hash`] into the canister certified data after updates.
+pub struct CertifiedBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    data: StableBTreeMap<K, V, M>,
+    nodes: StableBTreeMap<K, TreeNode<K>, M>,
+    root: StableCell<RootKey<K>, M>,
+}
+
+impl<K, V, M> CertifiedBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Loads the map from the memories or creates an empty one.
+    pub fn new(data_memory: M, tree_memory: M, root_memory: M) -> Result<Self> {
+        Ok(Self {
+            data: StableBTreeMap::new(data_memory),
+            nodes: StableBTreeMap::new(tree_memory),
+            root: StableCell::new(root_memory, RootKey(None))?,
+        })
+    }
+
+    /// Root hash of the hash tree.
+    pub fn root_hash(&self) -> Hash {
+        match &self.root.get().0 {
+            Some(root) => self.node(root).subtree_hash,
+            None => empty().digest(),
+        }
+    }
+
+    /// Sets the root hash as the canister certified data.
+    pub fn certify(&self) {
+        set_certified_data(&self.root_hash());
+    }
+
+    /// Returns the tree which reveals the value of the key, or proves that the key is absent.
+    pub fn witness(&self, key: &K) -> HashTree {
+        self.witness_range((RangeBound::Included(key), RangeBound::Included(key)))
+    }
+
+    /// Returns the tree which reveals all the entries in the range.
+    ///
+    /// The keys next to the range are revealed with pruned values,
+    /// so the tree also proves that there are no other keys in the range.
+    pub fn witness_range(&self, key_range: impl RangeBounds<K>) -> HashTree {
+        match &self.root.get().0 {
+            Some(root) => self.witness_node(root, &key_range),
+            None => empty(),
+        }
+    }
+
+    fn witness_node(&self, key: &K, key_range: &impl RangeBounds<K>) -> HashTree {
+        let node = self.node(key);
+
+        let is_above_start = match key_range.start_bound() {
+            RangeBound::Included(start) | RangeBound::Excluded(start) => start < key,
+            RangeBound::Unbounded => true,
+        };
+        let is_below_end = match key_range.end_bound() {
+            RangeBound::Included(end) | RangeBound::Excluded(end) => end > key,
+            RangeBound::Unbounded => true,
+        };
+        let child_witness = |child: &K, is_revealed: bool| {
+            if is_revealed {
+                self.witness_node(child, key_range)
+            } else {
+                pruned(self.node(child).subtree_hash)
+            }
+        };
+
+        let value = if key_range.contains(key) {
+            leaf(
+                self.data
+                    .get(key)
+                    .expect("tree key is in the map")
+                    .to_bytes(),
+            )
+        } else {
+            pruned(node.value_hash)
+        };
+
+        three_way_fork(
+            node.left
+                .as_ref()
+                .map(|left| child_witness(left, is_above_start)),
+            labeled(key.to_bytes().into_owned(), value),
+            node.right
+                .as_ref()
+                .map(|right| child_witness(right, is_below_end)),
+        )
+    }
+
+    fn node(&self, key: &K) -> TreeNode<K> {
+        self.nodes.get(key).expect("tree node is stored")
+    }
+
+    /// Computes the subtree hash of the node and stores it.
+    fn store_node(&mut self, key: &K, mut node: TreeNode<K>) {
+        let child_hash = |child: &Option<K>| child.as_ref().map(|key| self.node(key).subtree_hash);
+        let left = child_hash(&node.left);
+        let right = child_hash(&node.right);
+        let middle = labeled_hash(&key.to_bytes(), &node.value_hash);
+
+        node.subtree_hash = match (left, right) {
+            (None, None) => middle,
+            (None, Some(right)) => fork_hash(&middle, &right),
+            (Some(left), None) => fork_hash(&left, &middle),
+            (Some(left), Some(right)) => fork_hash(&left, &fork_hash(&middle, &right)),
+        };
+        self.nodes.insert(key.clone(), node);
+    }
+
+    /// Inserts the key into the subtree and returns the new root of the subtree.
+    fn insert_node(&mut self, subtree: Option<K>, key: &K, value_hash: Hash) -> K {
+        let Some(current) = subtree else {
+            self.store_node(key, TreeNode::new(value_hash));
+            return key.clone();
+        };
+
+        let mut node = self.node(&current);
+        match key.cmp(&current) {
+            Ordering::Equal => {
+                node.value_hash = value_hash;
+                self.store_node(&current, node);
+                current
+            }
+            Ordering::Less => {
+                let child = self.insert_node(node.left.take(), key, value_hash);
+                if outranks(&child, &current) {
+                    let mut child_node = self.node(&child);
+                    node.left = child_node.right.take();
+                    self.store_node(&current, node);
+                    child_node.right = Some(current);
+                    self.store_node(&child, child_node);
+                    child
+                } else {
+                    node.left = Some(child);
+                    self.store_node(&current, node);
+                    current
+                }
+            }
+            Ordering::Greater => {
+                let child = self.insert_node(node.right.take(), key, value_hash);
+                if outranks(&child, &current) {
+                    let mut child_node = self.node(&child);
+                    node.right = child_node.left.take();
+                    self.store_node(&current, node);
+                    child_node.left = Some(current);
+                    self.store_node(&child, child_node);
+                    child
+                } else {
+                    node.right = Some(child);
+                    self.store_node(&current, node);
+                    current
+                }
+            }
+        }
+    }
+
+    /// Removes the key, which must be in the subtree, and returns the new root of the subtree.
+    fn remove_node(&mut self, subtree: Option<K>, key: &K) -> Option<K> {
+        let current = subtree.expect("removed key is in the tree");
+        let mut node = self.node(&current);
+        match key.cmp(&current) {
+            Ordering::Equal => {
+                self.nodes.remove(&current);
+                self.merge_nodes(node.left, node.right)
+            }
+            Ordering::Less => {
+                node.left = self.remove_node(node.left.take(), key);
+                self.store_node(&current, node);
+                Some(current)
+            }
+            Ordering::Greater => {
+                node.right = self.remove_node(node.right.take(), key);
+                self.store_node(&current, node);
+                Some(current)
+            }
+        }
+    }
+
+    /// Merges two subtrees, all keys of the `left` being less than the keys of the `right`.
+    fn merge_nodes(&mut self, left: Option<K>, right: Option<K>) -> Option<K> {
+        match (left, right) {
+            (None, subtree) | (subtree, None) => subtree,
+            (Some(left), Some(right)) => {
+                if outranks(&left, &right) {
+                    let mut node = self.node(&left);
+                    node.right = self.merge_nodes(node.right.take(), Some(right));
+                    self.store_node(&left, node);
+                    Some(left)
+                } else {
+                    let mut node = self.node(&right);
+                    node.left = self.merge_nodes(Some(left), node.left.take());
+                    self.store_node(&right, node);
+                    Some(right)
+                }
+            }
+        }
+    }
+
+    fn set_root(&mut self, root: Option<K>) {
+        self.root
+            .set(RootKey(root))
+            .expect("root key fits into the cell memory");
+    }
+}
+
+impl<K, V, M> BTreeMapStructure<K, V> for CertifiedBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.data.get(key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let value_hash = leaf_hash(&value.to_bytes());
+        let previous = self.data.insert(key.clone(), value);
+
+        let root = self.root.get().0.clone();
+        let root = self.insert_node(root, &key, value_hash);
+        self.set_root(Some(root));
+
+        previous
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.data.remove(key)?;
+
+        let root = self.root.get().0.clone();
+        let root = self.remove_node(root, key);
+        self.set_root(root);
+
+        Some(value)
+    }
+
+    fn pop_first(&mut self) -> Option<(K, V)> {
+        let (key, _) = self.data.first_key_value()?;
+        self.remove(&key).map(|value| (key, value))
+    }
+
+    fn pop_last(&mut self) -> Option<(K, V)> {
+        let (key, _) = self.data.last_key_value()?;
+        self.remove(&key).map(|value| (key, value))
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.data.contains_key(key)
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        self.data.first_key_value()
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        self.data.last_key_value()
+    }
+
+    fn len(&self) -> u64 {
+        self.data.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.nodes.clear();
+        self.set_root(None);
+    }
+}
+
+impl<K, V, M> IterableSortedMapStructure<K, V> for CertifiedBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    type Iterator<'a>
+        = btreemap::Iter<'a, K, V, M>
+    where
+        Self: 'a;
+
+    fn iter(&self) -> Self::Iterator<'_> {
+        self.data.iter()
+    }
+
+    fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_> {
+        self.data.range(key_range)
+    }
+
+    fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_> {
+        self.data.iter_upper_bound(bound)
+    }
+
+    fn generation(&self) -> u64 {
+        self.data.generation()
+    }
+}
+
+/// Returns true if the node with the `key` must be above the node with the `other` key.
+fn outranks<K: Storable + Ord>(key: &K, other: &K) -> bool {
+    let priority =
+        |key: &K| -> [u8; 8] { sha256(&[&key.to_bytes()])[..8].try_into().expect("8 bytes") };
+    (priority(key), key) > (priority(other), other)
+}
+
+fn three_way_fork(left: Option<HashTree>, middle: HashTree, right: Option<HashTree>) -> HashTree {
+    match (left, right) {
+        (None, None) => middle,
+        (None, Some(right)) => fork(middle, right),
+        (Some(left), None) => fork(left, middle),
+        (Some(left), Some(right)) => fork(left, fork(middle, right)),
+    }
+}
+
+/// Node of the treap stored by the key.
+struct TreeNode<K> {
+    /// Leaf hash of the value bytes.
+    value_hash: Hash,
+    subtree_hash: Hash,
+    left: Option<K>,
+    right: Option<K>,
+}
+
+impl<K> TreeNode<K> {
+    fn new(value_hash: Hash) -> Self {
+        Self {
+            value_hash,
+            subtree_hash: [0; 32],
+            left: None,
+            right: None,
+        }
+    }
+}
+
+impl<K: Storable> Storable for TreeNode<K> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(&self.value_hash);
+        bytes.extend_from_slice(&self.subtree_hash);
+        for child in [&self.left, &self.right] {
+            match child {
+                Some(key) => {
+                    let key = key.to_bytes();
+                    bytes.push(1);
+                    bytes.extend_from_slice(&(key.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(&key);
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let hash_at =
+            |offset: usize| -> Hash { bytes[offset..offset + 32].try_into().expect("32 bytes") };
+        let mut offset = 64;
+        let mut read_child = || {
+            let is_some = bytes[offset] != 0;
+            offset += 1;
+            is_some.then(|| {
+                let len =
+                    u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"));
+                offset += 4;
+                let key = K::from_bytes(Cow::Borrowed(&bytes[offset..offset + len as usize]));
+                offset += len as usize;
+                key
+            })
+        };
+        let left = read_child();
+        let right = read_child();
+
+        Self {
+            value_hash: hash_at(0),
+            subtree_hash: hash_at(32),
+            left,
+            right,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Key of the treap root, empty for an empty map.
+struct RootKey<K>(Option<K>);
+
+impl<K: Storable> Storable for RootKey<K> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        match &self.0 {
+            Some(key) => key.to_bytes(),
+            None => Cow::Borrowed(&[]),
+        }
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self((!bytes.is_empty()).then(|| K::from_bytes(bytes)))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+    use ic_certification::LookupResult;
+
+    use super::*;
+
+    fn new_map() -> CertifiedBTreeMap<u64, String, VectorMemory> {
+        CertifiedBTreeMap::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn should_not_depend_on_insertion_order() {
+        let mut ascending = new_map();
+        let mut descending = new_map();
+        assert_eq!(ascending.root_hash(), empty().digest());
+
+        for key in 0..50u64 {
+            ascending.insert(key, key.to_string());
+            descending.insert(49 - key, (49 - key).to_string());
+        }
+        assert_eq!(ascending.root_hash(), descending.root_hash());
+        assert_eq!(ascending.witness_range(..).digest(), ascending.root_hash());
+
+        ascending.insert(7, "seven".to_string());
+        assert_ne!(ascending.root_hash(), descending.root_hash());
+        descending.insert(7, "seven".to_string());
+        assert_eq!(ascending.root_hash(), descending.root_hash());
+
+        for key in (0..50u64).step_by(3) {
+            assert!(ascending.remove(&key).is_some());
+        }
+        let mut rebuilt = new_map();
+        for (key, value) in ascending.iter() {
+            rebuilt.insert(key, value);
+        }
+        assert_eq!(ascending.root_hash(), rebuilt.root_hash());
+        assert_eq!(ascending.len(), rebuilt.len());
+    }
+
+    #[test]
+    fn should_create_witnesses() {
+        let mut map = new_map();
+        for key in (0..40u64).step_by(2) {
+            map.insert(key, format!("value {key}"));
+        }
+        let root = map.root_hash();
+
+        let witness = map.witness(&10);
+        assert_eq!(witness.digest(), root);
+        assert_eq!(
+            witness.lookup_path([10u64.to_bytes()]),
+            LookupResult::Found(&b"value 10"[..])
+        );
+        assert_eq!(
+            witness.lookup_path([12u64.to_bytes()]),
+            LookupResult::Unknown
+        );
+
+        let witness = map.witness(&11);
+        assert_eq!(witness.digest(), root);
+        assert_eq!(
+            witness.lookup_path([11u64.to_bytes()]),
+            LookupResult::Absent
+        );
+
+        let witness = map.witness_range(5..=9);
+        assert_eq!(witness.digest(), root);
+        for key in [6u64, 8] {
+            let value = format!("value {key}");
+            assert_eq!(
+                witness.lookup_path([key.to_bytes()]),
+                LookupResult::Found(value.as_bytes())
+            );
+        }
+        assert_eq!(witness.lookup_path([7u64.to_bytes()]), LookupResult::Absent);
+
+        assert_eq!(new_map().witness(&1).digest(), empty().digest());
+    }
+
+    #[test]
+    fn should_restore_from_memory() {
+        let data_memory = VectorMemory::default();
+        let tree_memory = VectorMemory::default();
+        let root_memory = VectorMemory::default();
+
+        let root = {
+            let mut map = CertifiedBTreeMap::<u64, u64, _>::new(
+                data_memory.clone(),
+                tree_memory.clone(),
+                root_memory.clone(),
+            )
+            .unwrap();
+            for key in 0..20 {
+                map.insert(key, key * 2);
+            }
+            map.root_hash()
+        };
+
+        let mut map =
+            CertifiedBTreeMap::<u64, u64, _>::new(data_memory, tree_memory, root_memory).unwrap();
+        assert_eq!(map.root_hash(), root);
+        assert_eq!(map.pop_first(), Some((0, 0)));
+        assert_eq!(map.pop_last(), Some((19, 38)));
+        assert_eq!(
+            map.range(5..8).collect::<Vec<_>>(),
+            [(5, 10), (6, 12), (7, 14)]
+        );
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.root_hash(), empty().digest());
+    }
+}
@@ -0,0 +1,29 @@
+use sha2::{Digest, Sha256};
+
+mod log;
+mod map;
+
+pub use ic_certification::HashTree;
+
+pub use self::log::{CertifiedLog, RangeProof};
+pub use self::map::CertifiedBTreeMap;
+
+/// SHA-256 hash of the certified data.
+pub type Hash = [u8; 32];
+
+/// Sets the canister certified data, so the queries can return it with the data certificate.
+///
+/// Does nothing outside of the IC, e.g. in unit tests.
+pub fn set_certified_data(hash: &Hash) {
+    if cfg!(target_family = "wasm") {
+        ic_cdk::api::set_certified_data(hash);
+    }
+}
+
+fn sha256(chunks: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    for chunk in chunks {
+        hasher.update(chunk);
+    }
+    hasher.finalize().into()
+}
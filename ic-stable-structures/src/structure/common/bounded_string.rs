@@ -0,0 +1,220 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::Storable;
+
+use super::Bounded;
+
+/// UTF-8 string of at most `N` bytes.
+///
+/// Unlike `String` it implements `Bounded`, so it can be used as a part of
+/// `StableMultimap` keys. Strings are compared by their bytes, which is the same
+/// as their `Storable` byte ordering and as the `String` ordering.
+///
+/// `MIN` is the empty string, `MAX` is the largest valid UTF-8 string of `N` bytes.
+#[derive(Clone, Copy)]
+pub struct BoundedString<const N: usize> {
+    len: usize,
+    bytes: [u8; N],
+}
+
+impl<const N: usize> BoundedString<N> {
+    /// Creates a new string, returns `None` if the string is longer than `N` bytes.
+    pub fn new(value: &str) -> Option<Self> {
+        if value.len() > N {
+            return None;
+        }
+
+        let mut bytes = [0; N];
+        bytes[..value.len()].copy_from_slice(value.as_bytes());
+        Some(Self {
+            len: value.len(),
+            bytes,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(self.as_bytes()).expect("BoundedString always contains valid utf-8")
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// The largest valid UTF-8 sequence of `N` bytes: `U+10FFFF` characters followed by the
+/// largest character that fits into the remaining bytes.
+const fn max_utf8_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    let mut i = 0;
+    while i + 4 <= N {
+        bytes[i] = 0xF4;
+        bytes[i + 1] = 0x8F;
+        bytes[i + 2] = 0xBF;
+        bytes[i + 3] = 0xBF;
+        i += 4;
+    }
+
+    match N - i {
+        1 => bytes[i] = 0x7F,
+        2 => {
+            bytes[i] = 0xDF;
+            bytes[i + 1] = 0xBF;
+        }
+        3 => {
+            bytes[i] = 0xEF;
+            bytes[i + 1] = 0xBF;
+            bytes[i + 2] = 0xBF;
+        }
+        _ => {}
+    }
+
+    bytes
+}
+
+impl<const N: usize> Bounded for BoundedString<N> {
+    const MIN: Self = Self {
+        len: 0,
+        bytes: [0; N],
+    };
+    const MAX: Self = Self {
+        len: N,
+        bytes: max_utf8_bytes::<N>(),
+    };
+}
+
+impl<const N: usize> Default for BoundedString<N> {
+    fn default() -> Self {
+        Self::MIN
+    }
+}
+
+impl<const N: usize> TryFrom<&str> for BoundedString<N> {
+    type Error = crate::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::new(value).ok_or(crate::Error::ValueTooLarge(value.len() as u64))
+    }
+}
+
+impl<const N: usize> PartialEq for BoundedString<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<const N: usize> Eq for BoundedString<N> {}
+
+impl<const N: usize> PartialOrd for BoundedString<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for BoundedString<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl<const N: usize> Hash for BoundedString<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
+impl<const N: usize> fmt::Debug for BoundedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> fmt::Display for BoundedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> Storable for BoundedString<N> {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: N as u32,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let value = std::str::from_utf8(&bytes).expect("invalid utf-8 string");
+        Self::new(value).expect("string is too long")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::common::assert_within_bounds;
+
+    #[test]
+    fn should_build_valid_max_string() {
+        assert_eq!(BoundedString::<0>::MAX.as_str(), "");
+        assert_eq!(BoundedString::<1>::MAX.as_str(), "\u{7F}");
+        assert_eq!(BoundedString::<2>::MAX.as_str(), "\u{7FF}");
+        assert_eq!(BoundedString::<3>::MAX.as_str(), "\u{FFFF}");
+        assert_eq!(BoundedString::<5>::MAX.as_str(), "\u{10FFFF}\u{7F}");
+        assert_eq!(BoundedString::<8>::MAX.len(), 8);
+    }
+
+    #[test]
+    fn should_reject_long_strings() {
+        assert!(BoundedString::<3>::new("abc").is_some());
+        assert!(BoundedString::<3>::new("abcd").is_none());
+        assert!(BoundedString::<3>::try_from("\u{10FFFF}").is_err());
+    }
+
+    #[test]
+    fn should_keep_values_within_bounds() {
+        let values = [
+            "",
+            "a",
+            "ICP",
+            "ckBTC",
+            "ñandú",
+            "zzzzzzzzz",
+            "\u{FFFF}",
+            "\u{7FF}\u{7FF}\u{7FF}\u{7FF}",
+            "\u{10FFFF}\u{10FFFF}",
+        ];
+        assert_within_bounds(values.map(|s| BoundedString::<9>::new(s).unwrap()));
+
+        assert_within_bounds([
+            (BoundedString::<5>::new("ICP").unwrap(), 1u64),
+            (
+                BoundedString::<5>::new("\u{10FFFF}\u{7F}").unwrap(),
+                u64::MAX,
+            ),
+        ]);
+    }
+
+    #[test]
+    fn should_roundtrip_and_compare_as_strings() {
+        let a = BoundedString::<8>::new("abc").unwrap();
+        let b = BoundedString::<8>::new("abd").unwrap();
+        assert!(a < b);
+        assert!(BoundedString::<8>::new("ab").unwrap() < a);
+        assert_eq!(BoundedString::<8>::from_bytes(a.to_bytes()), a);
+        assert_eq!(a.to_string(), "abc");
+    }
+}
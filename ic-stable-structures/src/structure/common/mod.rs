@@ -1,7 +1,19 @@
+mod bounded_string;
 pub mod ring_buffer;
+pub mod var_ring_buffer;
+mod write_limits;
 
+use std::fmt::Debug;
+
+pub use bounded_string::BoundedString;
 use candid::Principal;
+use dfinity_stable_structures::Storable;
 pub use ring_buffer::{StableRingBuffer, StableRingBufferIndices};
+pub use var_ring_buffer::{StableVarRingBuffer, StableVarRingBufferHeader, VarRingBufferRecord};
+pub use write_limits::{
+    check_soft_quota, check_storable_bound, clear_soft_quota, set_soft_quota,
+    set_soft_quota_for_memory,
+};
 
 /// A trait for types that have a minimum and maximum value.
 pub trait Bounded {
@@ -84,6 +96,33 @@ impl<const N: usize> Bounded for [u8; N] {
     const MAX: [u8; N] = [u8::MAX; N];
 }
 
+impl Bounded for bool {
+    const MIN: bool = false;
+    const MAX: bool = true;
+}
+
+impl Bounded for () {
+    const MIN: () = ();
+    const MAX: () = ();
+}
+
+/// Tuples are compared lexicographically, so the bounds are the tuples of the element bounds.
+macro_rules! impl_bounded_for_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: Bounded),+> Bounded for ($($name,)+) {
+            const MIN: Self = ($($name::MIN,)+);
+            const MAX: Self = ($($name::MAX,)+);
+        }
+    };
+}
+
+impl_bounded_for_tuple!(A);
+impl_bounded_for_tuple!(A, B);
+impl_bounded_for_tuple!(A, B, C);
+impl_bounded_for_tuple!(A, B, C, D);
+impl_bounded_for_tuple!(A, B, C, D, E);
+impl_bounded_for_tuple!(A, B, C, D, E, F);
+
 /// Principal sorted by two fields:
 /// 1) `len: u8` -> min = 0, max = 29;
 /// 2) `bytes: [u8; Self::MAX_LENGTH_IN_BYTES]` -> min = [], max = [0xFF; 29];
@@ -92,10 +131,49 @@ impl Bounded for Principal {
     const MAX: Self = Principal::from_slice(&[0xFF; 29]);
 }
 
+/// Checks that `T::MIN <= value <= T::MAX` for every value, both by `Ord`
+/// and by the byte ordering of the `Storable` representation.
+///
+/// Helps to catch the `Bounded` implementations which don't match the encoding,
+/// e.g. big-endian two's complement signed integers, where negative values
+/// are encoded into larger byte strings than the positive ones.
+///
+/// # Panics
+///
+/// Panics with the offending value if any of the checks fails.
+pub fn assert_within_bounds<T>(values: impl IntoIterator<Item = T>)
+where
+    T: Bounded + Storable + Ord + Debug,
+{
+    let (min, max) = (T::MIN, T::MAX);
+    let (min_bytes, max_bytes) = (min.to_bytes(), max.to_bytes());
+    assert!(
+        min <= max && min_bytes <= max_bytes,
+        "MIN {min:?} is greater than MAX {max:?}"
+    );
+
+    for value in values {
+        assert!(
+            min <= value && value <= max,
+            "{value:?} is out of bounds by Ord"
+        );
+
+        let bytes = value.to_bytes();
+        assert!(
+            min_bytes <= bytes && bytes <= max_bytes,
+            "{value:?} is out of bounds by Storable byte ordering"
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::borrow::Cow;
+
     use candid::Principal;
+    use dfinity_stable_structures::storable::Bound;
 
+    use super::*;
     use crate::Bounded;
 
     #[test]
@@ -114,4 +192,51 @@ mod tests {
             assert!(principal <= max_principal);
         }
     }
+
+    #[test]
+    fn should_check_tuple_bounds() {
+        assert_within_bounds([(0u8, 0u32), (1, 2), (u8::MAX, u32::MAX)]);
+        assert_within_bounds([
+            (Principal::anonymous(), 42u64),
+            (Principal::management_canister(), u64::MAX),
+        ]);
+        assert_within_bounds([(Principal::from_slice(&[0xFF; 29]), 0u64, [1u8; 4])]);
+    }
+
+    #[test]
+    fn should_check_unsigned_bounds() {
+        assert_within_bounds([0u16, 1, 300, u16::MAX]);
+        assert_within_bounds([0u64, 1, u64::MAX / 2, u64::MAX]);
+        assert_within_bounds([[0u8; 3], [1, 2, 3], [0xFF; 3]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "is greater than MAX")]
+    fn should_detect_encoding_not_matching_bounds() {
+        /// Big-endian two's complement: `MIN` is encoded as `0x80000000`, which is above `MAX`.
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+        struct Signed(i32);
+
+        impl Bounded for Signed {
+            const MIN: Self = Self(i32::MIN);
+            const MAX: Self = Self(i32::MAX);
+        }
+
+        impl Storable for Signed {
+            const BOUND: Bound = Bound::Bounded {
+                max_size: 4,
+                is_fixed_size: true,
+            };
+
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                Cow::Owned(self.0.to_be_bytes().to_vec())
+            }
+
+            fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                Self(i32::from_be_bytes(bytes.as_ref().try_into().unwrap()))
+            }
+        }
+
+        assert_within_bounds([Signed(0), Signed(-1)]);
+    }
 }
@@ -0,0 +1,530 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::num::NonZeroU64;
+use std::time::Duration;
+
+use dfinity_stable_structures::storable::Bound;
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{
+    BTreeMapStructure, CellStructure, IterableSortedMapStructure, StableBTreeMap, StableCell,
+};
+use crate::{Error, Result};
+
+const WASM_PAGE_SIZE_IN_BYTES: u64 = 65536;
+
+/// State and retention settings of the [`StableVarRingBuffer`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StableVarRingBufferHeader {
+    /// Sequence number of the next record
+    next_seq: u64,
+    /// Arena offset after the newest record
+    tail: u64,
+    /// Sum of the sizes of the records in the buffer
+    used_bytes: u64,
+    /// Size of the circular arena
+    capacity_bytes: u64,
+    /// Max age of the records in nanoseconds, `0` means no limit
+    max_age_nanos: u64,
+}
+
+impl StableVarRingBufferHeader {
+    /// Create a new header with the provided arena size and max record age
+    pub fn new(capacity_bytes: NonZeroU64, max_age: Option<Duration>) -> Self {
+        Self {
+            next_seq: 0,
+            tail: 0,
+            used_bytes: 0,
+            capacity_bytes: capacity_bytes.get(),
+            max_age_nanos: max_age.map_or(0, |age| age.as_nanos() as u64),
+        }
+    }
+
+    /// Returns the size of the arena in bytes
+    pub fn capacity_bytes(&self) -> u64 {
+        self.capacity_bytes
+    }
+
+    /// Returns the max age of the records, if any
+    pub fn max_age(&self) -> Option<Duration> {
+        (self.max_age_nanos != 0).then(|| Duration::from_nanos(self.max_age_nanos))
+    }
+}
+
+const HEADER_SIZE: usize = 5 * size_of::<u64>();
+
+impl Storable for StableVarRingBufferHeader {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: HEADER_SIZE as u32,
+        is_fixed_size: true,
+    };
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE);
+        buf.extend_from_slice(&self.next_seq.to_le_bytes());
+        buf.extend_from_slice(&self.tail.to_le_bytes());
+        buf.extend_from_slice(&self.used_bytes.to_le_bytes());
+        buf.extend_from_slice(&self.capacity_bytes.to_le_bytes());
+        buf.extend_from_slice(&self.max_age_nanos.to_le_bytes());
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+        };
+        Self {
+            next_seq: u64_at(0),
+            tail: u64_at(8),
+            used_bytes: u64_at(16),
+            capacity_bytes: u64_at(24),
+            max_age_nanos: u64_at(32),
+        }
+    }
+}
+
+/// Position of a record in the arena
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RecordLocation {
+    offset: u64,
+    len: u32,
+    timestamp: u64,
+}
+
+const RECORD_LOCATION_SIZE: usize = 2 * size_of::<u64>() + size_of::<u32>();
+
+impl Storable for RecordLocation {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: RECORD_LOCATION_SIZE as u32,
+        is_fixed_size: true,
+    };
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut buf = Vec::with_capacity(RECORD_LOCATION_SIZE);
+        buf.extend_from_slice(&self.offset.to_le_bytes());
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf.extend_from_slice(&self.timestamp.to_le_bytes());
+        buf.into()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            offset: u64::from_le_bytes(bytes[..8].try_into().expect("offset: expected 8 bytes")),
+            len: u32::from_le_bytes(bytes[8..12].try_into().expect("len: expected 4 bytes")),
+            timestamp: u64::from_le_bytes(
+                bytes[12..20]
+                    .try_into()
+                    .expect("timestamp: expected 8 bytes"),
+            ),
+        }
+    }
+}
+
+/// Record of the [`StableVarRingBuffer`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VarRingBufferRecord<T> {
+    /// Sequence number, increasing with every push
+    pub seq: u64,
+    /// Time of the push in nanoseconds
+    pub timestamp: u64,
+    pub value: T,
+}
+
+/// Ring buffer of variable-size records stored in a circular byte arena.
+///
+/// The oldest records are evicted when a new record doesn't fit into the arena,
+/// and when they get older than the max age. The records are addressed by the sequence
+/// numbers, which keep increasing after the evictions.
+pub struct StableVarRingBuffer<T: Storable, M: Memory> {
+    /// Circular arena with the record bytes
+    arena: M,
+    /// Arena locations of the records by sequence number
+    index: StableBTreeMap<u64, RecordLocation, M>,
+    header: StableCell<StableVarRingBufferHeader, M>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Storable, M: Memory> StableVarRingBuffer<T, M> {
+    /// Creates new ring buffer or loads it from the memories.
+    ///
+    /// The arena size and the max age are used only if the buffer is created.
+    pub fn new(
+        arena_memory: M,
+        index_memory: M,
+        header_memory: M,
+        capacity_bytes: NonZeroU64,
+        max_age: Option<Duration>,
+    ) -> Result<Self> {
+        Ok(Self {
+            arena: arena_memory,
+            index: StableBTreeMap::new(index_memory),
+            header: StableCell::new(
+                header_memory,
+                StableVarRingBufferHeader::new(capacity_bytes, max_age),
+            )?,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Number of records in the buffer
+    pub fn len(&self) -> u64 {
+        self.index.len()
+    }
+
+    /// Returns whether is empty
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Sum of the sizes of the records in the buffer
+    pub fn used_bytes(&self) -> u64 {
+        self.header.get().used_bytes
+    }
+
+    /// Size of the arena
+    pub fn capacity_bytes(&self) -> u64 {
+        self.header.get().capacity_bytes
+    }
+
+    /// Max age of the records, if any
+    pub fn max_age(&self) -> Option<Duration> {
+        self.header.get().max_age()
+    }
+
+    /// Updates the max age of the records. The expired records are evicted by the next push
+    /// or [`StableVarRingBuffer::evict_expired`].
+    pub fn set_max_age(&mut self, max_age: Option<Duration>) -> Result<()> {
+        let mut header = self.header.get().clone();
+        header.max_age_nanos = max_age.map_or(0, |age| age.as_nanos() as u64);
+        self.header.set(header)
+    }
+
+    /// Sequence number of the oldest record
+    pub fn first_seq(&self) -> Option<u64> {
+        self.index.first_key_value().map(|(seq, _)| seq)
+    }
+
+    /// Sequence number of the newest record
+    pub fn last_seq(&self) -> Option<u64> {
+        self.index.last_key_value().map(|(seq, _)| seq)
+    }
+
+    /// Pushes the record with the current time and returns its sequence number.
+    pub fn push(&mut self, value: &T) -> Result<u64> {
+        self.push_at(value, current_time())
+    }
+
+    /// Pushes the record with the given timestamp in nanoseconds and returns its
+    /// sequence number. The timestamps are expected to be non-decreasing.
+    ///
+    /// Returns `Error::ValueTooLarge` if the record is larger than the arena.
+    pub fn push_at(&mut self, value: &T, timestamp: u64) -> Result<u64> {
+        let bytes = value.to_bytes();
+        let len = bytes.len() as u64;
+        if len > self.capacity_bytes() || len > u32::MAX as u64 {
+            return Err(Error::ValueTooLarge(len));
+        }
+
+        self.evict_expired_at(timestamp);
+
+        let offset = loop {
+            let header = self.header.get();
+            let Some((_, oldest)) = self.index.first_key_value() else {
+                break 0;
+            };
+
+            let head = oldest.offset;
+            if head < header.tail {
+                // Live records are in `head..tail`: write after them or wrap to the start.
+                if header.tail + len <= header.capacity_bytes {
+                    break header.tail;
+                }
+                if len <= head {
+                    break 0;
+                }
+            } else if header.tail + len <= head {
+                // Live records are wrapped: the free space is in `tail..head`.
+                break header.tail;
+            }
+
+            self.evict_oldest();
+        };
+
+        self.ensure_arena_size(offset + len)?;
+        self.arena.write(offset, &bytes);
+
+        let mut header = self.header.get().clone();
+        let seq = header.next_seq;
+        header.next_seq += 1;
+        header.tail = offset + len;
+        header.used_bytes += len;
+        self.header.set(header)?;
+
+        self.index.insert(
+            seq,
+            RecordLocation {
+                offset,
+                len: len as u32,
+                timestamp,
+            },
+        );
+
+        Ok(seq)
+    }
+
+    /// Evicts the records older than the max age and returns their number.
+    pub fn evict_expired(&mut self) -> u64 {
+        self.evict_expired_at(current_time())
+    }
+
+    /// Same as [`StableVarRingBuffer::evict_expired`] with the given current time in nanoseconds.
+    pub fn evict_expired_at(&mut self, now: u64) -> u64 {
+        let Some(max_age) = self.max_age() else {
+            return 0;
+        };
+        let min_timestamp = now.saturating_sub(max_age.as_nanos() as u64);
+
+        let mut evicted = 0;
+        while self
+            .index
+            .first_key_value()
+            .is_some_and(|(_, location)| location.timestamp < min_timestamp)
+        {
+            self.evict_oldest();
+            evicted += 1;
+        }
+        evicted
+    }
+
+    /// Returns the record with the given sequence number, if it is not evicted yet.
+    pub fn get(&self, seq: u64) -> Option<VarRingBufferRecord<T>> {
+        let location = self.index.get(&seq)?;
+        Some(self.read_record(seq, location))
+    }
+
+    /// Returns the newest record.
+    pub fn last(&self) -> Option<VarRingBufferRecord<T>> {
+        self.iter_newest().next()
+    }
+
+    /// Iterates over the records from the newest to the oldest.
+    pub fn iter_newest(&self) -> impl Iterator<Item = VarRingBufferRecord<T>> + '_ {
+        self.index
+            .iter()
+            .rev()
+            .map(|(seq, location)| self.read_record(seq, location))
+    }
+
+    /// Iterates over the records from the oldest to the newest, starting from the given
+    /// sequence number.
+    pub fn iter_from(&self, seq: u64) -> impl Iterator<Item = VarRingBufferRecord<T>> + '_ {
+        self.index
+            .range(seq..)
+            .map(|(seq, location)| self.read_record(seq, location))
+    }
+
+    /// Removes all records. The sequence numbers keep increasing.
+    pub fn clear(&mut self) {
+        self.index.clear();
+
+        let mut header = self.header.get().clone();
+        header.tail = 0;
+        header.used_bytes = 0;
+        self.header
+            .set(header)
+            .expect("failed to update the header");
+    }
+
+    fn read_record(&self, seq: u64, location: RecordLocation) -> VarRingBufferRecord<T> {
+        let mut bytes = vec![0; location.len as usize];
+        self.arena.read(location.offset, &mut bytes);
+        VarRingBufferRecord {
+            seq,
+            timestamp: location.timestamp,
+            value: T::from_bytes(Cow::Owned(bytes)),
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let Some((_, location)) = self.index.pop_first() else {
+            return;
+        };
+
+        let mut header = self.header.get().clone();
+        header.used_bytes -= location.len as u64;
+        if self.index.is_empty() {
+            header.tail = 0;
+        }
+        self.header
+            .set(header)
+            .expect("failed to update the header");
+    }
+
+    fn ensure_arena_size(&self, size: u64) -> Result<()> {
+        let required_pages = size.div_ceil(WASM_PAGE_SIZE_IN_BYTES);
+        let current_pages = self.arena.size();
+        if required_pages > current_pages && self.arena.grow(required_pages - current_pages) < 0 {
+            return Err(Error::OutOfStableMemory);
+        }
+        Ok(())
+    }
+}
+
+/// Current IC time, or the system time outside of the IC.
+fn current_time() -> u64 {
+    if cfg!(target_family = "wasm") {
+        ic_cdk::api::time()
+    } else {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system time is after the epoch")
+            .as_nanos() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    fn new_buffer(
+        capacity_bytes: u64,
+        max_age: Option<Duration>,
+    ) -> StableVarRingBuffer<String, VectorMemory> {
+        StableVarRingBuffer::new(
+            VectorMemory::default(),
+            VectorMemory::default(),
+            VectorMemory::default(),
+            capacity_bytes.try_into().unwrap(),
+            max_age,
+        )
+        .unwrap()
+    }
+
+    fn values(buffer: &StableVarRingBuffer<String, VectorMemory>) -> Vec<String> {
+        buffer
+            .iter_newest()
+            .map(|record| record.value)
+            .collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn header_should_be_storable() {
+        let header =
+            StableVarRingBufferHeader::new(100.try_into().unwrap(), Some(Duration::from_secs(60)));
+        assert_eq!(
+            StableVarRingBufferHeader::from_bytes(header.to_bytes()),
+            header
+        );
+        assert_eq!(header.max_age(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn should_evict_by_size() {
+        let mut buffer = new_buffer(10, None);
+
+        assert_eq!(buffer.push_at(&"aaaa".to_string(), 0).unwrap(), 0);
+        assert_eq!(buffer.push_at(&"bbb".to_string(), 0).unwrap(), 1);
+        assert_eq!(buffer.push_at(&"cc".to_string(), 0).unwrap(), 2);
+        assert_eq!(values(&buffer), ["cc", "bbb", "aaaa"]);
+        assert_eq!(buffer.used_bytes(), 9);
+
+        // Doesn't fit after "cc", so wraps to the start and evicts "aaaa".
+        assert_eq!(buffer.push_at(&"ddd".to_string(), 0).unwrap(), 3);
+        assert_eq!(values(&buffer), ["ddd", "cc", "bbb"]);
+        assert_eq!(buffer.get(0), None);
+
+        // Evicts "bbb" and "cc" to fit after "ddd".
+        buffer.push_at(&"eeeeee".to_string(), 0).unwrap();
+        assert_eq!(values(&buffer), ["eeeeee", "ddd"]);
+        assert_eq!(buffer.first_seq(), Some(3));
+        assert_eq!(buffer.last_seq(), Some(4));
+        assert_eq!(buffer.used_bytes(), 9);
+
+        buffer.push_at(&"ffffffffff".to_string(), 0).unwrap();
+        assert_eq!(values(&buffer), ["ffffffffff"]);
+
+        assert!(matches!(
+            buffer.push_at(&"too large value".to_string(), 0),
+            Err(Error::ValueTooLarge(15))
+        ));
+    }
+
+    #[test]
+    fn should_evict_by_age() {
+        let mut buffer = new_buffer(1000, Some(Duration::from_nanos(100)));
+
+        buffer.push_at(&"first".to_string(), 10).unwrap();
+        buffer.push_at(&"second".to_string(), 50).unwrap();
+        buffer.push_at(&"third".to_string(), 100).unwrap();
+        assert_eq!(values(&buffer), ["third", "second", "first"]);
+
+        buffer.push_at(&"fourth".to_string(), 140).unwrap();
+        assert_eq!(values(&buffer), ["fourth", "third", "second"]);
+
+        assert_eq!(buffer.evict_expired_at(240), 2);
+        assert_eq!(values(&buffer), ["fourth"]);
+
+        buffer.set_max_age(None).unwrap();
+        assert_eq!(buffer.evict_expired_at(u64::MAX), 0);
+    }
+
+    #[test]
+    fn should_lookup_by_sequence_number() {
+        let mut buffer = new_buffer(20, None);
+        for i in 0..10 {
+            buffer.push_at(&format!("record {i}"), i).unwrap();
+        }
+
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(
+            buffer.get(9),
+            Some(VarRingBufferRecord {
+                seq: 9,
+                timestamp: 9,
+                value: "record 9".to_string(),
+            })
+        );
+        assert_eq!(buffer.get(7), None);
+        assert_eq!(
+            buffer
+                .iter_from(0)
+                .map(|record| record.seq)
+                .collect::<Vec<_>>(),
+            [8, 9]
+        );
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.push_at(&"next".to_string(), 10).unwrap(), 10);
+    }
+
+    #[test]
+    fn should_restore_from_memory() {
+        let arena_memory = VectorMemory::default();
+        let index_memory = VectorMemory::default();
+        let header_memory = VectorMemory::default();
+        let open = || {
+            StableVarRingBuffer::<String, _>::new(
+                arena_memory.clone(),
+                index_memory.clone(),
+                header_memory.clone(),
+                NonZeroU64::new(1 << 20).unwrap(),
+                None,
+            )
+            .unwrap()
+        };
+
+        let mut buffer = open();
+        let long_record = "x".repeat(100_000);
+        buffer.push_at(&long_record, 1).unwrap();
+        buffer.push_at(&"short".to_string(), 2).unwrap();
+
+        let buffer = open();
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.get(0).unwrap().value, long_record);
+        assert_eq!(buffer.last().unwrap().value, "short");
+        assert_eq!(buffer.capacity_bytes(), 1 << 20);
+    }
+}
@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+
+use dfinity_stable_structures::{DefaultMemoryImpl, Memory, Storable};
+
+use crate::{Bound, Error, Result};
+
+/// Soft limit of the stable memory size checked by the `try_*` write methods.
+struct SoftQuota {
+    max_pages: u64,
+    memory: Box<dyn Memory>,
+}
+
+thread_local! {
+    static SOFT_QUOTA: RefCell<Option<SoftQuota>> = const { RefCell::new(None) };
+}
+
+/// Makes the `try_*` write methods of the structures fail with `Error::SoftQuotaExceeded`
+/// once the canister stable memory reaches `max_pages` Wasm pages.
+///
+/// Unlike the hard limits, the quota leaves room for the writes which must succeed,
+/// e.g. the ones made by the plain `insert`/`push`/`append` methods.
+pub fn set_soft_quota(max_pages: u64) {
+    set_soft_quota_for_memory(max_pages, DefaultMemoryImpl::default());
+}
+
+/// Same as [`set_soft_quota`], but checks the size of the given `memory`
+/// instead of the canister stable memory.
+pub fn set_soft_quota_for_memory(max_pages: u64, memory: impl Memory + 'static) {
+    SOFT_QUOTA.with_borrow_mut(|quota| {
+        *quota = Some(SoftQuota {
+            max_pages,
+            memory: Box::new(memory),
+        })
+    });
+}
+
+/// Removes the soft quota.
+pub fn clear_soft_quota() {
+    SOFT_QUOTA.with_borrow_mut(|quota| *quota = None);
+}
+
+/// Returns `Error::SoftQuotaExceeded` if the memory has reached the soft quota.
+pub fn check_soft_quota() -> Result<()> {
+    SOFT_QUOTA.with_borrow(|quota| match quota {
+        Some(quota) if quota.memory.size() >= quota.max_pages => Err(Error::SoftQuotaExceeded {
+            max_pages: quota.max_pages,
+        }),
+        _ => Ok(()),
+    })
+}
+
+/// Checks that the `Storable` representation of the value satisfies `T::BOUND`,
+/// so writing it into a structure doesn't panic.
+pub fn check_storable_bound<T: Storable>(value: &T) -> Result<()> {
+    if let Bound::Bounded {
+        max_size,
+        is_fixed_size,
+    } = T::BOUND
+    {
+        let size = value.to_bytes().len() as u64;
+        if size > max_size as u64 {
+            return Err(Error::ValueTooLarge(size));
+        }
+        if is_fixed_size && size != max_size as u64 {
+            return Err(Error::UnexpectedFixedSize {
+                expected: max_size,
+                actual: size,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    struct Fixed(Vec<u8>);
+
+    impl Storable for Fixed {
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Borrowed(&self.0)
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Self(bytes.into_owned())
+        }
+
+        const BOUND: Bound = Bound::Bounded {
+            max_size: 2,
+            is_fixed_size: true,
+        };
+    }
+
+    #[test]
+    fn should_check_storable_bound() {
+        assert!(check_storable_bound(&1u64).is_ok());
+        assert!(check_storable_bound(&"unbounded".to_string()).is_ok());
+        assert!(check_storable_bound(&Fixed(vec![1, 2])).is_ok());
+        assert!(matches!(
+            check_storable_bound(&Fixed(vec![1, 2, 3])),
+            Err(Error::ValueTooLarge(3))
+        ));
+        assert!(matches!(
+            check_storable_bound(&Fixed(vec![1])),
+            Err(Error::UnexpectedFixedSize {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn should_check_soft_quota() {
+        let memory = VectorMemory::default();
+        set_soft_quota_for_memory(2, memory.clone());
+        assert!(check_soft_quota().is_ok());
+
+        memory.grow(2);
+        assert!(matches!(
+            check_soft_quota(),
+            Err(Error::SoftQuotaExceeded { max_pages: 2 })
+        ));
+
+        clear_soft_quota();
+        assert!(check_soft_quota().is_ok());
+    }
+}
@@ -0,0 +1,754 @@
+use std::borrow::Cow;
+use std::iter::Peekable;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+use dfinity_stable_structures::{btreemap, Memory, Storable};
+
+use super::registry::{MemorySlot, MigrationState, SchemaRecord, SchemaRegistry, SchemaVersion};
+use crate::structure::{BTreeMapStructure, IterableSortedMapStructure};
+use crate::{Error, Result};
+
+type RawMap<K, M> = btreemap::BTreeMap<K, Vec<u8>, M>;
+type RawIter<'a, K, M> = btreemap::Iter<'a, K, Vec<u8>, M>;
+/// Converts the value bytes of one schema version to the next one.
+type MigrationFn = Box<dyn Fn(&[u8]) -> Vec<u8>>;
+
+/// Ordered list of the value encoding changes: the migration `n` converts the values
+/// of the version `base_version + n` to the version `base_version + n + 1`.
+pub struct Migrations<V> {
+    base_version: SchemaVersion,
+    steps: Vec<MigrationFn>,
+    _value: PhantomData<V>,
+}
+
+impl<V: Storable> Migrations<V> {
+    /// Creates an empty list. The `base_version` is the version of the structures which
+    /// have no schema record, e.g. the ones created before the migrations were introduced.
+    pub fn new(base_version: SchemaVersion) -> Self {
+        Self {
+            base_version,
+            steps: vec![],
+            _value: PhantomData,
+        }
+    }
+
+    /// Registers the migration from the current target version to the next one.
+    /// `Old` is the value type of the current target version and `New` of the next one,
+    /// the last registered `New` type must be `V`.
+    pub fn with_migration<Old: Storable, New: Storable>(
+        mut self,
+        migrate: impl Fn(Old) -> New + 'static,
+    ) -> Self {
+        self.steps.push(Box::new(move |bytes| {
+            let old = Old::from_bytes(Cow::Borrowed(bytes));
+            migrate(old).to_bytes().into_owned()
+        }));
+        self
+    }
+
+    /// Version of the structures without a schema record.
+    pub fn base_version(&self) -> SchemaVersion {
+        self.base_version
+    }
+
+    /// Version of the value type `V`.
+    pub fn target_version(&self) -> SchemaVersion {
+        self.base_version + self.steps.len() as SchemaVersion
+    }
+
+    /// Converts the value bytes of the `from` version to the target version.
+    fn upgrade(&self, from: SchemaVersion, bytes: Vec<u8>) -> Vec<u8> {
+        let first_step = (from - self.base_version) as usize;
+        self.steps[first_step..]
+            .iter()
+            .fold(bytes, |bytes, migrate| migrate(&bytes))
+    }
+}
+
+/// What to do when an entry which is not migrated yet is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnmigratedReadPolicy {
+    /// Fail the read until the entry is migrated by a batch.
+    #[default]
+    Block,
+    /// Apply the migrations to the entry on the fly.
+    Upgrade,
+}
+
+/// Progress of the migration after a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationStatus {
+    InProgress {
+        migrated_entries: u64,
+        remaining_entries: u64,
+    },
+    Completed,
+}
+
+/// `StableBTreeMap` whose value type can change between canister versions.
+///
+/// The map uses two memories. When the registered [`Migrations`] have a newer version than
+/// the one in the [`SchemaRegistry`], the entries are moved from the active memory to the
+/// other one by [`Self::migrate_batch`], so the migration of any size fits into the instruction
+/// limit and survives upgrades. Writes always store the new version, the reads of the
+/// entries which are not migrated yet follow the [`UnmigratedReadPolicy`].
+///
+/// Only the value type may change, the key type must stay the same.
+pub struct MigratingBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    memory_id: u8,
+    registry: SchemaRegistry<M>,
+    record: SchemaRecord,
+    primary: RawMap<K, M>,
+    secondary: RawMap<K, M>,
+    migrations: Migrations<V>,
+    read_policy: UnmigratedReadPolicy,
+    generation: u64,
+}
+
+impl<K, V, M> MigratingBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Loads the map and starts the migration if the registered migrations are newer than the
+    /// stored schema version. The schema version is recorded in the `registry` by `memory_id`,
+    /// which is the id of the `primary` memory.
+    ///
+    /// Fails if the stored version is unknown to the `migrations`, or if a migration to
+    /// another version is still in progress: the canister should be upgraded with new
+    /// migrations only after the previous migration completes.
+    pub fn init(
+        registry: SchemaRegistry<M>,
+        memory_id: u8,
+        primary: M,
+        secondary: M,
+        migrations: Migrations<V>,
+        read_policy: UnmigratedReadPolicy,
+    ) -> Result<Self> {
+        let target_version = migrations.target_version();
+        let mut record = registry
+            .get(memory_id)
+            .unwrap_or_else(|| SchemaRecord::new(migrations.base_version()));
+        if record.version < migrations.base_version() || record.version > target_version {
+            return Err(Error::UnsupportedSchemaVersion(record.version));
+        }
+
+        let mut map = Self {
+            memory_id,
+            registry,
+            record,
+            primary: btreemap::BTreeMap::init(primary),
+            secondary: btreemap::BTreeMap::init(secondary),
+            migrations,
+            read_policy,
+            generation: 0,
+        };
+
+        match record.migration {
+            Some(migration) if migration.to_version != target_version => {
+                return Err(Error::MigrationInProgress {
+                    from_version: migration.from_version,
+                    to_version: migration.to_version,
+                });
+            }
+            Some(_) => {}
+            None if record.version < target_version => {
+                // The destination may contain the entries of an older schema version.
+                map.slot_mut(record.active.other()).clear_new();
+                record.migration = Some(MigrationState {
+                    from_version: record.version,
+                    to_version: target_version,
+                    migrated_entries: 0,
+                });
+            }
+            None => {}
+        }
+        map.save_record(record)?;
+
+        Ok(map)
+    }
+
+    /// Schema version of the migrated entries.
+    pub fn schema_version(&self) -> SchemaVersion {
+        self.record
+            .migration
+            .map_or(self.record.version, |migration| migration.to_version)
+    }
+
+    /// Migration in progress, if any.
+    pub fn migration(&self) -> Option<MigrationState> {
+        self.record.migration
+    }
+
+    /// Migrates up to `max_entries` entries and returns the progress.
+    ///
+    /// Call it from timers until the migration completes, see [`run_migration_in_timers`](super::run_migration_in_timers).
+    pub fn migrate_batch(&mut self, max_entries: usize) -> Result<MigrationStatus> {
+        let Some(mut migration) = self.record.migration else {
+            return Ok(MigrationStatus::Completed);
+        };
+
+        let source = self.record.active;
+        for _ in 0..max_entries {
+            let Some((key, bytes)) = self.slot_mut(source).pop_first() else {
+                break;
+            };
+            let bytes = self.migrations.upgrade(migration.from_version, bytes);
+            self.slot_mut(source.other()).insert(key, bytes);
+            migration.migrated_entries += 1;
+        }
+
+        let remaining_entries = self.slot(source).len();
+        if remaining_entries > 0 {
+            self.save_record(SchemaRecord {
+                migration: Some(migration),
+                ..self.record
+            })?;
+
+            return Ok(MigrationStatus::InProgress {
+                migrated_entries: migration.migrated_entries,
+                remaining_entries,
+            });
+        }
+
+        self.slot_mut(source).clear_new();
+        self.save_record(SchemaRecord {
+            version: migration.to_version,
+            active: source.other(),
+            migration: None,
+        })?;
+
+        Ok(MigrationStatus::Completed)
+    }
+
+    /// Returns the value for the key, or `Error::EntryNotMigrated` if the entry is not migrated
+    /// yet and the reads of such entries are blocked.
+    pub fn try_get(&self, key: &K) -> Result<Option<V>> {
+        if let Some(bytes) = self.current().get(key) {
+            return Ok(Some(decode(bytes)));
+        }
+
+        self.pending()
+            .and_then(|pending| pending.get(key))
+            .map(|bytes| self.read_pending(bytes))
+            .transpose()
+    }
+
+    /// Map with the entries of the target version.
+    fn current(&self) -> &RawMap<K, M> {
+        match self.record.migration {
+            Some(_) => self.slot(self.record.active.other()),
+            None => self.slot(self.record.active),
+        }
+    }
+
+    /// Map with the entries which are not migrated yet.
+    fn pending(&self) -> Option<&RawMap<K, M>> {
+        self.record.migration.map(|_| self.slot(self.record.active))
+    }
+
+    fn current_mut(&mut self) -> &mut RawMap<K, M> {
+        match self.record.migration {
+            Some(_) => self.slot_mut(self.record.active.other()),
+            None => self.slot_mut(self.record.active),
+        }
+    }
+
+    fn pending_mut(&mut self) -> Option<&mut RawMap<K, M>> {
+        match self.record.migration {
+            Some(_) => Some(self.slot_mut(self.record.active)),
+            None => None,
+        }
+    }
+
+    fn slot(&self, slot: MemorySlot) -> &RawMap<K, M> {
+        match slot {
+            MemorySlot::Primary => &self.primary,
+            MemorySlot::Secondary => &self.secondary,
+        }
+    }
+
+    fn slot_mut(&mut self, slot: MemorySlot) -> &mut RawMap<K, M> {
+        match slot {
+            MemorySlot::Primary => &mut self.primary,
+            MemorySlot::Secondary => &mut self.secondary,
+        }
+    }
+
+    fn save_record(&mut self, record: SchemaRecord) -> Result<()> {
+        self.registry.set(self.memory_id, record)?;
+        self.record = record;
+        Ok(())
+    }
+
+    /// Reads the value of an entry which is not migrated yet according to the read policy.
+    fn read_pending(&self, bytes: Vec<u8>) -> Result<V> {
+        match self.read_policy {
+            UnmigratedReadPolicy::Block => Err(Error::EntryNotMigrated(self.schema_version())),
+            UnmigratedReadPolicy::Upgrade => Ok(self.upgrade_pending(bytes)),
+        }
+    }
+
+    /// Converts the value of an entry which is not migrated yet.
+    fn upgrade_pending(&self, bytes: Vec<u8>) -> V {
+        let from_version = self.record.version;
+        decode(self.migrations.upgrade(from_version, bytes))
+    }
+
+    fn expect_pending(&self, bytes: Vec<u8>) -> V {
+        self.read_pending(bytes)
+            .unwrap_or_else(|err| panic!("failed to read the entry: {err}"))
+    }
+
+    fn merged_range(&self, range: (Bound<K>, Bound<K>)) -> MigratingBTreeMapIter<'_, K, V, M> {
+        MigratingBTreeMapIter {
+            map: self,
+            current: self.current().range(range.clone()).peekable(),
+            pending: self
+                .pending()
+                .map(|pending| pending.range(range).peekable()),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<K, V, M> BTreeMapStructure<K, V> for MigratingBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Panics if the entry is not migrated yet and the reads of such entries are blocked,
+    /// see [`MigratingBTreeMap::try_get`].
+    fn get(&self, key: &K) -> Option<V> {
+        self.try_get(key)
+            .unwrap_or_else(|err| panic!("failed to read the entry: {err}"))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.generation += 1;
+        let previous = self.pending_mut().and_then(|pending| pending.remove(&key));
+        let current = self
+            .current_mut()
+            .insert(key, value.to_bytes().into_owned());
+
+        match (current, previous) {
+            (Some(bytes), _) => Some(decode(bytes)),
+            (None, Some(bytes)) => Some(self.upgrade_pending(bytes)),
+            (None, None) => None,
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        if let Some(bytes) = self.current_mut().remove(key) {
+            self.generation += 1;
+            return Some(decode(bytes));
+        }
+
+        let bytes = self.pending_mut()?.remove(key)?;
+        self.generation += 1;
+        Some(self.upgrade_pending(bytes))
+    }
+
+    fn pop_first(&mut self) -> Option<(K, V)> {
+        let (key, _) = self.first_raw_key()?;
+        let value = self.remove(&key)?;
+        Some((key, value))
+    }
+
+    fn pop_last(&mut self) -> Option<(K, V)> {
+        let (key, _) = self.last_raw_key()?;
+        let value = self.remove(&key)?;
+        Some((key, value))
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.current().contains_key(key)
+            || self
+                .pending()
+                .is_some_and(|pending| pending.contains_key(key))
+    }
+
+    fn first_key_value(&self) -> Option<(K, V)> {
+        self.first_raw_key()
+            .map(|(key, is_pending)| self.read_entry(key, is_pending))
+    }
+
+    fn last_key_value(&self) -> Option<(K, V)> {
+        self.last_raw_key()
+            .map(|(key, is_pending)| self.read_entry(key, is_pending))
+    }
+
+    fn len(&self) -> u64 {
+        self.current().len() + self.pending().map_or(0, |pending| pending.len())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all the entries and completes the migration in progress.
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.primary.clear_new();
+        self.secondary.clear_new();
+        self.save_record(SchemaRecord::new(self.migrations.target_version()))
+            .expect("failed to update the schema registry");
+    }
+}
+
+impl<K, V, M> MigratingBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    /// Returns the first key and whether it's not migrated yet.
+    fn first_raw_key(&self) -> Option<(K, bool)> {
+        let current = self
+            .current()
+            .first_key_value()
+            .map(|(key, _)| (key, false));
+        let pending = self
+            .pending()
+            .and_then(|pending| pending.first_key_value())
+            .map(|(key, _)| (key, true));
+        [current, pending].into_iter().flatten().min()
+    }
+
+    /// Returns the last key and whether it's not migrated yet.
+    fn last_raw_key(&self) -> Option<(K, bool)> {
+        let current = self.current().last_key_value().map(|(key, _)| (key, false));
+        let pending = self
+            .pending()
+            .and_then(|pending| pending.last_key_value())
+            .map(|(key, _)| (key, true));
+        [current, pending].into_iter().flatten().max()
+    }
+
+    fn read_entry(&self, key: K, is_pending: bool) -> (K, V) {
+        let value = match (is_pending, self.pending()) {
+            (true, Some(pending)) => {
+                self.expect_pending(pending.get(&key).expect("entry should exist"))
+            }
+            _ => decode(self.current().get(&key).expect("entry should exist")),
+        };
+        (key, value)
+    }
+}
+
+impl<K, V, M> IterableSortedMapStructure<K, V> for MigratingBTreeMap<K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    type Iterator<'a>
+        = MigratingBTreeMapIter<'a, K, V, M>
+    where
+        Self: 'a;
+
+    /// The iterator panics on the entries which are not migrated yet if the reads
+    /// of such entries are blocked.
+    fn iter(&self) -> Self::Iterator<'_> {
+        self.merged_range((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_> {
+        self.merged_range((
+            key_range.start_bound().cloned(),
+            key_range.end_bound().cloned(),
+        ))
+    }
+
+    fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_> {
+        let current = self.current().iter_upper_bound(bound).next();
+        let pending = self
+            .pending()
+            .and_then(|pending| pending.iter_upper_bound(bound).next());
+
+        match [current, pending]
+            .into_iter()
+            .flatten()
+            .map(|(key, _)| key)
+            .max()
+        {
+            Some(start) => self.merged_range((Bound::Included(start), Bound::Unbounded)),
+            // There are no keys below the bound, so the iterators are empty.
+            None => MigratingBTreeMapIter {
+                map: self,
+                current: self.current().iter_upper_bound(bound).peekable(),
+                pending: self
+                    .pending()
+                    .map(|pending| pending.iter_upper_bound(bound).peekable()),
+                _value: PhantomData,
+            },
+        }
+    }
+
+    /// The migration batches don't change the generation, as they keep the entries the same.
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+/// Iterator over the entries of a [`MigratingBTreeMap`] in the key order.
+pub struct MigratingBTreeMapIter<'a, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    map: &'a MigratingBTreeMap<K, V, M>,
+    current: Peekable<RawIter<'a, K, M>>,
+    pending: Option<Peekable<RawIter<'a, K, M>>>,
+    _value: PhantomData<V>,
+}
+
+impl<K, V, M> Iterator for MigratingBTreeMapIter<'_, K, V, M>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A key is either migrated or pending, never both.
+        let pending = self.pending.as_mut().and_then(Peekable::peek);
+        let take_pending = match (self.current.peek(), pending) {
+            (Some((current, _)), Some((pending, _))) => pending < current,
+            (None, Some(_)) => true,
+            _ => false,
+        };
+
+        if take_pending {
+            let (key, bytes) = self.pending.as_mut().and_then(Iterator::next)?;
+            return Some((key, self.map.expect_pending(bytes)));
+        }
+
+        self.current.next().map(|(key, bytes)| (key, decode(bytes)))
+    }
+}
+
+fn decode<V: Storable>(bytes: Vec<u8>) -> V {
+    V::from_bytes(Cow::Owned(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+    use crate::StableBTreeMap;
+
+    const MAP_MEMORY_ID: u8 = 1;
+
+    struct Memories {
+        registry: VectorMemory,
+        primary: VectorMemory,
+        secondary: VectorMemory,
+    }
+
+    impl Memories {
+        /// Memories with a `u64 -> u32` map created before the migrations were introduced.
+        fn with_legacy_map(len: u32) -> Self {
+            let memories = Self {
+                registry: VectorMemory::default(),
+                primary: VectorMemory::default(),
+                secondary: VectorMemory::default(),
+            };
+            let mut map = StableBTreeMap::<u64, u32, _>::new(memories.primary.clone());
+            for i in 0..len {
+                map.insert(i as u64, i);
+            }
+            memories
+        }
+
+        fn init<V: Storable>(
+            &self,
+            migrations: Migrations<V>,
+            read_policy: UnmigratedReadPolicy,
+        ) -> Result<MigratingBTreeMap<u64, V, VectorMemory>> {
+            MigratingBTreeMap::init(
+                SchemaRegistry::new(self.registry.clone()).unwrap(),
+                MAP_MEMORY_ID,
+                self.primary.clone(),
+                self.secondary.clone(),
+                migrations,
+                read_policy,
+            )
+        }
+    }
+
+    fn to_string_migrations() -> Migrations<String> {
+        Migrations::new(0).with_migration(|value: u32| format!("value {value}"))
+    }
+
+    fn to_length_migrations() -> Migrations<u64> {
+        Migrations::new(0)
+            .with_migration(|value: u32| format!("value {value}"))
+            .with_migration(|value: String| value.len() as u64)
+    }
+
+    #[test]
+    fn should_migrate_in_batches() {
+        let memories = Memories::with_legacy_map(10);
+        let mut map = memories
+            .init(to_string_migrations(), UnmigratedReadPolicy::Block)
+            .unwrap();
+        assert_eq!(map.schema_version(), 1);
+        assert_eq!(map.len(), 10);
+
+        assert_eq!(
+            map.migrate_batch(4).unwrap(),
+            MigrationStatus::InProgress {
+                migrated_entries: 4,
+                remaining_entries: 6
+            }
+        );
+        assert_eq!(map.try_get(&3).unwrap(), Some("value 3".to_string()));
+        assert!(matches!(map.try_get(&4), Err(Error::EntryNotMigrated(1))));
+        assert_eq!(map.try_get(&100).unwrap(), None);
+
+        // The writes store the new version right away.
+        assert_eq!(
+            map.insert(8, "new".to_string()),
+            Some("value 8".to_string())
+        );
+        assert_eq!(map.remove(&9), Some("value 9".to_string()));
+        assert_eq!(map.len(), 9);
+
+        assert_eq!(map.migrate_batch(100).unwrap(), MigrationStatus::Completed);
+        assert_eq!(map.migration(), None);
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            (0..8)
+                .map(|i| (i, format!("value {i}")))
+                .chain([(8, "new".to_string())])
+                .collect::<Vec<_>>()
+        );
+
+        let registry = SchemaRegistry::new(memories.registry.clone()).unwrap();
+        assert_eq!(
+            registry.get(MAP_MEMORY_ID),
+            Some(SchemaRecord {
+                version: 1,
+                active: MemorySlot::Secondary,
+                migration: None
+            })
+        );
+    }
+
+    #[test]
+    fn should_resume_migration_after_upgrade() {
+        let memories = Memories::with_legacy_map(10);
+        let mut map = memories
+            .init(to_string_migrations(), UnmigratedReadPolicy::Block)
+            .unwrap();
+        map.migrate_batch(3).unwrap();
+        drop(map);
+
+        let mut map = memories
+            .init(to_string_migrations(), UnmigratedReadPolicy::Block)
+            .unwrap();
+        assert_eq!(
+            map.migration(),
+            Some(MigrationState {
+                from_version: 0,
+                to_version: 1,
+                migrated_entries: 3
+            })
+        );
+        assert_eq!(map.migrate_batch(100).unwrap(), MigrationStatus::Completed);
+        drop(map);
+
+        // The next migration moves the entries back to the primary memory.
+        let mut map = memories
+            .init(to_length_migrations(), UnmigratedReadPolicy::Block)
+            .unwrap();
+        assert_eq!(map.migrate_batch(100).unwrap(), MigrationStatus::Completed);
+        assert_eq!(map.get(&5), Some(7));
+        assert_eq!(map.schema_version(), 2);
+    }
+
+    #[test]
+    fn should_upgrade_unmigrated_entries_on_read() {
+        let memories = Memories::with_legacy_map(6);
+        let mut map = memories
+            .init(to_string_migrations(), UnmigratedReadPolicy::Upgrade)
+            .unwrap();
+        map.migrate_batch(2).unwrap();
+        map.insert(4, "new".to_string());
+
+        assert_eq!(map.get(&5), Some("value 5".to_string()));
+        assert_eq!(map.first_key_value(), Some((0, "value 0".to_string())));
+        assert_eq!(map.last_key_value(), Some((5, "value 5".to_string())));
+        assert_eq!(
+            map.range(1..5).collect::<Vec<_>>(),
+            vec![
+                (1, "value 1".to_string()),
+                (2, "value 2".to_string()),
+                (3, "value 3".to_string()),
+                (4, "new".to_string()),
+            ]
+        );
+        assert_eq!(
+            map.iter_upper_bound(&3).next(),
+            Some((2, "value 2".to_string()))
+        );
+        assert_eq!(map.iter_upper_bound(&0).next(), None);
+        assert_eq!(map.pop_last(), Some((5, "value 5".to_string())));
+        assert_eq!(map.pop_first(), Some((0, "value 0".to_string())));
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn should_reject_new_migrations_during_migration() {
+        let memories = Memories::with_legacy_map(10);
+        let mut map = memories
+            .init(to_string_migrations(), UnmigratedReadPolicy::Block)
+            .unwrap();
+        map.migrate_batch(1).unwrap();
+        drop(map);
+
+        assert!(matches!(
+            memories.init(to_length_migrations(), UnmigratedReadPolicy::Block),
+            Err(Error::MigrationInProgress {
+                from_version: 0,
+                to_version: 1
+            })
+        ));
+        assert!(matches!(
+            memories.init(Migrations::<u32>::new(5), UnmigratedReadPolicy::Block),
+            Err(Error::UnsupportedSchemaVersion(0))
+        ));
+    }
+
+    #[test]
+    fn should_complete_migration_on_clear() {
+        let memories = Memories::with_legacy_map(10);
+        let mut map = memories
+            .init(to_string_migrations(), UnmigratedReadPolicy::Block)
+            .unwrap();
+        map.clear();
+
+        assert!(map.is_empty());
+        assert_eq!(map.migration(), None);
+        assert_eq!(map.migrate_batch(1).unwrap(), MigrationStatus::Completed);
+        map.insert(1, "one".to_string());
+        drop(map);
+
+        let map = memories
+            .init(to_string_migrations(), UnmigratedReadPolicy::Block)
+            .unwrap();
+        assert_eq!(map.get(&1), Some("one".to_string()));
+    }
+}
@@ -0,0 +1,30 @@
+//! Migrations of the stable structures between the value schema versions.
+
+mod btreemap;
+mod registry;
+
+use std::time::Duration;
+
+pub use btreemap::{
+    MigratingBTreeMap, MigratingBTreeMapIter, MigrationStatus, Migrations, UnmigratedReadPolicy,
+};
+pub use registry::{MemorySlot, MigrationState, SchemaRecord, SchemaRegistry, SchemaVersion};
+
+use crate::Result;
+
+/// Runs the migration `batch` in timers, one batch per timer, until the migration completes
+/// or the batch fails. Each batch runs in its own message, so it's not limited by the instructions
+/// of the `post_upgrade`. The `batch` usually calls [`MigratingBTreeMap::migrate_batch`]
+/// on a thread-local map and may log the errors before returning them.
+///
+/// The timers don't survive upgrades, so call it from `post_upgrade` to resume the migration.
+pub fn run_migration_in_timers<F>(delay: Duration, mut batch: F)
+where
+    F: FnMut() -> Result<MigrationStatus> + 'static,
+{
+    ic_cdk_timers::set_timer(delay, move || {
+        if let Ok(MigrationStatus::InProgress { .. }) = batch() {
+            run_migration_in_timers(delay, batch);
+        }
+    });
+}
@@ -0,0 +1,210 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use dfinity_stable_structures::{Memory, Storable};
+
+use crate::structure::{CellStructure, StableCell};
+use crate::{Bound, Result};
+
+/// Version of the `Storable` encoding of the values of a structure.
+pub type SchemaVersion = u32;
+
+/// One of the two memories of a [`MigratingBTreeMap`](super::MigratingBTreeMap).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemorySlot {
+    #[default]
+    Primary,
+    Secondary,
+}
+
+impl MemorySlot {
+    /// The other slot, the destination of a migration.
+    pub fn other(self) -> Self {
+        match self {
+            Self::Primary => Self::Secondary,
+            Self::Secondary => Self::Primary,
+        }
+    }
+}
+
+/// Migration of the structure entries between two schema versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationState {
+    pub from_version: SchemaVersion,
+    pub to_version: SchemaVersion,
+    /// Number of entries migrated by the batches so far.
+    pub migrated_entries: u64,
+}
+
+/// Schema state of the structure stored in a memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaRecord {
+    /// Version of the entries in the active memory.
+    pub version: SchemaVersion,
+    /// Memory which holds the entries of the `version`.
+    pub active: MemorySlot,
+    /// Migration in progress, if any.
+    pub migration: Option<MigrationState>,
+}
+
+impl SchemaRecord {
+    /// Record of a structure which has no migrations in progress.
+    pub fn new(version: SchemaVersion) -> Self {
+        Self {
+            version,
+            active: MemorySlot::Primary,
+            migration: None,
+        }
+    }
+}
+
+/// Schema records of all the structures by memory id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct SchemaRecords(BTreeMap<u8, SchemaRecord>);
+
+/// Size of the encoded record: memory id, version, active slot, migration flag,
+/// migration versions and the number of migrated entries.
+const RECORD_SIZE: usize = 1 + 4 + 1 + 1 + 4 + 4 + 8;
+
+impl Storable for SchemaRecords {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(self.0.len() * RECORD_SIZE);
+        for (&memory_id, record) in &self.0 {
+            let migration = record.migration.unwrap_or(MigrationState {
+                from_version: 0,
+                to_version: 0,
+                migrated_entries: 0,
+            });
+
+            bytes.push(memory_id);
+            bytes.extend_from_slice(&record.version.to_le_bytes());
+            bytes.push(record.active as u8);
+            bytes.push(record.migration.is_some() as u8);
+            bytes.extend_from_slice(&migration.from_version.to_le_bytes());
+            bytes.extend_from_slice(&migration.to_version.to_le_bytes());
+            bytes.extend_from_slice(&migration.migrated_entries.to_le_bytes());
+        }
+
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let records = bytes
+            .chunks_exact(RECORD_SIZE)
+            .map(|chunk| {
+                let u32_at = |offset: usize| {
+                    u32::from_le_bytes(chunk[offset..offset + 4].try_into().expect("4 bytes"))
+                };
+                let active = match chunk[5] {
+                    0 => MemorySlot::Primary,
+                    _ => MemorySlot::Secondary,
+                };
+                let migration = (chunk[6] != 0).then(|| MigrationState {
+                    from_version: u32_at(7),
+                    to_version: u32_at(11),
+                    migrated_entries: u64::from_le_bytes(
+                        chunk[15..23].try_into().expect("8 bytes"),
+                    ),
+                });
+
+                (
+                    chunk[0],
+                    SchemaRecord {
+                        version: u32_at(1),
+                        active,
+                        migration,
+                    },
+                )
+            })
+            .collect();
+
+        Self(records)
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Schema versions of the structures by memory id, stored in a reserved `StableCell`.
+///
+/// The registry is a cheap handle: clones share the same cell, so one registry
+/// can be passed to all the migrating structures of the canister.
+pub struct SchemaRegistry<M: Memory>(Rc<RefCell<StableCell<SchemaRecords, M>>>);
+
+impl<M: Memory> SchemaRegistry<M> {
+    /// Loads the registry from the reserved memory or creates an empty one.
+    pub fn new(memory: M) -> Result<Self> {
+        Ok(Self(Rc::new(RefCell::new(StableCell::new(
+            memory,
+            SchemaRecords::default(),
+        )?))))
+    }
+
+    /// Schema record of the structure stored in the memory with the given id.
+    pub fn get(&self, memory_id: u8) -> Option<SchemaRecord> {
+        self.0.borrow().get().0.get(&memory_id).copied()
+    }
+
+    /// Schema version of the structure stored in the memory with the given id.
+    pub fn version(&self, memory_id: u8) -> Option<SchemaVersion> {
+        self.get(memory_id).map(|record| record.version)
+    }
+
+    /// Records of all the structures.
+    pub fn records(&self) -> Vec<(u8, SchemaRecord)> {
+        let cell = self.0.borrow();
+        cell.get()
+            .0
+            .iter()
+            .map(|(&memory_id, &record)| (memory_id, record))
+            .collect()
+    }
+
+    pub(super) fn set(&self, memory_id: u8, record: SchemaRecord) -> Result<()> {
+        let mut cell = self.0.borrow_mut();
+        let mut records = cell.get().clone();
+        records.0.insert(memory_id, record);
+        cell.set(records)
+    }
+}
+
+impl<M: Memory> Clone for SchemaRegistry<M> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    #[test]
+    fn should_persist_records() {
+        let memory = VectorMemory::default();
+        let registry = SchemaRegistry::new(memory.clone()).unwrap();
+        assert_eq!(registry.get(1), None);
+
+        let migrating = SchemaRecord {
+            version: 2,
+            active: MemorySlot::Secondary,
+            migration: Some(MigrationState {
+                from_version: 2,
+                to_version: 3,
+                migrated_entries: 42,
+            }),
+        };
+        registry.set(1, SchemaRecord::new(7)).unwrap();
+        registry.clone().set(200, migrating).unwrap();
+
+        let registry = SchemaRegistry::new(memory).unwrap();
+        assert_eq!(registry.version(1), Some(7));
+        assert_eq!(registry.get(200), Some(migrating));
+        assert_eq!(
+            registry.records(),
+            vec![(1, SchemaRecord::new(7)), (200, migrating)]
+        );
+    }
+}
@@ -1,13 +1,21 @@
-use std::ops::RangeBounds;
+use std::ops::{Bound, RangeBounds};
 
-use crate::Result;
+use candid::{CandidType, Deserialize};
+use dfinity_stable_structures::Storable;
+
+use crate::codec::{KeyPrefix, OrderedCodec};
+use crate::{Error, Result};
 
 mod cache;
+mod certified;
 mod common;
+mod migration;
 mod stable_storage;
 
 pub use cache::*;
+pub use certified::*;
 pub use common::*;
+pub use migration::*;
 pub use stable_storage::*;
 
 pub trait BTreeMapStructure<K, V> {
@@ -21,6 +29,20 @@ pub trait BTreeMapStructure<K, V> {
     ///   - `value.to_bytes().len() <= V::MAX_SIZE`
     fn insert(&mut self, key: K, value: V) -> Option<V>;
 
+    /// Same as `insert`, but returns `Error::ValueTooLarge` instead of panicking if the key
+    /// or the value don't satisfy their `Storable::BOUND`, and `Error::SoftQuotaExceeded`
+    /// if the stable memory has reached the soft quota.
+    fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>>
+    where
+        K: Storable,
+        V: Storable,
+    {
+        check_soft_quota()?;
+        check_storable_bound(&key)?;
+        check_storable_bound(&value)?;
+        Ok(self.insert(key, value))
+    }
+
     /// Remove value associated with `key` from stable memory.
     ///
     /// # Preconditions:
@@ -69,6 +91,67 @@ pub trait IterableSortedMapStructure<K, V> {
     /// Returns an iterator pointing to the first element below the given bound.
     /// Returns an empty iterator if there are no keys below the given bound.
     fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_>;
+
+    /// Counter of the map modifications. It changes with every insert and every removal
+    /// of an entry, so the callers can detect that the map was modified between two pages.
+    ///
+    /// The counter is kept in the heap memory and restarts from zero after an upgrade, so
+    /// cursors and generations of the pages don't survive upgrades: a page returned before
+    /// an upgrade may pass [`Self::check_generation`] even if the map was modified.
+    ///
+    /// Maps that don't track their modifications keep the default implementation, which
+    /// always returns zero, so the modifications of such maps are never detected.
+    fn generation(&self) -> u64 {
+        0
+    }
+
+    /// Returns up to `limit` entries with the keys greater than the `cursor`,
+    /// or from the start of the map if the cursor is `None`.
+    ///
+    /// Unlike an iterator, the page doesn't borrow the map, so it can be held across
+    /// an `.await`: the next page is requested with the returned `next_cursor`.
+    fn page(&self, cursor: Option<&K>, limit: usize) -> Page<K, V>
+    where
+        K: Clone,
+    {
+        let start = cursor.map_or(Bound::Unbounded, |key| Bound::Excluded(key.clone()));
+        let limit = limit.max(1);
+
+        let mut entries = self
+            .range((start, Bound::Unbounded))
+            .take(limit + 1)
+            .collect::<Vec<_>>();
+        let has_more = entries.len() > limit;
+        entries.truncate(limit);
+
+        Page {
+            next_cursor: has_more
+                .then(|| entries.last().map(|(key, _)| key.clone()))
+                .flatten(),
+            entries,
+            generation: self.generation(),
+        }
+    }
+
+    /// Returns `Error::StructureModified` if the map generation is not the `expected` one,
+    /// e.g. the generation of the previous page.
+    fn check_generation(&self, expected: u64) -> Result<()> {
+        let actual = self.generation();
+        if actual != expected {
+            return Err(Error::StructureModified { expected, actual });
+        }
+        Ok(())
+    }
+}
+
+/// Page of the map entries returned by [`IterableSortedMapStructure::page`].
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Page<K, V> {
+    pub entries: Vec<(K, V)>,
+    /// Cursor of the next page, `None` if this is the last page.
+    pub next_cursor: Option<K>,
+    /// Generation of the map at the moment of the page creation.
+    pub generation: u64,
 }
 
 pub trait CellStructure<T> {
@@ -77,6 +160,18 @@ pub trait CellStructure<T> {
 
     /// Updates value in stable memory.
     fn set(&mut self, value: T) -> Result<()>;
+
+    /// Same as `set`, but also returns `Error::ValueTooLarge` if the value doesn't satisfy
+    /// its `Storable::BOUND`, and `Error::SoftQuotaExceeded` if the stable memory has
+    /// reached the soft quota.
+    fn try_set(&mut self, value: T) -> Result<()>
+    where
+        T: Storable,
+    {
+        check_soft_quota()?;
+        check_storable_bound(&value)?;
+        self.set(value)
+    }
 }
 
 pub trait LogStructure<T> {
@@ -86,6 +181,18 @@ pub trait LogStructure<T> {
     /// Updates value in stable memory.
     fn append(&mut self, value: T) -> Result<u64>;
 
+    /// Same as `append`, but returns `Error::ValueTooLarge` instead of panicking if the value
+    /// doesn't satisfy its `Storable::BOUND`, and `Error::SoftQuotaExceeded`
+    /// if the stable memory has reached the soft quota.
+    fn try_append(&mut self, value: T) -> Result<u64>
+    where
+        T: Storable,
+    {
+        check_soft_quota()?;
+        check_storable_bound(&value)?;
+        self.append(value)
+    }
+
     /// Number of values in the log.
     fn len(&self) -> u64;
 
@@ -121,6 +228,22 @@ pub trait MultimapStructure<K1, K2, V> {
     ///   - `value.to_bytes().len() <= V::MAX_SIZE`
     fn insert(&mut self, first_key: &K1, second_key: &K2, value: V) -> Option<V>;
 
+    /// Same as `insert`, but returns `Error::ValueTooLarge` instead of panicking if the keys
+    /// or the value don't satisfy their `Storable::BOUND`, and `Error::SoftQuotaExceeded`
+    /// if the stable memory has reached the soft quota.
+    fn try_insert(&mut self, first_key: &K1, second_key: &K2, value: V) -> Result<Option<V>>
+    where
+        K1: Storable,
+        K2: Storable,
+        V: Storable,
+    {
+        check_soft_quota()?;
+        check_storable_bound(first_key)?;
+        check_storable_bound(second_key)?;
+        check_storable_bound(&value)?;
+        Ok(self.insert(first_key, second_key, value))
+    }
+
     /// Remove a specific value and return it.
     ///
     ///   - `first_key.to_bytes().len() <= K1::MAX_SIZE`
@@ -155,6 +278,69 @@ pub trait MultimapStructure<K1, K2, V> {
     fn clear(&mut self);
 }
 
+/// Map with composite tuple keys that supports queries by any key prefix.
+pub trait PrefixMapStructure<K, V>
+where
+    K: OrderedCodec,
+{
+    /// Iterator over the map entries.
+    type Iterator<'a>: Iterator<Item = (K, V)>
+    where
+        Self: 'a;
+
+    /// Return value associated with `key`.
+    fn get(&self, key: &K) -> Option<V>;
+
+    /// Add or replace value associated with `key`.
+    fn insert(&mut self, key: &K, value: V) -> Option<V>;
+
+    /// Same as `insert`, but returns `Error::ValueTooLarge` instead of panicking if the value
+    /// doesn't satisfy its `Storable::BOUND`, and `Error::SoftQuotaExceeded`
+    /// if the stable memory has reached the soft quota.
+    fn try_insert(&mut self, key: &K, value: V) -> Result<Option<V>>
+    where
+        V: Storable,
+    {
+        check_soft_quota()?;
+        check_storable_bound(&value)?;
+        Ok(self.insert(key, value))
+    }
+
+    /// Remove value associated with `key`.
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    /// True if contains the key.
+    fn contains_key(&self, key: &K) -> bool;
+
+    /// Iterator over all the entries which keys start with the `prefix`.
+    fn prefix_iter<P: KeyPrefix<K>>(&self, prefix: &P) -> Self::Iterator<'_>;
+
+    /// Remove all the entries which keys start with the `prefix`.
+    /// Returns the number of removed entries.
+    fn remove_prefix<P: KeyPrefix<K>>(&mut self, prefix: &P) -> u64;
+
+    /// Iterator over the entries which keys belong to the specified range.
+    fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_>;
+
+    /// Iterator over all the entries.
+    fn iter(&self) -> Self::Iterator<'_>;
+
+    /// Removes and returns the entry with the minimum key.
+    fn pop_first(&mut self) -> Option<(K, V)>;
+
+    /// Removes and returns the entry with the maximum key.
+    fn pop_last(&mut self) -> Option<(K, V)>;
+
+    /// Items count.
+    fn len(&self) -> u64;
+
+    /// Is map empty.
+    fn is_empty(&self) -> bool;
+
+    /// Remove all entries from the map.
+    fn clear(&mut self);
+}
+
 pub trait VecStructure<T> {
     /// Returns if vector is empty
     fn is_empty(&self) -> bool;
@@ -169,12 +355,34 @@ pub trait VecStructure<T> {
     /// WARN: this panics if index out of range
     fn set(&mut self, index: u64, item: &T) -> Result<()>;
 
+    /// Same as `set`, but returns `Error::ValueTooLarge` instead of panicking if the item
+    /// doesn't satisfy its `Storable::BOUND`. Still panics if the index is out of range.
+    fn try_set(&mut self, index: u64, item: &T) -> Result<()>
+    where
+        T: Storable,
+    {
+        check_storable_bound(item)?;
+        self.set(index, item)
+    }
+
     /// Returns the value at `index`
     fn get(&self, index: u64) -> Option<T>;
 
     /// Appends new value to the vector
     fn push(&mut self, item: &T) -> Result<()>;
 
+    /// Same as `push`, but returns `Error::ValueTooLarge` instead of panicking if the item
+    /// doesn't satisfy its `Storable::BOUND`, and `Error::SoftQuotaExceeded`
+    /// if the stable memory has reached the soft quota.
+    fn try_push(&mut self, item: &T) -> Result<()>
+    where
+        T: Storable,
+    {
+        check_soft_quota()?;
+        check_storable_bound(item)?;
+        self.push(item)
+    }
+
     /// Pops the last value from the vector
     fn pop(&mut self) -> Option<T>;
 }
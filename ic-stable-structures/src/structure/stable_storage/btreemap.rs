@@ -6,10 +6,14 @@ use crate::structure::BTreeMapStructure;
 use crate::IterableSortedMapStructure;
 
 /// Stores key-value data in stable memory.
-pub struct StableBTreeMap<K, V, M: Memory>(btreemap::BTreeMap<K, V, M>)
+pub struct StableBTreeMap<K, V, M: Memory>
 where
     K: Storable + Ord + Clone,
-    V: Storable;
+    V: Storable,
+{
+    inner: btreemap::BTreeMap<K, V, M>,
+    generation: u64,
+}
 
 impl<K, V, M> StableBTreeMap<K, V, M>
 where
@@ -19,12 +23,15 @@ where
 {
     /// Create new instance of key-value storage.
     pub fn new(memory: M) -> Self {
-        Self(btreemap::BTreeMap::init(memory))
+        Self {
+            inner: btreemap::BTreeMap::init(memory),
+            generation: 0,
+        }
     }
 
     /// Iterate over all currently stored key-value pairs.
     pub fn iter(&self) -> btreemap::Iter<'_, K, V, M> {
-        self.0.iter()
+        self.inner.iter()
     }
 }
 
@@ -35,47 +42,55 @@ where
     M: Memory,
 {
     fn get(&self, key: &K) -> Option<V> {
-        self.0.get(key)
+        self.inner.get(key)
     }
 
     fn insert(&mut self, key: K, value: V) -> Option<V> {
-        self.0.insert(key, value)
+        self.generation += 1;
+        self.inner.insert(key, value)
     }
 
     fn remove(&mut self, key: &K) -> Option<V> {
-        self.0.remove(key)
+        let value = self.inner.remove(key)?;
+        self.generation += 1;
+        Some(value)
     }
 
     fn pop_first(&mut self) -> Option<(K, V)> {
-        self.0.pop_first()
+        let entry = self.inner.pop_first()?;
+        self.generation += 1;
+        Some(entry)
     }
 
     fn pop_last(&mut self) -> Option<(K, V)> {
-        self.0.pop_last()
+        let entry = self.inner.pop_last()?;
+        self.generation += 1;
+        Some(entry)
     }
 
     fn len(&self) -> u64 {
-        self.0.len()
+        self.inner.len()
     }
 
     fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.inner.is_empty()
     }
 
     fn clear(&mut self) {
-        self.0.clear_new();
+        self.generation += 1;
+        self.inner.clear_new();
     }
 
     fn contains_key(&self, key: &K) -> bool {
-        self.0.contains_key(key)
+        self.inner.contains_key(key)
     }
 
     fn first_key_value(&self) -> Option<(K, V)> {
-        self.0.first_key_value()
+        self.inner.first_key_value()
     }
 
     fn last_key_value(&self) -> Option<(K, V)> {
-        self.0.last_key_value()
+        self.inner.last_key_value()
     }
 }
 
@@ -91,15 +106,19 @@ where
         Self: 'a;
 
     fn iter(&self) -> Self::Iterator<'_> {
-        self.0.iter()
+        self.inner.iter()
     }
 
     fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_> {
-        self.0.range(key_range)
+        self.inner.range(key_range)
     }
 
     fn iter_upper_bound(&self, bound: &K) -> Self::Iterator<'_> {
-        self.0.iter_upper_bound(bound)
+        self.inner.iter_upper_bound(bound)
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
     }
 }
 
@@ -111,7 +130,8 @@ mod tests {
     use dfinity_stable_structures::VectorMemory;
 
     use super::*;
-    use crate::test_utils::str_val;
+    use crate::test_utils::{str_val, BoundedBytes};
+    use crate::{clear_soft_quota, set_soft_quota_for_memory, Error};
 
     #[test]
     fn btreemap_works() {
@@ -409,4 +429,76 @@ mod tests {
         assert_eq!(iter.next(), Some(((10, 6), 60)));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn try_insert_should_reject_oversized_values() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        assert_eq!(
+            map.try_insert(1u64, BoundedBytes::<4>(vec![1; 4])).unwrap(),
+            None
+        );
+        assert!(matches!(
+            map.try_insert(2, BoundedBytes::<4>(vec![1; 5])),
+            Err(Error::ValueTooLarge(5))
+        ));
+        assert_eq!(map.len(), 1);
+
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        assert!(matches!(
+            map.try_insert(BoundedBytes::<2>(vec![1; 3]), 1u64),
+            Err(Error::ValueTooLarge(3))
+        ));
+    }
+
+    #[test]
+    fn try_insert_should_respect_soft_quota() {
+        let memory = VectorMemory::default();
+        set_soft_quota_for_memory(1, memory.clone());
+
+        let mut map = StableBTreeMap::new(memory.clone());
+        let mut key = 0u64;
+        let error = loop {
+            match map.try_insert(key, str_val(1024)) {
+                Ok(_) => key += 1,
+                Err(error) => break error,
+            }
+        };
+        clear_soft_quota();
+
+        assert!(matches!(error, Error::SoftQuotaExceeded { max_pages: 1 }));
+        assert_eq!(map.len(), key);
+        // The plain `insert` ignores the soft quota.
+        map.insert(key, str_val(1024));
+        assert_eq!(map.len(), key + 1);
+    }
+
+    #[test]
+    fn should_paginate_with_cursor() {
+        let mut map = StableBTreeMap::new(VectorMemory::default());
+        for key in 0..5u32 {
+            map.insert(key, key * 10);
+        }
+
+        let first = map.page(None, 2);
+        assert_eq!(first.entries, [(0, 0), (1, 10)]);
+        assert_eq!(first.next_cursor, Some(1));
+
+        // The map is modified while the caller awaits between the pages.
+        map.remove(&2);
+        assert!(matches!(
+            map.check_generation(first.generation),
+            Err(Error::StructureModified { .. })
+        ));
+
+        let second = map.page(first.next_cursor.as_ref(), 2);
+        assert_eq!(second.entries, [(3, 30), (4, 40)]);
+        assert_eq!(second.next_cursor, None);
+        assert_ne!(second.generation, first.generation);
+        assert!(map.check_generation(second.generation).is_ok());
+
+        // Removing a missing key doesn't modify the map.
+        map.remove(&2);
+        assert!(map.check_generation(second.generation).is_ok());
+        assert_eq!(map.page(Some(&4), 2).entries, []);
+    }
 }
@@ -2,10 +2,12 @@ mod btreemap;
 mod cell;
 mod log;
 mod multimap;
+mod prefix_map;
 mod vec;
 
 pub use btreemap::StableBTreeMap;
 pub use cell::StableCell;
 pub use log::StableLog;
 pub use multimap::{StableMultimap, StableMultimapIter, StableMultimapRangeIter};
+pub use prefix_map::{StablePrefixMap, StablePrefixMapIter};
 pub use vec::StableVec;
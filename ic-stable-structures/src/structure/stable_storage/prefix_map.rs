@@ -0,0 +1,325 @@
+use std::ops::{Bound, RangeBounds};
+
+use dfinity_stable_structures::{btreemap, Memory, StableBTreeMap, Storable};
+
+use crate::codec::{KeyPrefix, OrderedCodec, OrderedKey};
+use crate::structure::PrefixMapStructure;
+
+/// `StablePrefixMap` stores values against composite tuple keys, e.g. `(owner, token, timestamp)`,
+/// making it possible to fetch or remove all the values by any prefix of the key:
+/// `(owner,)` or `(owner, token)`.
+///
+/// Keys are stored in the order-preserving encoding, so unlike `StableMultimap`
+/// the key elements don't need to implement `Bounded`.
+pub struct StablePrefixMap<K, V, M>(StableBTreeMap<OrderedKey<K>, V, M>)
+where
+    K: OrderedCodec,
+    V: Storable,
+    M: Memory;
+
+impl<K, V, M> StablePrefixMap<K, V, M>
+where
+    K: OrderedCodec,
+    V: Storable,
+    M: Memory,
+{
+    /// Create a new instance of a `StablePrefixMap`.
+    pub fn new(memory: M) -> Self {
+        Self(StableBTreeMap::init(memory))
+    }
+
+    /// Number of entries which keys start with the `prefix`.
+    pub fn prefix_len<P: KeyPrefix<K>>(&self, prefix: &P) -> u64 {
+        self.prefix_iter(prefix).count() as u64
+    }
+
+    fn prefix_range<P: KeyPrefix<K>>(&self, prefix: &P) -> StablePrefixMapIter<'_, K, V, M> {
+        let prefix = OrderedKey::prefix(prefix);
+        let inner = self.0.range(prefix.clone()..);
+        StablePrefixMapIter::new(inner, Some(prefix))
+    }
+}
+
+impl<K, V, M> PrefixMapStructure<K, V> for StablePrefixMap<K, V, M>
+where
+    K: OrderedCodec,
+    V: Storable,
+    M: Memory,
+{
+    type Iterator<'a>
+        = StablePrefixMapIter<'a, K, V, M>
+    where
+        Self: 'a;
+
+    fn get(&self, key: &K) -> Option<V> {
+        self.0.get(&OrderedKey::new(key))
+    }
+
+    fn insert(&mut self, key: &K, value: V) -> Option<V> {
+        self.0.insert(OrderedKey::new(key), value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.0.remove(&OrderedKey::new(key))
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.0.contains_key(&OrderedKey::new(key))
+    }
+
+    fn prefix_iter<P: KeyPrefix<K>>(&self, prefix: &P) -> Self::Iterator<'_> {
+        self.prefix_range(prefix)
+    }
+
+    fn remove_prefix<P: KeyPrefix<K>>(&mut self, prefix: &P) -> u64 {
+        let prefix = OrderedKey::prefix(prefix);
+        let keys: Vec<_> = self
+            .0
+            .range(prefix.clone()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.as_bytes().starts_with(prefix.as_bytes()))
+            .collect();
+
+        let mut removed = 0;
+        for key in keys {
+            if self.0.remove(&key).is_some() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    fn range(&self, key_range: impl RangeBounds<K>) -> Self::Iterator<'_> {
+        let encode_bound = |bound: Bound<&K>| match bound {
+            Bound::Included(key) => Bound::Included(OrderedKey::new(key)),
+            Bound::Excluded(key) => Bound::Excluded(OrderedKey::new(key)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let range = (
+            encode_bound(key_range.start_bound()),
+            encode_bound(key_range.end_bound()),
+        );
+
+        StablePrefixMapIter::new(self.0.range(range), None)
+    }
+
+    fn iter(&self) -> Self::Iterator<'_> {
+        StablePrefixMapIter::new(self.0.iter(), None)
+    }
+
+    fn pop_first(&mut self) -> Option<(K, V)> {
+        self.0.pop_first().map(|(key, value)| (key.decode(), value))
+    }
+
+    fn pop_last(&mut self) -> Option<(K, V)> {
+        self.0.pop_last().map(|(key, value)| (key.decode(), value))
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.0.clear_new();
+    }
+}
+
+/// Iterator over the `StablePrefixMap` entries.
+pub struct StablePrefixMapIter<'a, K, V, M>
+where
+    K: OrderedCodec,
+    V: Storable,
+    M: Memory,
+{
+    inner: btreemap::Iter<'a, OrderedKey<K>, V, M>,
+    /// If set, the iteration stops at the first key that doesn't start with the prefix.
+    prefix: Option<OrderedKey<K>>,
+    is_finished: bool,
+}
+
+impl<'a, K, V, M> StablePrefixMapIter<'a, K, V, M>
+where
+    K: OrderedCodec,
+    V: Storable,
+    M: Memory,
+{
+    fn new(inner: btreemap::Iter<'a, OrderedKey<K>, V, M>, prefix: Option<OrderedKey<K>>) -> Self {
+        Self {
+            inner,
+            prefix,
+            is_finished: false,
+        }
+    }
+}
+
+impl<K, V, M> Iterator for StablePrefixMapIter<'_, K, V, M>
+where
+    K: OrderedCodec,
+    V: Storable,
+    M: Memory,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_finished {
+            return None;
+        }
+
+        let (key, value) = self.inner.next()?;
+        if let Some(prefix) = &self.prefix {
+            if !key.as_bytes().starts_with(prefix.as_bytes()) {
+                self.is_finished = true;
+                return None;
+            }
+        }
+
+        Some((key.decode(), value))
+    }
+}
+
+impl<'a, K, V, M> IntoIterator for &'a StablePrefixMap<K, V, M>
+where
+    K: OrderedCodec,
+    V: Storable,
+    M: Memory,
+{
+    type Item = (K, V);
+
+    type IntoIter = StablePrefixMapIter<'a, K, V, M>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Principal;
+    use dfinity_stable_structures::VectorMemory;
+
+    use super::*;
+
+    type Key = (Principal, String, u64);
+
+    fn alice() -> Principal {
+        Principal::from_slice(&[1; 29])
+    }
+
+    fn bob() -> Principal {
+        Principal::from_slice(&[2; 29])
+    }
+
+    fn key(owner: Principal, token: &str, timestamp: u64) -> Key {
+        (owner, token.to_string(), timestamp)
+    }
+
+    fn make_map() -> StablePrefixMap<Key, u64, VectorMemory> {
+        let mut map = StablePrefixMap::new(VectorMemory::default());
+        map.insert(&key(alice(), "ICP", 10), 1);
+        map.insert(&key(alice(), "ICP", 20), 2);
+        map.insert(&key(alice(), "ckBTC", 5), 3);
+        map.insert(&key(bob(), "ICP", 1), 4);
+        map.insert(&key(bob(), "ICPX", 1), 5);
+        map
+    }
+
+    #[test]
+    fn should_insert_get_remove() {
+        let mut map = make_map();
+        assert_eq!(map.len(), 5);
+
+        assert_eq!(map.get(&key(alice(), "ICP", 10)), Some(1));
+        assert_eq!(map.get(&key(alice(), "ICP", 11)), None);
+        assert!(map.contains_key(&key(bob(), "ICPX", 1)));
+
+        assert_eq!(map.insert(&key(alice(), "ICP", 10), 100), Some(1));
+        assert_eq!(map.get(&key(alice(), "ICP", 10)), Some(100));
+
+        assert_eq!(map.remove(&key(alice(), "ICP", 10)), Some(100));
+        assert_eq!(map.remove(&key(alice(), "ICP", 10)), None);
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn should_iterate_in_key_order() {
+        let map = make_map();
+        let values: Vec<_> = map.iter().map(|(_, value)| value).collect();
+        assert_eq!(values, vec![1, 2, 3, 4, 5]);
+
+        let keys: Vec<_> = map.into_iter().map(|(key, _)| key).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+
+    #[test]
+    fn should_iterate_by_prefix() {
+        let map = make_map();
+
+        let alice_values: Vec<_> = map.prefix_iter(&(alice(),)).map(|(_, v)| v).collect();
+        assert_eq!(alice_values, vec![1, 2, 3]);
+
+        let alice_icp: Vec<_> = map
+            .prefix_iter(&(alice(), "ICP".to_string()))
+            .map(|(_, v)| v)
+            .collect();
+        assert_eq!(alice_icp, vec![1, 2]);
+
+        // "ICP" is a string prefix of "ICPX", but not a key prefix.
+        let bob_icp: Vec<_> = map
+            .prefix_iter(&(bob(), "ICP".to_string()))
+            .map(|(k, v)| (k.2, v))
+            .collect();
+        assert_eq!(bob_icp, vec![(1, 4)]);
+
+        let exact: Vec<_> = map.prefix_iter(&key(bob(), "ICPX", 1)).collect();
+        assert_eq!(exact, vec![(key(bob(), "ICPX", 1), 5)]);
+
+        assert_eq!(map.prefix_iter(&(Principal::anonymous(),)).next(), None);
+        assert_eq!(map.prefix_len(&(bob(),)), 2);
+    }
+
+    #[test]
+    fn should_iterate_by_range() {
+        let map = make_map();
+
+        let values: Vec<_> = map
+            .range(key(alice(), "ICP", 15)..key(bob(), "ICPX", 0))
+            .map(|(_, v)| v)
+            .collect();
+        assert_eq!(values, vec![2, 3, 4]);
+
+        let values: Vec<_> = map.range(key(bob(), "ICP", 1)..).map(|(_, v)| v).collect();
+        assert_eq!(values, vec![4, 5]);
+    }
+
+    #[test]
+    fn should_remove_by_prefix() {
+        let mut map = make_map();
+
+        assert_eq!(map.remove_prefix(&(alice(), "ICP".to_string())), 2);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.remove_prefix(&(alice(), "ICP".to_string())), 0);
+
+        assert_eq!(map.remove_prefix(&(bob(),)), 2);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.iter().next(), Some((key(alice(), "ckBTC", 5), 3)));
+    }
+
+    #[test]
+    fn should_pop_and_clear() {
+        let mut map = make_map();
+
+        assert_eq!(map.pop_first(), Some((key(alice(), "ICP", 10), 1)));
+        assert_eq!(map.pop_last(), Some((key(bob(), "ICPX", 1), 5)));
+        assert_eq!(map.len(), 3);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.pop_first(), None);
+    }
+}
@@ -68,6 +68,8 @@ mod tests {
     use dfinity_stable_structures::VectorMemory;
 
     use super::*;
+    use crate::test_utils::BoundedBytes;
+    use crate::Error;
 
     #[test]
     fn vec_works() {
@@ -135,4 +137,20 @@ mod tests {
         vec.push(&item).unwrap();
         assert_eq!(Some(item), vec.get(0));
     }
+
+    #[test]
+    fn try_push_should_reject_oversized_items() {
+        let mut vec = StableVec::<BoundedBytes<4>, _>::new(VectorMemory::default()).unwrap();
+        vec.try_push(&BoundedBytes(vec![1; 4])).unwrap();
+        assert!(matches!(
+            vec.try_push(&BoundedBytes(vec![1; 5])),
+            Err(Error::ValueTooLarge(5))
+        ));
+        assert!(matches!(
+            vec.try_set(0, &BoundedBytes(vec![1; 6])),
+            Err(Error::ValueTooLarge(6))
+        ));
+        assert_eq!(vec.len(), 1);
+        assert_eq!(vec.get(0), Some(BoundedBytes(vec![1; 4])));
+    }
 }
@@ -52,3 +52,22 @@ impl<const N: usize> Storable for Array<N> {
         Array(buf)
     }
 }
+
+/// Bytes with the `Storable` bound of `N` bytes, which doesn't check the length on creation.
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+pub struct BoundedBytes<const N: usize>(pub Vec<u8>);
+
+impl<const N: usize> Storable for BoundedBytes<N> {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: N as u32,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+}
@@ -0,0 +1,140 @@
+use candid::Principal;
+use ic_stable_structures::{
+    assert_within_bounds, Bounded, BoundedString, MultimapStructure, StableMultimap, Storable,
+    VectorMemory,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Bounded, Storable)]
+#[storable(encoding = "ordered")]
+struct TransferKey {
+    token: Principal,
+    timestamp: i64,
+    nonce: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Bounded, Storable)]
+#[storable(encoding = "ordered")]
+struct Version(u16, u16);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Bounded, Storable)]
+#[storable(encoding = "ordered")]
+enum Status {
+    Pending,
+    Completed { at: u64 },
+    Failed(u32, bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Bounded, Storable)]
+#[storable(encoding = "ordered")]
+struct Marker;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Bounded, Storable)]
+#[storable(encoding = "ordered")]
+struct Tagged<T> {
+    tag: u8,
+    value: T,
+}
+
+#[test]
+fn should_derive_bounds_for_struct() {
+    assert_eq!(
+        TransferKey::MIN,
+        TransferKey {
+            token: Principal::MIN,
+            timestamp: i64::MIN,
+            nonce: 0,
+        }
+    );
+    assert_eq!(TransferKey::MAX.nonce, u64::MAX);
+
+    assert_within_bounds([
+        TransferKey {
+            token: Principal::anonymous(),
+            timestamp: -1,
+            nonce: 42,
+        },
+        TransferKey {
+            token: Principal::management_canister(),
+            timestamp: i64::MAX,
+            nonce: 0,
+        },
+        TransferKey {
+            token: Principal::from_slice(&[0xFF; 29]),
+            timestamp: i64::MIN,
+            nonce: u64::MAX,
+        },
+    ]);
+}
+
+#[test]
+fn should_derive_bounds_for_tuple_and_unit_structs() {
+    assert_eq!(Version::MIN, Version(0, 0));
+    assert_eq!(Version::MAX, Version(u16::MAX, u16::MAX));
+    assert_within_bounds([Version(0, 1), Version(1, 0), Version(300, 7)]);
+
+    assert_eq!(Marker::MIN, Marker::MAX);
+    assert_within_bounds([Marker]);
+}
+
+#[test]
+fn should_derive_bounds_for_enum() {
+    assert_eq!(Status::MIN, Status::Pending);
+    assert_eq!(Status::MAX, Status::Failed(u32::MAX, true));
+
+    assert_within_bounds([
+        Status::Pending,
+        Status::Completed { at: 0 },
+        Status::Completed { at: u64::MAX },
+        Status::Failed(0, false),
+        Status::Failed(10, true),
+    ]);
+}
+
+#[test]
+fn should_derive_bounds_for_generic_struct() {
+    assert_eq!(
+        Tagged::<i32>::MAX,
+        Tagged {
+            tag: u8::MAX,
+            value: i32::MAX,
+        }
+    );
+    assert_within_bounds([
+        Tagged {
+            tag: 1,
+            value: -5i32,
+        },
+        Tagged {
+            tag: 0,
+            value: i32::MAX,
+        },
+    ]);
+}
+
+#[test]
+fn should_use_derived_bounds_in_multimap() {
+    let mut map =
+        StableMultimap::<BoundedString<8>, TransferKey, u64, _>::new(VectorMemory::default());
+    let icp = BoundedString::new("ICP").unwrap();
+    let ckbtc = BoundedString::new("ckBTC").unwrap();
+
+    let key = |timestamp, nonce| TransferKey {
+        token: Principal::anonymous(),
+        timestamp,
+        nonce,
+    };
+
+    map.insert(&icp, &key(-10, 1), 1);
+    map.insert(&icp, &key(5, 0), 2);
+    map.insert(&ckbtc, &key(0, 0), 3);
+
+    let values: Vec<_> = map.range(&icp).map(|(_, value)| value).collect();
+    assert_eq!(values, vec![1, 2]);
+
+    assert!(map.remove_partial(&icp));
+    assert_eq!(map.len(), 1);
+    assert_eq!(map.get(&ckbtc, &key(0, 0)), Some(3));
+
+    let bytes = icp.to_bytes();
+    assert_eq!(bytes.as_ref(), b"ICP");
+}
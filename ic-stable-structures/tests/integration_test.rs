@@ -1,4 +1,6 @@
+mod bounded_derive;
 #[cfg(feature = "memory-mapped-files-memory")]
 mod memory_mapped_files;
 #[cfg(feature = "pocket-ic")]
 mod pocket_ic_tests;
+mod storable_derive;
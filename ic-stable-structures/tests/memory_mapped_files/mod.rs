@@ -1,8 +1,10 @@
 use std::sync::Arc;
 
 use ic_stable_structures::{
-    BTreeMapStructure, IcMemoryManager, MemoryId, MemoryManager, MemoryMappedFileMemory,
-    MemoryMappedFileMemoryManager, StableBTreeMap, StableVec, VecStructure,
+    BTreeMapStructure, BackupCompression, BackupKind, BackupManifest, IcMemoryManager,
+    IcStableMemoryImage, MemMapError, MemoryId, MemoryManager, MemoryMappedFileMemory,
+    MemoryMappedFileMemoryManager, StableBTreeMap, StableVec, SyncPolicy, VecStructure,
+    VectorMemory,
 };
 use parking_lot::Mutex;
 use tempfile::{NamedTempFile, TempDir};
@@ -170,3 +172,293 @@ fn test_memory_mapped_file_memory_manager_saves_copy() {
     assert_eq!(map.get(&2), Some(3));
     assert_eq!(map.get(&4), Some(5));
 }
+
+#[test]
+fn test_durable_memory_mapped_file_memory_manager() {
+    let base_dir = TempDir::new().unwrap();
+    let base_path = base_dir.path().to_path_buf();
+
+    let memory_manager = MemoryMappedFileMemoryManager::new(base_path.clone(), true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH)
+        .with_durable_mode(SyncPolicy::Explicit);
+
+    let mut map = StableBTreeMap::<u32, u64, _>::new(memory_manager.get(0));
+    map.insert(1, 2);
+    map.insert(2, 3);
+    memory_manager.sync().unwrap();
+
+    map.insert(4, 5);
+    drop(map);
+    drop(memory_manager);
+
+    let memory_manager = MemoryMappedFileMemoryManager::new(base_path.clone(), true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH)
+        .with_durable_mode(SyncPolicy::Explicit);
+
+    let map = StableBTreeMap::<u32, u64, _>::new(memory_manager.open("0").unwrap());
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.get(&4), Some(5));
+
+    // Simulate a crash after a write without sync.
+    let mut map = map;
+    map.insert(6, 7);
+    drop(map);
+    std::mem::forget(memory_manager);
+
+    let memory_manager = MemoryMappedFileMemoryManager::new(base_path, true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH)
+        .with_durable_mode(SyncPolicy::Explicit);
+    assert!(matches!(
+        memory_manager.open("0"),
+        Err(MemMapError::InconsistentFile { .. })
+    ));
+}
+
+fn backup_file_size(backup_path: &std::path::Path, name: &str) -> u64 {
+    std::fs::metadata(backup_path.join(format!("{name}.pages")))
+        .unwrap()
+        .len()
+}
+
+#[test]
+fn test_memory_mapped_file_memory_manager_incremental_backups() {
+    let base_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let full_path = backup_dir.path().join("full");
+    let increment_path = backup_dir.path().join("increment");
+
+    let memory_manager = MemoryMappedFileMemoryManager::new(base_dir.path().to_path_buf(), true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH);
+
+    assert!(matches!(
+        memory_manager.save_backup(
+            &increment_path,
+            BackupKind::Incremental,
+            BackupCompression::None
+        ),
+        Err(MemMapError::NoBaseBackup)
+    ));
+
+    let mut map = StableBTreeMap::<u32, u64, _>::new(memory_manager.get(0));
+    for i in 0..1000 {
+        map.insert(i, i as u64);
+    }
+
+    let full = memory_manager
+        .save_backup(&full_path, BackupKind::Full, BackupCompression::Deflate)
+        .unwrap();
+    assert_eq!(full.base_backup_id, None);
+    assert_eq!(full.files.len(), 1);
+
+    map.insert(5000, 1);
+    let mut vec = StableVec::<u32, _>::new(memory_manager.get(1)).unwrap();
+    vec.push(&42).unwrap();
+
+    let increment = memory_manager
+        .save_backup(
+            &increment_path,
+            BackupKind::Incremental,
+            BackupCompression::None,
+        )
+        .unwrap();
+    assert_eq!(increment.base_backup_id, Some(full.backup_id));
+    assert_eq!(increment.files.len(), 2);
+
+    // Only the modified pages of the map are in the increment.
+    let map_file = increment
+        .files
+        .iter()
+        .find(|file| file.name == "0")
+        .unwrap();
+    let stored: u64 = map_file.ranges.iter().map(|(start, end)| end - start).sum();
+    assert!(stored < map_file.length);
+    assert_eq!(backup_file_size(&increment_path, "0"), stored);
+    // The new memory is stored completely.
+    let vec_file = increment
+        .files
+        .iter()
+        .find(|file| file.name == "1")
+        .unwrap();
+    assert_eq!(vec_file.ranges, vec![(0, vec_file.length)]);
+
+    assert_eq!(BackupManifest::load(&increment_path).unwrap(), increment);
+
+    let restore_dir = TempDir::new().unwrap();
+    let restored = MemoryMappedFileMemoryManager::new(restore_dir.path().to_path_buf(), true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH);
+
+    // Increments can't be restored without the base.
+    assert!(matches!(
+        restored.restore_backups(&[&increment_path]),
+        Err(MemMapError::InvalidBackup(_))
+    ));
+
+    restored
+        .restore_backups(&[&full_path, &increment_path])
+        .unwrap();
+
+    let map = StableBTreeMap::<u32, u64, _>::new(restored.get(0));
+    assert_eq!(map.len(), 1001);
+    assert_eq!(map.get(&999), Some(999));
+    assert_eq!(map.get(&5000), Some(1));
+
+    let vec = StableVec::<u32, _>::new(restored.get(1)).unwrap();
+    assert_eq!(vec.get(0), Some(42));
+}
+
+#[test]
+fn test_memory_mapped_file_memory_manager_detects_corrupted_backup() {
+    let base_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+
+    let memory_manager = MemoryMappedFileMemoryManager::new(base_dir.path().to_path_buf(), true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH);
+    let mut map = StableBTreeMap::<u32, u64, _>::new(memory_manager.get(0));
+    map.insert(1, 2);
+
+    memory_manager
+        .save_backup(backup_dir.path(), BackupKind::Full, BackupCompression::None)
+        .unwrap();
+
+    // The directory already contains a backup.
+    assert!(matches!(
+        memory_manager.save_backup(backup_dir.path(), BackupKind::Full, BackupCompression::None),
+        Err(MemMapError::InvalidBackup(_))
+    ));
+
+    let data_path = backup_dir.path().join("0.pages");
+    let mut data = std::fs::read(&data_path).unwrap();
+    data[100] ^= 0xFF;
+    std::fs::write(&data_path, data).unwrap();
+
+    let restore_dir = TempDir::new().unwrap();
+    let restored = MemoryMappedFileMemoryManager::new(restore_dir.path().to_path_buf(), true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH);
+    assert!(matches!(
+        restored.restore_backups(&[backup_dir.path()]),
+        Err(MemMapError::InvalidBackup(_))
+    ));
+}
+
+#[test]
+fn test_read_only_memory_mapped_file_memory_manager() {
+    let base_dir = TempDir::new().unwrap();
+
+    let writer = MemoryMappedFileMemoryManager::new(base_dir.path().to_path_buf(), true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH)
+        .with_durable_mode(SyncPolicy::Explicit);
+    let mut map = StableBTreeMap::<u32, u64, _>::new(writer.get(0));
+    map.insert(1, 2);
+    map.insert(2, 3);
+    writer.sync().unwrap();
+
+    let reader = MemoryMappedFileMemoryManager::new_read_only(base_dir.path().to_path_buf())
+        .with_max_memory_size_per_id(RESERVED_LENGTH)
+        .with_durable_mode(SyncPolicy::Explicit);
+    let other_reader = MemoryMappedFileMemoryManager::new_read_only(base_dir.path().to_path_buf())
+        .with_max_memory_size_per_id(RESERVED_LENGTH)
+        .with_durable_mode(SyncPolicy::Explicit);
+
+    for reader in [&reader, &other_reader] {
+        let map = StableBTreeMap::<u32, u64, _>::new(reader.get(0));
+        assert_eq!(map.get(&1), Some(2));
+        assert_eq!(map.get(&2), Some(3));
+    }
+
+    // The reader sees the writer's changes, the writes of the reader are rejected.
+    map.insert(1, 10);
+    let mut reader_map = StableBTreeMap::<u32, u64, _>::new(reader.get(0));
+    assert_eq!(reader_map.get(&1), Some(10));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        reader_map.insert(3, 4);
+    }));
+    assert!(result.is_err());
+
+    assert!(matches!(
+        reader.open("1"),
+        Err(MemMapError::FileOpenError(_))
+    ));
+}
+
+#[test]
+fn test_memory_mapped_file_memory_manager_snapshot() {
+    let base_dir = TempDir::new().unwrap();
+
+    let memory_manager = MemoryMappedFileMemoryManager::new(base_dir.path().to_path_buf(), true)
+        .with_max_memory_size_per_id(RESERVED_LENGTH);
+    let mut map = StableBTreeMap::<u32, u64, _>::new(memory_manager.get(0));
+    let mut vec = StableVec::<u32, _>::new(memory_manager.get(1)).unwrap();
+    for i in 0..100 {
+        map.insert(i, i as u64);
+        vec.push(&i).unwrap();
+    }
+
+    let snapshot = memory_manager.snapshot().unwrap();
+    assert_eq!(snapshot.ids().collect::<Vec<_>>(), vec!["0", "1"]);
+
+    for i in 0..100 {
+        map.insert(i, 0);
+    }
+    for i in 100..1000 {
+        map.insert(i, i as u64);
+    }
+    vec.set(0, &42).unwrap();
+    vec.push(&1000).unwrap();
+
+    let snapshot_map = StableBTreeMap::<u32, u64, _>::new(snapshot.get(0));
+    assert_eq!(snapshot_map.len(), 100);
+    assert!(snapshot_map.iter().all(|(key, value)| key as u64 == value));
+
+    let snapshot_vec = StableVec::<u32, _>::new(snapshot.get(1)).unwrap();
+    assert_eq!(snapshot_vec.len(), 100);
+    assert_eq!(snapshot_vec.get(0), Some(0));
+
+    assert_eq!(map.len(), 1000);
+    assert_eq!(map.get(&5), Some(0));
+    assert_eq!(vec.get(0), Some(42));
+}
+
+#[test]
+fn test_load_ic_stable_memory_image() {
+    let memory = VectorMemory::default();
+    let memory_manager = IcMemoryManager::init_with_bucket_size(memory.clone(), 1);
+
+    // Interleave the allocations, so the memories are split into multiple buckets.
+    let mut map = StableBTreeMap::<u32, u64, _>::new(memory_manager.get(MemoryId::new(0)));
+    let mut vec = StableVec::<u64, _>::new(memory_manager.get(MemoryId::new(3))).unwrap();
+    for i in 0..20_000 {
+        map.insert(i, i as u64 * 2);
+        vec.push(&(i as u64)).unwrap();
+    }
+    drop(map);
+    drop(vec);
+    drop(memory_manager);
+
+    let image_file = NamedTempFile::new().unwrap();
+    std::fs::write(image_file.path(), memory.borrow().as_slice()).unwrap();
+
+    let image = IcStableMemoryImage::open(image_file.path()).unwrap();
+    assert_eq!(image.memory_ids(), vec![0, 3]);
+    assert_eq!(image.bucket_size_in_pages(), 1);
+    assert!(image.memory_size_in_pages(0) > 1);
+    assert!(image.memory(1).is_none());
+
+    // Read in place, without extracting the image.
+    let in_place_map = StableBTreeMap::<u32, u64, _>::new(image.memory(0).unwrap());
+    assert_eq!(in_place_map.len(), 20_000);
+    assert_eq!(in_place_map.get(&19_999), Some(39_998));
+    drop(in_place_map);
+
+    let base_dir = TempDir::new().unwrap();
+    let memory_manager = image.extract_to(base_dir.path().to_path_buf()).unwrap();
+    drop(image);
+    drop(image_file);
+
+    let map = StableBTreeMap::<u32, u64, _>::new(memory_manager.get(0));
+    assert_eq!(map.len(), 20_000);
+    assert_eq!(map.get(&12_345), Some(24_690));
+
+    let vec = StableVec::<u64, _>::new(memory_manager.get(3)).unwrap();
+    assert_eq!(vec.len(), 20_000);
+    assert_eq!(vec.get(19_999), Some(19_999));
+}
@@ -0,0 +1,267 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::stable_structures::storable::Bound;
+use ic_stable_structures::{
+    BTreeMapStructure, IterableSortedMapStructure, StableBTreeMap, StableVec, Storable,
+    VecStructure, VectorMemory,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Storable)]
+#[storable(encoding = "fixed")]
+struct Position {
+    x: i32,
+    y: i32,
+    is_visible: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Storable)]
+#[storable(encoding = "fixed")]
+enum Shape {
+    Point,
+    Circle(Position, u64),
+    Rect {
+        top_left: Position,
+        bottom_right: Position,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Storable)]
+#[storable(encoding = "ordered")]
+struct BalanceKey {
+    owner: Principal,
+    token: String,
+    nonce: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Storable)]
+#[storable(encoding = "ordered")]
+struct Timestamp(u64);
+
+#[derive(Debug, Clone, PartialEq, Storable)]
+#[storable(encoding = "varint")]
+struct Transfer {
+    from: Principal,
+    amount: u128,
+    fee: u64,
+    memo: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Deserialize, Storable)]
+#[storable(encoding = "candid", max_size = 1024)]
+struct Settings {
+    name: String,
+    enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Storable)]
+#[storable(encoding = "ordered")]
+struct Wrapper<T> {
+    inner: T,
+}
+
+fn roundtrip<T: Storable>(value: &T) -> T {
+    T::from_bytes(value.to_bytes())
+}
+
+#[test]
+fn fixed_encoding_should_compute_bound() {
+    assert_eq!(
+        Position::BOUND,
+        Bound::Bounded {
+            max_size: 9,
+            is_fixed_size: true
+        }
+    );
+    // The largest variant is `Rect`, plus one byte for the variant tag.
+    assert_eq!(
+        Shape::BOUND,
+        Bound::Bounded {
+            max_size: 19,
+            is_fixed_size: true
+        }
+    );
+
+    let position = Position {
+        x: -1,
+        y: 2,
+        is_visible: true,
+    };
+    assert_eq!(
+        position.to_bytes().as_ref(),
+        &[0xFF, 0xFF, 0xFF, 0xFF, 2, 0, 0, 0, 1]
+    );
+    assert_eq!(roundtrip(&position), position);
+
+    for shape in [
+        Shape::Point,
+        Shape::Circle(position, 10),
+        Shape::Rect {
+            top_left: position,
+            bottom_right: position,
+        },
+    ] {
+        assert_eq!(shape.to_bytes().len(), 19);
+        assert_eq!(roundtrip(&shape), shape);
+    }
+}
+
+#[test]
+fn fixed_encoding_should_work_with_stable_vec() {
+    let mut vec = StableVec::<Position, _>::new(VectorMemory::default()).unwrap();
+    for i in 0..10 {
+        vec.push(&Position {
+            x: i,
+            y: -i,
+            is_visible: i % 2 == 0,
+        })
+        .unwrap();
+    }
+
+    assert_eq!(vec.len(), 10);
+    assert_eq!(
+        vec.get(3),
+        Some(Position {
+            x: 3,
+            y: -3,
+            is_visible: false
+        })
+    );
+}
+
+#[test]
+fn ordered_encoding_should_preserve_order() {
+    let alice = Principal::from_slice(&[1; 10]);
+    let bob = Principal::from_slice(&[2; 10]);
+
+    let mut keys = vec![
+        BalanceKey {
+            owner: bob,
+            token: "ICP".to_string(),
+            nonce: -1,
+        },
+        BalanceKey {
+            owner: alice,
+            token: "ckBTC".to_string(),
+            nonce: 5,
+        },
+        BalanceKey {
+            owner: alice,
+            token: "ICP".to_string(),
+            nonce: 10,
+        },
+        BalanceKey {
+            owner: alice,
+            token: "ICP".to_string(),
+            nonce: -10,
+        },
+    ];
+    keys.sort();
+
+    let encoded: Vec<_> = keys.iter().map(|key| key.to_bytes().into_owned()).collect();
+    let mut sorted_encoded = encoded.clone();
+    sorted_encoded.sort();
+    assert_eq!(encoded, sorted_encoded);
+
+    for key in &keys {
+        assert_eq!(&roundtrip(key), key);
+    }
+
+    assert_eq!(BalanceKey::BOUND, Bound::Unbounded);
+    assert_eq!(
+        Timestamp::BOUND,
+        Bound::Bounded {
+            max_size: 8,
+            is_fixed_size: true
+        }
+    );
+}
+
+#[test]
+fn ordered_encoding_should_work_as_btreemap_key() {
+    let mut map = StableBTreeMap::<Timestamp, u64, _>::new(VectorMemory::default());
+    for i in [5u64, 1, 300, 42] {
+        map.insert(Timestamp(i), i * 2);
+    }
+
+    let keys: Vec<_> = map
+        .range(Timestamp(2)..Timestamp(301))
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, vec![Timestamp(5), Timestamp(42), Timestamp(300)]);
+}
+
+#[test]
+fn varint_encoding_should_be_compact() {
+    let transfer = Transfer {
+        from: Principal::anonymous(),
+        amount: 100,
+        fee: 10,
+        memo: None,
+    };
+
+    // principal (1 + 1), amount (1), fee (1), memo (1)
+    assert_eq!(transfer.to_bytes().len(), 5);
+    assert_eq!(roundtrip(&transfer), transfer);
+
+    let transfer = Transfer {
+        memo: Some(vec![1, 2, 3]),
+        amount: u128::MAX,
+        ..transfer
+    };
+    assert_eq!(roundtrip(&transfer), transfer);
+    assert_eq!(Transfer::BOUND, Bound::Unbounded);
+}
+
+#[test]
+fn candid_encoding_should_use_max_size() {
+    let settings = Settings {
+        name: "settings".to_string(),
+        enabled: true,
+    };
+
+    assert_eq!(roundtrip(&settings), settings);
+    assert_eq!(
+        Settings::BOUND,
+        Bound::Bounded {
+            max_size: 1024,
+            is_fixed_size: false
+        }
+    );
+}
+
+#[test]
+fn should_derive_for_generic_types() {
+    let value = Wrapper { inner: 42u32 };
+    assert_eq!(roundtrip(&value), value);
+    assert_eq!(
+        Wrapper::<u32>::BOUND,
+        Bound::Bounded {
+            max_size: 4,
+            is_fixed_size: true
+        }
+    );
+}
+
+#[cfg(feature = "bincode")]
+mod bincode_encoding {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Storable)]
+    #[storable(encoding = "bincode")]
+    struct Task {
+        id: u64,
+        payload: Vec<String>,
+    }
+
+    #[test]
+    fn bincode_encoding_should_roundtrip() {
+        let task = Task {
+            id: 1,
+            payload: vec!["a".to_string(), "b".to_string()],
+        };
+
+        assert_eq!(roundtrip(&task), task);
+        assert_eq!(Task::BOUND, Bound::Unbounded);
+    }
+}